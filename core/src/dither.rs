@@ -0,0 +1,157 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Error diffusion kernel, every entry is `(dx, dy, weight)` relative to the current pixel
+#[derive(Debug, PartialEq)]
+pub struct DiffusionKernel {
+    pub weights: &'static [(isize, isize, f32)],
+}
+
+pub const FLOYD_STEINBERG: DiffusionKernel = DiffusionKernel {
+    weights: &[
+        (1, 0, 7.0 / 16.0),
+        (-1, 1, 3.0 / 16.0),
+        (0, 1, 5.0 / 16.0),
+        (1, 1, 1.0 / 16.0),
+    ],
+};
+
+/// Only diffuses 3/4 of the error, which keeps highlights and shadows cleaner
+pub const ATKINSON: DiffusionKernel = DiffusionKernel {
+    weights: &[
+        (1, 0, 1.0 / 8.0),
+        (2, 0, 1.0 / 8.0),
+        (-1, 1, 1.0 / 8.0),
+        (0, 1, 1.0 / 8.0),
+        (1, 1, 1.0 / 8.0),
+        (0, 2, 1.0 / 8.0),
+    ],
+};
+
+/// Builds the `size`x`size` Bayer index matrix, `size` must be a power of two
+pub fn bayer_matrix(size: usize) -> Vec<Vec<usize>> {
+    let mut matrix = vec![vec![0usize]];
+    while matrix.len() < size {
+        let n = matrix.len();
+        let mut next = vec![vec![0usize; 2 * n]; 2 * n];
+        for y in 0..n {
+            for x in 0..n {
+                let value = 4 * matrix[y][x];
+                next[y][x] = value;
+                next[y][x + n] = value + 2;
+                next[y + n][x] = value + 3;
+                next[y + n][x + n] = value + 1;
+            }
+        }
+        matrix = next;
+    }
+    matrix
+}
+
+/**
+ * Offsets every pixel by `threshold(x, y) * spread` before `quantize` maps it to an output
+ * color. Thresholds between -0.5 and 0.5 suit quantizers that round to the nearest level,
+ * ones between 0 and 1 those that truncate. */
+pub fn ordered_dither(
+    pixels: &mut [u8],
+    width: usize,
+    pixel_bytes: usize,
+    threshold: impl Fn(usize, usize) -> f32,
+    spread: f32,
+    quantize: impl Fn(&mut [u8]),
+) {
+    for (i, pixel) in pixels.chunks_exact_mut(pixel_bytes).enumerate() {
+        let offset = threshold(i % width, i / width) * spread;
+        for channel in pixel.iter_mut() {
+            *channel = to_channel(*channel as f32 + offset);
+        }
+        quantize(pixel);
+    }
+}
+
+/**
+ * Maps every pixel with `quantize` and pushes `strength` times the difference onto its
+ * unvisited neighbours. Serpentine scanning walks every other row right to left and mirrors
+ * the kernel, which breaks up the diagonal "worm" patterns of one directional diffusion. */
+#[allow(clippy::too_many_arguments)]
+pub fn diffuse_error(
+    pixels: &mut [u8],
+    width: usize,
+    height: usize,
+    pixel_bytes: usize,
+    kernel: &DiffusionKernel,
+    strength: f32,
+    serpentine: bool,
+    quantize: impl Fn(&mut [u8]),
+) {
+    let mut buffer: Vec<f32> = pixels.iter().map(|&p| p as f32).collect();
+    let mut quantized = vec![0u8; pixel_bytes];
+
+    for y in 0..height {
+        let reversed = serpentine && y % 2 == 1;
+        for column in 0..width {
+            let x = if reversed { width - 1 - column } else { column };
+            let idx = (y * width + x) * pixel_bytes;
+            for channel in 0..pixel_bytes {
+                quantized[channel] = to_channel(buffer[idx + channel]);
+            }
+            let original: Vec<f32> = buffer[idx..idx + pixel_bytes].to_vec();
+            quantize(&mut quantized);
+            pixels[idx..idx + pixel_bytes].copy_from_slice(&quantized);
+
+            for &(dx, dy, weight) in kernel.weights {
+                let dx = if reversed { -dx } else { dx };
+                let nx = x as isize + dx;
+                let ny = y as isize + dy;
+                if nx < 0 || nx >= width as isize || ny >= height as isize {
+                    continue;
+                }
+                let neighbour = (ny as usize * width + nx as usize) * pixel_bytes;
+                for channel in 0..pixel_bytes {
+                    let error = (original[channel] - quantized[channel] as f32) * strength;
+                    buffer[neighbour + channel] += error * weight;
+                }
+            }
+        }
+    }
+}
+
+/// `f32::round` lives in std, clamping first leaves only non-negative values to round
+fn to_channel(value: f32) -> u8 {
+    (value.clamp(0.0, 255.0) + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FLOYD_STEINBERG, diffuse_error, ordered_dither};
+
+    #[test]
+    fn test_dithering_keeps_average() {
+        let one_bit = |pixel: &mut [u8]| pixel.fill(if pixel[0] > 127 { 255 } else { 0 });
+        let mut diffused = [64u8; 16 * 16];
+        diffuse_error(
+            &mut diffused,
+            16,
+            16,
+            1,
+            &FLOYD_STEINBERG,
+            1.0,
+            true,
+            one_bit,
+        );
+        let mean = diffused.iter().map(|&p| p as f32).sum::<f32>() / diffused.len() as f32;
+        assert!((mean - 64.0).abs() < 8.0);
+
+        let mut ordered = [64u8; 4];
+        let thresholds = [-0.375, 0.125, 0.375, -0.125];
+        ordered_dither(
+            &mut ordered,
+            2,
+            1,
+            |x, y| thresholds[y * 2 + x],
+            256.0,
+            one_bit,
+        );
+        assert_eq!(ordered, [0, 0, 255, 0]);
+    }
+}
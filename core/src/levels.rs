@@ -0,0 +1,47 @@
+/// Truncates every channel to `bit_depth` bits, which must be between 1 and 8
+pub fn truncate_bit_depth(pixels: &mut [u8], bit_depth: u8) {
+    let step = (256u16 >> bit_depth) as u8;
+    for byte in pixels.iter_mut() {
+        *byte = (*byte / step) * step;
+    }
+}
+
+/// Rounds every channel to the nearest of the levels `truncate_bit_depth` truncates to
+pub fn round_bit_depth(pixels: &mut [u8], bit_depth: u8) {
+    let step = 256u16 >> bit_depth;
+    for byte in pixels.iter_mut() {
+        *byte = ((*byte as u16 + step / 2) / step * step).min(256 - step) as u8;
+    }
+}
+
+/// Rounds every channel to the nearest of the sorted `levels`
+pub fn round_to_levels(pixels: &mut [u8], levels: &[u8]) {
+    for byte in pixels.iter_mut() {
+        *byte = nearest_level(levels, *byte);
+    }
+}
+
+fn nearest_level(levels: &[u8], value: u8) -> u8 {
+    let above = levels.partition_point(|&level| level < value);
+    match (above.checked_sub(1).map(|i| levels[i]), levels.get(above)) {
+        (Some(below), Some(&above)) if value - below < above - value => below,
+        (_, Some(&above)) => above,
+        (below, None) => below.unwrap_or(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{round_bit_depth, truncate_bit_depth};
+
+    #[test]
+    fn test_bit_depth() {
+        let mut truncated = [0, 63, 64, 200, 255];
+        truncate_bit_depth(&mut truncated, 2);
+        assert_eq!(truncated, [0, 0, 64, 192, 192]);
+
+        let mut rounded = [0, 31, 32, 200, 255];
+        round_bit_depth(&mut rounded, 2);
+        assert_eq!(rounded, [0, 0, 64, 192, 192]);
+    }
+}
@@ -0,0 +1,11 @@
+//! Resampling and color reduction loops shared by the smolres CLI and embedded targets.
+//! Only needs `alloc`, validation and error reporting are left to the caller.
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod dither;
+pub mod levels;
+pub mod palette;
+pub mod resample;
+pub mod types;
@@ -0,0 +1,146 @@
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Upper bound of pixels looked at while building a palette, larger images are sampled
+pub const MAX_SAMPLES: usize = 1 << 16;
+
+/// Every `stride`-th RGB color of the pixels, so that at most `MAX_SAMPLES` are taken
+pub fn sample_colors(pixels: &[u8], pixel_bytes: usize) -> Vec<[u8; 3]> {
+    let stride = (pixels.len() / pixel_bytes).div_ceil(MAX_SAMPLES).max(1);
+    pixels
+        .chunks_exact(pixel_bytes)
+        .step_by(stride)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect()
+}
+
+/**
+ * Builds at most `max_colors` sorted, distinct colors with the median cut algorithm.
+ * The color space is split repeatedly along the channel with the widest range
+ * until the budget is used up, each box then contributes its average color. */
+pub fn median_cut(samples: Vec<[u8; 3]>, max_colors: usize) -> Vec<[u8; 3]> {
+    let mut boxes: Vec<Vec<[u8; 3]>> = vec![samples];
+    while boxes.len() < max_colors {
+        // Pick the box with the widest channel range that can still be split
+        let candidate = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| (i, widest_channel(b)))
+            .filter(|(_, (_, range))| *range > 0)
+            .max_by_key(|(_, (_, range))| *range);
+
+        let Some((index, (channel, _))) = candidate else {
+            break;
+        };
+
+        let mut colors = boxes.swap_remove(index);
+        colors.sort_unstable_by_key(|c| c[channel]);
+        let upper = colors.split_off(colors.len() / 2);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    let mut colors: Vec<[u8; 3]> = boxes
+        .iter()
+        .filter(|b| !b.is_empty())
+        .map(|b| average_color(b))
+        .collect();
+    colors.sort_unstable();
+    colors.dedup();
+    colors
+}
+
+/// Index of the entry of `colors` closest to `color`, `colors` must not be empty
+pub fn nearest_index(
+    colors: &[[u8; 3]],
+    color: [u8; 3],
+    distance: impl Fn([u8; 3], [u8; 3]) -> f32,
+) -> usize {
+    (0..colors.len())
+        .min_by(|&a, &b| distance(colors[a], color).total_cmp(&distance(colors[b], color)))
+        .expect("palette is never empty")
+}
+
+/// Index of the nearest entry of `colors` for every pixel
+pub fn nearest_indices(
+    colors: &[[u8; 3]],
+    pixels: &[u8],
+    pixel_bytes: usize,
+    distance: impl Fn([u8; 3], [u8; 3]) -> f32,
+) -> Vec<usize> {
+    let mut cache: BTreeMap<[u8; 3], usize> = BTreeMap::new();
+    pixels
+        .chunks_exact(pixel_bytes)
+        .map(|pixel| {
+            let color = [pixel[0], pixel[1], pixel[2]];
+            *cache
+                .entry(color)
+                .or_insert_with(|| nearest_index(colors, color, &distance))
+        })
+        .collect()
+}
+
+/// Replaces every RGB pixel with its nearest entry of `colors`
+pub fn map_to_palette(
+    pixels: &mut [u8],
+    pixel_bytes: usize,
+    colors: &[[u8; 3]],
+    distance: impl Fn([u8; 3], [u8; 3]) -> f32,
+) {
+    // Pixelated images repeat the same colors a lot, so lookups are cached
+    let mut cache: BTreeMap<[u8; 3], [u8; 3]> = BTreeMap::new();
+    for pixel in pixels.chunks_exact_mut(pixel_bytes) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let mapped = *cache
+            .entry(color)
+            .or_insert_with(|| colors[nearest_index(colors, color, &distance)]);
+        pixel[..3].copy_from_slice(&mapped);
+    }
+}
+
+/// Rounded mean of the colors, which must not be empty
+pub fn average_color(colors: &[[u8; 3]]) -> [u8; 3] {
+    let mut sums = [0usize; 3];
+    for color in colors {
+        for channel in 0..3 {
+            sums[channel] += color[channel] as usize;
+        }
+    }
+    sums.map(|sum| ((sum + colors.len() / 2) / colors.len()) as u8)
+}
+
+fn widest_channel(colors: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let min = colors.iter().map(|c| c[channel]).min().unwrap_or(0);
+            let max = colors.iter().map(|c| c[channel]).max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{map_to_palette, median_cut, nearest_indices};
+
+    #[test]
+    fn test_median_cut_and_mapping() {
+        let samples = [[250, 0, 0], [255, 10, 0], [0, 0, 240], [0, 5, 255]];
+        let colors = median_cut(samples.to_vec(), 2);
+        assert_eq!(colors, [[0, 3, 248], [253, 5, 0]]);
+
+        let rgb = |a: [u8; 3], b: [u8; 3]| {
+            a.iter()
+                .zip(b)
+                .map(|(&x, y)| (x as f32 - y as f32) * (x as f32 - y as f32))
+                .sum()
+        };
+        let mut pixels = [200, 30, 30, 20, 20, 200];
+        assert_eq!(nearest_indices(&colors, &pixels, 3, rgb), [1, 0]);
+        map_to_palette(&mut pixels, 3, &colors, rgb);
+        assert_eq!(pixels, [253, 5, 0, 0, 3, 248]);
+    }
+}
@@ -0,0 +1,202 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::types::{Component, ImageBuffer};
+
+/**
+ * Averages every block of the source into one pixel of a `target_width` x `target_height`
+ * grid. Block edges are spread over the whole source so no trailing rows or columns are
+ * dropped, the target must not be larger than the source. */
+pub fn average_area_downsample<C: Component>(
+    src_pixels: &[C],
+    src_width: usize,
+    src_height: usize,
+    target_width: usize,
+    target_height: usize,
+    pixel_bytes: usize,
+) -> Vec<C> {
+    let columns = (0..target_width).map(|x| {
+        (
+            x * src_width / target_width,
+            (x + 1) * src_width / target_width,
+        )
+    });
+    let rows = (0..target_height).map(|y| {
+        (
+            y * src_height / target_height,
+            (y + 1) * src_height / target_height,
+        )
+    });
+    let mut target_pixels = Vec::new();
+    separable_box_average(
+        src_pixels,
+        src_width,
+        src_height,
+        columns.collect(),
+        rows.collect(),
+        pixel_bytes,
+        &mut target_pixels,
+    );
+    target_pixels
+}
+
+/// Fills `target` with the average of the source pixels each of its pixels overlaps
+pub fn average_area_upsample<C: Component>(
+    src_pixels: &[C],
+    src_width: usize,
+    src_height: usize,
+    target: &mut ImageBuffer<C>,
+) {
+    let scale_x = src_width as f64 / target.width as f64;
+    let scale_y = src_height as f64 / target.height as f64;
+    let columns = (0..target.width).map(|x| {
+        let end = ceil((x + 1) as f64 * scale_x).min(src_width);
+        ((x as f64 * scale_x) as usize, end)
+    });
+    let rows = (0..target.height).map(|y| {
+        let end = ceil((y + 1) as f64 * scale_y).min(src_height);
+        ((y as f64 * scale_y) as usize, end)
+    });
+    separable_box_average(
+        src_pixels,
+        src_width,
+        src_height,
+        columns.collect(),
+        rows.collect(),
+        target.channels,
+        &mut target.data,
+    );
+}
+
+/**
+ * Averages the source over the boxes spanned by every pair of `columns` and `rows` ranges
+ * into `target_pixels`, replacing what it held but keeping its allocation. Rows are summed
+ * horizontally first and those sums vertically, so every pixel costs the width plus the
+ * height of its box instead of their product. */
+fn separable_box_average<C: Component>(
+    src_pixels: &[C],
+    src_width: usize,
+    src_height: usize,
+    columns: Vec<(usize, usize)>,
+    rows: Vec<(usize, usize)>,
+    pixel_bytes: usize,
+    target_pixels: &mut Vec<C>,
+) {
+    let target_width = columns.len();
+    let mut row_sums = vec![0f64; src_height * target_width * pixel_bytes];
+    for (src_row, sums) in src_pixels
+        .chunks_exact(src_width * pixel_bytes)
+        .zip(row_sums.chunks_exact_mut(target_width * pixel_bytes))
+    {
+        for (&(x_start, x_end), sum) in columns.iter().zip(sums.chunks_exact_mut(pixel_bytes)) {
+            for pixel in
+                src_row[x_start * pixel_bytes..x_end * pixel_bytes].chunks_exact(pixel_bytes)
+            {
+                for (channel_sum, value) in sum.iter_mut().zip(pixel) {
+                    *channel_sum += value.to_f64();
+                }
+            }
+        }
+    }
+
+    target_pixels.clear();
+    target_pixels.reserve(rows.len() * target_width * pixel_bytes);
+    let mut sums = vec![0f64; target_width * pixel_bytes];
+    for &(y_start, y_end) in &rows {
+        sums.fill(0.0);
+        for row in row_sums
+            [y_start * target_width * pixel_bytes..y_end * target_width * pixel_bytes]
+            .chunks_exact(target_width * pixel_bytes)
+        {
+            for (sum, value) in sums.iter_mut().zip(row) {
+                *sum += value;
+            }
+        }
+        for (&(x_start, x_end), pixel) in columns.iter().zip(sums.chunks_exact(pixel_bytes)) {
+            let count = (x_end - x_start) * (y_end - y_start);
+            target_pixels.extend(pixel.iter().map(|&sum| C::from_mean(sum, count)));
+        }
+    }
+}
+
+/**
+ * Copies the source pixel at the top left of every target pixel into `target`, whose pixels
+ * must already have the length its dimensions call for. Works in both directions. */
+pub fn nearest_neighbor_resample<C: Component>(
+    src_pixels: &[C],
+    src_width: usize,
+    src_height: usize,
+    target: &mut ImageBuffer<C>,
+) {
+    let (target_width, target_height, pixel_bytes) = (target.width, target.height, target.channels);
+    let scale_x = src_width as f64 / target_width as f64;
+    let scale_y = src_height as f64 / target_height as f64;
+
+    for y in 0..target_height {
+        for x in 0..target_width {
+            // Positions are never negative, so truncating is flooring
+            let src_x = (x as f64 * scale_x) as usize;
+            let src_y = (y as f64 * scale_y) as usize;
+
+            let src_idx = (src_y * src_width + src_x) * pixel_bytes;
+            let out_idx = (y * target_width + x) * pixel_bytes;
+
+            target.data[out_idx..out_idx + pixel_bytes]
+                .copy_from_slice(&src_pixels[src_idx..src_idx + pixel_bytes]);
+        }
+    }
+}
+
+/// `f64::ceil` lives in std, this covers the non-negative positions the loops need
+fn ceil(value: f64) -> usize {
+    let truncated = value as usize;
+    if (truncated as f64) < value {
+        truncated + 1
+    } else {
+        truncated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{average_area_downsample, average_area_upsample, nearest_neighbor_resample};
+    use crate::types::ImageBuffer;
+
+    #[test]
+    fn test_round_trip() {
+        let src = [0u8, 10, 20, 30, 40, 50, 60, 70, 80];
+        let grid = average_area_downsample(&src, 3, 3, 2, 2, 1);
+        assert_eq!(grid, [0, 15, 45, 60]);
+
+        let mut nearest = ImageBuffer::new(4, 4, 1, vec![0; 16]);
+        nearest_neighbor_resample(&grid, 2, 2, &mut nearest);
+        assert_eq!(&nearest.data[..8], [0, 0, 15, 15, 0, 0, 15, 15]);
+
+        let mut averaged = ImageBuffer::new(3, 3, 1, vec![0; 9]);
+        average_area_upsample(&grid, 2, 2, &mut averaged);
+        assert_eq!(averaged.data, [0, 7, 15, 22, 30, 37, 45, 52, 60]);
+    }
+
+    #[test]
+    fn test_separable_matches_block_sums() {
+        let (width, height) = (23, 17);
+        let src: Vec<u16> = (0..width * height * 2)
+            .map(|i| (i * 7919 % 4099) as u16)
+            .collect();
+        let grid = average_area_downsample(&src, width, height, 5, 4, 2);
+
+        for (i, pixel) in grid.chunks_exact(2).enumerate() {
+            let (block_x, block_y) = (i % 5, i / 5);
+            let xs = block_x * width / 5..(block_x + 1) * width / 5;
+            let ys = block_y * height / 4..(block_y + 1) * height / 4;
+            for (channel, &value) in pixel.iter().enumerate() {
+                let sum: usize = ys
+                    .clone()
+                    .flat_map(|y| xs.clone().map(move |x| (y * width + x) * 2 + channel))
+                    .map(|idx| src[idx] as usize)
+                    .sum();
+                assert_eq!(value as usize, sum / (xs.len() * ys.len()));
+            }
+        }
+    }
+}
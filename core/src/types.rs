@@ -0,0 +1,74 @@
+use alloc::vec::Vec;
+
+/// Numeric type of a single color channel
+pub trait Component: Copy + Default + PartialEq + 'static {
+    fn to_f64(self) -> f64;
+
+    /// Turns an accumulated sum back into a component, integers truncate like integer division
+    fn from_mean(sum: f64, count: usize) -> Self;
+}
+
+impl Component for u8 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_mean(sum: f64, count: usize) -> Self {
+        (sum / count as f64) as u8
+    }
+}
+
+impl Component for u16 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_mean(sum: f64, count: usize) -> Self {
+        (sum / count as f64) as u16
+    }
+}
+
+impl Component for f32 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_mean(sum: f64, count: usize) -> Self {
+        (sum / count as f64) as f32
+    }
+}
+
+/// Decoded image independent of its file format, pixels are stored row by row
+/// with `channels` interleaved components per pixel (1 = grayscale, 3 = RGB)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageBuffer<C: Component = u8> {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub data: Vec<C>,
+}
+
+impl<C: Component> ImageBuffer<C> {
+    pub fn new(width: usize, height: usize, channels: usize, data: Vec<C>) -> Self {
+        debug_assert_eq!(data.len(), width * height * channels);
+        ImageBuffer {
+            width,
+            height,
+            channels,
+            data,
+        }
+    }
+
+    /// Repeats the luma channel of grayscale images, RGB images are returned as is
+    pub fn to_rgb(&self) -> ImageBuffer<C> {
+        match self.channels {
+            1 => ImageBuffer::new(
+                self.width,
+                self.height,
+                3,
+                self.data.iter().flat_map(|&v| [v, v, v]).collect(),
+            ),
+            _ => self.clone(),
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::io::{Read, Write};
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::encoder::{EncodeError, GIF_MAX_COLORS, dimensions_u16};
+use crate::palette::{Palette, PaletteError};
+use crate::types::ImageBuffer;
+
+/// Frames the shared palette of an animation is built from, spread evenly over its length
+const PALETTE_SAMPLE_FRAMES: usize = 16;
+
+#[derive(Debug, Error)]
+pub enum AnimationError {
+    #[error("Failed to read animation: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Failed to decode GIF: {0}")]
+    Decode(#[from] gif::DecodingError),
+
+    #[error("Failed to encode GIF: {0}")]
+    Encode(#[from] gif::EncodingError),
+
+    #[error("Animation has no frames")]
+    Empty,
+
+    #[error("No frames are shown in the selected time range")]
+    NothingSelected,
+
+    #[error("Animations can only be written as GIF: {0}")]
+    UnsupportedOutput(String),
+
+    #[error(transparent)]
+    Palette(#[from] PaletteError),
+
+    #[error(transparent)]
+    TooLarge(#[from] EncodeError),
+}
+
+/// A fully composited RGB frame and how long it is shown in 1/100 s
+#[derive(Clone)]
+pub struct Frame {
+    pub image: ImageBuffer,
+    pub delay: u16,
+}
+
+pub fn is_animation_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.eq_ignore_ascii_case("gif"))
+        .unwrap_or(false)
+}
+
+/**
+ * Decodes every frame of a GIF onto a canvas of the logical screen size, so each frame
+ * is a complete image even if the file only stores the changed region. */
+pub fn read_gif_frames(input: impl Read) -> Result<Vec<Frame>, AnimationError> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(input)?;
+    let (width, height) = (decoder.width() as usize, decoder.height() as usize);
+
+    let mut canvas = vec![0u8; width * height * 3];
+    let mut frames = vec![];
+    while let Some(frame) = decoder.read_next_frame()? {
+        let previous = canvas.clone();
+        let (left, top) = (frame.left as usize, frame.top as usize);
+        for (i, rgba) in frame.buffer.chunks_exact(4).enumerate() {
+            let (x, y) = (
+                left + i % frame.width as usize,
+                top + i / frame.width as usize,
+            );
+            if rgba[3] == 0 || x >= width || y >= height {
+                continue;
+            }
+            let idx = (y * width + x) * 3;
+            canvas[idx..idx + 3].copy_from_slice(&rgba[..3]);
+        }
+        frames.push(Frame {
+            image: ImageBuffer::new(width, height, 3, canvas.clone()),
+            delay: frame.delay,
+        });
+
+        match frame.dispose {
+            gif::DisposalMethod::Background => {
+                for y in top..(top + frame.height as usize).min(height) {
+                    let start = (y * width + left) * 3;
+                    let end = (y * width + (left + frame.width as usize).min(width)) * 3;
+                    canvas[start..end].fill(0);
+                }
+            }
+            gif::DisposalMethod::Previous => canvas = previous,
+            _ => {}
+        }
+    }
+
+    if frames.is_empty() {
+        return Err(AnimationError::Empty);
+    }
+    Ok(frames)
+}
+
+/**
+ * Builds one palette for the whole animation from evenly spaced frames. Reusing it for
+ * every frame keeps static areas from flickering between slightly different colors. */
+pub fn shared_palette(frames: &[ImageBuffer], max_colors: usize) -> Result<Palette, PaletteError> {
+    let stride = frames.len().div_ceil(PALETTE_SAMPLE_FRAMES).max(1);
+    let mut pixels = vec![];
+    let mut channels = 3;
+    for frame in frames.iter().step_by(stride) {
+        pixels.extend_from_slice(&frame.data);
+        channels = frame.channels;
+    }
+    Palette::median_cut(&pixels, channels, max_colors)
+}
+
+/**
+ * Keeps the frames shown between `start` and `start + duration` seconds, at most `fps` per
+ * second. Every kept frame is shown until the next one, so the playback speed stays. */
+pub fn select_frames(
+    frames: Vec<Frame>,
+    start: f32,
+    duration: Option<f32>,
+    fps: Option<f32>,
+) -> Result<Vec<Frame>, AnimationError> {
+    // Times are in 1/100 s like the delays
+    let start = f64::from(start) * 100.0;
+    let end = duration.map_or(f64::INFINITY, |duration| {
+        start + f64::from(duration) * 100.0
+    });
+    let interval = fps.map(|fps| 100.0 / f64::from(fps));
+
+    let mut kept: Vec<(f64, Frame)> = vec![];
+    let (mut time, mut next_slot) = (0f64, start);
+    for frame in frames {
+        let (begin, finish) = (time, time + f64::from(frame.delay));
+        time = finish;
+        if begin >= end {
+            break;
+        }
+        // Frames without a delay are kept if they start on or after the slot
+        if finish <= next_slot && begin < next_slot {
+            continue;
+        }
+        if let Some(interval) = interval {
+            next_slot += interval;
+            while next_slot < finish {
+                next_slot += interval;
+            }
+        }
+        kept.push((begin.max(start), frame));
+    }
+
+    let last_end = time.min(end);
+    let starts: Vec<f64> = kept.iter().map(|(start, _)| *start).collect();
+    let selected: Vec<Frame> = kept
+        .into_iter()
+        .enumerate()
+        .map(|(i, (shown, frame))| {
+            let until = starts.get(i + 1).copied().unwrap_or(last_end);
+            Frame {
+                delay: (until.round() - shown.round()).clamp(0.0, f64::from(u16::MAX)) as u16,
+                ..frame
+            }
+        })
+        .collect();
+    if selected.is_empty() {
+        return Err(AnimationError::NothingSelected);
+    }
+    Ok(selected)
+}
+
+/// Writes the frames as a looping GIF, frames with more than 256 colors share a median cut palette
+pub fn write_gif_frames(frames: &[Frame], output: impl Write) -> Result<(), AnimationError> {
+    let first = &frames.first().ok_or(AnimationError::Empty)?.image;
+    let (width, height) = dimensions_u16(first, "GIF")?;
+    let images: Vec<ImageBuffer> = frames.iter().map(|frame| frame.image.to_rgb()).collect();
+    let all_pixels: Vec<u8> = images.iter().flat_map(|image| image.data.clone()).collect();
+    let palette = match Palette::exact(&all_pixels, 3, GIF_MAX_COLORS) {
+        Some(palette) => palette,
+        None => shared_palette(&images, GIF_MAX_COLORS)?,
+    };
+    let color_table: Vec<u8> = palette.colors().iter().flatten().copied().collect();
+
+    let mut encoder = gif::Encoder::new(output, width, height, &color_table)?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+    for (frame, image) in frames.iter().zip(&images) {
+        let indices: Vec<u8> = palette
+            .indices(&image.data, 3)
+            .into_iter()
+            .map(|index| index as u8)
+            .collect();
+        let mut gif_frame = gif::Frame::from_indexed_pixels(width, height, indices, None);
+        gif_frame.delay = frame.delay;
+        encoder.write_frame(&gif_frame)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+
+    use super::{
+        AnimationError, Frame, read_gif_frames, select_frames, shared_palette, write_gif_frames,
+    };
+    use crate::types::ImageBuffer;
+
+    #[test]
+    fn test_gif_round_trip() {
+        let path = env::temp_dir().join("test_animation.gif");
+        let frames: Vec<Frame> = [[255, 0, 0], [0, 0, 255]]
+            .iter()
+            .map(|color| Frame {
+                image: ImageBuffer::new(2, 2, 3, color.repeat(4)),
+                delay: 10,
+            })
+            .collect();
+        write_gif_frames(&frames, File::create(&path).unwrap()).unwrap();
+
+        let decoded = read_gif_frames(File::open(&path).unwrap()).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[1].image.data, [0, 0, 255].repeat(4));
+        assert_eq!(decoded[0].delay, 10);
+
+        // Clean up
+        fs::remove_file(path).unwrap();
+
+        // GIF stores dimensions in 16 bits, larger frames are an error instead of a panic
+        let wide = Frame {
+            image: ImageBuffer::new(70_000, 1, 3, vec![0; 70_000 * 3]),
+            delay: 10,
+        };
+        assert!(matches!(
+            write_gif_frames(&[wide], Vec::new()),
+            Err(AnimationError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_shared_palette_covers_all_frames() {
+        let frames = vec![
+            ImageBuffer::new(1, 1, 3, vec![255, 0, 0]),
+            ImageBuffer::new(1, 1, 3, vec![0, 255, 0]),
+        ];
+        let palette = shared_palette(&frames, 4).unwrap();
+        assert_eq!(palette.colors(), &[[0, 255, 0], [255, 0, 0]]);
+    }
+
+    #[test]
+    fn test_select_frames() {
+        // Ten frames of 1/10 s, each filled with its number
+        let frames: Vec<Frame> = (0..10)
+            .map(|i| Frame {
+                image: ImageBuffer::new(1, 1, 1, vec![i]),
+                delay: 10,
+            })
+            .collect();
+        let summary = |frames: Vec<Frame>| -> Vec<(u8, u16)> {
+            frames
+                .iter()
+                .map(|frame| (frame.image.data[0], frame.delay))
+                .collect()
+        };
+
+        let selected = select_frames(frames.clone(), 0.25, Some(0.3), None).unwrap();
+        assert_eq!(summary(selected), [(2, 5), (3, 10), (4, 10), (5, 5)]);
+
+        let selected = select_frames(frames.clone(), 0.0, None, Some(4.0)).unwrap();
+        assert_eq!(summary(selected), [(0, 20), (2, 30), (5, 20), (7, 30)]);
+
+        assert!(matches!(
+            select_frames(frames, 2.0, None, None),
+            Err(AnimationError::NothingSelected)
+        ));
+    }
+}
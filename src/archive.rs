@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+use thiserror::Error;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+use crate::cli::is_input_path;
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("Failed to access archive: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid zip archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+
+    #[error("Archive must be a .zip or .tar file: {0}")]
+    UnsupportedFormat(String),
+}
+
+enum Archive {
+    Zip(Box<ZipWriter<BufWriter<File>>>),
+    Tar(tar::Builder<BufWriter<File>>),
+}
+
+/**
+ * Collects the outputs of a batch run in a single zip or tar archive. Entries are written
+ * as soon as they are added, so workers of a parallel batch can share one writer. */
+pub struct ArchiveWriter {
+    /// Outputs are stored relative to this directory, or by file name if they are outside
+    base: PathBuf,
+    archive: Mutex<Archive>,
+    /// Entries held back until `finish` to write them sorted by name, if set
+    sorted: Option<Mutex<BTreeMap<String, Vec<u8>>>>,
+}
+
+/// Whether the path has the extension of an archive format smolres can write and read
+pub fn is_archive_path(path: &Path) -> bool {
+    matches!(archive_extension(path).as_deref(), Some("zip" | "tar"))
+}
+
+fn archive_extension(path: &Path) -> Option<String> {
+    Some(path.extension()?.to_str()?.to_lowercase())
+}
+
+/**
+ * Reads every supported image inside a zip or tar archive into memory, together with its
+ * path inside the archive. Entries that would leave the archive root are skipped. */
+pub fn read_archive(path: &Path) -> Result<Vec<(PathBuf, Vec<u8>)>, ArchiveError> {
+    let input = BufReader::new(File::open(path)?);
+    let mut images = vec![];
+    match archive_extension(path).as_deref() {
+        Some("zip") => {
+            let mut zip = ZipArchive::new(input)?;
+            for index in 0..zip.len() {
+                let mut file = zip.by_index(index)?;
+                let Some(name) = file.enclosed_name() else {
+                    continue;
+                };
+                if file.is_file() && is_input_path(&name) {
+                    let mut data = Vec::with_capacity(file.size() as usize);
+                    file.read_to_end(&mut data)?;
+                    images.push((name, data));
+                }
+            }
+        }
+        Some("tar") => {
+            let mut tar = tar::Archive::new(input);
+            for entry in tar.entries()? {
+                let mut entry = entry?;
+                let name = normal_path(&entry.path()?);
+                if entry.header().entry_type().is_file() && is_input_path(&name) {
+                    let mut data = Vec::with_capacity(entry.size() as usize);
+                    entry.read_to_end(&mut data)?;
+                    images.push((name, data));
+                }
+            }
+        }
+        _ => return Err(ArchiveError::UnsupportedFormat(path.display().to_string())),
+    }
+    Ok(images)
+}
+
+/// Drops root, `.` and `..` components so a path stays inside the directory it is joined to
+fn normal_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+impl Archive {
+    /// Entries get a fixed timestamp, so the same outputs always give the same archive
+    fn append(&mut self, name: &str, data: &[u8]) -> Result<(), ArchiveError> {
+        match self {
+            Archive::Zip(zip) => {
+                // Image formats are compressed already, deflating them again gains nothing
+                let options = SimpleFileOptions::default()
+                    .compression_method(CompressionMethod::Stored)
+                    .last_modified_time(DateTime::default())
+                    .unix_permissions(0o644);
+                zip.start_file(name, options)?;
+                zip.write_all(data)?;
+            }
+            Archive::Tar(tar) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(0);
+                tar.append_data(&mut header, name, data)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ArchiveWriter {
+    /**
+     * With `sorted` the entries are written in the order of their names instead of the
+     * order they are added in, which differs between parallel runs. */
+    pub fn create(path: &Path, base: &Path, sorted: bool) -> Result<Self, ArchiveError> {
+        let extension = archive_extension(path);
+        if !is_archive_path(path) {
+            return Err(ArchiveError::UnsupportedFormat(path.display().to_string()));
+        }
+        let output = BufWriter::new(File::create(path)?);
+        let archive = match extension.as_deref() {
+            Some("zip") => Archive::Zip(Box::new(ZipWriter::new(output))),
+            _ => Archive::Tar(tar::Builder::new(output)),
+        };
+        Ok(ArchiveWriter {
+            base: base.to_path_buf(),
+            archive: Mutex::new(archive),
+            sorted: sorted.then(Default::default),
+        })
+    }
+
+    /// Adds a file that would otherwise have been written to `path`
+    pub fn add(&self, path: &Path, data: &[u8]) -> Result<(), ArchiveError> {
+        let name = self.entry_name(path);
+        match &self.sorted {
+            Some(entries) => {
+                entries.lock().unwrap().insert(name, data.to_vec());
+                Ok(())
+            }
+            None => self.archive.lock().unwrap().append(&name, data),
+        }
+    }
+
+    pub fn finish(self) -> Result<(), ArchiveError> {
+        let mut archive = self.archive.into_inner().unwrap();
+        if let Some(entries) = self.sorted {
+            for (name, data) in entries.into_inner().unwrap() {
+                archive.append(&name, &data)?;
+            }
+        }
+        match archive {
+            Archive::Zip(zip) => zip.finish()?.flush()?,
+            Archive::Tar(tar) => tar.into_inner()?.flush()?,
+        }
+        Ok(())
+    }
+
+    /// Archive entries always use `/` and never leave the archive root
+    fn entry_name(&self, path: &Path) -> String {
+        let relative = match path.strip_prefix(&self.base) {
+            Ok(relative) => relative,
+            Err(_) => Path::new(path.file_name().unwrap_or_default()),
+        };
+        normal_path(relative)
+            .iter()
+            .map(|part| part.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::path::Path;
+
+    use super::{ArchiveWriter, read_archive};
+
+    #[test]
+    fn test_zip_entries_relative_to_base() {
+        let path = env::temp_dir().join("archive_writer.zip");
+        let writer = ArchiveWriter::create(&path, Path::new("/assets"), false).unwrap();
+        writer
+            .add(Path::new("/assets/sprites/a.png"), b"a")
+            .unwrap();
+        writer.add(Path::new("/elsewhere/b.png"), b"bb").unwrap();
+        writer.finish().unwrap();
+
+        let mut zip = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mut contents = String::new();
+        zip.by_name("sprites/a.png")
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "a");
+        assert_eq!(zip.by_name("b.png").unwrap().size(), 2);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_tar_entries() {
+        let path = env::temp_dir().join("archive_writer.tar");
+        let writer = ArchiveWriter::create(&path, Path::new("out"), false).unwrap();
+        writer.add(Path::new("out/a.jpeg"), b"jpeg").unwrap();
+        writer.finish().unwrap();
+
+        let mut tar = tar::Archive::new(File::open(&path).unwrap());
+        let entries: Vec<String> = tar
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert_eq!(entries, vec!["a.jpeg"]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_read_archive_keeps_images() {
+        let path = env::temp_dir().join("archive_reader.zip");
+        let writer = ArchiveWriter::create(&path, Path::new(""), false).unwrap();
+        writer.add(Path::new("photos/a.jpeg"), b"jpeg").unwrap();
+        writer.add(Path::new("notes.txt"), b"text").unwrap();
+        writer.finish().unwrap();
+
+        let images = read_archive(&path).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].0, Path::new("photos/a.jpeg"));
+        assert_eq!(images[0].1, b"jpeg");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_sorted_archive_is_reproducible() {
+        let write = |path: &Path, names: [&str; 2]| {
+            let writer = ArchiveWriter::create(path, Path::new(""), true).unwrap();
+            for name in names {
+                writer.add(Path::new(name), name.as_bytes()).unwrap();
+            }
+            writer.finish().unwrap();
+            fs::read(path).unwrap()
+        };
+        for extension in ["zip", "tar"] {
+            let path = env::temp_dir().join(format!("archive_sorted.{extension}"));
+            assert_eq!(
+                write(&path, ["a.png", "b.png"]),
+                write(&path, ["b.png", "a.png"])
+            );
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::env;
+use std::io::{self, BufRead, BufReader};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use thiserror::Error;
+
+use crate::geometry::Region;
+
+#[derive(Debug, Error)]
+pub enum CaptureError {
+    #[error("Failed to start ffmpeg, which reads the webcam: {0}")]
+    Spawn(io::Error),
+
+    #[error("Failed to read frame: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Webcam {0} did not deliver a frame")]
+    NoFrame(u32),
+
+    #[error("ffmpeg could not capture the screen")]
+    NoScreenshot,
+
+    #[error("Capturing is only supported on Linux and macOS")]
+    UnsupportedPlatform,
+}
+
+/**
+ * Frames of a webcam as JPEG files. They are read through ffmpeg, which knows the camera
+ * API of every platform, and it keeps running until the camera is dropped. */
+pub struct Camera {
+    child: Child,
+    frames: BufReader<ChildStdout>,
+    device: u32,
+}
+
+impl Camera {
+    pub fn open(device: u32) -> Result<Self, CaptureError> {
+        let mut child = Command::new("ffmpeg")
+            .args(["-loglevel", "error"])
+            .args(input_args(device)?)
+            .args(["-f", "image2pipe", "-c:v", "mjpeg", "-q:v", "2", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(CaptureError::Spawn)?;
+        let frames = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        Ok(Camera {
+            child,
+            frames,
+            device,
+        })
+    }
+
+    /// Waits for the next frame of the camera
+    pub fn next_frame(&mut self) -> Result<Vec<u8>, CaptureError> {
+        read_jpeg(&mut self.frames)?.ok_or(CaptureError::NoFrame(self.device))
+    }
+}
+
+impl Drop for Camera {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Takes a screenshot of the whole screen or a region of it as a JPEG file, through ffmpeg
+pub fn screenshot(region: Option<Region>) -> Result<Vec<u8>, CaptureError> {
+    let (format, input) = if cfg!(target_os = "linux") {
+        (
+            "x11grab",
+            env::var("DISPLAY").unwrap_or_else(|_| String::from(":0")),
+        )
+    } else if cfg!(target_os = "macos") {
+        ("avfoundation", String::from("Capture screen 0"))
+    } else {
+        return Err(CaptureError::UnsupportedPlatform);
+    };
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-loglevel", "error", "-f", format, "-i", &input])
+        .args(["-frames:v", "1"]);
+    if let Some(region) = region {
+        let crop = format!(
+            "crop={}:{}:{}:{}",
+            region.width, region.height, region.x, region.y
+        );
+        command.args(["-vf", &crop]);
+    }
+    let output = command
+        .args(["-f", "image2pipe", "-c:v", "mjpeg", "-q:v", "2", "-"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(CaptureError::Spawn)?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(CaptureError::NoScreenshot);
+    }
+    Ok(output.stdout)
+}
+
+fn input_args(device: u32) -> Result<[String; 4], CaptureError> {
+    let (format, input) = if cfg!(target_os = "linux") {
+        ("v4l2", format!("/dev/video{device}"))
+    } else if cfg!(target_os = "macos") {
+        ("avfoundation", device.to_string())
+    } else {
+        return Err(CaptureError::UnsupportedPlatform);
+    };
+    Ok([
+        String::from("-f"),
+        String::from(format),
+        String::from("-i"),
+        input,
+    ])
+}
+
+/**
+ * Reads the next JPEG file from a stream of concatenated ones. Segments are skipped by
+ * their length, so thumbnails embedded in the metadata do not end the file early. Returns
+ * `None` at the end of the stream. */
+fn read_jpeg(reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    // Anything before the start of image marker is skipped
+    let mut previous = 0;
+    loop {
+        let Some(byte) = read_byte(reader)? else {
+            return Ok(None);
+        };
+        if previous == 0xFF && byte == 0xD8 {
+            break;
+        }
+        previous = byte;
+    }
+
+    let mut data = vec![0xFF, 0xD8];
+    loop {
+        // Markers may be padded with any number of 0xFF bytes
+        let marker = loop {
+            match read_byte(reader)? {
+                None => return Ok(None),
+                Some(0xFF) => continue,
+                Some(byte) => break byte,
+            }
+        };
+        data.extend([0xFF, marker]);
+        match marker {
+            0xD9 => return Ok(Some(data)),
+            0x01 | 0xD0..=0xD7 => continue,
+            _ => {}
+        }
+        let mut length = [0; 2];
+        reader.read_exact(&mut length)?;
+        data.extend(length);
+        let start = data.len();
+        data.resize(
+            start + (u16::from_be_bytes(length) as usize).saturating_sub(2),
+            0,
+        );
+        reader.read_exact(&mut data[start..])?;
+
+        // Compressed data follows a start of scan, in it 0xFF is always followed by 0x00 or
+        // a restart marker
+        if marker == 0xDA {
+            loop {
+                if reader.read_until(0xFF, &mut data)? == 0 {
+                    return Ok(None);
+                }
+                match reader.fill_buf()?.first() {
+                    None => return Ok(None),
+                    Some(&byte @ (0x00 | 0xD0..=0xD7)) => {
+                        data.push(byte);
+                        reader.consume(1);
+                    }
+                    Some(0xFF) => {}
+                    Some(_) => {
+                        data.pop();
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn read_byte(reader: &mut impl BufRead) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    Ok((reader.read(&mut byte)? == 1).then_some(byte[0]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::read_jpeg;
+
+    #[test]
+    fn test_read_jpeg_splits_stream() {
+        // A comment segment hiding markers and a scan with stuffed bytes and a restart marker
+        let frame = [
+            &[0xFF, 0xD8, 0xFF, 0xFE, 0x00, 0x06, 0xFF, 0xD8, 0xFF, 0xD9][..],
+            &[
+                0xFF, 0xDA, 0x00, 0x03, 1, 2, 0xFF, 0x00, 3, 0xFF, 0xD3, 4, 0xFF, 0xD9,
+            ],
+        ]
+        .concat();
+        let stream = [&b"garbage"[..], &frame, &frame, &frame[..8]].concat();
+        let mut reader = &stream[..];
+        assert_eq!(read_jpeg(&mut reader).unwrap(), Some(frame.clone()));
+        assert_eq!(read_jpeg(&mut reader).unwrap(), Some(frame));
+        assert!(read_jpeg(&mut reader).is_err());
+
+        let horse = fs::read("examples/horse.jpeg").unwrap();
+        let stream = [&horse[..], &horse].concat();
+        let mut reader = &stream[..];
+        assert_eq!(read_jpeg(&mut reader).unwrap().as_ref(), Some(&horse));
+        assert_eq!(read_jpeg(&mut reader).unwrap().as_ref(), Some(&horse));
+        assert_eq!(read_jpeg(&mut reader).unwrap(), None);
+    }
+}
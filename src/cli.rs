@@ -1,76 +1,937 @@
-use clap::{Parser, ValueEnum};
+use crate::animation::is_animation_path;
+use crate::archive::is_archive_path;
+use crate::color::{Distance, from_hex};
+use crate::decoder::{JpegDecoderBackend, is_hdr_path};
+use crate::encoder::{JpegBackend, MatrixValues, OutputFormat, PixelFormat};
+use crate::geometry::{Quad, Region};
+use crate::palette::{NamedPalette, built_in_palette};
+use crate::traverse::is_valid_pattern;
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
 use std::fmt;
 use std::fs;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(name = "smolres")]
 #[command(version, about)]
+#[command(subcommand_negates_reqs = true)]
 pub struct Args {
-    /// Path to input image file
-    #[arg(short, long, value_parser=validate_input_path)]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Report how long every stage took, repeat for more detail
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Path or HTTP(S) URL of the input image file, JPEG, animated GIF or OpenEXR/Radiance
+    /// HDR. Every image inside a .zip or .tar archive is processed into a directory named
+    /// after it
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["files_from", "screenshot"],
+        value_parser=validate_input_path
+    )]
+    pub input: Option<PathBuf>,
+
+    /// Take a screenshot as the input, optionally only of the region WIDTHxHEIGHT+X+Y. Needs
+    /// ffmpeg, on Linux the screen is read from the X11 display
+    #[arg(long, value_name = "REGION", conflicts_with_all = ["input", "files_from"], value_parser=validate_screen_region)]
+    pub screenshot: Option<Option<Region>>,
+
+    /// Average these images into the input before pixelation, e.g. several aligned
+    /// exposures of a scene to reduce noise. They need the size of the input. Can be given
+    /// multiple times
+    #[arg(long, value_name = "INPUT", conflicts_with_all = ["files_from", "screenshot"], value_parser=validate_input_path)]
+    pub stack: Vec<PathBuf>,
+
+    /// Pixelate the input with every algorithm, write each result with the algorithm appended
+    /// to its name and print a table of their timings, PSNR and SSIM against the source
+    #[arg(
+        long,
+        requires = "input",
+        conflicts_with_all = ["algorithm", "thumbnail", "format", "max_size", "stack", "files_from"]
+    )]
+    pub compare_algorithms: bool,
+
+    /// Print the interpolation algorithms and dither modes with a short description and exit
+    #[arg(long, exclusive = true)]
+    pub list_algorithms: bool,
+
+    /// Print the built-in palettes of `--palette` and exit
+    #[arg(long, exclusive = true)]
+    pub list_palettes: bool,
+
+    /// Show how far along the current image is on stderr
+    #[arg(long)]
+    pub progress: bool,
+
+    #[command(flatten)]
+    pub limits: Limits,
+
+    /// Read newline separated input paths from a file, or from stdin with `-`, and process
+    /// each of them with the same settings
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["input", "output"])]
+    pub files_from: Option<PathBuf>,
+
+    /// Tone mapping curve used to bring HDR inputs into 8 bit
+    #[cfg(feature = "hdr")]
+    #[arg(long, default_value_t = ToneMap::Reinhard)]
+    pub tone_map: ToneMap,
+
+    /// Path to output image file, JPEG, PNG, WebP or GIF
+    #[arg(short, long, value_parser=validate_output_path)]
+    pub output: Option<PathBuf>,
+
+    /// Write the result in each of these formats, e.g. `jpeg,png,gif`. Defaults to the
+    /// format of the output path
+    #[arg(short, long, value_delimiter = ',')]
+    pub format: Vec<OutputFormat>,
+
+    /// Pixel layout of the c-header format
+    #[arg(long, value_enum, default_value_t = PixelFormat::Rgb565)]
+    pub pixel_format: PixelFormat,
+
+    /// Cells of the json and csv formats
+    #[arg(long, value_enum, default_value_t = MatrixValues::Hex)]
+    pub matrix_values: MatrixValues,
+
+    /// Largest size of the JPEG output, e.g. `100kb` or `2mb`. The highest quality that fits
+    /// is used
+    #[arg(long, value_name = "SIZE", value_parser=validate_file_size)]
+    pub max_size: Option<usize>,
+
+    /// Library JPEG outputs are encoded with
+    #[arg(long, value_enum, default_value_t = JpegBackend::JpegEncoder)]
+    pub encoder: JpegBackend,
+
+    /// Library JPEG inputs are decoded with
+    #[arg(long, value_enum, default_value_t = JpegDecoderBackend::JpegDecoder)]
+    pub decoder: JpegDecoderBackend,
+
+    /// Embed a small copy of the result as the EXIF thumbnail of JPEG outputs, so file
+    /// browsers show a preview without decoding the whole image
+    #[arg(long, conflicts_with = "strip_metadata")]
+    pub exif_thumbnail: bool,
+
+    /// Outputs never carry the EXIF, GPS or ICC data of the source, the encoders only write
+    /// the pixels. This flag additionally forbids `--exif-thumbnail`, so no metadata at all
+    /// ends up in published images
+    #[arg(long)]
+    pub strip_metadata: bool,
+
+    /// Let `--max-size` scale the result down when even the lowest quality is too large
+    #[arg(long, requires = "max_size")]
+    pub shrink_to_fit: bool,
+
+    /// Scale of virtualized resolution, `32x18` for a grid that is not square
+    #[arg(short, long, default_value_t = Resolution::from(16), value_parser=validate_resolution)]
+    pub resolution: Resolution,
+
+    /// Only pixelate the frames of an animation from this many seconds on
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.0, value_parser = validate_non_negative)]
+    pub start: f32,
+
+    /// Only pixelate the frames of an animation shown within this many seconds after `--start`
+    #[arg(long, value_name = "SECONDS", value_parser = validate_positive)]
+    pub duration: Option<f32>,
+
+    /// Keep at most this many frames of an animation per second, the others are dropped and
+    /// the kept ones shown longer
+    #[arg(long, value_parser = validate_positive)]
+    pub fps: Option<f32>,
+
+    /// Swap the width and height of a non-square `--resolution` when the source is oriented
+    /// the other way, so portrait photos are not squeezed into a landscape grid
+    #[arg(long)]
+    pub match_orientation: bool,
+
+    /// Shrink the resolution to the size of sources smaller than it with a warning, instead
+    /// of failing, e.g. for batches of mixed size images
+    #[arg(long)]
+    pub clamp_resolution: bool,
+
+    /// Enlarge sources smaller than the resolution by a whole factor instead of failing, so
+    /// tiny icons work with the same command as large photos
+    #[arg(long, conflicts_with_all = ["clamp_resolution", "thumbnail"])]
+    pub upscale: bool,
+
+    /// Color depth of individual pixels. 1 gives black and white by luminance, dithered with
+    /// floyd-steinberg unless `--dither` picks another method, `--threshold` leaves it undithered
+    #[arg(short, long, default_value_t = 8, value_parser=validate_bit_depth)]
+    pub bit_depth: u8,
+
+    /// How channels are reduced to the bit depth, truncating keeps the image darker
+    #[arg(long, value_enum, default_value_t = Rounding::Truncate, conflicts_with_all = ["colors", "threshold"])]
+    pub rounding: Rounding,
+
+    /// Spread the levels of the bit depth along a power curve instead of evenly, values
+    /// above 1.0 spend more of them on the shadows, where banding is easier to see
+    #[arg(long, value_name = "GAMMA", conflicts_with_all = ["colors", "threshold", "rounding"], value_parser=validate_positive)]
+    pub level_gamma: Option<f32>,
+
+    /// Round every channel to the nearest of these values, e.g. '0,16,48,96,160,255'
+    #[arg(long, value_name = "LIST", conflicts_with_all = ["bit_depth", "level_gamma", "colors", "threshold", "rounding"], value_parser=validate_channel_levels)]
+    pub channel_levels: Option<ChannelLevels>,
+
+    /// Give the smoother half of the blocks one bit less and the more detailed half one bit
+    /// more than the bit depth, for finer levels where they show at the same budget
+    #[arg(long, conflicts_with_all = ["colors", "threshold", "rounding", "level_gamma", "channel_levels", "dithering", "thumbnail"])]
+    pub adaptive: bool,
+
+    /// Map the colors to a built-in palette, `--list-palettes` shows them all
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["bit_depth", "colors", "threshold", "adaptive", "level_gamma", "channel_levels"], value_parser=validate_palette)]
+    pub palette: Option<&'static NamedPalette>,
+
+    /// Total number of colors in the output, builds an optimized palette instead of reducing the bit depth
+    #[arg(short, long, conflicts_with = "bit_depth", value_parser=validate_colors)]
+    pub colors: Option<u16>,
+
+    /// Pick palette colors that stay distinguishable with protanopia, deuteranopia and
+    /// tritanopia, at some cost to how faithful the colors are
+    #[arg(long, requires = "colors")]
+    pub colorblind_safe: bool,
+
+    /// Black and white output without dithering, pixels brighter than this cutoff between 0 and
+    /// 255 become white. `auto` picks the cutoff per image with Otsu's method
+    #[arg(long, value_name = "CUTOFF", conflicts_with_all = ["bit_depth", "colors", "dithering"], value_parser=validate_threshold)]
+    pub threshold: Option<Threshold>,
+
+    /// Dithering applied when reducing colors, error diffusion or an ordered Bayer matrix
+    #[arg(short, long, group = "dithering")]
+    pub dither: Option<Dither>,
+
+    /// Ordered dithering with a threshold matrix read from a text file, one row of ranks per
+    /// line, e.g. for halftone dots or diagonal lines
+    #[arg(long, value_name = "FILE", group = "dithering", value_parser=validate_existing_file)]
+    pub dither_matrix: Option<PathBuf>,
+
+    /// Strength of the dithering between 0.0 and 1.0, lower values are less noisy
+    #[arg(long, default_value_t = 1.0, requires = "dithering", value_parser=validate_dither_strength)]
+    pub dither_strength: f32,
+
+    /// How colors are compared when mapping them to the palette of `--colors`, the metric
+    /// changes which entries get used
+    #[arg(long, value_enum, default_value_t = Distance::Rgb)]
+    pub distance: Distance,
+
+    /// Refine the palette shared by all frames of an animation towards each frame, trades some
+    /// stability for accuracy
+    #[arg(long, requires = "colors")]
+    pub refine_palette: bool,
+
+    /// Alternate the scan direction of error diffusion per row to avoid directional artifacts
+    #[arg(long, requires = "dither")]
+    pub serpentine: bool,
+
+    /// Exposure correction in stops, applied in linear light (before tone mapping for HDR inputs)
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub exposure: f32,
+
+    /// Gamma correction applied before quantization, values above 1.0 brighten the midtones
+    #[arg(long, default_value_t = 1.0, value_parser=validate_positive)]
+    pub gamma: f32,
+
+    /// Correct a color cast before quantization, so it does not use up palette entries.
+    /// Either auto, which makes the average color gray, or the color temperature of the light
+    /// in kelvin, e.g. 3200 for tungsten
+    #[arg(long, value_name = "auto|KELVIN", value_parser=validate_white_balance)]
+    pub white_balance: Option<WhiteBalance>,
+
+    /// Creative warmth from -100 for cool blue to 100 for warm orange, applied before
+    /// quantization
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true, value_parser=validate_slider)]
+    pub temperature: f32,
+
+    /// Creative tint from -100 for green to 100 for magenta, applied before quantization
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true, value_parser=validate_slider)]
+    pub tint: f32,
+
+    /// Stretch the values to the full range before quantization, either every channel on its
+    /// own or all of them by the luma, which keeps the hues
+    #[arg(long, value_name = "MODE", num_args = 0..=1, default_missing_value = "channels")]
+    pub auto_levels: Option<Levels>,
+
+    /// Percentage of the darkest and of the brightest values `--auto-levels` ignores, so a
+    /// few outliers do not limit the stretch
+    #[arg(long, value_name = "PERCENT", default_value_t = 0.5, requires = "auto_levels", value_parser=validate_clip_percent)]
+    pub levels_clip: f32,
+
+    /// Equalize the luma histogram before quantization, which rescues flat, low contrast
+    /// photos at low bit depths. clahe equalizes tiles of the image separately
+    #[arg(long, value_name = "MODE", num_args = 0..=1, default_missing_value = "global")]
+    pub equalize: Option<Equalize>,
+
+    /// Denoise the source before downsampling so sensor noise does not turn into speckled blocks
+    #[arg(long)]
+    pub denoise: Option<Denoise>,
+
+    /// Radius of the denoise window in pixels, the non-local means search window is twice as large
+    #[arg(long, default_value_t = 1, requires = "denoise")]
+    pub denoise_radius: usize,
+
+    /// Painterly stylization of the source before downsampling, its flat patches suit small
+    /// palettes
+    #[arg(long)]
+    pub stylize: Option<Stylize>,
+
+    /// Radius of the stylize window in pixels
+    #[arg(long, default_value_t = 4, requires = "stylize", value_parser=validate_radius)]
+    pub stylize_radius: usize,
+
+    /// Strength of an unsharp mask applied before downsampling, helps small details survive
+    #[arg(long, value_parser=validate_non_negative)]
+    pub sharpen: Option<f32>,
+
+    /// Standard deviation of the blur used by the unsharp mask
+    #[arg(long, default_value_t = 1.0, requires = "sharpen", value_parser=validate_positive)]
+    pub sharpen_sigma: f32,
+
+    /// Standard deviation of a gaussian blur, softens noisy sources or frosts the result
+    #[arg(long, value_parser=validate_positive)]
+    pub blur: Option<f32>,
+
+    /// Whether the blur runs before or after pixelation
+    #[arg(long, default_value_t = Stage::Before, requires = "blur")]
+    pub blur_stage: Stage,
+
+    /// Warp the quadrilateral with these corners, clockwise from the top left in source
+    /// pixels, to a rectangle before anything else, e.g. a poster or screen photographed at
+    /// an angle
+    #[arg(long, value_name = "x1,y1,...,x4,y4", value_parser=validate_perspective)]
+    pub perspective: Option<Quad>,
+
+    /// Trim uniform borders like letterbox bars or white margins before pixelating, so no
+    /// grid cells are spent on them
+    #[arg(long)]
+    pub autocrop: bool,
+
+    /// Maximum difference per channel to the border color that still counts as border
+    #[arg(long, default_value_t = 16, requires = "autocrop")]
+    pub autocrop_tolerance: u8,
+
+    /// How a source with a different aspect ratio is mapped onto the grid: letterboxed,
+    /// center-cropped, cropped to the part with the most detail or distorted
+    #[arg(long, default_value_t = Fit::Stretch)]
+    pub fit: Fit,
+
+    /// Pad the source with this color, black by default, to the aspect ratio of the grid so
+    /// it does not distort the subject. Like `--fit contain` with a choice of color
+    #[arg(long, value_name = "COLOR", num_args = 0..=1, default_missing_value = "#000000", conflicts_with = "fit", value_parser=validate_color)]
+    pub pad_square: Option<[u8; 3]>,
+
+    /// Sort runs of bright pixels of the result by luminance along rows or columns
+    #[arg(long, value_name = "DIRECTION")]
+    pub pixel_sort: Option<SortDirection>,
+
+    /// Pixels with a luminance above this value are sorted, darker ones stay in place
+    #[arg(long, default_value_t = 128, requires = "pixel_sort")]
+    pub sort_threshold: u8,
+
+    /// Share of block rows between 0.0 and 1.0 that are glitched: shifted, color swapped or
+    /// duplicated on the pixelated grid
+    #[arg(long, value_name = "AMOUNT", conflicts_with = "thumbnail", value_parser=validate_fraction)]
+    pub glitch: Option<f32>,
+
+    /// Seed for random effects like `--glitch`, the same seed always gives the same result
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Print the column, row and color of every block to stdout as it is produced, e.g. to
+    /// stream the grid to an LED matrix
+    #[arg(long, conflicts_with = "thumbnail")]
+    pub print_blocks: bool,
+
+    /// Print the unique color count, entropy and average block variance of the result, to
+    /// judge whether fewer colors or a lower bit depth would look the same
+    #[arg(long, conflicts_with = "thumbnail")]
+    pub stats: bool,
+
+    /// Print how much of the source the chosen settings discard, as the share of variance
+    /// the result still explains and of fine detail that is flattened, to find the mildest
+    /// settings for a look
+    #[arg(long, conflicts_with = "thumbnail")]
+    pub report_loss: bool,
+
+    /// Turn the image into its negative, either fully or only its brightness
+    #[arg(long, value_name = "MODE", num_args = 0..=1, default_missing_value = "full")]
+    pub invert: Option<Invert>,
+
+    /// Whether the inversion runs before or after pixelation, after it the palette is
+    /// inverted as well
+    #[arg(long, default_value_t = Stage::After, requires = "invert")]
+    pub invert_stage: Stage,
+
+    /// Replace a color of the quantized result with another, e.g. '#306230=#8bac0f' to
+    /// recolor a sprite. Can be given multiple times
+    #[arg(long, value_name = "FROM=TO", value_parser=validate_remap)]
+    pub remap: Vec<Remap>,
+
+    /// Blend neighbouring blocks across their edges, from 0.0 for hard blocks to 1.0 for a
+    /// fade between block centers, for a softer mosaic
+    #[arg(long, value_name = "AMOUNT", conflicts_with = "thumbnail", value_parser=validate_fraction)]
+    pub soften: Option<f32>,
+
+    /// Offset the red and blue channels of the result by this many pixels in opposite
+    /// directions, for the color fringes of an old CRT
+    #[arg(long, value_name = "PIXELS")]
+    pub chromatic_aberration: Option<usize>,
+
+    /// PNG image composited onto the result after pixelation, e.g. a logo
+    #[arg(long, value_name = "PNG", value_parser=validate_watermark_path)]
+    pub watermark: Option<PathBuf>,
+
+    /// Where the watermark is placed
+    #[arg(long, value_enum, default_value_t = Position::BottomRight, requires = "watermark")]
+    pub watermark_pos: Position,
+
+    /// Opacity of the watermark between 0.0 and 1.0, multiplied with its own transparency
+    #[arg(long, default_value_t = 1.0, requires = "watermark", value_parser=validate_fraction)]
+    pub watermark_opacity: f32,
+
+    /// Single line of text written at the bottom of the result in a pixel font
+    #[arg(long)]
+    pub caption: Option<String>,
+
+    /// Color of the caption text as a hex code
+    #[arg(long, value_name = "COLOR", default_value = "#ffffff", requires = "caption", value_parser=validate_color)]
+    pub caption_color: [u8; 3],
+
+    /// Frame drawn around the result, in output pixels like `8` or `8px`, or in blocks of the
+    /// pixelated grid like `1blocks`
+    #[arg(long, value_name = "WIDTH", value_parser=validate_border)]
+    pub border: Option<BorderWidth>,
+
+    /// Color of the border as a hex code
+    #[arg(long, value_name = "COLOR", default_value = "#000000", requires = "border", value_parser=validate_color)]
+    pub border_color: [u8; 3],
+
+    /// Algorithm to be used for the pixel interpolation. `auto` tries every algorithm on a
+    /// small copy of the image and keeps the one closest to the source
+    #[arg(short, long)]
+    pub algorithm: Option<Algorithm>,
+
+    /// Downsample to N times the grid with the algorithm first and average that down to the
+    /// grid, which reduces aliasing of fast algorithms like `nearestneighbor`
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = validate_supersample)]
+    pub supersample: usize,
+
+    /// Run downsampling and upsampling as compute shaders on the GPU
+    #[cfg(feature = "gpu")]
+    #[arg(long)]
+    pub gpu: bool,
+
+    /// Guarantee the same bytes for the same input and settings. Outputs never carry
+    /// timestamps and `--seed` is fixed, this additionally rules out `--gpu`, whose results
+    /// depend on the driver
+    #[arg(long)]
+    #[cfg_attr(feature = "gpu", arg(conflicts_with = "gpu"))]
+    pub deterministic: bool,
+
+    /// Create a thumbnail whose longest side is at most N pixels instead of pixelating
+    #[arg(short, long, value_parser=validate_thumbnail_size)]
+    pub thumbnail: Option<u16>,
+}
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Compare two images and report per-pixel difference statistics
+    Diff(DiffArgs),
+    /// Print the dominant colors of an image as hex codes
+    Colors(ColorsArgs),
+    /// Process every entry of a CSV or JSON manifest with its own settings, or every image
+    /// in a directory tree
+    Batch(BatchArgs),
+    /// Print the average color of an image as a hex code, e.g. for placeholder backgrounds
+    Average(AverageArgs),
+    /// Pixelate a frame of a webcam, with the options given before the subcommand, e.g.
+    /// `smolres -r 32 capture`. Needs ffmpeg
+    Capture(CaptureArgs),
+    /// Turn a grid of colors written by the json or csv output format back into an image,
+    /// e.g. after editing it by hand
+    Render(RenderArgs),
+    /// Pixelate several images with the options given before the subcommand and tile them
+    /// into one labeled sheet, e.g. `smolres -r 32 montage a.jpeg b.jpeg -o sheet.png`
+    Montage(MontageArgs),
+    /// Keep running and process jobs sent over a Unix socket, one JSON object with the
+    /// fields of a batch manifest entry per line, e.g. `{"input": "a.jpeg", "resolution": 16}`
+    #[cfg(unix)]
+    Daemon(DaemonArgs),
+    /// Preview the pixelation in the terminal and adjust it with the keyboard
+    #[cfg(feature = "tui")]
+    Tui(TuiArgs),
+}
+/// Largest inputs that are decoded, checked against their header before pixels are allocated
+#[derive(clap::Args, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// Reject inputs with more pixels than this, e.g. decompression bombs whose small file
+    /// claims huge dimensions
+    #[arg(long, value_name = "PIXELS", value_parser=validate_pixel_count)]
+    pub max_pixels: Option<usize>,
+
+    /// Reject inputs that would need more memory than this to pixelate, e.g. `512mb`
+    #[arg(long, value_name = "SIZE", value_parser=validate_file_size)]
+    pub max_memory: Option<usize>,
+}
+#[derive(clap::Args, Debug, Clone)]
+pub struct DiffArgs {
+    /// First image to compare
+    #[arg(value_parser=validate_input_path)]
+    pub first: PathBuf,
+
+    /// Second image to compare, must have the same dimensions
+    #[arg(value_parser=validate_input_path)]
+    pub second: PathBuf,
+
+    /// Write an image of the absolute differences to this path
+    #[arg(short, long, value_parser=validate_output_path)]
+    pub output: Option<PathBuf>,
+
+    /// Factor the differences are multiplied by in the difference image
+    #[arg(long, default_value_t = 10.0, requires = "output", value_parser=validate_positive)]
+    pub amplify: f32,
+}
+#[derive(clap::Args, Debug, Clone)]
+pub struct ColorsArgs {
+    /// Image to extract the colors from
+    #[arg(value_parser=validate_input_path)]
+    pub input: PathBuf,
+
+    /// Number of colors to extract
+    #[arg(short = 'n', long, default_value_t = 8, value_parser=validate_colors)]
+    pub count: u16,
+
+    /// Write a swatch image with one square per color, most dominant first
+    #[arg(short, long, value_parser=validate_output_path)]
+    pub swatch: Option<PathBuf>,
+}
+#[derive(clap::Args, Debug, Clone)]
+pub struct BatchArgs {
+    /// Manifest with the columns input, output, resolution, bit_depth, palette and algorithm,
+    /// only input is required. A directory or .zip/.tar archive is searched for images
+    /// instead, which are processed with the default settings
+    #[arg(value_parser=validate_batch_source)]
+    pub source: PathBuf,
+
+    /// Follow symlinks to files and directories while searching a directory
+    #[arg(long, overrides_with = "no_follow_symlinks")]
+    pub follow_symlinks: bool,
+
+    /// Skip symlinks while searching a directory, the default
+    #[arg(long, overrides_with = "follow_symlinks")]
+    pub no_follow_symlinks: bool,
+
+    /// Skip paths in the directory matching this glob, e.g. '*.bak' or 'tmp/**'
+    #[arg(long, value_name = "PATTERN", value_parser=validate_exclude_pattern)]
+    pub exclude: Vec<String>,
+
+    /// Read additional exclude patterns from a .gitignore style file
+    #[arg(long, value_name = "FILE", value_parser=validate_existing_file)]
+    pub exclude_from: Option<PathBuf>,
+
+    /// Write all outputs into this .zip or .tar archive instead of next to their inputs
+    #[arg(long, value_name = "ARCHIVE", value_parser=validate_archive_path)]
+    pub output_archive: Option<PathBuf>,
+
+    /// Number of images processed in parallel, defaults to the number of CPU cores
+    #[arg(short, long, value_parser=validate_jobs)]
+    pub jobs: Option<usize>,
+
+    /// Guarantee the same bytes on every run like `--deterministic` does for single images,
+    /// entries of `--output-archive` are also written sorted by name instead of in the order
+    /// they finish
+    #[arg(long)]
+    pub deterministic: bool,
+
+    /// Print the summary at the end of the batch as JSON instead of text
+    #[arg(long)]
+    pub json: bool,
+
+    /// Library JPEG inputs are decoded with
+    #[arg(long, value_enum, default_value_t = JpegDecoderBackend::JpegDecoder)]
+    pub decoder: JpegDecoderBackend,
+
+    #[command(flatten)]
+    pub limits: Limits,
+}
+#[derive(clap::Args, Debug, Clone)]
+pub struct AverageArgs {
+    /// Image to average
+    #[arg(value_parser=validate_input_path)]
     pub input: PathBuf,
 
-    /// Path to output image file
+    /// Also write the color as a 1x1 image
     #[arg(short, long, value_parser=validate_output_path)]
     pub output: Option<PathBuf>,
+}
+#[derive(clap::Args, Debug, Clone)]
+pub struct CaptureArgs {
+    /// Number of the webcam, /dev/videoN on Linux
+    #[arg(short, long, default_value_t = 0)]
+    pub device: u32,
+
+    /// Keep pixelating new frames into the output until interrupted
+    #[arg(long)]
+    pub live: bool,
+
+    /// Also stream the results as MJPEG over HTTP on this address, e.g. 127.0.0.1:8080, to
+    /// watch them live in a browser
+    #[arg(long, value_name = "ADDR", requires = "live")]
+    pub serve: Option<SocketAddr>,
+}
+#[cfg(unix)]
+#[derive(clap::Args, Debug, Clone)]
+pub struct DaemonArgs {
+    /// Path of the socket to listen on, e.g. /run/smolres.sock
+    #[arg(long)]
+    pub socket: PathBuf,
+
+    #[command(flatten)]
+    pub limits: Limits,
+}
+#[derive(clap::Args, Debug, Clone)]
+pub struct MontageArgs {
+    /// Images to pixelate, in the order they are tiled
+    #[arg(required = true, value_parser=validate_input_path)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Where the sheet is written, the format is given by the extension
+    #[arg(short, long, value_parser=validate_output_path)]
+    pub output: PathBuf,
+
+    /// Tiles per row, by default as many as make the sheet about square
+    #[arg(long, value_parser=clap::value_parser!(u16).range(1..))]
+    pub columns: Option<u16>,
+
+    /// Width and height in pixels of the square every image is scaled into
+    #[arg(long, default_value_t = 256, value_parser=clap::value_parser!(u16).range(16..))]
+    pub tile_size: u16,
+
+    /// Put the source before every result, for before and after comparisons
+    #[arg(long)]
+    pub with_source: bool,
+}
+#[derive(clap::Args, Debug, Clone)]
+pub struct RenderArgs {
+    /// Grid to render, a .json or .csv file
+    #[arg(value_parser=validate_matrix_path)]
+    pub input: PathBuf,
+
+    /// Where the image is written, the format is given by the extension
+    #[arg(short, long, value_parser=validate_output_path)]
+    pub output: PathBuf,
+
+    /// Width and height in pixels every cell of the grid is scaled up to
+    #[arg(long, default_value_t = 16, value_parser=validate_block_size)]
+    pub block_size: u16,
+}
+#[cfg(feature = "tui")]
+#[derive(clap::Args, Debug, Clone)]
+pub struct TuiArgs {
+    /// Image to preview
+    #[arg(value_parser=validate_input_path)]
+    pub input: PathBuf,
 
-    /// Scale of virtualized resolution
+    /// Where the result is saved, next to the input by default
+    #[arg(short, long, value_parser=validate_output_path)]
+    pub output: Option<PathBuf>,
+
+    /// Resolution the preview starts with
     #[arg(short, long, default_value_t = 16)]
     pub resolution: u16,
-    /// Color depth of individual pixelds
+
+    /// Bit depth the preview starts with
     #[arg(short, long, default_value_t = 8, value_parser=validate_bit_depth)]
     pub bit_depth: u8,
 
-    /// Algorithm to be used for the pixel interpolation
-    #[arg(short, long)]
-    pub algorithm: Option<Algorithm>,
+    /// Algorithm the preview starts with
+    #[arg(short, long, value_enum, default_value_t = Algorithm::AverageArea)]
+    pub algorithm: Algorithm,
 }
-#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Algorithm {
+    /// One source pixel per block, hard edges but fine detail may alias
     Nearestneighbor,
+    /// Average of all source pixels of a block, smooth and faithful colors
     AverageArea,
+    /// Tries every algorithm on a small proxy and picks the one closest to the source
+    Auto,
 }
 impl fmt::Display for Algorithm {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = match self {
             Algorithm::Nearestneighbor => "nearest",
             Algorithm::AverageArea => "average",
+            Algorithm::Auto => "auto",
+        };
+        write!(f, "{}", s)
+    }
+}
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Dither {
+    /// Error diffusion spreading the whole error over four neighbours
+    FloydSteinberg,
+    /// Error diffusion of 3/4 of the error, cleaner highlights and shadows
+    Atkinson,
+    /// Ordered dithering with a 2x2 Bayer matrix, a coarse regular pattern
+    Bayer2,
+    /// Ordered dithering with a 4x4 Bayer matrix
+    Bayer4,
+    /// Ordered dithering with an 8x8 Bayer matrix, the finest pattern
+    Bayer8,
+}
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Rounding {
+    Truncate,
+    Round,
+}
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Invert {
+    Full,
+    Luma,
+}
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Levels {
+    Channels,
+    Luma,
+}
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Equalize {
+    Global,
+    Clahe,
+}
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Denoise {
+    Median,
+    #[value(name = "nlm")]
+    NonLocalMeans,
+}
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Stylize {
+    /// Mean of the calmest window around every pixel, flat patches with sharp edges
+    Kuwahara,
+    /// Most common intensity around every pixel, broad strokes like an oil painting
+    OilPaint,
+}
+#[cfg(feature = "hdr")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ToneMap {
+    Reinhard,
+    Aces,
+}
+#[cfg(feature = "hdr")]
+impl fmt::Display for ToneMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ToneMap::Reinhard => "reinhard",
+            ToneMap::Aces => "aces",
+        };
+        write!(f, "{}", s)
+    }
+}
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Fit {
+    Contain,
+    Cover,
+    /// Like cover, but the crop follows the most detailed part instead of the center
+    Smart,
+    Stretch,
+}
+impl fmt::Display for Fit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Fit::Contain => "contain",
+            Fit::Cover => "cover",
+            Fit::Smart => "smart",
+            Fit::Stretch => "stretch",
         };
         write!(f, "{}", s)
     }
 }
-pub fn default_output_path(input: &PathBuf, resolution: u16, algorithm: Algorithm) -> PathBuf {
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Position {
+    #[value(name = "tl")]
+    TopLeft,
+    #[value(name = "tr")]
+    TopRight,
+    #[value(name = "bl")]
+    BottomLeft,
+    #[value(name = "br")]
+    BottomRight,
+    Center,
+}
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SortDirection {
+    Rows,
+    Columns,
+}
+/// Cutoff of `--threshold`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Threshold {
+    Cutoff(u8),
+    Auto,
+}
+/// Rule of `--remap`, replacing one palette color of the result with another
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Remap {
+    pub from: [u8; 3],
+    pub to: [u8; 3],
+}
+/// Sorted values of `--channel-levels`, without duplicates
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelLevels(pub Vec<u8>);
+/// Light `--white-balance` corrects for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WhiteBalance {
+    Auto,
+    Kelvin(u32),
+}
+/// Size of the pixel grid in blocks
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: u16,
+    pub height: u16,
+}
+impl Resolution {
+    /// Shrinks the grid to at most one block per pixel of a `width` x `height` source
+    pub fn clamped_to(self, width: usize, height: usize) -> Self {
+        let clamp =
+            |blocks: u16, pixels: usize| blocks.min(pixels.clamp(1, u16::MAX.into()) as u16);
+        Resolution {
+            width: clamp(self.width, width),
+            height: clamp(self.height, height),
+        }
+    }
+
+    /// Width and height as an aspect ratio for the geometry helpers
+    pub fn aspect(self) -> (usize, usize) {
+        (self.width.into(), self.height.into())
+    }
+
+    /// Swaps width and height if the grid is landscape and the source portrait or vice versa
+    pub fn oriented_like(self, width: usize, height: usize) -> Self {
+        let (grid_landscape, source_landscape) = (self.width > self.height, width > height);
+        if self.width != self.height && width != height && grid_landscape != source_landscape {
+            Resolution {
+                width: self.height,
+                height: self.width,
+            }
+        } else {
+            self
+        }
+    }
+}
+impl From<u16> for Resolution {
+    fn from(size: u16) -> Self {
+        Resolution {
+            width: size,
+            height: size,
+        }
+    }
+}
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.width == self.height {
+            true => write!(f, "{}", self.width),
+            false => write!(f, "{}x{}", self.width, self.height),
+        }
+    }
+}
+/// Thickness of `--border`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BorderWidth {
+    Pixels(usize),
+    Blocks(usize),
+}
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Stage {
+    Before,
+    After,
+}
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Stage::Before => "before",
+            Stage::After => "after",
+        };
+        write!(f, "{}", s)
+    }
+}
+pub fn default_output_path(input: &Path, resolution: Resolution, algorithm: Algorithm) -> PathBuf {
     let parent = input.parent().unwrap_or_else(|| Path::new(""));
     let stem = input.file_stem().unwrap_or_default().to_string_lossy();
-    let ext = input.extension().and_then(|e| e.to_str()).unwrap_or("jpeg"); // fallback if extension is missing or not valid UTF-8
+    let ext = output_extension(input);
     let filename = format!("{}_res{}_{}.{}", stem, resolution, algorithm, ext);
     parent.join(filename)
 }
 
+pub fn default_thumbnail_path(input: &Path, max_dimension: u16, algorithm: Algorithm) -> PathBuf {
+    let parent = input.parent().unwrap_or_else(|| Path::new(""));
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = output_extension(input);
+    let filename = format!("{}_thumb{}_{}.{}", stem, max_dimension, algorithm, ext);
+    parent.join(filename)
+}
+
+/// Keeps the extension of JPEG and GIF inputs, everything else is written as `.jpeg`
+fn output_extension(input: &Path) -> &str {
+    input
+        .extension()
+        .and_then(|e| e.to_str()) // fallback if extension is missing or not valid UTF-8
+        .filter(|e| matches!(e.to_lowercase().as_str(), "jpg" | "jpeg" | "gif"))
+        .unwrap_or("jpeg")
+}
+
 /**
-*  Checks whether the path exists and the file is a `.jpeg`.
+*  Checks whether the path exists and the file is a `.jpeg`, a `.gif` or an HDR image.
 * TODO: Add other file types like .png
 * TODO: Optimize mut and borrowing here */
-fn validate_input_path(path: &str) -> Result<PathBuf, String> {
+pub fn validate_input_path(path: &str) -> Result<PathBuf, String> {
     let mut pb = &PathBuf::from(path);
+    if is_url(pb) {
+        if cfg!(feature = "http") {
+            return Ok(pb.to_owned());
+        }
+        return Err(String::from(
+            "URL inputs need smolres to be built with the `http` feature",
+        ));
+    }
 
     // add validators here
     pb = validate_existance(pb)?;
-    pb = validate_file_extension(pb)?;
-    return Ok(pb.to_owned());
+    if !is_hdr_path(pb) && !is_animation_path(pb) && !is_archive_path(pb) {
+        pb = validate_file_extension(pb)?;
+    }
+    Ok(pb.to_owned())
 }
 
 fn validate_output_path(path: &str) -> Result<PathBuf, String> {
-    let mut pb = &PathBuf::from(path);
-    pb = validate_file_extension(pb)?;
+    let pb = &PathBuf::from(path);
+    if OutputFormat::from_path(pb).is_none() {
+        return Err(format!("Unsupported output format: {}", pb.display()));
+    }
 
-    if let Some(parent) = pb.parent() {
-        if !parent.exists() {
-            fs::create_dir_all(parent).expect("Failed to create parent directory");
-        }
+    if let Some(parent) = pb.parent()
+        && !parent.exists()
+    {
+        fs::create_dir_all(parent).expect("Failed to create parent directory");
     }
-    return Ok(pb.to_owned());
+    Ok(pb.to_owned())
+}
+
+/// Whether the input is an HTTP(S) URL rather than a local path
+pub fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// Whether the path has the extension of an image format smolres can read
+pub fn is_input_path(path: &Path) -> bool {
+    validate_file_extension(&path.to_path_buf()).is_ok()
+        || is_hdr_path(path)
+        || is_animation_path(path)
 }
 
 fn validate_existance(path: &PathBuf) -> Result<&PathBuf, String> {
@@ -78,7 +939,7 @@ fn validate_existance(path: &PathBuf) -> Result<&PathBuf, String> {
         return Err(format!("Path does not exist: {}", path.display()));
     }
 
-    return Ok(path);
+    Ok(path)
 }
 
 fn validate_file_extension(path: &PathBuf) -> Result<&PathBuf, String> {
@@ -95,7 +956,73 @@ fn validate_file_extension(path: &PathBuf) -> Result<&PathBuf, String> {
         return Err(format!("No file extension found: {}", path.display()));
     }
 
-    return Ok(path);
+    Ok(path)
+}
+
+fn validate_watermark_path(path: &str) -> Result<PathBuf, String> {
+    let pb = PathBuf::from(path);
+    validate_existance(&pb)?;
+    if OutputFormat::from_path(&pb) != Some(OutputFormat::Png) {
+        return Err(format!("Watermark must be a PNG image: {}", pb.display()));
+    }
+    Ok(pb)
+}
+
+fn validate_batch_source(path: &str) -> Result<PathBuf, String> {
+    let pb = PathBuf::from(path);
+    validate_existance(&pb)?;
+    if pb.is_dir() || is_archive_path(&pb) {
+        return Ok(pb);
+    }
+    match pb
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+    {
+        Some(ext) if ext == "csv" || ext == "json" => Ok(pb),
+        _ => Err(format!(
+            "Batch source must be a directory, archive or .csv or .json manifest: {}",
+            pb.display()
+        )),
+    }
+}
+
+fn validate_matrix_path(path: &str) -> Result<PathBuf, String> {
+    let pb = PathBuf::from(path);
+    validate_existance(&pb)?;
+    match pb
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+    {
+        Some(ext) if ext == "csv" || ext == "json" => Ok(pb),
+        _ => Err(format!(
+            "Grid must be a .csv or .json file: {}",
+            pb.display()
+        )),
+    }
+}
+
+fn validate_block_size(s: &str) -> Result<u16, String> {
+    match s.parse::<u16>() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(String::from(
+            "block size must be an integer between 1 and 65535",
+        )),
+    }
+}
+
+fn validate_exclude_pattern(pattern: &str) -> Result<String, String> {
+    match is_valid_pattern(pattern) {
+        Ok(()) => Ok(pattern.to_string()),
+        Err(e) => Err(format!("Invalid exclude pattern: {}", e)),
+    }
+}
+
+fn validate_existing_file(path: &str) -> Result<PathBuf, String> {
+    let pb = PathBuf::from(path);
+    validate_existance(&pb)?;
+    Ok(pb)
 }
 
 fn validate_bit_depth(s: &str) -> Result<u8, String> {
@@ -104,15 +1031,286 @@ fn validate_bit_depth(s: &str) -> Result<u8, String> {
         _ => Err(String::from("bit_depth must be an integer between 1 and 8")),
     }
 }
+
+fn validate_threshold(s: &str) -> Result<Threshold, String> {
+    if s == "auto" {
+        return Ok(Threshold::Auto);
+    }
+    s.parse::<u8>()
+        .map(Threshold::Cutoff)
+        .map_err(|_| String::from("threshold must be an integer between 0 and 255 or auto"))
+}
+
+fn validate_white_balance(s: &str) -> Result<WhiteBalance, String> {
+    if s == "auto" {
+        return Ok(WhiteBalance::Auto);
+    }
+    match s.trim_end_matches(['K', 'k']).parse::<u32>() {
+        Ok(kelvin) if (1000..=40000).contains(&kelvin) => Ok(WhiteBalance::Kelvin(kelvin)),
+        _ => Err(String::from(
+            "white balance must be auto or a temperature between 1000 and 40000 kelvin",
+        )),
+    }
+}
+
+fn validate_colors(s: &str) -> Result<u16, String> {
+    match s.parse::<u16>() {
+        Ok(value) if (2..=256).contains(&value) => Ok(value),
+        _ => Err(String::from("colors must be an integer between 2 and 256")),
+    }
+}
+
+fn validate_slider(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if (-100.0..=100.0).contains(&value) => Ok(value),
+        _ => Err(String::from("must be a number between -100 and 100")),
+    }
+}
+
+fn validate_dither_strength(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+        _ => Err(String::from(
+            "dither_strength must be a number between 0.0 and 1.0",
+        )),
+    }
+}
+
+fn validate_fraction(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+        _ => Err(String::from("must be a number between 0.0 and 1.0")),
+    }
+}
+
+fn validate_clip_percent(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if (0.0..50.0).contains(&value) => Ok(value),
+        _ => Err(String::from(
+            "must be a percentage of at least 0 and below 50",
+        )),
+    }
+}
+
+fn validate_non_negative(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if value >= 0.0 && value.is_finite() => Ok(value),
+        _ => Err(String::from("must be a non-negative number")),
+    }
+}
+
+fn validate_positive(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if value > 0.0 && value.is_finite() => Ok(value),
+        _ => Err(String::from("must be a positive number")),
+    }
+}
+
+fn validate_thumbnail_size(s: &str) -> Result<u16, String> {
+    match s.parse::<u16>() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(String::from(
+            "thumbnail must be an integer between 1 and 65535",
+        )),
+    }
+}
+
+fn validate_archive_path(path: &str) -> Result<PathBuf, String> {
+    let pb = PathBuf::from(path);
+    if !is_archive_path(&pb) {
+        return Err(format!(
+            "Archive must be a .zip or .tar file: {}",
+            pb.display()
+        ));
+    }
+    Ok(pb)
+}
+
+fn validate_color(s: &str) -> Result<[u8; 3], String> {
+    from_hex(s).ok_or_else(|| String::from("color must be a hex code like #ff8800"))
+}
+
+fn validate_remap(s: &str) -> Result<Remap, String> {
+    let error = || String::from("remap must look like #306230=#8bac0f");
+    let (from, to) = s.split_once('=').ok_or_else(error)?;
+    Ok(Remap {
+        from: from_hex(from.trim()).ok_or_else(error)?,
+        to: from_hex(to.trim()).ok_or_else(error)?,
+    })
+}
+
+fn validate_channel_levels(s: &str) -> Result<ChannelLevels, String> {
+    let mut levels = s
+        .split(',')
+        .map(|level| level.trim().parse::<u8>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| {
+            String::from("levels must be numbers between 0 and 255 separated by commas")
+        })?;
+    levels.sort_unstable();
+    levels.dedup();
+    match levels.len() {
+        2.. => Ok(ChannelLevels(levels)),
+        _ => Err(String::from("at least two different levels are needed")),
+    }
+}
+
+fn validate_border(s: &str) -> Result<BorderWidth, String> {
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let width = number
+        .parse::<usize>()
+        .map_err(|_| String::from("border must start with a whole number"))?;
+    match unit {
+        "" | "px" => Ok(BorderWidth::Pixels(width)),
+        "b" | "block" | "blocks" => Ok(BorderWidth::Blocks(width)),
+        _ => Err(format!("Unknown border unit `{}`, use px or blocks", unit)),
+    }
+}
+
+fn validate_resolution(s: &str) -> Result<Resolution, String> {
+    let parse = |n: &str| match n.parse::<u16>() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(String::from(
+            "resolution must be a positive integer or WIDTHxHEIGHT",
+        )),
+    };
+    match s.split_once('x') {
+        Some((width, height)) => Ok(Resolution {
+            width: parse(width)?,
+            height: parse(height)?,
+        }),
+        None => parse(s).map(Resolution::from),
+    }
+}
+
+/// X11 style geometry, `WIDTHxHEIGHT+X+Y`
+fn validate_screen_region(s: &str) -> Result<Region, String> {
+    let error = || String::from("region must look like WIDTHxHEIGHT+X+Y, e.g. 640x480+0+0");
+    let (size, offset) = s.split_once('+').ok_or_else(error)?;
+    let (width, height) = size.split_once('x').ok_or_else(error)?;
+    let (x, y) = offset.split_once('+').ok_or_else(error)?;
+    let parse = |n: &str| n.parse::<usize>().map_err(|_| error());
+    let region = Region {
+        x: parse(x)?,
+        y: parse(y)?,
+        width: parse(width)?,
+        height: parse(height)?,
+    };
+    if region.width == 0 || region.height == 0 {
+        return Err(String::from("region must not be empty"));
+    }
+    Ok(region)
+}
+
+fn validate_perspective(s: &str) -> Result<Quad, String> {
+    let numbers = s
+        .split(',')
+        .map(|n| {
+            n.trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|n| n.is_finite() && *n >= 0.0)
+        })
+        .collect::<Option<Vec<f32>>>()
+        .filter(|numbers| numbers.len() == 8)
+        .ok_or_else(|| String::from("perspective needs 8 non-negative numbers x1,y1,...,x4,y4"))?;
+    let corners: [(f32, f32); 4] = std::array::from_fn(|i| (numbers[2 * i], numbers[2 * i + 1]));
+    // Turning clockwise at every corner, with y pointing down, keeps the quad convex and
+    // the result from being mirrored
+    let clockwise = (0..4).all(|i| {
+        let [(ax, ay), (bx, by), (cx, cy)] = [0, 1, 2].map(|j| corners[(i + j) % 4]);
+        (bx - ax) * (cy - by) - (by - ay) * (cx - bx) > 0.0
+    });
+    if !clockwise {
+        return Err(String::from(
+            "perspective corners must form a convex quadrilateral, clockwise from the top left",
+        ));
+    }
+    Ok(Quad(corners))
+}
+
+/// Bytes, `kb` and `mb` are multiples of 1024
+fn validate_file_size(s: &str) -> Result<usize, String> {
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let size = number
+        .parse::<usize>()
+        .map_err(|_| String::from("size must start with a whole number"))?;
+    let factor = match unit.to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1 << 10,
+        "m" | "mb" => 1 << 20,
+        _ => return Err(format!("Unknown size unit `{}`, use b, kb or mb", unit)),
+    };
+    match size.checked_mul(factor) {
+        Some(0) => Err(String::from("size must be larger than 0")),
+        Some(bytes) => Ok(bytes),
+        None => Err(format!("size must be at most {} bytes", usize::MAX)),
+    }
+}
+
+fn validate_palette(name: &str) -> Result<&'static NamedPalette, String> {
+    built_in_palette(name).ok_or_else(|| {
+        format!("unknown palette `{name}`, see --list-palettes for the built-in ones")
+    })
+}
+
+fn validate_radius(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(String::from("radius must be a positive integer")),
+    }
+}
+
+fn validate_supersample(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(value) if (1..=16).contains(&value) => Ok(value),
+        _ => Err(String::from("supersample factor must be between 1 and 16")),
+    }
+}
+
+fn validate_pixel_count(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(String::from("pixel count must be a positive integer")),
+    }
+}
+
+fn validate_jobs(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(String::from("jobs must be a positive integer")),
+    }
+}
 #[cfg(test)]
 mod tests {
     use std::env;
     use std::fs;
+    use std::path::Path;
+
+    use clap::Parser;
 
+    use crate::cli::Algorithm;
+    use crate::cli::Args;
+    use crate::cli::BorderWidth;
+    use crate::cli::Command;
+    use crate::cli::Resolution;
+    use crate::cli::default_output_path;
+    use crate::cli::validate_border;
+    use crate::cli::validate_colors;
+    use crate::cli::validate_dither_strength;
     use crate::cli::validate_existance;
     use crate::cli::validate_file_extension;
+    use crate::cli::validate_file_size;
     use crate::cli::validate_input_path;
+    use crate::cli::validate_non_negative;
     use crate::cli::validate_output_path;
+    use crate::cli::validate_perspective;
+    use crate::cli::validate_positive;
+    use crate::cli::validate_resolution;
+    use crate::cli::validate_screen_region;
+    use crate::cli::validate_thumbnail_size;
+    use crate::encoder::OutputFormat;
+    use crate::geometry::Region;
 
     #[test]
     fn test_file_exists() {
@@ -172,7 +1370,7 @@ mod tests {
     fn test_input_valid_path() {
         // Create a temporary file
         let tmp_dir = env::temp_dir();
-        let file_path = tmp_dir.join("test_file.jpg");
+        let file_path = tmp_dir.join("test_input_file.jpg");
         fs::write(&file_path, "test").expect("Failed to write temp file");
 
         let result = validate_input_path(file_path.to_str().unwrap());
@@ -187,7 +1385,7 @@ mod tests {
     fn test_output_valid_path() {
         // Create a temporary file
         let tmp_dir = env::temp_dir();
-        let file_path = tmp_dir.join("test_file.jpg");
+        let file_path = tmp_dir.join("test_output_file.jpg");
         fs::write(&file_path, "test").expect("Failed to write temp file");
 
         let result = validate_output_path(file_path.to_str().unwrap());
@@ -197,10 +1395,227 @@ mod tests {
         // Clean up
         fs::remove_file(file_path).unwrap();
     }
+    #[test]
+    fn test_output_formats() {
+        let args = Args::parse_from([
+            "smolres",
+            "--input",
+            "examples/horse.jpeg",
+            "--format",
+            "png,webp",
+        ]);
+        assert_eq!(args.format, vec![OutputFormat::Png, OutputFormat::Webp]);
+        assert!(validate_output_path("out.webp").is_ok());
+        assert!(validate_output_path("out.gif").is_ok());
+        assert!(validate_output_path("out.tiff").is_err());
+    }
+
     #[test]
     fn test_output_invalid_path_no_parent_dir() {
         let file_path: &str = "does/not/exist";
         let result = validate_output_path(file_path);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_thumbnail_size() {
+        assert_eq!(validate_thumbnail_size("256"), Ok(256));
+        assert!(validate_thumbnail_size("0").is_err());
+        assert!(validate_thumbnail_size("-1").is_err());
+    }
+
+    #[test]
+    fn test_colors() {
+        assert_eq!(validate_colors("16"), Ok(16));
+        assert!(validate_colors("1").is_err());
+        assert!(validate_colors("257").is_err());
+    }
+
+    #[test]
+    fn test_dither_strength() {
+        assert_eq!(validate_dither_strength("0.5"), Ok(0.5));
+        assert!(validate_dither_strength("1.5").is_err());
+        assert!(validate_dither_strength("-0.1").is_err());
+    }
+
+    #[test]
+    fn test_non_negative_and_positive() {
+        assert_eq!(validate_non_negative("0"), Ok(0.0));
+        assert!(validate_non_negative("-1").is_err());
+        assert!(validate_positive("0").is_err());
+        assert_eq!(validate_positive("1.5"), Ok(1.5));
+    }
+
+    #[test]
+    fn test_diff_subcommand() {
+        let args = Args::parse_from([
+            "smolres",
+            "-vv",
+            "diff",
+            "examples/horse.jpeg",
+            "examples/horse_res32_average.jpeg",
+        ]);
+        let Some(Command::Diff(diff)) = args.command else {
+            panic!("expected the diff subcommand");
+        };
+        assert_eq!(diff.second, Path::new("examples/horse_res32_average.jpeg"));
+        assert!(args.input.is_none());
+        assert_eq!(args.verbose, 2);
+
+        assert!(Args::try_parse_from(["smolres"]).is_err());
+    }
+
+    #[test]
+    fn test_batch_directory_options() {
+        let args = Args::parse_from([
+            "smolres",
+            "batch",
+            "examples",
+            "--follow-symlinks",
+            "--no-follow-symlinks",
+            "--exclude",
+            "*.bak",
+            "--exclude",
+            "tmp/**",
+        ]);
+        let Some(Command::Batch(batch)) = args.command else {
+            panic!("expected the batch subcommand");
+        };
+        assert!(!batch.follow_symlinks);
+        assert_eq!(batch.exclude, vec!["*.bak", "tmp/**"]);
+
+        assert!(
+            Args::try_parse_from(["smolres", "batch", "examples", "--exclude", "a[b"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_border_units() {
+        assert_eq!(validate_border("8"), Ok(BorderWidth::Pixels(8)));
+        assert_eq!(validate_border("8px"), Ok(BorderWidth::Pixels(8)));
+        assert_eq!(validate_border("2blocks"), Ok(BorderWidth::Blocks(2)));
+        assert!(validate_border("2cm").is_err());
+        assert!(validate_border("px").is_err());
+    }
+
+    #[test]
+    fn test_resolution() {
+        let grid = validate_resolution("32x18").unwrap();
+        assert_eq!((grid.width, grid.height), (32, 18));
+        assert_eq!(grid.to_string(), "32x18");
+        assert_eq!(validate_resolution("16"), Ok(Resolution::from(16)));
+        assert_eq!(Resolution::from(16).to_string(), "16");
+        assert!(validate_resolution("0x4").is_err());
+        assert!(validate_resolution("4x").is_err());
+
+        assert_eq!(
+            grid.oriented_like(300, 400),
+            validate_resolution("18x32").unwrap()
+        );
+        assert_eq!(grid.oriented_like(400, 300), grid);
+        assert_eq!(grid.oriented_like(300, 300), grid);
+
+        assert_eq!(
+            grid.clamped_to(8, 100),
+            validate_resolution("8x18").unwrap()
+        );
+        assert_eq!(grid.clamped_to(300, 300), grid);
+    }
+
+    #[test]
+    fn test_list_flags_stand_alone() {
+        let args = Args::parse_from(["smolres", "--list-palettes"]);
+        assert!(args.list_palettes && args.input.is_none());
+        assert!(Args::try_parse_from(["smolres", "--list-algorithms", "-r", "8"]).is_err());
+
+        let args = Args::parse_from([
+            "smolres",
+            "-i",
+            "examples/horse.jpeg",
+            "--palette",
+            "PICO-8",
+        ]);
+        assert_eq!(args.palette.map(|palette| palette.name), Some("pico-8"));
+        assert!(
+            Args::try_parse_from(["smolres", "-i", "examples/horse.jpeg", "--palette", "nes"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_perspective_corners() {
+        let quad = validate_perspective("10,0, 90,5, 100,80, 0,70").unwrap();
+        assert_eq!(quad.0[2], (100.0, 80.0));
+        // Counterclockwise, folded over and incomplete corners
+        assert!(validate_perspective("10,0,0,70,100,80,90,5").is_err());
+        assert!(validate_perspective("0,0,100,80,100,0,0,80").is_err());
+        assert!(validate_perspective("0,0,100,0,100,80").is_err());
+    }
+
+    #[test]
+    fn test_file_size_units() {
+        assert_eq!(validate_file_size("500"), Ok(500));
+        assert_eq!(validate_file_size("100kb"), Ok(100 * 1024));
+        assert_eq!(validate_file_size("2MB"), Ok(2 * 1024 * 1024));
+        assert!(validate_file_size("0kb").is_err());
+        assert!(validate_file_size("1gb").is_err());
+        assert!(validate_file_size("99999999999999999mb").is_err());
+    }
+
+    #[test]
+    fn test_screenshot_region() {
+        assert_eq!(
+            validate_screen_region("640x480+10+20"),
+            Ok(Region {
+                x: 10,
+                y: 20,
+                width: 640,
+                height: 480
+            })
+        );
+        assert!(validate_screen_region("640x480").is_err());
+        assert!(validate_screen_region("0x480+0+0").is_err());
+
+        let args = Args::parse_from(["smolres", "--screenshot"]);
+        assert_eq!(args.screenshot, Some(None));
+        assert!(
+            Args::try_parse_from(["smolres", "--screenshot", "-i", "examples/horse.jpeg"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_pad_square_color_is_optional() {
+        let input = ["smolres", "-i", "examples/horse.jpeg", "--pad-square"];
+        let args = Args::parse_from(input.iter().chain(&["-r", "8"]));
+        assert_eq!(args.pad_square, Some([0, 0, 0]));
+        assert_eq!(args.resolution, Resolution::from(8));
+
+        let args = Args::parse_from(input.iter().chain(&["#ffffff"]));
+        assert_eq!(args.pad_square, Some([255, 255, 255]));
+        assert!(Args::try_parse_from(input.iter().chain(&["white"])).is_err());
+    }
+
+    #[cfg(feature = "exr")]
+    #[test]
+    fn test_input_hdr_path() {
+        let tmp_dir = env::temp_dir();
+        let file_path = tmp_dir.join("test_input_file.exr");
+        fs::write(&file_path, "test").expect("Failed to write temp file");
+
+        let result = validate_input_path(file_path.to_str().unwrap());
+        assert!(result.is_ok());
+
+        // Clean up
+        fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn test_default_output_path_for_hdr_input() {
+        let output = default_output_path(
+            Path::new("renders/scene.exr"),
+            32.into(),
+            Algorithm::AverageArea,
+        );
+        assert_eq!(output, Path::new("renders/scene_res32_average.jpeg"));
+    }
 }
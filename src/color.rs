@@ -0,0 +1,323 @@
+use clap::ValueEnum;
+
+/// Converts an sRGB encoded value in `0.0..=1.0` into linear light
+pub fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts linear light in `0.0..=1.0` into an sRGB encoded value
+pub fn linear_to_srgb(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Formats a color as `#rrggbb`
+pub fn to_hex(color: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+/// Parses `#rrggbb`, the `#` is optional
+pub fn from_hex(hex: &str) -> Option<[u8; 3]> {
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Rec. 601 luma, the weighting JPEG uses for grayscale
+pub fn luma(color: [u8; 3]) -> u8 {
+    ((299 * color[0] as u32 + 587 * color[1] as u32 + 114 * color[2] as u32) / 1000) as u8
+}
+
+/// CIELAB coordinates of an sRGB color under the D65 white point
+pub fn to_lab(color: [u8; 3]) -> [f32; 3] {
+    let [r, g, b] = color.map(|c| srgb_to_linear(c as f32 / 255.0));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/**
+ * CIEDE2000 color difference of two CIELAB colors. A difference of about 1 is the smallest
+ * one most people notice, identical colors give 0. */
+pub fn ciede2000(first: [f32; 3], second: [f32; 3]) -> f32 {
+    let [l1, a1, b1] = first;
+    let [l2, a2, b2] = second;
+    let pow7 = |c: f32| c.powi(7);
+    let chroma_weight = |c: f32| (pow7(c) / (pow7(c) + pow7(25.0))).sqrt();
+
+    // Stretch the a axis for low chroma colors, where it is too compressed
+    let g = 0.5 * (1.0 - chroma_weight(((a1.hypot(b1)) + a2.hypot(b2)) / 2.0));
+    let (a1, a2) = ((1.0 + g) * a1, (1.0 + g) * a2);
+    let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+    let hue = |a: f32, b: f32| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+    let (h1, h2) = (hue(a1, b1), hue(a2, b2));
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else if h2 - h1 < -180.0 {
+        h2 - h1 + 360.0
+    } else {
+        h2 - h1
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+    let mean_l = (l1 + l2) / 2.0;
+    let mean_c = (c1 + c2) / 2.0;
+    let mean_h = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+    let cos = |degrees: f32| degrees.to_radians().cos();
+    let t =
+        1.0 - 0.17 * cos(mean_h - 30.0) + 0.24 * cos(2.0 * mean_h) + 0.32 * cos(3.0 * mean_h + 6.0)
+            - 0.20 * cos(4.0 * mean_h - 63.0);
+
+    let weight_l = 1.0 + 0.015 * (mean_l - 50.0).powi(2) / (20.0 + (mean_l - 50.0).powi(2)).sqrt();
+    let weight_c = 1.0 + 0.045 * mean_c;
+    let weight_h = 1.0 + 0.015 * mean_c * t;
+    // Blue hues need a correction for the interaction of chroma and hue differences
+    let rotation = 30.0 * (-((mean_h - 275.0) / 25.0).powi(2)).exp();
+    let rotation = -(2.0 * rotation).to_radians().sin() * 2.0 * chroma_weight(mean_c);
+
+    let (l, c, h) = (delta_l / weight_l, delta_c / weight_c, delta_h / weight_h);
+    (l * l + c * c + h * h + rotation * c * h).sqrt()
+}
+
+/// How the difference between two colors is measured when mapping them to a palette
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Distance {
+    /// Euclidean distance of the sRGB values
+    #[default]
+    Rgb,
+    /// Euclidean distance with channel weights that follow the mean red level, a cheap
+    /// estimate of perceived differences
+    WeightedRgb,
+    /// Euclidean distance in CIELAB
+    Lab,
+    /// CIEDE2000 color difference, the most accurate and the slowest
+    Ciede2000,
+}
+
+impl Distance {
+    /// Difference of the two colors, only comparable to others of the same metric
+    pub fn between(self, first: [u8; 3], second: [u8; 3]) -> f32 {
+        let squared = |a: [f32; 3], b: [f32; 3]| a.into_iter().zip(b).map(|(x, y)| (x - y).powi(2));
+        match self {
+            Distance::Rgb => squared(first.map(f32::from), second.map(f32::from)).sum(),
+            Distance::WeightedRgb => {
+                let mean_red = (first[0] as f32 + second[0] as f32) / 2.0;
+                let weights = [
+                    2.0 + mean_red / 256.0,
+                    4.0,
+                    2.0 + (255.0 - mean_red) / 256.0,
+                ];
+                squared(first.map(f32::from), second.map(f32::from))
+                    .zip(weights)
+                    .map(|(difference, weight)| difference * weight)
+                    .sum()
+            }
+            Distance::Lab => squared(to_lab(first), to_lab(second)).sum(),
+            Distance::Ciede2000 => ciede2000(to_lab(first), to_lab(second)),
+        }
+    }
+}
+
+/// Common forms of color vision deficiency, each missing one type of cone
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Deficiency {
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+impl Deficiency {
+    pub const ALL: [Deficiency; 3] = [
+        Deficiency::Protanopia,
+        Deficiency::Deuteranopia,
+        Deficiency::Tritanopia,
+    ];
+
+    /// Full severity matrices of Machado, Oliveira and Fernandes (2009) for linear RGB
+    fn matrix(self) -> [[f32; 3]; 3] {
+        match self {
+            Deficiency::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            Deficiency::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            Deficiency::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+        }
+    }
+
+    /// How the color appears with this deficiency, as sRGB encoded values in `0.0..=1.0`
+    pub fn simulate(self, color: [u8; 3]) -> [f32; 3] {
+        let linear = color.map(|c| srgb_to_linear(c as f32 / 255.0));
+        self.matrix().map(|row| {
+            let value: f32 = row.iter().zip(linear).map(|(m, c)| m * c).sum();
+            linear_to_srgb(value.clamp(0.0, 1.0))
+        })
+    }
+}
+
+/**
+ * Color of a black body at `kelvin` in linear light, using Tanner Helland's fit of the
+ * blackbody curve. Light at 6600K is about white, lower temperatures are orange and higher
+ * ones blue. */
+pub fn kelvin_to_linear(kelvin: u32) -> [f32; 3] {
+    let t = kelvin.clamp(1000, 40000) as f32 / 100.0;
+    let red = if t <= 66.0 {
+        255.0
+    } else {
+        329.69873 * (t - 60.0).powf(-0.13320476)
+    };
+    let green = if t <= 66.0 {
+        99.4708 * t.ln() - 161.11957
+    } else {
+        288.12216 * (t - 60.0).powf(-0.075514846)
+    };
+    let blue = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.51773 * (t - 10.0).ln() - 305.0448
+    };
+    // Channels are kept above zero so they can be divided by
+    [red, green, blue].map(|c| srgb_to_linear(c.clamp(1.0, 255.0) / 255.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Deficiency, Distance, ciede2000, from_hex, kelvin_to_linear, linear_to_srgb,
+        srgb_to_linear, to_hex, to_lab,
+    };
+
+    #[test]
+    fn test_kelvin_to_linear() {
+        let [red, green, blue] = kelvin_to_linear(3200);
+        assert!(red > green && green > blue);
+        let [red, green, blue] = kelvin_to_linear(10000);
+        assert!(blue > green && green > red);
+        let daylight = kelvin_to_linear(6600);
+        assert!(daylight.iter().all(|c| *c > 0.95));
+    }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex([255, 8, 171]), "#ff08ab");
+        assert_eq!(from_hex("#ff08ab"), Some([255, 8, 171]));
+        assert_eq!(from_hex("FF08AB"), Some([255, 8, 171]));
+        assert_eq!(from_hex("#ff08a"), None);
+        assert_eq!(from_hex("#gg0000"), None);
+    }
+
+    #[test]
+    fn test_srgb_round_trip() {
+        for i in 0..=255 {
+            let x = i as f32 / 255.0;
+            assert!((linear_to_srgb(srgb_to_linear(x)) - x).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_ciede2000() {
+        // Reference pairs from Sharma, Wu and Dalal (2005)
+        let pairs = [
+            ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+            ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+            ([50.0, -1.0, 2.0], [50.0, 0.0, 0.0], 2.3669),
+            (
+                [60.2574, -34.0099, 36.2677],
+                [60.4626, -34.1751, 39.4387],
+                1.2644,
+            ),
+            (
+                [22.7233, 20.0904, -46.6940],
+                [23.0331, 14.9730, -42.5619],
+                2.0373,
+            ),
+        ];
+        for (first, second, expected) in pairs {
+            assert!((ciede2000(first, second) - expected).abs() < 1e-3);
+            assert!((ciede2000(second, first) - expected).abs() < 1e-3);
+        }
+        let white = to_lab([255, 255, 255]);
+        assert!((white[0] - 100.0).abs() < 0.01 && white[1].abs() < 0.01);
+        assert_eq!(ciede2000(to_lab([12, 34, 56]), to_lab([12, 34, 56])), 0.0);
+    }
+
+    #[test]
+    fn test_deficiency_simulation() {
+        // Grays look the same to everyone
+        for deficiency in Deficiency::ALL {
+            let gray = deficiency.simulate([128, 128, 128]);
+            assert!(gray.iter().all(|c| (c - 128.0 / 255.0).abs() < 0.01));
+        }
+        // Red and green collapse into similar yellows without red cones
+        let red = Deficiency::Protanopia.simulate([255, 0, 0]);
+        let green = Deficiency::Protanopia.simulate([0, 255, 0]);
+        assert!((red[0] - red[1]).abs() < 0.15 && (green[0] - green[1]).abs() < 0.15);
+    }
+
+    #[test]
+    fn test_distances_disagree() {
+        // A dark blue and a dark green are about as far from black in RGB, but not in Lab
+        let (black, blue, green) = ([0, 0, 0], [0, 0, 120], [0, 120, 0]);
+        assert_eq!(
+            Distance::Rgb.between(black, blue),
+            Distance::Rgb.between(black, green)
+        );
+        for distance in [Distance::WeightedRgb, Distance::Lab, Distance::Ciede2000] {
+            assert_eq!(distance.between(blue, blue), 0.0);
+            assert_ne!(
+                distance.between(black, blue),
+                distance.between(black, green),
+                "{distance:?}"
+            );
+        }
+    }
+}
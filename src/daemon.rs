@@ -0,0 +1,114 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::manifest::ManifestEntry;
+
+/// Answer to one request, written as a single line of JSON
+#[derive(Debug, Serialize)]
+struct Response {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Listens on `path`, a socket left behind by an earlier daemon is replaced
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+/**
+ * Accepts clients until accepting one fails, that error is returned. Every line a client
+ * sends is a JSON object with the fields of a batch manifest entry, which `run` processes
+ * before the next line is read. Each request is answered with `{"ok":true}` or
+ * `{"ok":false,"error":"..."}`, errors of a client connection are logged. */
+pub fn serve<F>(listener: UnixListener, run: F) -> io::Result<()>
+where
+    F: Fn(ManifestEntry) -> Result<(), String> + Send + Sync + 'static,
+{
+    let run = Arc::new(run);
+    for client in listener.incoming() {
+        let client = client?;
+        let run = Arc::clone(&run);
+        // A client that disconnects only ends its own thread
+        thread::spawn(move || {
+            if let Err(error) = handle_client(client, run.as_ref()) {
+                warn!("client disconnected: {error}");
+            }
+        });
+    }
+    unreachable!("incoming connections never end")
+}
+
+fn handle_client<F>(client: UnixStream, run: &F) -> io::Result<()>
+where
+    F: Fn(ManifestEntry) -> Result<(), String>,
+{
+    let mut writer = client.try_clone()?;
+    for line in BufReader::new(client).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = serde_json::from_str::<ManifestEntry>(&line)
+            .map_err(|e| format!("Invalid request: {e}"))
+            .and_then(run);
+        let response = Response {
+            ok: result.is_ok(),
+            error: result.err(),
+        };
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::path::Path;
+    use std::thread;
+
+    use super::{bind, serve};
+
+    #[test]
+    fn test_answers_every_request() {
+        let path = env::temp_dir().join("test_daemon.sock");
+        let listener = bind(&path).unwrap();
+        thread::spawn(|| {
+            serve(listener, |entry| match entry.input == Path::new("a.jpeg") {
+                true => Ok(()),
+                false => Err(String::from("not found")),
+            })
+        });
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(b"{\"input\":\"a.jpeg\"}\n\n{\"input\":\"b.jpeg\"}\n{\"resolution\":8}\n")
+            .unwrap();
+        let mut lines = BufReader::new(client).lines().map(Result::unwrap);
+        assert_eq!(lines.next().unwrap(), r#"{"ok":true}"#);
+        assert_eq!(lines.next().unwrap(), r#"{"ok":false,"error":"not found"}"#);
+        assert!(
+            lines
+                .next()
+                .unwrap()
+                .starts_with(r#"{"ok":false,"error":"Invalid request: missing field `input`"#)
+        );
+
+        // A second daemon takes over the socket of the first
+        bind(&path).unwrap();
+    }
+}
@@ -1,14 +1,521 @@
 extern crate jpeg_decoder as jpeg;
 
-use jpeg_decoder::{Decoder, ImageInfo};
-use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
-
-pub fn decode(file: &PathBuf) -> (Vec<u8>, ImageInfo) {
-    let file = File::open(file).expect("failed to open file");
-    let mut decoder = Decoder::new(BufReader::new(file));
-    let pixels = decoder.decode().expect("failed to decode image");
-    let metadata: ImageInfo = decoder.info().unwrap();
-    return (pixels, metadata);
+use jpeg_decoder::{Decoder, PixelFormat};
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::animation::{AnimationError, read_gif_frames};
+use crate::cli::Limits;
+#[cfg(feature = "hdr")]
+use crate::hdr::HdrError;
+#[cfg(any(feature = "exr", feature = "radiance"))]
+use crate::hdr::{HdrImage, tone_map};
+#[cfg(feature = "exr")]
+use crate::hdr::{exr_dimensions, read_exr};
+#[cfg(feature = "radiance")]
+use crate::hdr::{radiance_dimensions, read_radiance};
+use crate::types::ImageBuffer;
+use clap::ValueEnum;
+use thiserror::Error;
+use tracing::{info_span, warn};
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("Failed to read file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid JPEG: {0}")]
+    Jpeg(#[from] jpeg::Error),
+
+    #[cfg(feature = "zune-jpeg")]
+    #[error("Invalid JPEG: {0}")]
+    ZuneJpeg(#[from] zune_jpeg::errors::DecodeErrors),
+
+    /// Carries the data completed so that it decodes, with the missing part in grey
+    #[error("Image data ends early")]
+    Truncated(Vec<u8>),
+
+    #[error("Image of {0}x{1} pixels is larger than --max-pixels {2}")]
+    TooManyPixels(usize, usize, usize),
+
+    #[error("Image of {0}x{1} pixels needs about {2} MB, more than --max-memory allows")]
+    OverMemoryBudget(usize, usize, usize),
+
+    #[error("Invalid GIF: {0}")]
+    Gif(#[from] AnimationError),
+
+    #[cfg(feature = "hdr")]
+    #[error(transparent)]
+    Hdr(#[from] HdrError),
+}
+
+/// Library JPEG inputs are decoded with
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum JpegDecoderBackend {
+    /// Pure Rust jpeg-decoder, also completes truncated files
+    #[default]
+    JpegDecoder,
+    /// zune-jpeg, SIMD accelerated and faster on large batches
+    #[cfg(feature = "zune-jpeg")]
+    ZuneJpeg,
+}
+
+/// Settings that only apply to some input formats
+#[cfg_attr(not(feature = "hdr"), derive(Default))]
+pub struct DecodeOptions {
+    /// Tone mapping curve for HDR inputs
+    #[cfg(feature = "hdr")]
+    pub tone_map_operator: fn(f32) -> f32,
+    /// Linear exposure multiplier applied to HDR inputs before tone mapping
+    #[cfg(feature = "hdr")]
+    pub exposure: f32,
+    /// Largest inputs that are decoded
+    pub limits: Limits,
+    pub jpeg_backend: JpegDecoderBackend,
+}
+
+#[cfg(feature = "hdr")]
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            #[cfg(feature = "hdr")]
+            tone_map_operator: crate::hdr::reinhard,
+            #[cfg(feature = "hdr")]
+            exposure: 1.0,
+            limits: Limits::default(),
+            jpeg_backend: JpegDecoderBackend::default(),
+        }
+    }
+}
+
+/// Whether the path is an HDR image supported by this build
+#[cfg(feature = "hdr")]
+pub fn is_hdr_path(path: &Path) -> bool {
+    crate::hdr::is_hdr_path(path)
+}
+
+#[cfg(not(feature = "hdr"))]
+pub fn is_hdr_path(_path: &Path) -> bool {
+    false
+}
+
+/// Input formats, recognized by their magic bytes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    Jpeg,
+    Gif,
+    #[cfg(feature = "exr")]
+    Exr,
+    #[cfg(feature = "radiance")]
+    Radiance,
+}
+
+impl InputFormat {
+    /**
+     * Sniffs the format from the first bytes, so misnamed files still decode. Data without a
+     * known signature falls back to the extension of `path` and then to JPEG. */
+    pub fn detect(path: &Path, data: &[u8]) -> Self {
+        match data {
+            [0xff, 0xd8, 0xff, ..] => return InputFormat::Jpeg,
+            [b'G', b'I', b'F', b'8', ..] => return InputFormat::Gif,
+            #[cfg(feature = "exr")]
+            [0x76, 0x2f, 0x31, 0x01, ..] => return InputFormat::Exr,
+            #[cfg(feature = "radiance")]
+            [b'#', b'?', ..] => return InputFormat::Radiance,
+            _ => {}
+        }
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        match ext.as_deref() {
+            Some("gif") => InputFormat::Gif,
+            #[cfg(feature = "exr")]
+            Some("exr") => InputFormat::Exr,
+            #[cfg(feature = "radiance")]
+            Some("hdr") => InputFormat::Radiance,
+            _ => InputFormat::Jpeg,
+        }
+    }
+
+    pub fn is_animation(self) -> bool {
+        self == InputFormat::Gif
+    }
+
+    /// High dynamic range formats are tone mapped into 8 bit while decoding
+    pub fn is_hdr(self) -> bool {
+        match self {
+            InputFormat::Jpeg | InputFormat::Gif => false,
+            #[cfg(feature = "exr")]
+            InputFormat::Exr => true,
+            #[cfg(feature = "radiance")]
+            InputFormat::Radiance => true,
+        }
+    }
+
+    /// Width and height from the header, without decoding any pixels
+    pub fn dimensions(self, data: &[u8]) -> Option<(usize, usize)> {
+        match self {
+            InputFormat::Jpeg => {
+                let mut decoder = Decoder::new(data);
+                decoder.read_info().ok()?;
+                let info = decoder.info()?;
+                Some((info.width.into(), info.height.into()))
+            }
+            // Frames are drawn onto the logical screen, so its size is what gets allocated
+            InputFormat::Gif => {
+                let screen = data.get(6..10)?;
+                let width = u16::from_le_bytes([screen[0], screen[1]]);
+                let height = u16::from_le_bytes([screen[2], screen[3]]);
+                Some((width.into(), height.into()))
+            }
+            #[cfg(feature = "exr")]
+            InputFormat::Exr => exr_dimensions(data),
+            #[cfg(feature = "radiance")]
+            InputFormat::Radiance => radiance_dimensions(data),
+        }
+    }
+
+    pub fn decoder(self) -> &'static dyn ImageDecoder {
+        match self {
+            InputFormat::Jpeg => &JpegDecoder,
+            InputFormat::Gif => &GifDecoder,
+            #[cfg(feature = "exr")]
+            InputFormat::Exr => &ExrDecoder,
+            #[cfg(feature = "radiance")]
+            InputFormat::Radiance => &RadianceDecoder,
+        }
+    }
+}
+
+/// Reads images of one format into the common buffer type
+pub trait ImageDecoder {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> Result<ImageBuffer, DecodeError>;
+}
+
+/// Decodes an image held in memory, its format is sniffed from the data
+pub fn decode_bytes(
+    path: &Path,
+    data: &[u8],
+    options: &DecodeOptions,
+) -> Result<ImageBuffer, DecodeError> {
+    let _span = info_span!("decode", path = %path.display()).entered();
+    let format = InputFormat::detect(path, data);
+    check_limits(format, data, &options.limits)?;
+    let decoder = format.decoder();
+    match decoder.decode(data, options) {
+        Err(DecodeError::Truncated(completed)) => {
+            warn!(path = %path.display(), "image is truncated, the missing part is filled with grey");
+            decoder.decode(&completed, options)
+        }
+        result => result,
+    }
+}
+
+struct JpegDecoder;
+
+impl ImageDecoder for JpegDecoder {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> Result<ImageBuffer, DecodeError> {
+        match options.jpeg_backend {
+            JpegDecoderBackend::JpegDecoder => match decode_jpeg(data) {
+                Err(jpeg::Error::Io(error)) if error.kind() == ErrorKind::UnexpectedEof => {
+                    Err(complete_truncated_jpeg(data)
+                        .map_or(jpeg::Error::Io(error).into(), DecodeError::Truncated))
+                }
+                result => Ok(result?),
+            },
+            #[cfg(feature = "zune-jpeg")]
+            JpegDecoderBackend::ZuneJpeg => Ok(decode_zune_jpeg(data)?),
+        }
+    }
+}
+
+fn decode_jpeg(data: &[u8]) -> Result<ImageBuffer, jpeg::Error> {
+    let mut decoder = Decoder::new(data);
+    let pixels = decoder.decode()?;
+    let metadata = decoder.info().expect("info is available after decoding");
+    let (channels, data) = match metadata.pixel_format {
+        PixelFormat::L8 => (1, pixels),
+        // Only the most significant byte of big endian 16 bit samples is kept
+        PixelFormat::L16 => (1, pixels.chunks_exact(2).map(|s| s[0]).collect()),
+        PixelFormat::RGB24 => (3, pixels),
+        PixelFormat::CMYK32 => (3, cmyk_to_rgb(&pixels)),
+    };
+    Ok(ImageBuffer::new(
+        metadata.width.into(),
+        metadata.height.into(),
+        channels,
+        data,
+    ))
+}
+
+/// Gray inputs stay gray, everything else is converted to RGB like jpeg-decoder does
+#[cfg(feature = "zune-jpeg")]
+fn decode_zune_jpeg(data: &[u8]) -> Result<ImageBuffer, zune_jpeg::errors::DecodeErrors> {
+    use zune_jpeg::zune_core::bytestream::ZCursor;
+    use zune_jpeg::zune_core::colorspace::ColorSpace;
+
+    let mut decoder = zune_jpeg::JpegDecoder::new(ZCursor::new(data));
+    decoder.decode_headers()?;
+    let (channels, colorspace) = match decoder.input_colorspace() {
+        Some(ColorSpace::Luma) => (1, ColorSpace::Luma),
+        _ => (3, ColorSpace::RGB),
+    };
+    // --max-pixels and --max-memory limit the size instead of the decoder's own default
+    let options = decoder
+        .options()
+        .jpeg_set_out_colorspace(colorspace)
+        .set_max_width(u16::MAX.into())
+        .set_max_height(u16::MAX.into());
+    decoder.set_options(options);
+    let pixels = decoder.decode()?;
+    let (width, height) = decoder
+        .dimensions()
+        .expect("dimensions are known after decoding");
+    Ok(ImageBuffer::new(width, height, channels, pixels))
+}
+
+/**
+ * Ends a JPEG that was cut off inside its entropy coded data. The decoder reads zero bits
+ * after a marker, so appending the restart markers it still expects and an end of image
+ * marker lets it decode the blocks that are there. `None` if the data ends before a scan. */
+fn complete_truncated_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    let read_u16 = |at: usize| Some(u16::from_be_bytes([*data.get(at)?, *data.get(at + 1)?]));
+    let (mut width, mut height, mut restart_interval) = (0, 0, 0);
+    let mut index = 2;
+    let scan = loop {
+        if data.get(index) != Some(&0xff) {
+            return None;
+        }
+        let marker = *data.get(index + 1)?;
+        let end = index + 2 + read_u16(index + 2)? as usize;
+        match marker {
+            0xc4 | 0xc8 | 0xcc => {}
+            0xc0..=0xcf => {
+                height = read_u16(index + 5)? as usize;
+                width = read_u16(index + 7)? as usize;
+            }
+            0xdd => restart_interval = read_u16(index + 4)? as usize,
+            0xda => break data.get(end..)?,
+            _ => {}
+        }
+        index = end;
+    };
+
+    // Restart markers count up modulo 8 from the one after the last that made it
+    let mut next_restart = 0;
+    for pair in scan.windows(2) {
+        match pair {
+            [0xff, marker @ 0xd0..=0xd7] => next_restart = (*marker as usize - 0xcf) % 8,
+            [0xff, 0xda] => next_restart = 0,
+            _ => {}
+        }
+    }
+    let mut completed = data.strip_suffix(&[0xff]).unwrap_or(data).to_vec();
+    if restart_interval > 0 {
+        // Every block being its own MCU is the most restarts an image can have
+        let restarts = (width.div_ceil(8) * height.div_ceil(8)).div_ceil(restart_interval);
+        for restart in 0..restarts {
+            completed.extend([0xff, 0xd0 + ((next_restart + restart) % 8) as u8]);
+        }
+    }
+    completed.extend([0xff, 0xd9]);
+    Some(completed)
+}
+
+/// Single image operations only look at the first frame of an animation
+struct GifDecoder;
+
+impl ImageDecoder for GifDecoder {
+    fn decode(&self, data: &[u8], _options: &DecodeOptions) -> Result<ImageBuffer, DecodeError> {
+        let mut frames = read_gif_frames(data)?;
+        Ok(frames.swap_remove(0).image)
+    }
+}
+
+#[cfg(feature = "exr")]
+struct ExrDecoder;
+
+#[cfg(feature = "exr")]
+impl ImageDecoder for ExrDecoder {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> Result<ImageBuffer, DecodeError> {
+        Ok(tone_mapped(read_exr(data)?, options))
+    }
+}
+
+#[cfg(feature = "radiance")]
+struct RadianceDecoder;
+
+#[cfg(feature = "radiance")]
+impl ImageDecoder for RadianceDecoder {
+    fn decode(&self, data: &[u8], options: &DecodeOptions) -> Result<ImageBuffer, DecodeError> {
+        Ok(tone_mapped(read_radiance(data)?, options))
+    }
+}
+
+/// Rough peak memory per pixel, the decoded image, its prepared copy, the upsampled result
+/// and the buffer handed to the encoder
+const BYTES_PER_PIXEL: usize = 16;
+
+/// Rejects inputs whose header claims more pixels than `limits` allow, before they are decoded
+pub fn check_limits(format: InputFormat, data: &[u8], limits: &Limits) -> Result<(), DecodeError> {
+    if *limits == Limits::default() {
+        return Ok(());
+    }
+    let Some((width, height)) = format.dimensions(data) else {
+        // The decoder reports broken headers itself
+        return Ok(());
+    };
+    let pixels = width.saturating_mul(height);
+    if let Some(max_pixels) = limits.max_pixels
+        && pixels > max_pixels
+    {
+        return Err(DecodeError::TooManyPixels(width, height, max_pixels));
+    }
+    let memory = pixels.saturating_mul(BYTES_PER_PIXEL);
+    if let Some(max_memory) = limits.max_memory
+        && memory > max_memory
+    {
+        return Err(DecodeError::OverMemoryBudget(width, height, memory >> 20));
+    }
+    Ok(())
+}
+
+fn cmyk_to_rgb(pixels: &[u8]) -> Vec<u8> {
+    pixels
+        .chunks_exact(4)
+        .flat_map(|cmyk| {
+            let k = 255 - cmyk[3] as u16;
+            [0, 1, 2].map(|i| ((255 - cmyk[i] as u16) * k / 255) as u8)
+        })
+        .collect()
+}
+
+#[cfg(any(feature = "exr", feature = "radiance"))]
+fn tone_mapped(image: HdrImage, options: &DecodeOptions) -> ImageBuffer {
+    let pixels = tone_map(&image.pixels, options.tone_map_operator, options.exposure);
+    ImageBuffer::new(image.width, image.height, 3, pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use jpeg_encoder::{ColorType, Encoder};
+
+    #[cfg(feature = "zune-jpeg")]
+    use super::JpegDecoderBackend;
+    use super::{DecodeError, DecodeOptions, InputFormat, check_limits, decode_bytes};
+    use crate::cli::Limits;
+
+    #[test]
+    fn test_format_is_sniffed_before_extension() {
+        let jpeg = [0xff, 0xd8, 0xff, 0xe0];
+        assert_eq!(
+            InputFormat::detect(Path::new("photo.gif"), &jpeg),
+            InputFormat::Jpeg
+        );
+        assert_eq!(
+            InputFormat::detect(Path::new("photo.jpg"), b"GIF89a"),
+            InputFormat::Gif
+        );
+        // Unknown signatures fall back to the extension
+        assert_eq!(
+            InputFormat::detect(Path::new("clip.GIF"), b""),
+            InputFormat::Gif
+        );
+        assert_eq!(
+            InputFormat::detect(Path::new("photo"), b""),
+            InputFormat::Jpeg
+        );
+    }
+
+    #[test]
+    fn test_truncated_jpeg_is_completed() {
+        let pixels: Vec<u8> = (0..64 * 64 * 3).map(|i| (i * 37 % 251) as u8).collect();
+        let mut data = vec![];
+        let mut encoder = Encoder::new(&mut data, 90);
+        encoder.set_restart_interval(2);
+        encoder.encode(&pixels, 64, 64, ColorType::Rgb).unwrap();
+        let options = DecodeOptions::default();
+        let complete = decode_bytes(Path::new("full.jpeg"), &data, &options).unwrap();
+        data.truncate(data.len() / 2);
+
+        let image = decode_bytes(Path::new("cut.jpeg"), &data, &options)
+            .expect("truncated data should decode");
+        assert_eq!((image.width, image.height), (64, 64));
+        assert_eq!(image.data[..64 * 3], complete.data[..64 * 3]);
+        assert!(
+            image.data[image.data.len() - 64 * 3..]
+                .iter()
+                .all(|&v| v.abs_diff(128) <= 2)
+        );
+
+        // Data that ends before the first scan cannot be completed
+        assert!(
+            decode_bytes(
+                Path::new("cut.jpeg"),
+                &data[..20],
+                &DecodeOptions::default()
+            )
+            .is_err()
+        );
+    }
+
+    #[cfg(feature = "zune-jpeg")]
+    #[test]
+    fn test_zune_jpeg_matches_jpeg_decoder() {
+        let zune = DecodeOptions {
+            jpeg_backend: JpegDecoderBackend::ZuneJpeg,
+            ..DecodeOptions::default()
+        };
+        for (color_type, channels) in [(ColorType::Rgb, 3), (ColorType::Luma, 1)] {
+            let pixels: Vec<u8> = (0..48 * 32 * channels)
+                .map(|i| (i * 37 % 251) as u8)
+                .collect();
+            let mut data = vec![];
+            Encoder::new(&mut data, 90)
+                .encode(&pixels, 48, 32, color_type)
+                .unwrap();
+            let reference =
+                decode_bytes(Path::new("a.jpeg"), &data, &DecodeOptions::default()).unwrap();
+            let image = decode_bytes(Path::new("a.jpeg"), &data, &zune).unwrap();
+            assert_eq!(
+                (image.width, image.height, image.channels),
+                (48, 32, channels)
+            );
+            // The decoders round the inverse DCT and color conversion slightly differently
+            assert!(
+                image
+                    .data
+                    .iter()
+                    .zip(&reference.data)
+                    .all(|(a, b)| a.abs_diff(*b) <= 3)
+            );
+        }
+    }
+
+    #[test]
+    fn test_limits_are_checked_before_decoding() {
+        // A GIF header claiming a 65535x65535 screen with nothing behind it
+        let bomb = [b"GIF89a".as_slice(), &[0xff, 0xff, 0xff, 0xff, 0, 0, 0]].concat();
+        let limits = Limits {
+            max_pixels: Some(1_000_000),
+            max_memory: None,
+        };
+        assert!(matches!(
+            check_limits(InputFormat::Gif, &bomb, &limits),
+            Err(DecodeError::TooManyPixels(65535, 65535, 1_000_000))
+        ));
+
+        let limits = Limits {
+            max_pixels: None,
+            max_memory: Some(1 << 20),
+        };
+        assert!(matches!(
+            check_limits(InputFormat::Gif, &bomb, &limits),
+            Err(DecodeError::OverMemoryBudget(65535, 65535, _))
+        ));
+        assert!(check_limits(InputFormat::Gif, &bomb, &Limits::default()).is_ok());
+    }
 }
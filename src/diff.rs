@@ -0,0 +1,263 @@
+use std::fmt;
+
+use thiserror::Error;
+
+use crate::color::{ciede2000, luma, to_lab};
+use crate::types::ImageBuffer;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum DiffError {
+    #[error("Images have different dimensions: {0}x{1} and {2}x{3}")]
+    DimensionMismatch(usize, usize, usize, usize),
+}
+
+/// Per-pixel difference statistics of two images, channel values are in 0..=255
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffStats {
+    pub pixels: usize,
+    /// Number of pixels where at least one channel differs
+    pub changed_pixels: usize,
+    pub max_difference: u8,
+    pub mean_absolute_error: f64,
+    pub root_mean_square_error: f64,
+    /// Perceptual CIEDE2000 difference per pixel, about 1 is barely noticeable
+    pub mean_delta_e: f64,
+    pub max_delta_e: f64,
+}
+
+impl DiffStats {
+    /// Peak signal to noise ratio in dB, infinite for identical images
+    pub fn psnr(&self) -> f64 {
+        if self.root_mean_square_error == 0.0 {
+            return f64::INFINITY;
+        }
+        20.0 * (255.0 / self.root_mean_square_error).log10()
+    }
+}
+
+impl fmt::Display for DiffStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let changed_percent = if self.pixels == 0 {
+            0.0
+        } else {
+            100.0 * self.changed_pixels as f64 / self.pixels as f64
+        };
+        writeln!(
+            f,
+            "changed pixels: {} of {} ({:.2}%)",
+            self.changed_pixels, self.pixels, changed_percent
+        )?;
+        writeln!(f, "max difference: {}", self.max_difference)?;
+        writeln!(f, "mean absolute error: {:.4}", self.mean_absolute_error)?;
+        writeln!(f, "rmse: {:.4}", self.root_mean_square_error)?;
+        writeln!(f, "psnr: {:.2} dB", self.psnr())?;
+        write!(
+            f,
+            "delta e (ciede2000): mean {:.4}, max {:.4}",
+            self.mean_delta_e, self.max_delta_e
+        )
+    }
+}
+
+/// Grayscale images are compared against RGB ones by repeating the luma channel
+fn channel_pair(a: &ImageBuffer, b: &ImageBuffer) -> (Vec<u8>, Vec<u8>, usize) {
+    let expand =
+        |image: &ImageBuffer| -> Vec<u8> { image.data.iter().flat_map(|&v| [v, v, v]).collect() };
+    match (a.channels, b.channels) {
+        (1, 3) => (expand(a), b.data.clone(), 3),
+        (3, 1) => (a.data.clone(), expand(b), 3),
+        _ => (a.data.clone(), b.data.clone(), a.channels),
+    }
+}
+
+/// Gray pixels are compared as the color with all channels at their value
+fn rgb(pixel: &[u8]) -> [u8; 3] {
+    match pixel {
+        [r, g, b, ..] => [*r, *g, *b],
+        _ => [pixel[0]; 3],
+    }
+}
+
+fn check_dimensions(a: &ImageBuffer, b: &ImageBuffer) -> Result<(), DiffError> {
+    if (a.width, a.height) != (b.width, b.height) {
+        return Err(DiffError::DimensionMismatch(
+            a.width, a.height, b.width, b.height,
+        ));
+    }
+    Ok(())
+}
+
+pub fn compare(a: &ImageBuffer, b: &ImageBuffer) -> Result<DiffStats, DiffError> {
+    check_dimensions(a, b)?;
+    let (a_data, b_data, channels) = channel_pair(a, b);
+
+    let mut changed_pixels = 0;
+    let mut max_difference = 0u8;
+    let mut absolute_sum = 0f64;
+    let mut square_sum = 0f64;
+    let mut delta_e_sum = 0f64;
+    let mut max_delta_e = 0f64;
+    for (a_pixel, b_pixel) in a_data
+        .chunks_exact(channels)
+        .zip(b_data.chunks_exact(channels))
+    {
+        let mut changed = false;
+        for (&a_value, &b_value) in a_pixel.iter().zip(b_pixel) {
+            let difference = a_value.abs_diff(b_value);
+            changed |= difference != 0;
+            max_difference = max_difference.max(difference);
+            absolute_sum += difference as f64;
+            square_sum += (difference as f64).powi(2);
+        }
+        changed_pixels += changed as usize;
+        if changed {
+            let delta_e = ciede2000(to_lab(rgb(a_pixel)), to_lab(rgb(b_pixel))) as f64;
+            delta_e_sum += delta_e;
+            max_delta_e = max_delta_e.max(delta_e);
+        }
+    }
+
+    let samples = a_data.len().max(1) as f64;
+    let pixels = a.width * a.height;
+    Ok(DiffStats {
+        pixels,
+        changed_pixels,
+        max_difference,
+        mean_absolute_error: absolute_sum / samples,
+        root_mean_square_error: (square_sum / samples).sqrt(),
+        mean_delta_e: delta_e_sum / pixels.max(1) as f64,
+        max_delta_e,
+    })
+}
+
+/// Side of the square windows `ssim` compares
+const SSIM_WINDOW: usize = 8;
+
+/**
+ * Mean structural similarity of the luma of two images, compared in windows of 8x8 pixels.
+ * 1.0 for identical images, lower the more local contrast and structure differ. */
+pub fn ssim(a: &ImageBuffer, b: &ImageBuffer) -> Result<f64, DiffError> {
+    check_dimensions(a, b)?;
+    let luma_of = |image: &ImageBuffer| -> Vec<f64> {
+        image
+            .data
+            .chunks_exact(image.channels)
+            .map(|pixel| luma(rgb(pixel)) as f64)
+            .collect()
+    };
+    let (a_luma, b_luma) = (luma_of(a), luma_of(b));
+    let (c1, c2) = ((0.01f64 * 255.0).powi(2), (0.03f64 * 255.0).powi(2));
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for top in (0..a.height).step_by(SSIM_WINDOW) {
+        for left in (0..a.width).step_by(SSIM_WINDOW) {
+            let indices = (top..(top + SSIM_WINDOW).min(a.height)).flat_map(|y| {
+                (left..(left + SSIM_WINDOW).min(a.width)).map(move |x| y * a.width + x)
+            });
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+                (0.0, 0.0, 0.0, 0.0, 0.0);
+            let mut count = 0.0;
+            for i in indices {
+                let (x, y) = (a_luma[i], b_luma[i]);
+                sum_a += x;
+                sum_b += y;
+                sum_aa += x * x;
+                sum_bb += y * y;
+                sum_ab += x * y;
+                count += 1.0;
+            }
+            let (mean_a, mean_b) = (sum_a / count, sum_b / count);
+            let variance_a = sum_aa / count - mean_a * mean_a;
+            let variance_b = sum_bb / count - mean_b * mean_b;
+            let covariance = sum_ab / count - mean_a * mean_b;
+            total += (2.0 * mean_a * mean_b + c1) * (2.0 * covariance + c2)
+                / ((mean_a * mean_a + mean_b * mean_b + c1) * (variance_a + variance_b + c2));
+            windows += 1;
+        }
+    }
+    Ok(total / windows.max(1) as f64)
+}
+
+/// Absolute per-channel difference multiplied by `amplify`, so subtle changes become visible
+pub fn difference_image(
+    a: &ImageBuffer,
+    b: &ImageBuffer,
+    amplify: f32,
+) -> Result<ImageBuffer, DiffError> {
+    check_dimensions(a, b)?;
+    let (a_data, b_data, channels) = channel_pair(a, b);
+    let data = a_data
+        .iter()
+        .zip(&b_data)
+        .map(|(&a_value, &b_value)| {
+            (a_value.abs_diff(b_value) as f32 * amplify)
+                .round()
+                .min(255.0) as u8
+        })
+        .collect();
+    Ok(ImageBuffer::new(a.width, a.height, channels, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiffError, compare, difference_image, ssim};
+    use crate::types::ImageBuffer;
+
+    #[test]
+    fn test_identical_images() {
+        let image = ImageBuffer::new(2, 2, 3, (0..12).collect());
+        let stats = compare(&image, &image).unwrap();
+        assert_eq!(stats.changed_pixels, 0);
+        assert_eq!(stats.max_difference, 0);
+        assert_eq!(stats.psnr(), f64::INFINITY);
+        assert_eq!((stats.mean_delta_e, stats.max_delta_e), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_difference_statistics() {
+        let a = ImageBuffer::new(2, 1, 1, vec![10, 20]);
+        let b = ImageBuffer::new(2, 1, 1, vec![10, 24]);
+        let stats = compare(&a, &b).unwrap();
+        assert_eq!(stats.changed_pixels, 1);
+        assert_eq!(stats.max_difference, 4);
+        assert_eq!(stats.mean_absolute_error, 2.0);
+        assert_eq!(stats.root_mean_square_error, 8f64.sqrt());
+
+        // Only the changed pixel adds to the perceptual difference
+        assert!(stats.max_delta_e > 1.0);
+        assert_eq!(stats.mean_delta_e, stats.max_delta_e / 2.0);
+
+        let diff = difference_image(&a, &b, 10.0).unwrap();
+        assert_eq!(diff.data, vec![0, 40]);
+    }
+
+    #[test]
+    fn test_grayscale_against_rgb() {
+        let gray = ImageBuffer::new(1, 1, 1, vec![100]);
+        let rgb = ImageBuffer::new(1, 1, 3, vec![100, 100, 110]);
+        let diff = difference_image(&gray, &rgb, 1.0).unwrap();
+        assert_eq!((diff.channels, diff.data), (3, vec![0, 0, 10]));
+    }
+
+    #[test]
+    fn test_dimension_mismatch() {
+        let a = ImageBuffer::new(2, 1, 1, vec![0, 0]);
+        let b = ImageBuffer::new(1, 2, 1, vec![0, 0]);
+        assert_eq!(
+            compare(&a, &b),
+            Err(DiffError::DimensionMismatch(2, 1, 1, 2))
+        );
+    }
+
+    #[test]
+    fn test_ssim() {
+        let image = ImageBuffer::new(16, 16, 1, (0..=255).collect());
+        assert!((ssim(&image, &image).unwrap() - 1.0).abs() < 1e-9);
+
+        let flat = ImageBuffer::new(16, 16, 1, vec![128; 256]);
+        let noisy = ImageBuffer::new(16, 16, 1, (0..256).map(|i| (i * 97 % 256) as u8).collect());
+        assert!(ssim(&image, &flat).unwrap() < 0.5);
+        assert!(ssim(&image, &noisy).unwrap() < ssim(&image, &image).unwrap());
+    }
+}
@@ -0,0 +1,267 @@
+use crate::cli::{Remap, SortDirection};
+use crate::color::luma;
+use crate::interpolation::sample_blocks;
+use crate::types::ImageBuffer;
+
+/**
+ * Shifts the red channel right and the blue channel left by `offset` pixels, like the
+ * color fringes of a misaligned CRT. Edge pixels are repeated, grayscale images have no
+ * channels to separate and stay as they are. */
+pub fn chromatic_aberration(image: &mut ImageBuffer, offset: usize) {
+    if image.channels < 3 || offset == 0 {
+        return;
+    }
+    let (width, channels) = (image.width, image.channels);
+    for row in image.data.chunks_exact_mut(width * channels) {
+        let source = row.to_vec();
+        for x in 0..width {
+            let red = x.saturating_sub(offset);
+            let blue = (x + offset).min(width - 1);
+            row[x * channels] = source[red * channels];
+            row[x * channels + 2] = source[blue * channels + 2];
+        }
+    }
+}
+
+/**
+ * Sorts every run of pixels brighter than `threshold` by luminance, along rows or columns.
+ * Darker pixels stay in place and split the runs, so the outlines of the image survive. */
+pub fn pixel_sort(image: &mut ImageBuffer, direction: SortDirection, threshold: u8) {
+    let (width, height, channels) = (image.width, image.height, image.channels);
+    let (lines, length) = match direction {
+        SortDirection::Rows => (height, width),
+        SortDirection::Columns => (width, height),
+    };
+    let index = |line: usize, i: usize| match direction {
+        SortDirection::Rows => (line * width + i) * channels,
+        SortDirection::Columns => (i * width + line) * channels,
+    };
+    let brightness = |pixel: &[u8]| match pixel.len() {
+        1 => pixel[0],
+        _ => luma([pixel[0], pixel[1], pixel[2]]),
+    };
+
+    for line in 0..lines {
+        let mut pixels: Vec<Vec<u8>> = (0..length)
+            .map(|i| image.data[index(line, i)..index(line, i) + channels].to_vec())
+            .collect();
+        for run in
+            pixels.chunk_by_mut(|a, b| (brightness(a) > threshold) == (brightness(b) > threshold))
+        {
+            if brightness(&run[0]) > threshold {
+                run.sort_by_key(|pixel| brightness(pixel));
+            }
+        }
+        for (i, pixel) in pixels.iter().enumerate() {
+            image.data[index(line, i)..index(line, i) + channels].copy_from_slice(pixel);
+        }
+    }
+}
+
+/// SplitMix64, small and good enough for effects that only need to be reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0.0..1.0`
+    fn float(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound.max(1) as u64) as usize
+    }
+}
+
+/**
+ * Glitches a share of the block rows given by `amount`: a row is either shifted sideways,
+ * has its color channels rotated or is replaced by a copy of another row. The same seed
+ * always produces the same glitches. */
+pub fn glitch(grid: &mut ImageBuffer, amount: f32, seed: u64) {
+    let mut rng = Rng(seed);
+    let row = grid.width * grid.channels;
+    for y in 0..grid.height {
+        if rng.float() >= amount {
+            continue;
+        }
+        let start = y * row;
+        match rng.below(3) {
+            0 => {
+                let shift = 1 + rng.below(grid.width.div_ceil(4));
+                grid.data[start..start + row].rotate_right(shift * grid.channels);
+            }
+            1 if grid.channels >= 3 => {
+                for pixel in grid.data[start..start + row].chunks_exact_mut(grid.channels) {
+                    pixel[..3].rotate_left(1);
+                }
+            }
+            _ => {
+                let source = rng.below(grid.height) * row;
+                grid.data.copy_within(source..source + row, start);
+            }
+        }
+    }
+}
+
+/**
+ * Swaps every pixel of exactly the `from` color of a rule for its `to` color. Rules apply
+ * to the original colors, so `a=b` and `b=a` swap two colors. Expects an RGB image. */
+pub fn remap_colors(image: &mut ImageBuffer, rules: &[Remap]) {
+    for pixel in image.data.chunks_exact_mut(image.channels) {
+        if let Some(rule) = rules.iter().find(|rule| pixel[..3] == rule.from) {
+            pixel[..3].copy_from_slice(&rule.to);
+        }
+    }
+}
+
+/**
+ * Blends the colors of neighbouring blocks of a pixelated image across their shared edges.
+ * Within `amount` of half a block from an edge a pixel fades towards the color of the next
+ * block, reaching half and half right at the edge, so 0.0 keeps hard blocks and 1.0 fades
+ * all the way between block centers. Corners mix all four blocks around them. */
+pub fn soften_blocks(image: &mut ImageBuffer, grid: (usize, usize), amount: f32) {
+    if amount <= 0.0 {
+        return;
+    }
+    let (width, channels) = (image.width, image.channels);
+    let blocks = sample_blocks(image, grid);
+    let columns = edge_weights(width, grid.0, amount);
+    let rows = edge_weights(image.height, grid.1, amount);
+    let color = |x: usize, y: usize, channel: usize| {
+        blocks.data[(y * grid.0 + x) * channels + channel] as f32
+    };
+
+    for (y, &(row, other_row, weight_y)) in rows.iter().enumerate() {
+        for (x, &(column, other_column, weight_x)) in columns.iter().enumerate() {
+            if weight_x == 0.0 && weight_y == 0.0 {
+                continue;
+            }
+            let index = (y * width + x) * channels;
+            for channel in 0..channels {
+                let mix = |a: f32, b: f32, weight: f32| a + (b - a) * weight;
+                let top = mix(
+                    color(column, row, channel),
+                    color(other_column, row, channel),
+                    weight_x,
+                );
+                let bottom = mix(
+                    color(column, other_row, channel),
+                    color(other_column, other_row, channel),
+                    weight_x,
+                );
+                image.data[index + channel] = mix(top, bottom, weight_y).round() as u8;
+            }
+        }
+    }
+}
+
+/**
+ * For every pixel along a side of `length` pixels split into `blocks` blocks, its block, the
+ * block across the nearer edge and how much of that block's color it takes on. */
+fn edge_weights(length: usize, blocks: usize, amount: f32) -> Vec<(usize, usize, f32)> {
+    // Matches the upsampling, which gives pixel `i` the color of block `i * blocks / length`
+    let edge = |block: usize| (block * length).div_ceil(blocks);
+    (0..length)
+        .map(|i| {
+            let block = i * blocks / length;
+            let (start, end) = (edge(block), edge(block + 1));
+            let (to_start, to_end) = (i - start, end - 1 - i);
+            let (distance, other) = if to_start <= to_end && block > 0 {
+                (to_start, block - 1)
+            } else if block + 1 < blocks {
+                (to_end, block + 1)
+            } else {
+                return (block, block, 0.0);
+            };
+            let position = (distance as f32 + 0.5) / (end - start) as f32 / (amount * 0.5);
+            (block, other, 0.5 * (1.0 - position).max(0.0))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chromatic_aberration, glitch, pixel_sort, remap_colors, soften_blocks};
+    use crate::cli::{Remap, SortDirection};
+    use crate::types::ImageBuffer;
+
+    #[test]
+    fn test_chromatic_aberration_shifts_red_and_blue() {
+        // A white pixel between two black ones
+        let mut image = ImageBuffer::new(3, 1, 3, vec![0, 0, 0, 255, 255, 255, 0, 0, 0]);
+        chromatic_aberration(&mut image, 1);
+        assert_eq!(image.data, vec![0, 0, 255, 0, 255, 0, 255, 0, 0]);
+
+        let mut gray = ImageBuffer::new(2, 1, 1, vec![0, 255]);
+        chromatic_aberration(&mut gray, 1);
+        assert_eq!(gray.data, vec![0, 255]);
+    }
+
+    #[test]
+    fn test_pixel_sort_keeps_dark_pixels() {
+        let mut image = ImageBuffer::new(6, 1, 1, vec![200, 150, 10, 250, 180, 220]);
+        pixel_sort(&mut image, SortDirection::Rows, 100);
+        assert_eq!(image.data, vec![150, 200, 10, 180, 220, 250]);
+
+        let mut column = ImageBuffer::new(1, 3, 1, vec![250, 200, 150]);
+        pixel_sort(&mut column, SortDirection::Columns, 0);
+        assert_eq!(column.data, vec![150, 200, 250]);
+    }
+
+    #[test]
+    fn test_glitch_is_seeded() {
+        let grid = ImageBuffer::new(8, 8, 3, (0..192).collect());
+        let glitched = |seed| {
+            let mut grid = grid.clone();
+            glitch(&mut grid, 0.5, seed);
+            grid
+        };
+        assert_eq!(glitched(7), glitched(7));
+        assert_ne!(glitched(7), grid);
+        assert_ne!(glitched(7), glitched(8));
+
+        let mut untouched = grid.clone();
+        glitch(&mut untouched, 0.0, 7);
+        assert_eq!(untouched, grid);
+    }
+
+    #[test]
+    fn test_remap_colors_swaps() {
+        let mut image = ImageBuffer::new(3, 1, 3, vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let rules = [
+            Remap {
+                from: [1, 2, 3],
+                to: [4, 5, 6],
+            },
+            Remap {
+                from: [4, 5, 6],
+                to: [1, 2, 3],
+            },
+        ];
+        remap_colors(&mut image, &rules);
+        assert_eq!(image.data, vec![4, 5, 6, 1, 2, 3, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_soften_blocks_blends_edges() {
+        // Two blocks of four pixels
+        let hard = ImageBuffer::new(8, 1, 1, vec![0, 0, 0, 0, 200, 200, 200, 200]);
+        let mut image = hard.clone();
+        soften_blocks(&mut image, (2, 1), 0.0);
+        assert_eq!(image, hard);
+
+        soften_blocks(&mut image, (2, 1), 1.0);
+        assert_eq!(image.data, vec![0, 0, 25, 75, 125, 175, 200, 200]);
+
+        let mut column = ImageBuffer::new(1, 8, 1, hard.data.clone());
+        soften_blocks(&mut column, (1, 2), 1.0);
+        assert_eq!(column.data, image.data);
+    }
+}
@@ -1,13 +1,818 @@
+use clap::ValueEnum;
 use jpeg_encoder::{ColorType, Encoder};
 use std::fs::File;
-use std::io::BufWriter;
-use std::path::PathBuf;
-
-pub fn encode(vec: Vec<u8>, height: u16, width: u16, output_file_path: PathBuf) -> () {
-    // Encodes the pixel vector back to an jpeg file and also saves it to a path
-    let output = File::create(output_file_path).unwrap();
-    let encoder = Encoder::new(BufWriter::new(output), 100);
-    encoder
-        .encode(&vec, width as u16, height, ColorType::Rgb)
-        .expect("JPEG encoding failed");
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tracing::info_span;
+
+use crate::color::{luma, to_hex};
+use crate::geometry::scale_to_fit;
+use crate::matrix::{Cell, Matrix};
+use crate::palette::{PICO8, Palette};
+use crate::types::ImageBuffer;
+
+#[derive(Debug, Error)]
+pub enum EncodeError {
+    #[error("Failed to write file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Cannot encode {0} channels as {1}")]
+    UnsupportedChannels(usize, &'static str),
+
+    #[error("Image of {0}x{1} pixels is too large for {2}")]
+    TooLarge(usize, usize, &'static str),
+
+    #[error("Image does not fit into {0} bytes even at the lowest JPEG quality")]
+    OverBudget(usize),
+
+    #[error("JPEG encoding failed: {0}")]
+    Jpeg(#[from] jpeg_encoder::EncodingError),
+
+    #[cfg(feature = "mozjpeg")]
+    #[error("mozjpeg failed to encode the image")]
+    Mozjpeg,
+
+    #[error("PNG encoding failed: {0}")]
+    Png(#[from] png::EncodingError),
+
+    #[error("WebP encoding failed: {0}")]
+    Webp(#[from] image_webp::EncodingError),
+
+    #[error("GIF encoding failed: {0}")]
+    Gif(#[from] gif::EncodingError),
+
+    #[error("JSON encoding failed: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("CSV encoding failed: {0}")]
+    Csv(#[from] csv::Error),
+}
+
+/// JPEG quality used unless a file size budget asks for less
+pub const JPEG_QUALITY: u8 = 100;
+
+/// Longest side of the thumbnail `--exif-thumbnail` embeds, the size EXIF readers expect
+const EXIF_THUMBNAIL_SIZE: usize = 160;
+const EXIF_THUMBNAIL_QUALITY: u8 = 90;
+
+/// Width and height of the PICO-8 sprite sheet
+pub const P8_SHEET_SIZE: usize = 128;
+
+/// GIF frames index into a color table of at most 256 entries
+pub const GIF_MAX_COLORS: usize = 256;
+
+/// Layout of a pixel in a C header
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PixelFormat {
+    /// Two bytes per pixel, high byte first, as most SPI displays expect
+    Rgb565,
+    /// Three bytes per pixel
+    Rgb888,
+}
+
+/// Library JPEG outputs are encoded with
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum JpegBackend {
+    /// Pure Rust jpeg-encoder, fast and portable
+    JpegEncoder,
+    /// mozjpeg, noticeably smaller files at the same quality but slower to encode
+    #[cfg(feature = "mozjpeg")]
+    Mozjpeg,
+}
+
+/// What the cells of the json and csv formats contain
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MatrixValues {
+    /// `#rrggbb` colors
+    Hex,
+    /// Indices into the colors of the grid sorted by value, which json lists as `palette`
+    Index,
+}
+
+/// Container formats the result can be written as
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    Webp,
+    Gif,
+    /// C header with the pixels of the grid as a `const uint8_t[]`, for microcontrollers
+    CHeader,
+    /// 1-bit X bitmap of the grid, dark blocks are set
+    Xbm,
+    /// 1-bit grid packed into rows of bytes, most significant bit first and light blocks set,
+    /// as e-paper drivers expect
+    Bitplane,
+    /// PICO-8 cart whose sprite sheet holds the grid in the 16 colors of the console
+    P8,
+    /// Rows of the grid as JSON, see `--matrix-values`
+    Json,
+    /// Rows of the grid as CSV, see `--matrix-values`
+    Csv,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Gif => "gif",
+            OutputFormat::CHeader => "h",
+            OutputFormat::Xbm => "xbm",
+            OutputFormat::Bitplane => "bin",
+            OutputFormat::P8 => "p8",
+            OutputFormat::Json => "json",
+            OutputFormat::Csv => "csv",
+        }
+    }
+
+    /// Formats that store one pixel per block instead of the upsampled image
+    pub fn is_grid(&self) -> bool {
+        matches!(
+            self,
+            OutputFormat::CHeader
+                | OutputFormat::Xbm
+                | OutputFormat::Bitplane
+                | OutputFormat::P8
+                | OutputFormat::Json
+                | OutputFormat::Csv
+        )
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "png" => Some(OutputFormat::Png),
+            "webp" => Some(OutputFormat::Webp),
+            "gif" => Some(OutputFormat::Gif),
+            "h" => Some(OutputFormat::CHeader),
+            "xbm" => Some(OutputFormat::Xbm),
+            "bin" => Some(OutputFormat::Bitplane),
+            "p8" => Some(OutputFormat::P8),
+            "json" => Some(OutputFormat::Json),
+            "csv" => Some(OutputFormat::Csv),
+            _ => None,
+        }
+    }
+
+    /// Path for this format, keeps `path` as is if its extension already matches
+    pub fn output_path(&self, path: &Path) -> PathBuf {
+        if OutputFormat::from_path(path) == Some(*self) {
+            return path.to_path_buf();
+        }
+        path.with_extension(self.extension())
+    }
+}
+
+/// Writes images in one container format
+pub trait ImageEncoder {
+    fn encode(&self, image: &ImageBuffer, output: &mut dyn Write) -> Result<(), EncodeError>;
+}
+
+impl OutputFormat {
+    /// Encoder writing this format with the default settings
+    pub fn encoder(&self) -> &'static dyn ImageEncoder {
+        match self {
+            OutputFormat::Jpeg => &JpegEncoder {
+                quality: JPEG_QUALITY,
+                exif_thumbnail: false,
+                backend: JpegBackend::JpegEncoder,
+            },
+            OutputFormat::Png => &PngEncoder,
+            OutputFormat::Webp => &WebpEncoder,
+            OutputFormat::Gif => &GifEncoder,
+            OutputFormat::CHeader => &CHeaderEncoder {
+                pixel_format: PixelFormat::Rgb565,
+            },
+            OutputFormat::Xbm => &XbmEncoder,
+            OutputFormat::Bitplane => &BitplaneEncoder,
+            OutputFormat::P8 => &P8Encoder,
+            OutputFormat::Json => &JsonEncoder {
+                values: MatrixValues::Hex,
+            },
+            OutputFormat::Csv => &CsvEncoder {
+                values: MatrixValues::Hex,
+            },
+        }
+    }
+}
+
+/// Writes the image in the format given by the extension of the path, JPEG if it is unknown
+pub fn encode(image: &ImageBuffer, output_file_path: PathBuf) -> Result<(), EncodeError> {
+    let _span = info_span!("encode", path = %output_file_path.display()).entered();
+    let format = OutputFormat::from_path(&output_file_path).unwrap_or(OutputFormat::Jpeg);
+    let mut output = BufWriter::new(File::create(output_file_path)?);
+    format.encoder().encode(image, &mut output)?;
+    Ok(output.flush()?)
+}
+
+/**
+ * Encodes as JPEG at the highest quality whose file fits into `max_bytes`, `None` if not even
+ * the lowest quality does. The file size grows with the quality, so it is a binary search. */
+pub fn encode_jpeg_within(
+    image: &ImageBuffer,
+    max_bytes: usize,
+    exif_thumbnail: bool,
+    backend: JpegBackend,
+) -> Result<Option<Vec<u8>>, EncodeError> {
+    let (mut low, mut high) = (1, JPEG_QUALITY);
+    let mut best = None;
+    while low <= high {
+        let quality = low + (high - low) / 2;
+        let mut data = Vec::new();
+        JpegEncoder {
+            quality,
+            exif_thumbnail,
+            backend,
+        }
+        .encode(image, &mut data)?;
+        if data.len() <= max_bytes {
+            best = Some(data);
+            low = quality + 1;
+        } else {
+            high = quality - 1;
+        }
+    }
+    Ok(best)
+}
+
+pub struct JpegEncoder {
+    pub quality: u8,
+    /// Embed a small copy of the image as EXIF thumbnail, for instant previews in file browsers
+    pub exif_thumbnail: bool,
+    pub backend: JpegBackend,
+}
+
+impl ImageEncoder for JpegEncoder {
+    fn encode(&self, image: &ImageBuffer, output: &mut dyn Write) -> Result<(), EncodeError> {
+        let color_type = match image.channels {
+            1 => ColorType::Luma,
+            3 => ColorType::Rgb,
+            channels => return Err(EncodeError::UnsupportedChannels(channels, "JPEG")),
+        };
+        let (width, height) = dimensions_u16(image, "JPEG")?;
+        let app1 = match self.exif_thumbnail {
+            true => Some(exif_thumbnail(image)?),
+            false => None,
+        };
+        match self.backend {
+            JpegBackend::JpegEncoder => {
+                let mut encoder = Encoder::new(output, self.quality);
+                if let Some(app1) = &app1 {
+                    encoder.add_app_segment(1, app1)?;
+                }
+                encoder.encode(&image.data, width, height, color_type)?;
+            }
+            #[cfg(feature = "mozjpeg")]
+            JpegBackend::Mozjpeg => {
+                output.write_all(&encode_mozjpeg(image, self.quality, app1.as_deref())?)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encodes with mozjpeg, whose errors unwind out of libjpeg and are caught here
+#[cfg(feature = "mozjpeg")]
+fn encode_mozjpeg(
+    image: &ImageBuffer,
+    quality: u8,
+    app1: Option<&[u8]>,
+) -> Result<Vec<u8>, EncodeError> {
+    let color_space = match image.channels {
+        1 => mozjpeg::ColorSpace::JCS_GRAYSCALE,
+        _ => mozjpeg::ColorSpace::JCS_RGB,
+    };
+    std::panic::catch_unwind(|| {
+        let mut compress = mozjpeg::Compress::new(color_space);
+        compress.set_size(image.width, image.height);
+        compress.set_quality(quality as f32);
+        let mut started = compress.start_compress(Vec::new())?;
+        if let Some(app1) = app1 {
+            started.write_marker(mozjpeg::Marker::APP(1), app1);
+        }
+        started.write_scanlines(&image.data)?;
+        started.finish()
+    })
+    .map_err(|_| EncodeError::Mozjpeg)?
+    .map_err(EncodeError::from)
+}
+
+/**
+ * APP1 segment with the smallest EXIF structure that carries a thumbnail: an empty first IFD
+ * for the image itself and a second one pointing at `image` scaled down and encoded as JPEG.
+ * Offsets count from the start of the little endian TIFF header. */
+fn exif_thumbnail(image: &ImageBuffer) -> Result<Vec<u8>, EncodeError> {
+    let mut thumbnail = Vec::new();
+    JpegEncoder {
+        quality: EXIF_THUMBNAIL_QUALITY,
+        exif_thumbnail: false,
+        backend: JpegBackend::JpegEncoder,
+    }
+    .encode(
+        &scale_to_fit(image, EXIF_THUMBNAIL_SIZE, EXIF_THUMBNAIL_SIZE),
+        &mut thumbnail,
+    )?;
+
+    // TIFF header, IFD0 without entries, then IFD1 with three entries
+    const IFD1: u32 = 8 + 6;
+    const THUMBNAIL: u32 = IFD1 + 2 + 3 * 12 + 4;
+    let mut tiff = Vec::with_capacity(THUMBNAIL as usize + thumbnail.len());
+    tiff.extend(b"II*\0");
+    tiff.extend(8u32.to_le_bytes());
+    tiff.extend(0u16.to_le_bytes());
+    tiff.extend(IFD1.to_le_bytes());
+    tiff.extend(3u16.to_le_bytes());
+    // Tag, type (3 is a short, 4 a long), count and value of every entry
+    for (tag, kind, value) in [
+        (0x0103u16, 3u16, 6u32),             // Compression: JPEG
+        (0x0201, 4, THUMBNAIL),              // JPEGInterchangeFormat
+        (0x0202, 4, thumbnail.len() as u32), // JPEGInterchangeFormatLength
+    ] {
+        tiff.extend(tag.to_le_bytes());
+        tiff.extend(kind.to_le_bytes());
+        tiff.extend(1u32.to_le_bytes());
+        tiff.extend(value.to_le_bytes());
+    }
+    tiff.extend(0u32.to_le_bytes());
+    tiff.extend(thumbnail);
+    Ok([b"Exif\0\0".as_slice(), &tiff].concat())
+}
+
+pub struct PngEncoder;
+
+impl ImageEncoder for PngEncoder {
+    fn encode(&self, image: &ImageBuffer, output: &mut dyn Write) -> Result<(), EncodeError> {
+        let color_type = match image.channels {
+            1 => png::ColorType::Grayscale,
+            3 => png::ColorType::Rgb,
+            channels => return Err(EncodeError::UnsupportedChannels(channels, "PNG")),
+        };
+        let mut encoder = png::Encoder::new(output, image.width as u32, image.height as u32);
+        encoder.set_color(color_type);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&image.data)?;
+        Ok(())
+    }
+}
+
+/// WebP output is lossless, lossy compression would smear the hard block edges
+pub struct WebpEncoder;
+
+impl ImageEncoder for WebpEncoder {
+    fn encode(&self, image: &ImageBuffer, output: &mut dyn Write) -> Result<(), EncodeError> {
+        let color_type = match image.channels {
+            1 => image_webp::ColorType::L8,
+            3 => image_webp::ColorType::Rgb8,
+            channels => return Err(EncodeError::UnsupportedChannels(channels, "WebP")),
+        };
+        image_webp::WebPEncoder::new(output).encode(
+            &image.data,
+            image.width as u32,
+            image.height as u32,
+            color_type,
+        )?;
+        Ok(())
+    }
+}
+
+/**
+ * Images that already use at most 256 colors, like the result of `--colors`, keep their
+ * palette exactly. Anything else is reduced with a median cut palette first. */
+pub struct GifEncoder;
+
+impl ImageEncoder for GifEncoder {
+    fn encode(&self, image: &ImageBuffer, output: &mut dyn Write) -> Result<(), EncodeError> {
+        let rgb = image.to_rgb();
+        let palette = Palette::exact(&rgb.data, 3, GIF_MAX_COLORS).unwrap_or_else(|| {
+            Palette::median_cut(&rgb.data, 3, GIF_MAX_COLORS).expect("pixels are RGB")
+        });
+        let color_table: Vec<u8> = palette.colors().iter().flatten().copied().collect();
+        let indices: Vec<u8> = palette
+            .indices(&rgb.data, 3)
+            .into_iter()
+            .map(|index| index as u8)
+            .collect();
+
+        let (width, height) = dimensions_u16(image, "GIF")?;
+        let mut encoder = gif::Encoder::new(output, width, height, &color_table)?;
+        let frame = gif::Frame::from_indexed_pixels(width, height, indices, None);
+        encoder.write_frame(&frame)?;
+        Ok(())
+    }
+}
+
+/// JPEG and GIF store their dimensions as 16 bit integers
+pub fn dimensions_u16(
+    image: &ImageBuffer,
+    format: &'static str,
+) -> Result<(u16, u16), EncodeError> {
+    match (u16::try_from(image.width), u16::try_from(image.height)) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err(EncodeError::TooLarge(image.width, image.height, format)),
+    }
+}
+
+/**
+ * Writes the pixels as a C array together with `IMAGE_WIDTH` and `IMAGE_HEIGHT`, one image
+ * row per line. */
+pub struct CHeaderEncoder {
+    pub pixel_format: PixelFormat,
+}
+
+impl ImageEncoder for CHeaderEncoder {
+    fn encode(&self, image: &ImageBuffer, output: &mut dyn Write) -> Result<(), EncodeError> {
+        let rgb = image.to_rgb();
+        let format = match self.pixel_format {
+            PixelFormat::Rgb565 => "RGB565",
+            PixelFormat::Rgb888 => "RGB888",
+        };
+        writeln!(
+            output,
+            "// {}x{} {format} pixels written by smolres",
+            rgb.width, rgb.height
+        )?;
+        writeln!(output, "#pragma once\n\n#include <stdint.h>\n")?;
+        writeln!(output, "#define IMAGE_WIDTH {}", rgb.width)?;
+        writeln!(output, "#define IMAGE_HEIGHT {}\n", rgb.height)?;
+        writeln!(output, "static const uint8_t image_data[] = {{")?;
+        for row in rgb.data.chunks_exact(rgb.width.max(1) * 3) {
+            let bytes: Vec<String> = row
+                .chunks_exact(3)
+                .flat_map(|pixel| match self.pixel_format {
+                    PixelFormat::Rgb565 => {
+                        let value = (pixel[0] as u16 >> 3) << 11
+                            | (pixel[1] as u16 >> 2) << 5
+                            | pixel[2] as u16 >> 3;
+                        value.to_be_bytes().to_vec()
+                    }
+                    PixelFormat::Rgb888 => pixel.to_vec(),
+                })
+                .map(|byte| format!("0x{byte:02x},"))
+                .collect();
+            writeln!(output, "    {}", bytes.join(" "))?;
+        }
+        writeln!(output, "}};")?;
+        Ok(())
+    }
+}
+
+/**
+ * One bit per pixel, set where `set` holds for the luma, rows are padded to whole bytes.
+ * Already dithered or thresholded results keep their exact pattern. */
+fn pack_bits(image: &ImageBuffer, set: impl Fn(u8) -> bool, msb_first: bool) -> Vec<u8> {
+    let rgb = image.to_rgb();
+    let mut bytes = Vec::with_capacity(rgb.width.div_ceil(8) * rgb.height);
+    for row in rgb.data.chunks_exact(rgb.width.max(1) * 3) {
+        for pixels in row.chunks(8 * 3) {
+            let mut byte = 0u8;
+            for (i, pixel) in pixels.chunks_exact(3).enumerate() {
+                if set(luma([pixel[0], pixel[1], pixel[2]])) {
+                    byte |= if msb_first { 0x80 >> i } else { 1 << i };
+                }
+            }
+            bytes.push(byte);
+        }
+    }
+    bytes
+}
+
+pub struct XbmEncoder;
+
+impl ImageEncoder for XbmEncoder {
+    fn encode(&self, image: &ImageBuffer, output: &mut dyn Write) -> Result<(), EncodeError> {
+        let bits = pack_bits(image, |luma| luma < 128, false);
+        writeln!(output, "#define image_width {}", image.width)?;
+        writeln!(output, "#define image_height {}", image.height)?;
+        writeln!(output, "static unsigned char image_bits[] = {{")?;
+        for line in bits.chunks(12) {
+            let bytes: Vec<String> = line.iter().map(|byte| format!("0x{byte:02x},")).collect();
+            writeln!(output, "    {}", bytes.join(" "))?;
+        }
+        writeln!(output, "}};")?;
+        Ok(())
+    }
+}
+
+pub struct BitplaneEncoder;
+
+impl ImageEncoder for BitplaneEncoder {
+    fn encode(&self, image: &ImageBuffer, output: &mut dyn Write) -> Result<(), EncodeError> {
+        Ok(output.write_all(&pack_bits(image, |luma| luma >= 128, true))?)
+    }
+}
+
+/**
+ * Writes a cart with only a `__gfx__` section, which PICO-8 loads as is. The grid goes into
+ * the top left of the 128x128 sprite sheet with every block mapped to the nearest of the
+ * 16 colors, the rest of the sheet stays black. */
+pub struct P8Encoder;
+
+impl ImageEncoder for P8Encoder {
+    fn encode(&self, image: &ImageBuffer, output: &mut dyn Write) -> Result<(), EncodeError> {
+        if image.width > P8_SHEET_SIZE || image.height > P8_SHEET_SIZE {
+            return Err(EncodeError::TooLarge(image.width, image.height, "PICO-8"));
+        }
+        let rgb = image.to_rgb();
+        let palette = Palette::new(PICO8.to_vec()).expect("PICO-8 has colors");
+        let indices = palette.indices(&rgb.data, 3);
+        writeln!(
+            output,
+            "pico-8 cartridge // http://www.pico-8.com\nversion 41\n__gfx__"
+        )?;
+        for y in 0..P8_SHEET_SIZE {
+            let line: String = (0..P8_SHEET_SIZE)
+                .map(|x| {
+                    if y < rgb.height && x < rgb.width {
+                        char::from_digit(indices[y * rgb.width + x] as u32, 16).unwrap()
+                    } else {
+                        '0'
+                    }
+                })
+                .collect();
+            writeln!(output, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Rows of cells and, for indices, the colors they refer to
+fn matrix(image: &ImageBuffer, values: MatrixValues) -> (Option<Palette>, Vec<Vec<Cell>>) {
+    let rgb = image.to_rgb();
+    let colors = rgb
+        .data
+        .chunks_exact(3)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]]);
+    let (palette, cells): (_, Vec<Cell>) = match values {
+        MatrixValues::Hex => (None, colors.map(|color| Cell::Hex(to_hex(color))).collect()),
+        MatrixValues::Index => {
+            let palette = Palette::exact(&rgb.data, 3, usize::MAX).expect("images are not empty");
+            let indices = palette.indices(&rgb.data, 3);
+            (
+                Some(palette),
+                indices.into_iter().map(Cell::Index).collect(),
+            )
+        }
+    };
+    let mut cells = cells.into_iter();
+    let rows = (0..rgb.height)
+        .map(|_| cells.by_ref().take(rgb.width).collect())
+        .collect();
+    (palette, rows)
+}
+
+pub struct JsonEncoder {
+    pub values: MatrixValues,
+}
+
+impl ImageEncoder for JsonEncoder {
+    fn encode(&self, image: &ImageBuffer, output: &mut dyn Write) -> Result<(), EncodeError> {
+        let (palette, rows) = matrix(image, self.values);
+        let matrix = Matrix {
+            width: image.width,
+            height: image.height,
+            palette: palette.map(|palette| palette.colors().iter().map(|c| to_hex(*c)).collect()),
+            rows,
+        };
+        serde_json::to_writer(&mut *output, &matrix)?;
+        Ok(writeln!(output)?)
+    }
+}
+
+/// One line per row of the grid, without a header
+pub struct CsvEncoder {
+    pub values: MatrixValues,
+}
+
+impl ImageEncoder for CsvEncoder {
+    fn encode(&self, image: &ImageBuffer, output: &mut dyn Write) -> Result<(), EncodeError> {
+        let mut writer = csv::Writer::from_writer(output);
+        for row in matrix(image, self.values).1 {
+            writer.write_record(row.iter().map(Cell::to_string))?;
+        }
+        Ok(writer.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{
+        BitplaneEncoder, CHeaderEncoder, CsvEncoder, ImageEncoder, JpegBackend, JpegEncoder,
+        JsonEncoder, MatrixValues, OutputFormat, P8_SHEET_SIZE, P8Encoder, PixelFormat, XbmEncoder,
+        encode_jpeg_within,
+    };
+    use crate::types::ImageBuffer;
+    use clap::ValueEnum;
+
+    #[test]
+    fn test_output_path_per_format() {
+        let path = Path::new("out/horse.jpg");
+        assert_eq!(
+            OutputFormat::Jpeg.output_path(path),
+            Path::new("out/horse.jpg")
+        );
+        assert_eq!(
+            OutputFormat::Png.output_path(path),
+            Path::new("out/horse.png")
+        );
+        assert_eq!(
+            OutputFormat::Webp.output_path(path),
+            Path::new("out/horse.webp")
+        );
+    }
+
+    #[test]
+    fn test_jpeg_quality_fits_budget() {
+        let noise: Vec<u8> = (0..64 * 64 * 3)
+            .map(|i: u32| (i * 7919 % 251) as u8)
+            .collect();
+        let image = ImageBuffer::new(64, 64, 3, noise);
+        let full = encode_jpeg_within(&image, usize::MAX, false, JpegBackend::JpegEncoder)
+            .unwrap()
+            .unwrap();
+        let budget = full.len() / 2;
+        let reduced = encode_jpeg_within(&image, budget, false, JpegBackend::JpegEncoder)
+            .unwrap()
+            .unwrap();
+        assert!(reduced.len() <= budget);
+        assert!(
+            encode_jpeg_within(&image, 100, false, JpegBackend::JpegEncoder)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_jpeg_exif_thumbnail() {
+        let image = ImageBuffer::new(320, 200, 3, vec![90; 320 * 200 * 3]);
+        let mut data = Vec::new();
+        JpegEncoder {
+            quality: 90,
+            exif_thumbnail: true,
+            backend: JpegBackend::JpegEncoder,
+        }
+        .encode(&image, &mut data)
+        .unwrap();
+
+        // The thumbnail follows the EXIF header and is a complete JPEG of its own
+        let exif = data.windows(6).position(|w| w == b"Exif\0\0").unwrap() + 6;
+        let tiff = &data[exif..];
+        let field = |at: usize| u32::from_le_bytes(tiff[at..at + 4].try_into().unwrap()) as usize;
+        let (offset, length) = (field(14 + 2 + 12 + 8), field(14 + 2 + 24 + 8));
+        let mut decoder = jpeg_decoder::Decoder::new(&tiff[offset..offset + length]);
+        decoder.decode().unwrap();
+        let info = decoder.info().unwrap();
+        assert_eq!((info.width, info.height), (160, 100));
+
+        let mut decoder = jpeg_decoder::Decoder::new(&data[..]);
+        decoder.decode().unwrap();
+        assert_eq!(decoder.info().unwrap().width, 320);
+    }
+
+    #[cfg(feature = "mozjpeg")]
+    #[test]
+    fn test_mozjpeg_backend() {
+        let noise = (0..64 * 64 * 3)
+            .map(|i: u32| (i * 7919 % 251) as u8)
+            .collect();
+        let image = ImageBuffer::new(64, 64, 3, noise);
+        let encode = |backend| {
+            let mut data = Vec::new();
+            JpegEncoder {
+                quality: 80,
+                exif_thumbnail: true,
+                backend,
+            }
+            .encode(&image, &mut data)
+            .unwrap();
+            data
+        };
+        let data = encode(JpegBackend::Mozjpeg);
+        assert!(data.windows(6).any(|w| w == b"Exif\0\0"));
+        let mut decoder = jpeg_decoder::Decoder::new(&data[..]);
+        assert_eq!(decoder.decode().unwrap().len(), 64 * 64 * 3);
+        assert!(data.len() < encode(JpegBackend::JpegEncoder).len());
+
+        let gray = ImageBuffer::new(8, 8, 1, vec![128; 64]);
+        let mut data = Vec::new();
+        JpegEncoder {
+            quality: 80,
+            exif_thumbnail: false,
+            backend: JpegBackend::Mozjpeg,
+        }
+        .encode(&gray, &mut data)
+        .unwrap();
+        assert_eq!(
+            jpeg_decoder::Decoder::new(&data[..])
+                .decode()
+                .unwrap()
+                .len(),
+            64
+        );
+    }
+
+    #[test]
+    fn test_c_header_pixels() {
+        let image = ImageBuffer::new(2, 1, 3, vec![255, 0, 0, 0, 0, 255]);
+        let header = |pixel_format| {
+            let mut data = Vec::new();
+            CHeaderEncoder { pixel_format }
+                .encode(&image, &mut data)
+                .unwrap();
+            String::from_utf8(data).unwrap()
+        };
+        let rgb565 = header(PixelFormat::Rgb565);
+        assert!(rgb565.contains("#define IMAGE_WIDTH 2\n#define IMAGE_HEIGHT 1\n"));
+        assert!(rgb565.contains("    0xf8, 0x00, 0x00, 0x1f,\n};"));
+        assert!(header(PixelFormat::Rgb888).contains("    0xff, 0x00, 0x00, 0x00, 0x00, 0xff,\n"));
+    }
+
+    #[test]
+    fn test_packed_bits() {
+        // A 9 pixel row needs two bytes, the dark pixels are the first and the last
+        let mut pixels = vec![255; 9];
+        (pixels[0], pixels[8]) = (0, 0);
+        let image = ImageBuffer::new(9, 1, 1, pixels);
+        let mut data = Vec::new();
+        BitplaneEncoder.encode(&image, &mut data).unwrap();
+        assert_eq!(data, vec![0x7f, 0x00]);
+
+        let mut data = Vec::new();
+        XbmEncoder.encode(&image, &mut data).unwrap();
+        assert!(
+            String::from_utf8(data)
+                .unwrap()
+                .contains("    0x01, 0x01,\n};")
+        );
+    }
+
+    #[test]
+    fn test_p8_sprite_sheet() {
+        let image = ImageBuffer::new(2, 1, 3, vec![255, 0, 77, 250, 200, 170]);
+        let mut data = Vec::new();
+        P8Encoder.encode(&image, &mut data).unwrap();
+        let cart = String::from_utf8(data).unwrap();
+        let sheet: Vec<&str> = cart.lines().skip_while(|line| *line != "__gfx__").collect();
+        assert_eq!(sheet.len(), 1 + P8_SHEET_SIZE);
+        assert_eq!(sheet[1], format!("8f{}", "0".repeat(126)));
+
+        let too_large = ImageBuffer::new(129, 1, 1, vec![0; 129]);
+        assert!(P8Encoder.encode(&too_large, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_matrix_formats() {
+        let image = ImageBuffer::new(2, 2, 1, vec![255, 0, 0, 0]);
+        let encode = |encoder: &dyn ImageEncoder| {
+            let mut data = Vec::new();
+            encoder.encode(&image, &mut data).unwrap();
+            String::from_utf8(data).unwrap()
+        };
+        assert_eq!(
+            encode(&CsvEncoder {
+                values: MatrixValues::Hex
+            }),
+            "#ffffff,#000000\n#000000,#000000\n"
+        );
+        assert_eq!(
+            encode(&CsvEncoder {
+                values: MatrixValues::Index
+            }),
+            "1,0\n0,0\n"
+        );
+        assert_eq!(
+            encode(&JsonEncoder {
+                values: MatrixValues::Index
+            }),
+            "{\"width\":2,\"height\":2,\"palette\":[\"#000000\",\"#ffffff\"],\"rows\":[[1,0],[0,0]]}\n"
+        );
+    }
+
+    #[test]
+    fn test_encoders_write_into_memory() {
+        let image = ImageBuffer::new(2, 2, 3, vec![200; 12]);
+        for format in OutputFormat::value_variants() {
+            let mut data = Vec::new();
+            format.encoder().encode(&image, &mut data).unwrap();
+            let magic: &[u8] = match format {
+                OutputFormat::Jpeg => b"\xff\xd8",
+                OutputFormat::Png => b"\x89PNG",
+                OutputFormat::Webp => b"RIFF",
+                OutputFormat::Gif => b"GIF8",
+                OutputFormat::CHeader => b"// 2x2 RGB565",
+                OutputFormat::Xbm => b"#define image_width 2",
+                OutputFormat::Bitplane => &[0xc0, 0xc0],
+                OutputFormat::P8 => b"pico-8 cartridge",
+                OutputFormat::Json => b"{\"width\":2",
+                OutputFormat::Csv => b"#c8c8c8,#c8c8c8\n",
+            };
+            assert!(data.starts_with(magic), "{:?}", format);
+        }
+    }
 }
@@ -0,0 +1,92 @@
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+use tracing::info_span;
+
+use crate::cli::is_input_path;
+
+/// Larger responses are rejected instead of being buffered in memory
+const MAX_DOWNLOAD_SIZE: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum FetchError {
+    #[error("Request failed: {0}")]
+    Http(#[from] ureq::Error),
+
+    #[error("Response is not a supported image: {0}")]
+    UnsupportedType(String),
+}
+
+/**
+ * Downloads the image at `url` into memory. It is named after the last segment of the URL
+ * path, or after the content type of the response if that has no supported extension. */
+pub fn download(url: &str) -> Result<(PathBuf, Vec<u8>), FetchError> {
+    let _span = info_span!("download", url).entered();
+    let mut response = ureq::get(url).call()?;
+    let content_type = response.body().mime_type().map(String::from);
+    let Some(name) = file_name(url, content_type.as_deref()) else {
+        return Err(FetchError::UnsupportedType(
+            content_type.unwrap_or_else(|| String::from("unknown content type")),
+        ));
+    };
+    let data = response
+        .body_mut()
+        .with_config()
+        .limit(MAX_DOWNLOAD_SIZE)
+        .read_to_vec()?;
+    Ok((name, data))
+}
+
+fn file_name(url: &str, content_type: Option<&str>) -> Option<PathBuf> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let segment = Path::new(
+        path.trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default(),
+    );
+    if is_input_path(segment) {
+        return Some(segment.to_path_buf());
+    }
+
+    let extension = match content_type? {
+        "image/jpeg" => "jpeg",
+        "image/gif" => "gif",
+        #[cfg(feature = "radiance")]
+        "image/vnd.radiance" => "hdr",
+        #[cfg(feature = "exr")]
+        "image/x-exr" | "image/aces" => "exr",
+        _ => return None,
+    };
+    let stem = segment
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .filter(|stem| !stem.starts_with('.'))
+        .unwrap_or("download");
+    Some(PathBuf::from(format!("{}.{}", stem, extension)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::file_name;
+
+    #[test]
+    fn test_file_name_from_url() {
+        let name = file_name("https://example.com/photos/horse.JPG?size=large#top", None);
+        assert_eq!(name.as_deref(), Some(Path::new("horse.JPG")));
+    }
+
+    #[test]
+    fn test_file_name_from_content_type() {
+        let name = file_name("https://example.com/image/1234", Some("image/jpeg"));
+        assert_eq!(name.as_deref(), Some(Path::new("1234.jpeg")));
+        let name = file_name("https://example.com/", Some("image/gif"));
+        assert_eq!(name.as_deref(), Some(Path::new("example.gif")));
+        assert_eq!(
+            file_name("https://example.com/page", Some("text/html")),
+            None
+        );
+    }
+}
@@ -0,0 +1,757 @@
+use crate::color::{linear_to_srgb, luma, srgb_to_linear};
+use crate::types::Component;
+
+/// Builds a normalized 1D gaussian kernel covering three standard deviations
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let radius = (sigma * 3.0).ceil().max(1.0) as isize;
+    let mut kernel: Vec<f32> = (-radius..=radius)
+        .map(|x| (-((x * x) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    for weight in kernel.iter_mut() {
+        *weight /= sum;
+    }
+    kernel
+}
+
+/**
+ * Blurs the image with a gaussian of the given standard deviation.
+ * The kernel is separable, so it runs as a horizontal and a vertical pass.
+ * Pixels beyond the edges are clamped to the nearest edge pixel. */
+pub fn gaussian_blur(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    pixel_bytes: usize,
+    sigma: f32,
+) -> Vec<u8> {
+    if sigma <= 0.0 || width == 0 || height == 0 {
+        return pixels.to_vec();
+    }
+
+    let kernel = gaussian_kernel(sigma);
+    let radius = (kernel.len() / 2) as isize;
+
+    let mut horizontal = vec![0f32; pixels.len()];
+    for y in 0..height {
+        for x in 0..width {
+            for channel in 0..pixel_bytes {
+                let mut sum = 0.0;
+                for (k, weight) in kernel.iter().enumerate() {
+                    let sx = (x as isize + k as isize - radius).clamp(0, width as isize - 1);
+                    let idx = (y * width + sx as usize) * pixel_bytes + channel;
+                    sum += pixels[idx] as f32 * weight;
+                }
+                horizontal[(y * width + x) * pixel_bytes + channel] = sum;
+            }
+        }
+    }
+
+    let mut target_pixels = vec![0u8; pixels.len()];
+    for y in 0..height {
+        for x in 0..width {
+            for channel in 0..pixel_bytes {
+                let mut sum = 0.0;
+                for (k, weight) in kernel.iter().enumerate() {
+                    let sy = (y as isize + k as isize - radius).clamp(0, height as isize - 1);
+                    let idx = (sy as usize * width + x) * pixel_bytes + channel;
+                    sum += horizontal[idx] * weight;
+                }
+                target_pixels[(y * width + x) * pixel_bytes + channel] =
+                    sum.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    target_pixels
+}
+
+/// Sharpens the image by adding back `amount` times the difference to a blurred copy
+pub fn unsharp_mask(
+    pixels: &mut [u8],
+    width: usize,
+    height: usize,
+    pixel_bytes: usize,
+    sigma: f32,
+    amount: f32,
+) {
+    let blurred = gaussian_blur(pixels, width, height, pixel_bytes, sigma);
+    for (pixel, blurred) in pixels.iter_mut().zip(blurred) {
+        let detail = *pixel as f32 - blurred as f32;
+        *pixel = (*pixel as f32 + amount * detail).round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+/// Replaces every channel with the median of the square window of the given radius around it
+pub fn median_filter(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    pixel_bytes: usize,
+    radius: usize,
+) -> Vec<u8> {
+    let mut target_pixels = vec![0u8; pixels.len()];
+    let mut window: Vec<u8> = Vec::with_capacity((2 * radius + 1).pow(2));
+
+    for y in 0..height {
+        let y_start = y.saturating_sub(radius);
+        let y_end = (y + radius + 1).min(height);
+        for x in 0..width {
+            let x_start = x.saturating_sub(radius);
+            let x_end = (x + radius + 1).min(width);
+            for channel in 0..pixel_bytes {
+                window.clear();
+                for wy in y_start..y_end {
+                    for wx in x_start..x_end {
+                        window.push(pixels[(wy * width + wx) * pixel_bytes + channel]);
+                    }
+                }
+                let middle = window.len() / 2;
+                let (_, median, _) = window.select_nth_unstable(middle);
+                target_pixels[(y * width + x) * pixel_bytes + channel] = *median;
+            }
+        }
+    }
+    target_pixels
+}
+
+/// Filtering parameter of the non-local means weights, larger values smooth more
+const NLM_FILTER_STRENGTH: f32 = 10.0;
+
+/**
+ * A small non-local means denoiser. Every pixel becomes a weighted average of the
+ * pixels in its search window, weighted by how similar their surrounding 3x3 patches
+ * are. Unlike a plain blur this keeps edges, because patches across an edge differ. */
+pub fn non_local_means(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    pixel_bytes: usize,
+    search_radius: usize,
+) -> Vec<u8> {
+    let patch_radius: isize = 1;
+    let at = |x: isize, y: isize, channel: usize| -> f32 {
+        let x = x.clamp(0, width as isize - 1) as usize;
+        let y = y.clamp(0, height as isize - 1) as usize;
+        pixels[(y * width + x) * pixel_bytes + channel] as f32
+    };
+    let patch_size = ((2 * patch_radius + 1).pow(2) as usize * pixel_bytes) as f32;
+    let h2 = NLM_FILTER_STRENGTH * NLM_FILTER_STRENGTH;
+
+    let mut target_pixels = vec![0u8; pixels.len()];
+    let mut sums = vec![0f32; pixel_bytes];
+    let radius = search_radius as isize;
+
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            sums.iter_mut().for_each(|s| *s = 0.0);
+            let mut total_weight = 0.0;
+
+            for sy in (y - radius).max(0)..(y + radius + 1).min(height as isize) {
+                for sx in (x - radius).max(0)..(x + radius + 1).min(width as isize) {
+                    let mut distance = 0.0;
+                    for py in -patch_radius..=patch_radius {
+                        for px in -patch_radius..=patch_radius {
+                            for channel in 0..pixel_bytes {
+                                let d = at(x + px, y + py, channel) - at(sx + px, sy + py, channel);
+                                distance += d * d;
+                            }
+                        }
+                    }
+                    let weight = (-(distance / patch_size) / h2).exp();
+                    total_weight += weight;
+                    for (channel, sum) in sums.iter_mut().enumerate() {
+                        *sum += weight * at(sx, sy, channel);
+                    }
+                }
+            }
+
+            let idx = (y as usize * width + x as usize) * pixel_bytes;
+            for (channel, sum) in sums.iter().enumerate() {
+                target_pixels[idx + channel] = (sum / total_weight).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    target_pixels
+}
+
+/**
+ * Scales the image by `2^exposure` in linear light and then applies a gamma curve,
+ * gamma values above 1.0 lift the midtones. Runs through a lookup table since
+ * every 8 bit value maps to exactly one output. */
+pub fn adjust_exposure_gamma(pixels: &mut [u8], exposure: f32, gamma: f32) {
+    let scale = 2f32.powf(exposure);
+    let lut: Vec<u8> = (0..=255u8)
+        .map(|value| {
+            let linear = srgb_to_linear(value as f32 / 255.0) * scale;
+            let exposed = linear_to_srgb(linear.clamp(0.0, 1.0));
+            (exposed.powf(1.0 / gamma) * 255.0)
+                .round()
+                .clamp(0.0, 255.0) as u8
+        })
+        .collect();
+    for pixel in pixels.iter_mut() {
+        *pixel = lut[*pixel as usize];
+    }
+}
+
+/// Average color of an RGB or RGBA image in linear light, the light of the scene if the
+/// world is gray on average
+pub fn average_linear(pixels: &[u8], pixel_bytes: usize) -> [f32; 3] {
+    let mut sums = [0f64; 3];
+    for pixel in pixels.chunks_exact(pixel_bytes) {
+        for (sum, &value) in sums.iter_mut().zip(pixel) {
+            *sum += srgb_to_linear(value as f32 / 255.0) as f64;
+        }
+    }
+    let count = (pixels.len() / pixel_bytes).max(1) as f64;
+    sums.map(|sum| (sum / count) as f32)
+}
+
+/// Gains per channel that turn `light` into a neutral gray of the same green
+pub fn neutral_gains(light: [f32; 3]) -> [f32; 3] {
+    light.map(|c| if c > 0.0 { light[1] / c } else { 1.0 })
+}
+
+/// Multiplies the red, green and blue channels of an RGB or RGBA image in linear light
+pub fn apply_channel_gains(pixels: &mut [u8], pixel_bytes: usize, gains: [f32; 3]) {
+    let luts: Vec<[u8; 256]> = gains
+        .iter()
+        .map(|gain| {
+            std::array::from_fn(|value| {
+                let linear = srgb_to_linear(value as f32 / 255.0) * gain;
+                (linear_to_srgb(linear.clamp(0.0, 1.0)) * 255.0).round() as u8
+            })
+        })
+        .collect();
+    for pixel in pixels.chunks_exact_mut(pixel_bytes) {
+        for (value, lut) in pixel.iter_mut().zip(&luts) {
+            *value = lut[*value as usize];
+        }
+    }
+}
+
+/// Stops the channel gains change by at the ends of the temperature and tint sliders
+const GRADING_STOPS: f32 = 0.5;
+
+/**
+ * Channel gains of the creative temperature and tint sliders, both from -100 to 100.
+ * Positive temperatures warm the image by raising red and lowering blue, positive tints
+ * lower green towards magenta. */
+pub fn grading_gains(temperature: f32, tint: f32) -> [f32; 3] {
+    let stops = |amount: f32| 2f32.powf(GRADING_STOPS * amount / 100.0);
+    [stops(temperature), stops(-tint), stops(-temperature)]
+}
+
+/// Lowest and highest value after ignoring `clip` percent of the samples at either end
+fn percentile_range(histogram: &[u32; 256], clip: f32) -> (u8, u8) {
+    let total: u32 = histogram.iter().sum();
+    let skip = (total as f32 * clip / 100.0) as u32;
+    let first_beyond = |values: &mut dyn Iterator<Item = usize>| {
+        let mut seen = 0;
+        for value in values {
+            seen += histogram[value];
+            if seen > skip {
+                return value as u8;
+            }
+        }
+        0
+    };
+    (
+        first_beyond(&mut (0..256)),
+        first_beyond(&mut (0..256).rev()),
+    )
+}
+
+/**
+ * Stretches the values so the darkest and brightest ones, apart from `clip` percent at
+ * either end, become 0 and 255. Either every color channel is stretched on its own, which
+ * also removes color casts, or all of them by the range of the luma, which keeps the hues.
+ * Alpha is kept. */
+pub fn auto_levels(pixels: &mut [u8], pixel_bytes: usize, clip: f32, per_channel: bool) {
+    let color_channels = if pixel_bytes.is_multiple_of(2) {
+        pixel_bytes - 1
+    } else {
+        pixel_bytes
+    };
+    let ranges: Vec<(u8, u8)> = if per_channel {
+        (0..color_channels)
+            .map(|channel| {
+                let mut histogram = [0u32; 256];
+                for pixel in pixels.chunks_exact(pixel_bytes) {
+                    histogram[pixel[channel] as usize] += 1;
+                }
+                percentile_range(&histogram, clip)
+            })
+            .collect()
+    } else {
+        let mut histogram = [0u32; 256];
+        for pixel in pixels.chunks_exact(pixel_bytes) {
+            histogram[pixel_luma(pixel) as usize] += 1;
+        }
+        vec![percentile_range(&histogram, clip); color_channels]
+    };
+    let luts: Vec<[u8; 256]> = ranges
+        .into_iter()
+        .map(|(low, high)| {
+            let scale = 255.0 / high.saturating_sub(low).max(1) as f32;
+            std::array::from_fn(|value| {
+                ((value as f32 - low as f32) * scale)
+                    .round()
+                    .clamp(0.0, 255.0) as u8
+            })
+        })
+        .collect();
+    for pixel in pixels.chunks_exact_mut(pixel_bytes) {
+        for (value, lut) in pixel.iter_mut().zip(&luts) {
+            *value = lut[*value as usize];
+        }
+    }
+}
+
+/**
+ * Turns the image into its negative. With `luma_only` just the brightness is inverted and
+ * every color channel is shifted by the change of its luma, so hues roughly stay. Alpha is
+ * kept. */
+pub fn invert(pixels: &mut [u8], pixel_bytes: usize, luma_only: bool) {
+    let color_channels = if pixel_bytes.is_multiple_of(2) {
+        pixel_bytes - 1
+    } else {
+        pixel_bytes
+    };
+    for pixel in pixels.chunks_exact_mut(pixel_bytes) {
+        if luma_only {
+            let shift = 255 - 2 * pixel_luma(pixel) as i16;
+            for channel in &mut pixel[..color_channels] {
+                *channel = (*channel as i16 + shift).clamp(0, 255) as u8;
+            }
+        } else {
+            for channel in &mut pixel[..color_channels] {
+                *channel = 255 - *channel;
+            }
+        }
+    }
+}
+
+/// Number of tiles along each axis of adaptive equalization
+pub const CLAHE_TILES: usize = 8;
+/// Multiple of the average histogram bin above which adaptive equalization clips a tile
+const CLAHE_CLIP_LIMIT: f32 = 3.0;
+
+/// Luma of a pixel, the value itself for grayscale
+fn pixel_luma(pixel: &[u8]) -> u8 {
+    match pixel.len() {
+        1 | 2 => pixel[0],
+        _ => luma([pixel[0], pixel[1], pixel[2]]),
+    }
+}
+
+/// Maps every value to its position in the cumulative histogram, spread over 0 to 255
+fn equalization_lut(histogram: &mut [u32; 256], clip_limit: Option<u32>) -> [f32; 256] {
+    if let Some(limit) = clip_limit {
+        let mut excess = 0;
+        for count in histogram.iter_mut() {
+            excess += count.saturating_sub(limit);
+            *count = (*count).min(limit);
+        }
+        for (value, count) in histogram.iter_mut().enumerate() {
+            *count += excess / 256 + ((value as u32) < excess % 256) as u32;
+        }
+    }
+    let total: u32 = histogram.iter().sum();
+    let lowest = histogram
+        .iter()
+        .copied()
+        .find(|&count| count > 0)
+        .unwrap_or(0);
+    let mut lut = [0.0; 256];
+    let mut cumulative = 0;
+    for (value, count) in histogram.iter().enumerate() {
+        cumulative += count;
+        lut[value] = if total > lowest {
+            255.0 * (cumulative - lowest.min(cumulative)) as f32 / (total - lowest) as f32
+        } else {
+            value as f32
+        };
+    }
+    lut
+}
+
+/**
+ * Histogram equalization of the luma, so low contrast images use the whole range. With more
+ * than one tile per axis it is adaptive: every tile gets its own clipped histogram and the
+ * mappings of the nearest tiles are blended, which lifts local contrast without blowing up
+ * noise. Color channels are shifted by the change of their luma, alpha is kept. */
+pub fn equalize(pixels: &mut [u8], width: usize, height: usize, pixel_bytes: usize, tiles: usize) {
+    let (tiles_x, tiles_y) = (tiles.clamp(1, width.max(1)), tiles.clamp(1, height.max(1)));
+    let lumas: Vec<u8> = pixels.chunks_exact(pixel_bytes).map(pixel_luma).collect();
+    let mut histograms = vec![[0u32; 256]; tiles_x * tiles_y];
+    for (i, &value) in lumas.iter().enumerate() {
+        let (x, y) = (i % width, i / width);
+        histograms[(y * tiles_y / height) * tiles_x + x * tiles_x / width][value as usize] += 1;
+    }
+    let luts: Vec<[f32; 256]> = histograms
+        .iter_mut()
+        .map(|histogram| {
+            let clip_limit = (tiles > 1).then(|| {
+                let count: u32 = histogram.iter().sum();
+                (CLAHE_CLIP_LIMIT * count as f32 / 256.0).ceil().max(1.0) as u32
+            });
+            equalization_lut(histogram, clip_limit)
+        })
+        .collect();
+
+    // Position of a pixel between the centers of the tiles around it
+    let neighbors = |position: usize, size: usize, tiles: usize| {
+        let offset = ((position as f32 + 0.5) * tiles as f32 / size as f32 - 0.5)
+            .clamp(0.0, (tiles - 1) as f32);
+        let first = offset as usize;
+        (first, (first + 1).min(tiles - 1), offset - first as f32)
+    };
+    let color_channels = if pixel_bytes.is_multiple_of(2) {
+        pixel_bytes - 1
+    } else {
+        pixel_bytes
+    };
+    for (i, pixel) in pixels.chunks_exact_mut(pixel_bytes).enumerate() {
+        let (x0, x1, wx) = neighbors(i % width, width, tiles_x);
+        let (y0, y1, wy) = neighbors(i / width, height, tiles_y);
+        let value = lumas[i] as usize;
+        let at = |tx: usize, ty: usize| luts[ty * tiles_x + tx][value];
+        let top = at(x0, y0) * (1.0 - wx) + at(x1, y0) * wx;
+        let bottom = at(x0, y1) * (1.0 - wx) + at(x1, y1) * wx;
+        let shift = top * (1.0 - wy) + bottom * wy - value as f32;
+        for channel in &mut pixel[..color_channels] {
+            *channel = (*channel as f32 + shift).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/**
+ * Averages aligned layers of the same size sample by sample, like the blocks of average area
+ * downsampling, so the noise of several exposures of a scene cancels out. */
+pub fn stack(layers: &[&[u8]]) -> Vec<u8> {
+    let mut sums = vec![0f64; layers.first().map_or(0, |layer| layer.len())];
+    for layer in layers {
+        for (sum, &value) in sums.iter_mut().zip(layer.iter()) {
+            *sum += value.to_f64();
+        }
+    }
+    sums.into_iter()
+        .map(|sum| u8::from_mean(sum, layers.len()))
+        .collect()
+}
+
+/// Sums over rectangles in constant time, entry `(x, y)` holds the sum above and left of it
+struct SummedArea {
+    width: usize,
+    table: Vec<f64>,
+}
+
+impl SummedArea {
+    fn new(values: impl Iterator<Item = f64>, width: usize, height: usize) -> Self {
+        let mut table = vec![0f64; (width + 1) * (height + 1)];
+        for (i, value) in values.enumerate() {
+            let (x, y) = (i % width + 1, i / width + 1);
+            table[y * (width + 1) + x] =
+                value + table[(y - 1) * (width + 1) + x] + table[y * (width + 1) + x - 1]
+                    - table[(y - 1) * (width + 1) + x - 1];
+        }
+        SummedArea { width, table }
+    }
+
+    /// Sum of the values in columns `x_start..x_end` of rows `y_start..y_end`
+    fn sum(&self, (x_start, x_end): (usize, usize), (y_start, y_end): (usize, usize)) -> f64 {
+        let at = |x: usize, y: usize| self.table[y * (self.width + 1) + x];
+        at(x_end, y_end) - at(x_start, y_end) - at(x_end, y_start) + at(x_start, y_start)
+    }
+}
+
+/**
+ * Kuwahara filter, every pixel takes the mean color of the one of the four windows of
+ * `radius + 1` pixels square it is a corner of that has the least luma variance. Areas
+ * flatten into painterly patches while edges stay sharp. The window sums come from summed
+ * area tables, so larger radii cost no more. */
+pub fn kuwahara(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    pixel_bytes: usize,
+    radius: usize,
+) -> Vec<u8> {
+    let lumas: Vec<f64> = pixels
+        .chunks_exact(pixel_bytes)
+        .map(|pixel| pixel_luma(pixel) as f64)
+        .collect();
+    let luma = SummedArea::new(lumas.iter().copied(), width, height);
+    let squares = SummedArea::new(lumas.iter().map(|l| l * l), width, height);
+    let channels: Vec<SummedArea> = (0..pixel_bytes)
+        .map(|c| {
+            let values = pixels.iter().skip(c).step_by(pixel_bytes);
+            SummedArea::new(values.map(|&v| v as f64), width, height)
+        })
+        .collect();
+
+    let mut target_pixels = Vec::with_capacity(pixels.len());
+    for y in 0..height {
+        let above = (y.saturating_sub(radius), y + 1);
+        let below = (y, (y + radius + 1).min(height));
+        for x in 0..width {
+            let left = (x.saturating_sub(radius), x + 1);
+            let right = (x, (x + radius + 1).min(width));
+            let (columns, rows) = [(left, above), (right, above), (left, below), (right, below)]
+                .into_iter()
+                .min_by(|&(a_x, a_y), &(b_x, b_y)| {
+                    let variance = |columns: (usize, usize), rows: (usize, usize)| {
+                        let count = ((columns.1 - columns.0) * (rows.1 - rows.0)) as f64;
+                        let mean = luma.sum(columns, rows) / count;
+                        squares.sum(columns, rows) / count - mean * mean
+                    };
+                    variance(a_x, a_y).total_cmp(&variance(b_x, b_y))
+                })
+                .expect("there are four windows");
+            let count = (columns.1 - columns.0) * (rows.1 - rows.0);
+            for channel in &channels {
+                target_pixels.push(u8::from_mean(channel.sum(columns, rows), count));
+            }
+        }
+    }
+    target_pixels
+}
+
+/// Intensity buckets of the oil paint filter, fewer give broader strokes
+const OIL_PAINT_LEVELS: usize = 20;
+
+/**
+ * Oil paint filter, every pixel takes the mean color of the most common intensity bucket in
+ * the square window of the given radius around it. The window histogram slides along each
+ * row, only the columns entering and leaving it are counted. */
+pub fn oil_paint(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    pixel_bytes: usize,
+    radius: usize,
+) -> Vec<u8> {
+    let buckets: Vec<usize> = pixels
+        .chunks_exact(pixel_bytes)
+        .map(|pixel| pixel_luma(pixel) as usize * OIL_PAINT_LEVELS / 256)
+        .collect();
+    // Per bucket the pixel count followed by the channel sums
+    let stride = 1 + pixel_bytes;
+    let mut histogram = vec![0u32; OIL_PAINT_LEVELS * stride];
+
+    let mut target_pixels = Vec::with_capacity(pixels.len());
+    for y in 0..height {
+        let rows = y.saturating_sub(radius)..(y + radius + 1).min(height);
+        histogram.fill(0);
+        let count_column = |x: usize, sign: i32, histogram: &mut [u32]| {
+            for row in rows.clone() {
+                let i = row * width + x;
+                let entry = &mut histogram[buckets[i] * stride..][..stride];
+                entry[0] = entry[0].wrapping_add_signed(sign);
+                for (sum, &value) in entry[1..].iter_mut().zip(&pixels[i * pixel_bytes..]) {
+                    *sum = sum.wrapping_add_signed(sign * value as i32);
+                }
+            }
+        };
+        for x in 0..radius.min(width) {
+            count_column(x, 1, &mut histogram);
+        }
+        for x in 0..width {
+            if x + radius < width {
+                count_column(x + radius, 1, &mut histogram);
+            }
+            if x > radius {
+                count_column(x - radius - 1, -1, &mut histogram);
+            }
+            let entry = histogram
+                .chunks_exact(stride)
+                .max_by_key(|entry| entry[0])
+                .expect("there is at least one bucket");
+            for &sum in &entry[1..] {
+                target_pixels.push(u8::from_mean(sum as f64, entry[0] as usize));
+            }
+        }
+    }
+    target_pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CLAHE_TILES, adjust_exposure_gamma, apply_channel_gains, auto_levels, average_linear,
+        equalize, gaussian_blur, grading_gains, invert, kuwahara, median_filter, neutral_gains,
+        non_local_means, oil_paint, stack, unsharp_mask,
+    };
+
+    #[test]
+    fn test_gaussian_blur_keeps_flat_image() {
+        let pixels = vec![77u8; 5 * 5 * 3];
+        let blurred = gaussian_blur(&pixels, 5, 5, 3, 1.5);
+        assert_eq!(blurred, pixels);
+    }
+
+    #[test]
+    fn test_gaussian_blur_softens_edges() {
+        let pixels: Vec<u8> = (0..8).map(|x| if x < 4 { 0 } else { 255 }).collect();
+        let blurred = gaussian_blur(&pixels, 8, 1, 1, 1.0);
+        assert!(blurred[3] > 0);
+        assert!(blurred[4] < 255);
+    }
+
+    #[test]
+    fn test_unsharp_mask_increases_contrast() {
+        let mut pixels: Vec<u8> = (0..8).map(|x| if x < 4 { 100 } else { 150 }).collect();
+        unsharp_mask(&mut pixels, 8, 1, 1, 1.0, 1.0);
+        assert!(pixels[3] < 100);
+        assert!(pixels[4] > 150);
+    }
+
+    #[test]
+    fn test_median_filter_removes_speckle() {
+        let mut pixels = vec![50u8; 5 * 5];
+        pixels[12] = 255;
+        let denoised = median_filter(&pixels, 5, 5, 1, 1);
+        assert!(denoised.iter().all(|&p| p == 50));
+    }
+
+    #[test]
+    fn test_non_local_means_keeps_edges() {
+        // A hard vertical edge with a bit of noise on both sides
+        let pixels: Vec<u8> = (0..64)
+            .map(|i| {
+                let noise = if i % 3 == 0 { 6 } else { 0 };
+                if i % 8 < 4 { 20 + noise } else { 220 - noise }
+            })
+            .collect();
+        let denoised = non_local_means(&pixels, 8, 8, 1, 2);
+        for y in 0..8 {
+            assert!(denoised[y * 8 + 3] < 40);
+            assert!(denoised[y * 8 + 4] > 200);
+        }
+    }
+
+    #[test]
+    fn test_adjust_exposure_gamma() {
+        let source: Vec<u8> = (0..=255).collect();
+
+        let mut unchanged = source.clone();
+        adjust_exposure_gamma(&mut unchanged, 0.0, 1.0);
+        assert_eq!(unchanged, source);
+
+        // One stop doubles linear light, so midtones get brighter and white stays white
+        let mut brighter = source.clone();
+        adjust_exposure_gamma(&mut brighter, 1.0, 1.0);
+        assert!(brighter[128] > 128);
+        assert_eq!(brighter[255], 255);
+
+        let mut lifted = source.clone();
+        adjust_exposure_gamma(&mut lifted, 0.0, 2.2);
+        assert!(lifted[64] > 64);
+        assert_eq!(lifted[0], 0);
+    }
+
+    #[test]
+    fn test_equalize_stretches_contrast() {
+        // A dull gradient between 100 and 131 with an opaque alpha channel
+        let source: Vec<u8> = (0..64).flat_map(|i| [100 + i / 2, 255]).collect();
+        let mut global = source.clone();
+        equalize(&mut global, 8, 8, 2, 1);
+        assert_eq!(global[0], 0);
+        assert_eq!(global[126], 255);
+        assert!(global.chunks_exact(2).all(|pixel| pixel[1] == 255));
+        assert!(global.chunks_exact(2).is_sorted_by_key(|pixel| pixel[0]));
+
+        // The tiles need enough pixels for their histograms to mean something
+        let source: Vec<u8> = (0..128 * 128).map(|i| 100 + (i % 128 / 4) as u8).collect();
+        let mut adaptive = source.clone();
+        equalize(&mut adaptive, 128, 128, 1, CLAHE_TILES);
+        let range = |pixels: &[u8]| pixels.iter().max().unwrap() - pixels.iter().min().unwrap();
+        assert!(range(&adaptive) > range(&source));
+    }
+
+    #[test]
+    fn test_auto_levels() {
+        // Red spans 50 to 150 with one outlier, green 100 to 200
+        let mut source: Vec<u8> = (0..=100).flat_map(|i| [50 + i, 100 + i, 0]).collect();
+        source.extend([255, 150, 0]);
+
+        let mut per_channel = source.clone();
+        auto_levels(&mut per_channel, 3, 1.0, true);
+        assert_eq!(&per_channel[..3], &[0, 0, 0]);
+        assert_eq!(&per_channel[300..303], &[255, 255, 0]);
+
+        // One range for all channels keeps the difference between red and green
+        let mut luma = source.clone();
+        auto_levels(&mut luma, 3, 0.0, false);
+        assert!(luma[1] > luma[0]);
+        assert!(luma.chunks_exact(3).all(|pixel| pixel[2] == 0));
+    }
+
+    #[test]
+    fn test_gray_world_white_balance() {
+        // An orange cast over a gray and a blue patch
+        let mut pixels = vec![200, 150, 100, 255, 150, 110, 160, 255];
+        let gains = neutral_gains(average_linear(&pixels, 4));
+        assert_eq!(gains[1], 1.0);
+        assert!(gains[0] < gains[2]);
+        apply_channel_gains(&mut pixels, 4, gains);
+        let average = average_linear(&pixels, 4);
+        assert!((average[0] - average[2]).abs() < 0.01);
+        assert_eq!((pixels[3], pixels[7]), (255, 255));
+    }
+
+    #[test]
+    fn test_grading_gains() {
+        assert_eq!(grading_gains(0.0, 0.0), [1.0; 3]);
+        let [red, green, blue] = grading_gains(100.0, -100.0);
+        assert_eq!(red, 2f32.sqrt());
+        assert_eq!(green, red);
+        assert_eq!(blue, 1.0 / red);
+    }
+
+    #[test]
+    fn test_invert() {
+        let mut full = vec![0, 100, 255, 128];
+        invert(&mut full, 4, false);
+        assert_eq!(full, vec![255, 155, 0, 128]);
+
+        // Gray turns into its negative either way, colors keep their differences
+        let mut luma = vec![40, 40, 40, 200, 100, 100];
+        invert(&mut luma, 3, true);
+        assert_eq!(&luma[..3], &[215, 215, 215]);
+        assert_eq!(luma[3] - luma[4], 100);
+    }
+
+    #[test]
+    fn test_stack() {
+        let (a, b, c) = ([0u8, 100, 255], [30, 100, 255], [60, 103, 254]);
+        assert_eq!(stack(&[&a, &b, &c]), vec![30, 101, 254]);
+        assert_eq!(stack(&[&a]), a.to_vec());
+    }
+
+    #[test]
+    fn test_kuwahara_keeps_edges() {
+        // A hard vertical edge with a little noise on both sides
+        let pixels: Vec<u8> = (0..8 * 8)
+            .map(|i| if i % 8 < 4 { 40 } else { 200 } + (i % 3) as u8)
+            .collect();
+        let filtered = kuwahara(&pixels, 8, 8, 1, 2);
+        for row in filtered.chunks_exact(8) {
+            assert!(row[..4].iter().all(|&v| (40..43).contains(&v)));
+            assert!(row[4..].iter().all(|&v| (200..203).contains(&v)));
+        }
+    }
+
+    #[test]
+    fn test_oil_paint_picks_the_common_color() {
+        // A lone bright pixel in a dark field is painted over
+        let mut pixels = vec![30u8; 5 * 5 * 3];
+        pixels[12 * 3..13 * 3].copy_from_slice(&[250, 250, 250]);
+        let painted = oil_paint(&pixels, 5, 5, 3, 1);
+        assert_eq!(painted, vec![30u8; 5 * 5 * 3]);
+
+        let flat = vec![128u8; 4 * 3];
+        assert_eq!(oil_paint(&flat, 4, 3, 1, 2), flat);
+    }
+}
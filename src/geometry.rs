@@ -0,0 +1,428 @@
+use crate::color::luma;
+use crate::types::ImageBuffer;
+
+/// Rectangular part of an image in pixels
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    pub fn full(image: &ImageBuffer) -> Self {
+        Region {
+            x: 0,
+            y: 0,
+            width: image.width,
+            height: image.height,
+        }
+    }
+
+    /// Smallest region containing both
+    pub fn union(self, other: Region) -> Self {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Region {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        }
+    }
+}
+
+/// Corners of a quadrilateral in source pixels, clockwise from the top left
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quad(pub [(f32, f32); 4]);
+
+/**
+ * Finds the content inside a uniform border. The border color is taken from the top left
+ * pixel, rows and columns at the edges are trimmed while every pixel in them is within
+ * `tolerance` of it in each channel. Uniform images are returned whole. */
+pub fn content_bounds(image: &ImageBuffer, tolerance: u8) -> Region {
+    let channels = image.channels;
+    if image.data.is_empty() {
+        return Region::full(image);
+    }
+    let border = &image.data[..channels];
+    let is_border = |x: usize, y: usize| {
+        let idx = (y * image.width + x) * channels;
+        image.data[idx..idx + channels]
+            .iter()
+            .zip(border)
+            .all(|(&a, &b)| a.abs_diff(b) <= tolerance)
+    };
+    let row_is_border = |y: usize| (0..image.width).all(|x| is_border(x, y));
+
+    let Some(top) = (0..image.height).find(|&y| !row_is_border(y)) else {
+        return Region::full(image);
+    };
+    let bottom = (top..image.height)
+        .rfind(|&y| !row_is_border(y))
+        .unwrap_or(top);
+    let column_is_border = |x: usize| (top..=bottom).all(|y| is_border(x, y));
+    let left = (0..image.width)
+        .find(|&x| !column_is_border(x))
+        .unwrap_or(0);
+    let right = (left..image.width)
+        .rfind(|&x| !column_is_border(x))
+        .unwrap_or(left);
+
+    Region {
+        x: left,
+        y: top,
+        width: right - left + 1,
+        height: bottom - top + 1,
+    }
+}
+
+/// Largest `width` x `height` size with the aspect ratio of `aspect` that fits inside
+fn inner_size(width: usize, height: usize, aspect: (usize, usize)) -> (usize, usize) {
+    let (aspect_width, aspect_height) = (aspect.0.max(1), aspect.1.max(1));
+    if width * aspect_height >= height * aspect_width {
+        (
+            (height * aspect_width / aspect_height).clamp(1, width.max(1)),
+            height,
+        )
+    } else {
+        (
+            width,
+            (width * aspect_height / aspect_width).clamp(1, height.max(1)),
+        )
+    }
+}
+
+/// Smallest size with the aspect ratio of `aspect` that contains `width` x `height`
+fn outer_size(width: usize, height: usize, aspect: (usize, usize)) -> (usize, usize) {
+    let (aspect_width, aspect_height) = (aspect.0.max(1), aspect.1.max(1));
+    if width * aspect_height >= height * aspect_width {
+        (width, (width * aspect_height).div_ceil(aspect_width))
+    } else {
+        ((height * aspect_width).div_ceil(aspect_height), height)
+    }
+}
+
+/// Largest region with the aspect ratio `aspect` as width and height in the center of the image
+pub fn center_region(image: &ImageBuffer, aspect: (usize, usize)) -> Region {
+    let (width, height) = inner_size(image.width, image.height, aspect);
+    Region {
+        x: (image.width - width) / 2,
+        y: (image.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/**
+ * Largest window with the aspect ratio `aspect` that contains the most detail, measured as
+ * the differences between neighboring pixels summed over all images. The images need to have
+ * the same size, animations pass all frames so the window does not jump around. Without
+ * any detail the center is kept. */
+pub fn salient_region(images: &[&ImageBuffer], aspect: (usize, usize)) -> Region {
+    let first = images[0];
+    let (width, height, channels) = (first.width, first.height, first.channels);
+    let (window_width, window_height) = inner_size(width, height, aspect);
+    // The window spans the full height and slides sideways or the other way around
+    let horizontal = window_height == height;
+    let (length, window) = match horizontal {
+        true => (width, window_width),
+        false => (height, window_height),
+    };
+
+    // Detail per column when sliding sideways and per row otherwise
+    let mut profile = vec![0u64; length];
+    for image in images {
+        let pixel = |x: usize, y: usize| {
+            let idx = (y * width + x) * channels;
+            &image.data[idx..idx + channels]
+        };
+        for y in 0..height {
+            for x in 0..width {
+                let mut detail = 0;
+                for (nx, ny) in [(x + 1, y), (x, y + 1)] {
+                    if nx < width && ny < height {
+                        detail += pixel(x, y)
+                            .iter()
+                            .zip(pixel(nx, ny))
+                            .map(|(&a, &b)| a.abs_diff(b) as u64)
+                            .sum::<u64>();
+                    }
+                }
+                profile[if horizontal { x } else { y }] += detail;
+            }
+        }
+    }
+
+    let center = (length - window) / 2;
+    let mut best = (profile[center..center + window].iter().sum::<u64>(), center);
+    let mut sum: u64 = profile[..window].iter().sum();
+    for start in 0..=length - window {
+        if start > 0 {
+            sum = sum + profile[start + window - 1] - profile[start - 1];
+        }
+        if sum > best.0 {
+            best = (sum, start);
+        }
+    }
+    let (x, y) = if horizontal { (best.1, 0) } else { (0, best.1) };
+    Region {
+        x,
+        y,
+        width: window_width,
+        height: window_height,
+    }
+}
+
+/// Copies the region out of the image, it has to lie within its bounds
+pub fn crop(image: &ImageBuffer, region: Region) -> ImageBuffer {
+    let channels = image.channels;
+    let mut data = Vec::with_capacity(region.width * region.height * channels);
+    for y in region.y..region.y + region.height {
+        let start = (y * image.width + region.x) * channels;
+        data.extend_from_slice(&image.data[start..start + region.width * channels]);
+    }
+    ImageBuffer::new(region.width, region.height, channels, data)
+}
+
+/// Centers the image on a `width` x `height` canvas filled with `color`, which must not be
+/// smaller than the image
+pub fn pad(image: &ImageBuffer, width: usize, height: usize, color: [u8; 3]) -> ImageBuffer {
+    let channels = image.channels;
+    let fill = match channels {
+        1 => vec![luma(color)],
+        _ => color.to_vec(),
+    };
+    let mut data = fill.repeat(width * height);
+    let (left, top) = ((width - image.width) / 2, (height - image.height) / 2);
+    let row = image.width * channels;
+    for (y, source) in image.data.chunks_exact(row.max(1)).enumerate() {
+        let start = ((top + y) * width + left) * channels;
+        data[start..start + row].copy_from_slice(source);
+    }
+    ImageBuffer::new(width, height, channels, data)
+}
+
+/// Pads the image evenly until it has the aspect ratio `aspect` as width and height
+pub fn pad_to_aspect(image: &ImageBuffer, aspect: (usize, usize), color: [u8; 3]) -> ImageBuffer {
+    let (width, height) = outer_size(image.width, image.height, aspect);
+    pad(image, width, height, color)
+}
+
+/**
+ * Scales the image with nearest neighbor sampling to the largest size that fits into
+ * `width` x `height` with its aspect ratio, so pixelated blocks stay sharp. */
+pub fn scale_to_fit(image: &ImageBuffer, width: usize, height: usize) -> ImageBuffer {
+    let scale = (width as f64 / image.width as f64).min(height as f64 / image.height as f64);
+    let target_width = ((image.width as f64 * scale).round() as usize).clamp(1, width);
+    let target_height = ((image.height as f64 * scale).round() as usize).clamp(1, height);
+    let channels = image.channels;
+    let mut data = Vec::with_capacity(target_width * target_height * channels);
+    for y in 0..target_height {
+        let source_y = y * image.height / target_height;
+        for x in 0..target_width {
+            let index = (source_y * image.width + x * image.width / target_width) * channels;
+            data.extend_from_slice(&image.data[index..index + channels]);
+        }
+    }
+    ImageBuffer::new(target_width, target_height, channels, data)
+}
+
+/**
+ * Warps the quadrilateral `quad` of the image onto a rectangle, e.g. a poster photographed
+ * at an angle. The rectangle gets the longer of each pair of opposite edges as its size,
+ * pixels are sampled bilinearly and corners outside the image repeat its edge pixels. */
+pub fn warp_perspective(image: &ImageBuffer, quad: &Quad) -> ImageBuffer {
+    let [top_left, top_right, bottom_right, bottom_left] = quad.0;
+    let distance = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0).hypot(a.1 - b.1);
+    let width = distance(top_left, top_right).max(distance(bottom_left, bottom_right));
+    let height = distance(top_left, bottom_left).max(distance(top_right, bottom_right));
+    let (width, height) = (
+        width.round().max(1.0) as usize,
+        height.round().max(1.0) as usize,
+    );
+
+    let [a, b, c, d, e, f, g, h] = square_to_quad(quad);
+    let mut data = Vec::with_capacity(width * height * image.channels);
+    for y in 0..height {
+        let v = (y as f64 + 0.5) / height as f64;
+        for x in 0..width {
+            let u = (x as f64 + 0.5) / width as f64;
+            let w = g * u + h * v + 1.0;
+            // Pixel centers sit half a pixel into the pixel
+            let source_x = (a * u + b * v + c) / w - 0.5;
+            let source_y = (d * u + e * v + f) / w - 0.5;
+            sample_bilinear(image, source_x, source_y, &mut data);
+        }
+    }
+    ImageBuffer::new(width, height, image.channels, data)
+}
+
+/**
+ * Coefficients of the projective map from the unit square onto the quad, `(0, 0)` goes to
+ * the top left and `(1, 1)` to the bottom right corner. A point `(u, v)` maps to
+ * `((a u + b v + c) / w, (d u + e v + f) / w)` with `w = g u + h v + 1`, after Heckbert's
+ * "Fundamentals of Texture Mapping and Image Warping". */
+fn square_to_quad(quad: &Quad) -> [f64; 8] {
+    let [(x0, y0), (x1, y1), (x2, y2), (x3, y3)] = quad.0.map(|(x, y)| (x as f64, y as f64));
+    let (dx1, dx2, dx3) = (x1 - x2, x3 - x2, x0 - x1 + x2 - x3);
+    let (dy1, dy2, dy3) = (y1 - y2, y3 - y2, y0 - y1 + y2 - y3);
+    let det = dx1 * dy2 - dx2 * dy1;
+    let g = (dx3 * dy2 - dx2 * dy3) / det;
+    let h = (dx1 * dy3 - dx3 * dy1) / det;
+    [
+        x1 - x0 + g * x1,
+        x3 - x0 + h * x3,
+        x0,
+        y1 - y0 + g * y1,
+        y3 - y0 + h * y3,
+        y0,
+        g,
+        h,
+    ]
+}
+
+/// Appends the channels at a fractional position, clamped to the image
+fn sample_bilinear(image: &ImageBuffer, x: f64, y: f64, out: &mut Vec<u8>) {
+    let x = x.clamp(0.0, (image.width - 1) as f64);
+    let y = y.clamp(0.0, (image.height - 1) as f64);
+    let (left, top) = (x as usize, y as usize);
+    let (right, bottom) = (
+        (left + 1).min(image.width - 1),
+        (top + 1).min(image.height - 1),
+    );
+    let (fx, fy) = (x - left as f64, y - top as f64);
+    let pixel = |x: usize, y: usize| &image.data[(y * image.width + x) * image.channels..];
+    let (a, b) = (pixel(left, top), pixel(right, top));
+    let (c, d) = (pixel(left, bottom), pixel(right, bottom));
+    for channel in 0..image.channels {
+        let upper = a[channel] as f64 * (1.0 - fx) + b[channel] as f64 * fx;
+        let lower = c[channel] as f64 * (1.0 - fx) + d[channel] as f64 * fx;
+        out.push((upper * (1.0 - fy) + lower * fy).round() as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Quad, Region, center_region, content_bounds, crop, pad_to_aspect, salient_region,
+        scale_to_fit, warp_perspective,
+    };
+    use crate::types::ImageBuffer;
+
+    /// White 5x4 image with a gray 2x2 square at (1, 1)
+    fn framed() -> ImageBuffer {
+        let mut data = vec![255; 5 * 4];
+        for (x, y) in [(1, 1), (2, 1), (1, 2), (2, 2)] {
+            data[y * 5 + x] = 100;
+        }
+        ImageBuffer::new(5, 4, 1, data)
+    }
+
+    #[test]
+    fn test_content_bounds() {
+        let region = content_bounds(&framed(), 0);
+        assert_eq!(
+            region,
+            Region {
+                x: 1,
+                y: 1,
+                width: 2,
+                height: 2,
+            }
+        );
+        assert_eq!(crop(&framed(), region).data, vec![100; 4]);
+    }
+
+    #[test]
+    fn test_content_bounds_tolerance() {
+        let mut image = framed();
+        image.data[4] = 250;
+        assert_eq!(content_bounds(&image, 0).width, 4);
+        assert_eq!(content_bounds(&image, 8).width, 2);
+
+        let uniform = ImageBuffer::new(3, 3, 1, vec![7; 9]);
+        assert_eq!(content_bounds(&uniform, 0), Region::full(&uniform));
+    }
+
+    #[test]
+    fn test_pad_to_aspect() {
+        let image = ImageBuffer::new(1, 3, 3, vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let padded = pad_to_aspect(&image, (1, 1), [255, 0, 0]);
+        assert_eq!((padded.width, padded.height), (3, 3));
+        assert_eq!(&padded.data[..6], &[255, 0, 0, 1, 2, 3]);
+        assert_eq!(&padded.data[21..], &[7, 8, 9, 255, 0, 0]);
+
+        let gray = pad_to_aspect(&ImageBuffer::new(2, 1, 1, vec![9, 9]), (1, 1), [255; 3]);
+        assert_eq!(gray.data, vec![9, 9, 255, 255]);
+
+        let wide = pad_to_aspect(&ImageBuffer::new(2, 2, 1, vec![9; 4]), (2, 1), [0; 3]);
+        assert_eq!(wide.data, vec![0, 9, 9, 0, 0, 9, 9, 0]);
+    }
+
+    #[test]
+    fn test_center_region() {
+        let image = ImageBuffer::new(5, 2, 1, (0..10).collect());
+        let region = center_region(&image, (1, 1));
+        assert_eq!((region.x, region.y, region.width), (1, 0, 2));
+        assert_eq!(crop(&image, region).data, vec![1, 2, 6, 7]);
+
+        let wide = center_region(&image, (4, 1));
+        assert_eq!((wide.x, wide.y, wide.width, wide.height), (0, 0, 5, 1));
+    }
+
+    #[test]
+    fn test_salient_region_follows_detail() {
+        // Flat 6x2 image with a checkered patch on the right
+        let mut data = vec![128; 12];
+        for (x, y) in [(4, 0), (5, 1)] {
+            data[y * 6 + x] = 0;
+        }
+        let image = ImageBuffer::new(6, 2, 1, data);
+        // Both windows containing the patch column have the same detail
+        assert!((3..=4).contains(&salient_region(&[&image], (1, 1)).x));
+
+        let flat = ImageBuffer::new(6, 2, 1, vec![128; 12]);
+        assert_eq!(
+            salient_region(&[&flat], (1, 1)),
+            center_region(&flat, (1, 1))
+        );
+    }
+
+    #[test]
+    fn test_scale_to_fit() {
+        let image = ImageBuffer::new(2, 1, 1, vec![10, 20]);
+        let scaled = scale_to_fit(&image, 8, 8);
+        assert_eq!((scaled.width, scaled.height), (8, 4));
+        assert_eq!(&scaled.data[..8], &[10, 10, 10, 10, 20, 20, 20, 20]);
+
+        let large = ImageBuffer::new(8, 4, 1, (0..32).collect());
+        let scaled = scale_to_fit(&large, 4, 4);
+        assert_eq!((scaled.width, scaled.height), (4, 2));
+        assert_eq!(scaled.data, vec![0, 2, 4, 6, 16, 18, 20, 22]);
+    }
+
+    #[test]
+    fn test_warp_perspective() {
+        // The identity quad gives back the image
+        let image = framed();
+        let quad = Quad([(0.0, 0.0), (5.0, 0.0), (5.0, 4.0), (0.0, 4.0)]);
+        assert_eq!(warp_perspective(&image, &quad), image);
+
+        // A trapezoid narrowing towards the top is stretched back into a rectangle
+        let inside = |x: f32, y: f32| (x - 10.0).abs() <= 5.0 + y / 4.0;
+        let data = (0..20 * 20)
+            .map(|i| inside((i % 20) as f32 + 0.5, (i / 20) as f32 + 0.5) as u8 * 255)
+            .collect();
+        let image = ImageBuffer::new(20, 20, 1, data);
+        let quad = Quad([(5.0, 0.0), (15.0, 0.0), (20.0, 20.0), (0.0, 20.0)]);
+        let warped = warp_perspective(&image, &quad);
+        assert_eq!((warped.width, warped.height), (20, 21));
+        // Only the outer columns blend with the stair steps of the trapezoid
+        for row in warped.data.chunks_exact(20) {
+            assert!(row[1..19].iter().all(|&v| v > 200));
+        }
+    }
+}
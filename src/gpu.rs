@@ -0,0 +1,292 @@
+use std::sync::{OnceLock, mpsc};
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use crate::interpolation::{InterpolationAlgorithm, InterpolationError};
+use crate::types::ImageBuffer;
+
+const SHADER: &str = include_str!("shaders/interpolation.wgsl");
+
+/// Side length of the compute workgroups, has to match `@workgroup_size` in the shader
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Sampling used by the compute shaders, matches the CPU algorithms
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GpuSampling {
+    AverageArea,
+    Nearest,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct Params {
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+    channels: u32,
+    mode: u32,
+    _pad: [u32; 2],
+}
+
+/// Device and compiled pipelines, created once per process and shared by all images
+pub struct GpuContext {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    downsample: wgpu::ComputePipeline,
+    upsample: wgpu::ComputePipeline,
+}
+
+fn gpu_error(error: impl std::fmt::Display) -> InterpolationError {
+    InterpolationError::Gpu(error.to_string())
+}
+
+impl GpuContext {
+    /// Batch and animation runs reuse the same device instead of setting it up per image
+    pub fn shared() -> Result<&'static GpuContext, InterpolationError> {
+        static CONTEXT: OnceLock<Result<GpuContext, String>> = OnceLock::new();
+        CONTEXT
+            .get_or_init(|| pollster::block_on(GpuContext::new()).map_err(|e| e.to_string()))
+            .as_ref()
+            .map_err(|e| InterpolationError::Gpu(e.clone()))
+    }
+
+    async fn new() -> Result<Self, InterpolationError> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .await
+            .map_err(gpu_error)?;
+        // Large images need more than the default storage buffer size
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                required_limits: adapter.limits(),
+                ..Default::default()
+            })
+            .await
+            .map_err(gpu_error)?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("interpolation"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: None,
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let downsample = pipeline("downsample");
+        let upsample = pipeline("upsample");
+
+        Ok(GpuContext {
+            device,
+            queue,
+            downsample,
+            upsample,
+        })
+    }
+
+    /// Runs one resampling pass and reads the result back into `target_pixels`
+    fn dispatch(
+        &self,
+        pipeline: &wgpu::ComputePipeline,
+        src_pixels: &[u8],
+        params: Params,
+        target_pixels: &mut Vec<u8>,
+    ) -> Result<(), InterpolationError> {
+        let widened: Vec<u32> = src_pixels.iter().map(|&v| v as u32).collect();
+        let src_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("source"),
+                contents: bytemuck::cast_slice(&widened),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+        let params_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("params"),
+                contents: bytemuck::bytes_of(&params),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        let size = (params.dst_width * params.dst_height * params.channels) as u64 * 4;
+        let dst_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("target"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: src_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: dst_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(
+                params.dst_width.div_ceil(WORKGROUP_SIZE),
+                params.dst_height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+        encoder.copy_buffer_to_buffer(&dst_buffer, 0, &staging_buffer, 0, size);
+        self.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = mpsc::channel();
+        staging_buffer.map_async(wgpu::MapMode::Read, .., move |result| {
+            let _ = sender.send(result);
+        });
+        self.device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .map_err(gpu_error)?;
+        receiver.recv().map_err(gpu_error)?.map_err(gpu_error)?;
+
+        let view = staging_buffer.get_mapped_range(..).map_err(gpu_error)?;
+        target_pixels.clear();
+        target_pixels.extend(
+            bytemuck::cast_slice::<u8, u32>(&view)
+                .iter()
+                .map(|&v| v as u8),
+        );
+        Ok(())
+    }
+}
+
+/// Runs the resampling passes as compute shaders, quantization stays on the CPU
+pub struct GpuInterpolation {
+    context: &'static GpuContext,
+    sampling: GpuSampling,
+}
+
+impl GpuInterpolation {
+    pub fn new(sampling: GpuSampling) -> Result<Self, InterpolationError> {
+        Ok(GpuInterpolation {
+            context: GpuContext::shared()?,
+            sampling,
+        })
+    }
+
+    fn params(
+        &self,
+        src_width: usize,
+        src_height: usize,
+        target_width: usize,
+        target_height: usize,
+        pixel_bytes: usize,
+    ) -> Params {
+        Params {
+            src_width: src_width as u32,
+            src_height: src_height as u32,
+            dst_width: target_width as u32,
+            dst_height: target_height as u32,
+            channels: pixel_bytes as u32,
+            mode: match self.sampling {
+                GpuSampling::AverageArea => 0,
+                GpuSampling::Nearest => 1,
+            },
+            _pad: [0; 2],
+        }
+    }
+}
+
+impl InterpolationAlgorithm for GpuInterpolation {
+    fn downsample(
+        &self,
+        src_pixels: &[u8],
+        src_width: usize,
+        src_height: usize,
+        target_width: usize,
+        target_height: usize,
+        pixel_bytes: usize,
+    ) -> Result<Vec<u8>, InterpolationError> {
+        if target_height > src_height || target_width > src_width {
+            return Err(InterpolationError::DownsampleTargetLargerThanSource(
+                format!(
+                    "Target resolution ({}, {}) > Source resolution ({}, {})",
+                    target_width, target_height, src_width, src_height
+                ),
+            ));
+        }
+        let params = self.params(
+            src_width,
+            src_height,
+            target_width,
+            target_height,
+            pixel_bytes,
+        );
+        let mut target_pixels = Vec::new();
+        self.context.dispatch(
+            &self.context.downsample,
+            src_pixels,
+            params,
+            &mut target_pixels,
+        )?;
+        Ok(target_pixels)
+    }
+
+    fn upsample_into(
+        &self,
+        src_pixels: &[u8],
+        src_width: usize,
+        src_height: usize,
+        target: &mut ImageBuffer,
+    ) -> Result<(), InterpolationError> {
+        let params = self.params(
+            src_width,
+            src_height,
+            target.width,
+            target.height,
+            target.channels,
+        );
+        self.context
+            .dispatch(&self.context.upsample, src_pixels, params, &mut target.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GpuInterpolation, GpuSampling};
+    use crate::interpolation::{AverageAreaInterpolation, InterpolationAlgorithm};
+
+    #[test]
+    fn test_gpu_matches_cpu_downsample() {
+        // Machines without a usable adapter cannot run the shaders
+        let Ok(gpu) = GpuInterpolation::new(GpuSampling::AverageArea) else {
+            return;
+        };
+        let pixels: Vec<u8> = (0..24 * 24 * 3).map(|v| (v * 7 % 256) as u8).collect();
+        let expected = AverageAreaInterpolation
+            .downsample(&pixels, 24, 24, 5, 5, 3)
+            .unwrap();
+        assert_eq!(gpu.downsample(&pixels, 24, 24, 5, 5, 3).unwrap(), expected);
+    }
+}
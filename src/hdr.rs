@@ -0,0 +1,288 @@
+#[cfg(feature = "radiance")]
+use std::io::{BufRead, Read};
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::color::linear_to_srgb;
+
+#[derive(Debug, Error)]
+pub enum HdrError {
+    #[error("Failed to read HDR file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[cfg(feature = "exr")]
+    #[error("Failed to read OpenEXR file: {0}")]
+    Exr(#[from] exr::error::Error),
+
+    #[cfg(feature = "radiance")]
+    #[error("Invalid Radiance HDR file: {0}")]
+    InvalidRadiance(String),
+}
+
+/// Linear floating point RGB image as read from an HDR source
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<f32>,
+}
+
+/// Extensions of the HDR formats compiled into this build
+const HDR_EXTENSIONS: &[&str] = &[
+    #[cfg(feature = "exr")]
+    "exr",
+    #[cfg(feature = "radiance")]
+    "hdr",
+];
+
+pub fn is_hdr_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| HDR_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+#[cfg(feature = "exr")]
+pub fn read_exr(data: &[u8]) -> Result<HdrImage, HdrError> {
+    use exr::prelude::traits::*;
+
+    let image = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .rgba_channels(
+            |resolution, _| HdrImage {
+                width: resolution.width(),
+                height: resolution.height(),
+                pixels: vec![0f32; resolution.width() * resolution.height() * 3],
+            },
+            |image: &mut HdrImage, position, (r, g, b, _a): (f32, f32, f32, f32)| {
+                let idx = (position.y() * image.width + position.x()) * 3;
+                image.pixels[idx..idx + 3].copy_from_slice(&[r, g, b]);
+            },
+        )
+        .first_valid_layer()
+        .all_attributes()
+        .from_buffered(std::io::Cursor::new(data))?;
+    Ok(image.layer_data.channel_data.pixels)
+}
+
+/// Width and height of the first layer, read from the header only
+#[cfg(feature = "exr")]
+pub fn exr_dimensions(data: &[u8]) -> Option<(usize, usize)> {
+    let meta = exr::meta::MetaData::read_from_buffered(data, false).ok()?;
+    let size = meta.headers.first()?.layer_size;
+    Some((size.width(), size.height()))
+}
+
+#[cfg(feature = "radiance")]
+/**
+ * Reads a Radiance RGBE file. Only the standard `-Y height +X width` orientation is
+ * supported, scanlines may be flat or use the adaptive run length encoding. */
+pub fn read_radiance(mut reader: impl BufRead) -> Result<HdrImage, HdrError> {
+    let (width, height) = read_radiance_header(&mut reader)?;
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    let mut scanline = vec![0u8; width * 4];
+    for _ in 0..height {
+        read_scanline(&mut reader, &mut scanline, width)?;
+        for rgbe in scanline.chunks_exact(4) {
+            pixels.extend_from_slice(&rgbe_to_rgb(rgbe));
+        }
+    }
+
+    Ok(HdrImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+#[cfg(feature = "radiance")]
+/// Reads the header up to and including the resolution line, returns the width and height
+fn read_radiance_header(reader: &mut impl BufRead) -> Result<(usize, usize), HdrError> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(HdrError::InvalidRadiance(String::from(
+            "missing #? signature",
+        )));
+    }
+
+    // Header lines end with an empty line, the resolution string follows
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(HdrError::InvalidRadiance(String::from(
+                "unexpected end of header",
+            )));
+        }
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            break;
+        }
+        if let Some(format) = trimmed.strip_prefix("FORMAT=")
+            && format != "32-bit_rle_rgbe"
+        {
+            return Err(HdrError::InvalidRadiance(format!(
+                "unsupported format {}",
+                format
+            )));
+        }
+    }
+
+    line.clear();
+    reader.read_line(&mut line)?;
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let (height, width) = match parts.as_slice() {
+        ["-Y", height, "+X", width] => (height.parse::<usize>().ok(), width.parse::<usize>().ok()),
+        _ => (None, None),
+    };
+    let (Some(height), Some(width)) = (height, width) else {
+        return Err(HdrError::InvalidRadiance(format!(
+            "unsupported resolution line: {}",
+            line.trim()
+        )));
+    };
+    Ok((width, height))
+}
+
+#[cfg(feature = "radiance")]
+/// Width and height from the header of a Radiance file
+pub fn radiance_dimensions(mut data: &[u8]) -> Option<(usize, usize)> {
+    read_radiance_header(&mut data).ok()
+}
+
+#[cfg(feature = "radiance")]
+fn read_scanline(
+    reader: &mut impl Read,
+    scanline: &mut [u8],
+    width: usize,
+) -> Result<(), HdrError> {
+    let mut start = [0u8; 4];
+    reader.read_exact(&mut start)?;
+
+    let is_rle = (8..=0x7fff).contains(&width)
+        && start[0] == 2
+        && start[1] == 2
+        && ((start[2] as usize) << 8 | start[3] as usize) == width;
+    if !is_rle {
+        scanline[..4].copy_from_slice(&start);
+        reader.read_exact(&mut scanline[4..])?;
+        return Ok(());
+    }
+
+    // Run length encoded scanlines store each of the four components separately
+    let mut component = vec![0u8; width];
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            reader.read_exact(&mut count)?;
+            let count = count[0] as usize;
+            if count > 128 {
+                let run = count - 128;
+                if x + run > width {
+                    return Err(HdrError::InvalidRadiance(String::from(
+                        "run exceeds scanline",
+                    )));
+                }
+                let mut value = [0u8; 1];
+                reader.read_exact(&mut value)?;
+                component[x..x + run].fill(value[0]);
+                x += run;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(HdrError::InvalidRadiance(String::from("bad literal run")));
+                }
+                reader.read_exact(&mut component[x..x + count])?;
+                x += count;
+            }
+        }
+        for (i, value) in component.iter().enumerate() {
+            scanline[i * 4 + channel] = *value;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "radiance")]
+fn rgbe_to_rgb(rgbe: &[u8]) -> [f32; 3] {
+    if rgbe[3] == 0 {
+        return [0.0; 3];
+    }
+    let scale = 2f32.powi(rgbe[3] as i32 - 136);
+    [
+        rgbe[0] as f32 * scale,
+        rgbe[1] as f32 * scale,
+        rgbe[2] as f32 * scale,
+    ]
+}
+
+pub fn reinhard(x: f32) -> f32 {
+    x / (1.0 + x)
+}
+
+/// Narkowicz' fit of the ACES filmic curve
+pub fn aces(x: f32) -> f32 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    ((x * (a * x + b)) / (x * (c * x + d) + e)).clamp(0.0, 1.0)
+}
+
+/// Compresses linear HDR values into 8-bit sRGB with the given tone mapping curve,
+/// `exposure` scales the linear values beforehand
+pub fn tone_map(pixels: &[f32], operator: fn(f32) -> f32, exposure: f32) -> Vec<u8> {
+    pixels
+        .iter()
+        .map(|&value| {
+            let mapped = operator((value * exposure).max(0.0)).clamp(0.0, 1.0);
+            (linear_to_srgb(mapped) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{aces, reinhard, tone_map};
+
+    #[cfg(feature = "radiance")]
+    use super::read_radiance;
+
+    #[cfg(feature = "radiance")]
+    #[test]
+    fn test_read_flat_radiance() {
+        let mut file = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".to_vec();
+        // 1.0 and 0.5 encoded with exponent 129
+        file.extend_from_slice(&[128, 128, 128, 129, 64, 64, 64, 129]);
+        let image = read_radiance(&file[..]).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels, vec![1.0, 1.0, 1.0, 0.5, 0.5, 0.5]);
+    }
+
+    #[cfg(feature = "radiance")]
+    #[test]
+    fn test_read_rle_radiance() {
+        let mut file = b"#?RADIANCE\n\n-Y 1 +X 8\n".to_vec();
+        file.extend_from_slice(&[2, 2, 0, 8]);
+        for value in [128u8, 128, 128, 129] {
+            file.extend_from_slice(&[128 + 8, value]);
+        }
+        let image = read_radiance(&file[..]).unwrap();
+        assert_eq!(image.pixels, vec![1.0; 8 * 3]);
+    }
+
+    #[cfg(feature = "radiance")]
+    #[test]
+    fn test_rejects_invalid_signature() {
+        assert!(read_radiance(&b"P6\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_tone_map_range() {
+        let pixels = vec![0.0, 0.18, 1.0, 100.0];
+        for operator in [reinhard, aces] {
+            let mapped = tone_map(&pixels, operator, 1.0);
+            assert_eq!(mapped[0], 0);
+            assert!(mapped.windows(2).all(|w| w[0] <= w[1]));
+            assert!(mapped[3] >= 250);
+        }
+    }
+}
@@ -1,7 +1,12 @@
-use std::convert::Infallible;
-
-use jpeg_decoder::{ImageInfo, PixelFormat};
+pub use smolres_core::levels::round_to_levels;
+use smolres_core::{levels, resample};
 use thiserror::Error;
+use tracing::info_span;
+
+use crate::palette::PaletteError;
+use crate::pool;
+use crate::quantization::{Quantization, Quantizer, quantize_blocks, quantize_grid};
+use crate::types::{Component, ImageBuffer};
 
 #[derive(Debug, Error)]
 pub enum InterpolationError {
@@ -11,47 +16,55 @@ pub enum InterpolationError {
     #[error("Source dimensions are larger than target dimensions: {0}")]
     UpsampleSourceLargerThanTarget(String),
 
-    #[error("Failed to resolve image metadata")]
-    ImageMetadataResolve,
-
     #[error("Bit depth must be between 1 and 8, got: {0}")]
     InvalidBitDepth(u8),
+
+    #[error("Adaptive quantization needs a bit depth between 2 and 7, got: {0}")]
+    AdaptiveBitDepth(u8),
+
+    #[error("Failed to quantize colors: {0}")]
+    Palette(#[from] PaletteError),
+
+    #[cfg(feature = "gpu")]
+    #[error("GPU interpolation failed: {0}")]
+    Gpu(String),
 }
-pub trait InterpolationAlgorithm {
+/// Resampling strategy, generic over the component type so 8 bit, 16 bit and
+/// floating point images share the same loops
+pub trait InterpolationAlgorithm<C: Component = u8> {
     fn downsample(
         &self,
-        src_pixels: Vec<u8>,
+        src_pixels: &[C],
         src_width: usize,
         src_height: usize,
         target_width: usize,
         target_height: usize,
-        pixel_format: PixelFormat,
-    ) -> Result<Vec<u8>, InterpolationError>;
+        pixel_bytes: usize,
+    ) -> Result<Vec<C>, InterpolationError>;
 
-    fn upsample(
+    /// Upsamples into `target`, keeping its dimensions and reusing the allocation of its pixels
+    fn upsample_into(
         &self,
-        src_pixels: Vec<u8>,
+        src_pixels: &[C],
         src_width: usize,
         src_height: usize,
-        target_width: usize,
-        target_height: usize,
-        pixel_format: PixelFormat,
-    ) -> Result<Vec<u8>, InterpolationError>;
+        target: &mut ImageBuffer<C>,
+    ) -> Result<(), InterpolationError>;
 }
 
 pub struct AverageAreaInterpolation;
 pub struct NearestNeighborInterpolation;
 
-impl InterpolationAlgorithm for AverageAreaInterpolation {
+impl<C: Component> InterpolationAlgorithm<C> for AverageAreaInterpolation {
     fn downsample(
         &self,
-        src_pixels: Vec<u8>,
+        src_pixels: &[C],
         src_width: usize,
         src_height: usize,
         target_width: usize,
         target_height: usize,
-        pixel_format: PixelFormat,
-    ) -> Result<Vec<u8>, InterpolationError> {
+        pixel_bytes: usize,
+    ) -> Result<Vec<C>, InterpolationError> {
         if target_height > src_height || target_width > src_width {
             return Err(InterpolationError::DownsampleTargetLargerThanSource(
                 format!(
@@ -61,101 +74,38 @@ impl InterpolationAlgorithm for AverageAreaInterpolation {
             ));
         }
 
-        let pixel_bytes: usize = pixel_format
-            .pixel_bytes()
-            .try_into()
-            .map_err(|_e: Infallible| InterpolationError::ImageMetadataResolve)?;
-
-        let block_size_x = src_width / target_width;
-        let block_size_y = src_height / target_height;
-
-        let mut target_pixels: Vec<u8> =
-            Vec::with_capacity(target_height * target_width * pixel_bytes);
-
-        for block_y in 0..target_height {
-            for block_x in 0..target_width {
-                let mut sums = vec![0usize; pixel_bytes];
-
-                for y in 0..block_size_y {
-                    for x in 0..block_size_x {
-                        let pixel_x = block_x * block_size_x + x;
-                        let pixel_y = block_y * block_size_y + y;
-                        let idx = (pixel_y * src_width + pixel_x) * pixel_bytes;
-                        for channel in 0..pixel_bytes {
-                            sums[channel] += src_pixels[idx + channel] as usize;
-                        }
-                    }
-                }
-
-                let count = block_size_x * block_size_y;
-                for channel_sum in sums {
-                    target_pixels.push((channel_sum / count) as u8);
-                }
-            }
-        }
-
-        Ok(target_pixels)
+        Ok(resample::average_area_downsample(
+            src_pixels,
+            src_width,
+            src_height,
+            target_width,
+            target_height,
+            pixel_bytes,
+        ))
     }
 
-    fn upsample(
+    fn upsample_into(
         &self,
-        src_pixels: Vec<u8>,
+        src_pixels: &[C],
         src_width: usize,
         src_height: usize,
-        target_width: usize,
-        target_height: usize,
-        pixel_format: PixelFormat,
-    ) -> Result<Vec<u8>, InterpolationError> {
-        let pixel_bytes: usize = pixel_format
-            .pixel_bytes()
-            .try_into()
-            .map_err(|_e: Infallible| InterpolationError::ImageMetadataResolve)?;
-
-        let mut target_pixels = Vec::with_capacity(target_height * target_width * pixel_bytes);
-        let scale_x = src_width as f64 / target_width as f64;
-        let scale_y = src_height as f64 / target_height as f64;
-
-        for y_target in 0..target_height {
-            for x_target in 0..target_width {
-                let x_start = (x_target as f64 * scale_x).floor() as usize;
-                let x_end = ((x_target + 1) as f64 * scale_x).ceil() as usize;
-
-                let y_start = (y_target as f64 * scale_y).floor() as usize;
-                let y_end = ((y_target + 1) as f64 * scale_y).ceil() as usize;
-
-                let mut sums = vec![0usize; pixel_bytes];
-                let mut count = 0;
-
-                for y in y_start..y_end.min(src_height) {
-                    for x in x_start..x_end.min(src_width) {
-                        let idx = (y * src_width + x) * pixel_bytes;
-                        for c in 0..pixel_bytes {
-                            sums[c] += src_pixels[idx + c] as usize;
-                        }
-                        count += 1;
-                    }
-                }
-
-                for sum in sums {
-                    target_pixels.push((sum / count) as u8);
-                }
-            }
-        }
-
-        Ok(target_pixels)
+        target: &mut ImageBuffer<C>,
+    ) -> Result<(), InterpolationError> {
+        resample::average_area_upsample(src_pixels, src_width, src_height, target);
+        Ok(())
     }
 }
 
-impl InterpolationAlgorithm for NearestNeighborInterpolation {
+impl<C: Component> InterpolationAlgorithm<C> for NearestNeighborInterpolation {
     fn downsample(
         &self,
-        src_pixels: Vec<u8>,
+        src_pixels: &[C],
         src_width: usize,
         src_height: usize,
         target_width: usize,
         target_height: usize,
-        pixel_format: PixelFormat,
-    ) -> Result<Vec<u8>, InterpolationError> {
+        pixel_bytes: usize,
+    ) -> Result<Vec<C>, InterpolationError> {
         if target_height > src_height || target_width > src_width {
             return Err(InterpolationError::DownsampleTargetLargerThanSource(
                 format!(
@@ -165,114 +115,318 @@ impl InterpolationAlgorithm for NearestNeighborInterpolation {
             ));
         }
 
-        let pixel_bytes = pixel_format.pixel_bytes() as usize;
-        let mut target_pixels = vec![0u8; target_width * target_height * pixel_bytes];
-
-        let scale_x = src_width as f64 / target_width as f64;
-        let scale_y = src_height as f64 / target_height as f64;
-
-        for y in 0..target_height {
-            for x in 0..target_width {
-                let src_x = (x as f64 * scale_x).floor() as usize;
-                let src_y = (y as f64 * scale_y).floor() as usize;
-
-                let src_idx = (src_y * src_width + src_x) * pixel_bytes;
-                let out_idx = (y * target_width + x) * pixel_bytes;
+        let mut target = ImageBuffer::new(
+            target_width,
+            target_height,
+            pixel_bytes,
+            vec![C::default(); target_width * target_height * pixel_bytes],
+        );
+        resample::nearest_neighbor_resample(src_pixels, src_width, src_height, &mut target);
+        Ok(target.data)
+    }
 
-                target_pixels[out_idx..out_idx + pixel_bytes]
-                    .copy_from_slice(&src_pixels[src_idx..src_idx + pixel_bytes]);
-            }
+    fn upsample_into(
+        &self,
+        src_pixels: &[C],
+        src_width: usize,
+        src_height: usize,
+        target: &mut ImageBuffer<C>,
+    ) -> Result<(), InterpolationError> {
+        let (target_width, target_height, pixel_bytes) =
+            (target.width, target.height, target.channels);
+        // A target of the source size is allowed, it is copied as it is
+        if target_width < src_width || target_height < src_height {
+            return Err(InterpolationError::UpsampleSourceLargerThanTarget(format!(
+                "Source resolution ({}, {}) > Target resolution ({}, {})",
+                src_width, src_height, target_width, target_height
+            )));
         }
+        let target_pixels = &mut target.data;
+        target_pixels.clear();
+        target_pixels.resize(target_width * target_height * pixel_bytes, C::default());
 
-        Ok(target_pixels)
+        resample::nearest_neighbor_resample(src_pixels, src_width, src_height, target);
+        Ok(())
     }
+}
+
+/**
+ * Downsamples with `inner` to `factor` times the target grid and averages that down to the
+ * grid, so sampling algorithms like nearest neighbor alias less without fractional area math.
+ * Upsampling is left to `inner`. */
+pub struct Supersampled {
+    pub inner: Box<dyn InterpolationAlgorithm>,
+    pub factor: usize,
+}
 
-    fn upsample(
+impl InterpolationAlgorithm for Supersampled {
+    fn downsample(
         &self,
-        src_pixels: Vec<u8>,
+        src_pixels: &[u8],
         src_width: usize,
         src_height: usize,
         target_width: usize,
         target_height: usize,
-        pixel_format: PixelFormat,
+        pixel_bytes: usize,
     ) -> Result<Vec<u8>, InterpolationError> {
-        let pixel_bytes = pixel_format.pixel_bytes() as usize;
-        let mut target_pixels = vec![0u8; target_width * target_height * pixel_bytes];
+        // The intermediate grid is never larger than the source
+        let width = (target_width * self.factor).min(src_width.max(target_width));
+        let height = (target_height * self.factor).min(src_height.max(target_height));
+        let supersampled = self.inner.downsample(
+            src_pixels,
+            src_width,
+            src_height,
+            width,
+            height,
+            pixel_bytes,
+        )?;
+        AverageAreaInterpolation.downsample(
+            &supersampled,
+            width,
+            height,
+            target_width,
+            target_height,
+            pixel_bytes,
+        )
+    }
 
-        if target_pixels.len() <= src_pixels.len() {
-            return Err(InterpolationError::UpsampleSourceLargerThanTarget(format!(
-                "Source pixel vec is {}, target vec is {}",
-                src_pixels.len(),
-                target_pixels.len()
-            )));
-        }
+    fn upsample_into(
+        &self,
+        src_pixels: &[u8],
+        src_width: usize,
+        src_height: usize,
+        target: &mut ImageBuffer,
+    ) -> Result<(), InterpolationError> {
+        self.inner
+            .upsample_into(src_pixels, src_width, src_height, target)
+    }
+}
 
-        let scale_x = src_width as f64 / target_width as f64;
-        let scale_y = src_height as f64 / target_height as f64;
+/**
+ * Truncates every channel to `bit_depth` bits. `round_bit_depth` picks the nearest level
+ * instead, dithered variants go through `quantization::dither` with a `Quantizer::BitDepth`
+ * or `Quantizer::RoundedBitDepth` and the kernel of choice. */
+pub fn reduce_bit_depth(pixels: &mut [u8], bit_depth: u8) -> Result<(), InterpolationError> {
+    if bit_depth == 0 || bit_depth > 8 {
+        return Err(InterpolationError::InvalidBitDepth(bit_depth));
+    }
 
-        for y in 0..target_height {
-            for x in 0..target_width {
-                let src_x = (x as f64 * scale_x).floor() as usize;
-                let src_y = (y as f64 * scale_y).floor() as usize;
+    levels::truncate_bit_depth(pixels, bit_depth);
+    Ok(())
+}
 
-                let src_idx = (src_y * src_width + src_x) * pixel_bytes;
-                let out_idx = (y * target_width + x) * pixel_bytes;
+/// Rounds every channel to the nearest of the levels `reduce_bit_depth` truncates to
+pub fn round_bit_depth(pixels: &mut [u8], bit_depth: u8) -> Result<(), InterpolationError> {
+    if bit_depth == 0 || bit_depth > 8 {
+        return Err(InterpolationError::InvalidBitDepth(bit_depth));
+    }
 
-                target_pixels[out_idx..out_idx + pixel_bytes]
-                    .copy_from_slice(&src_pixels[src_idx..src_idx + pixel_bytes]);
-            }
-        }
+    levels::round_bit_depth(pixels, bit_depth);
+    Ok(())
+}
+
+/**
+ * `2^bit_depth` levels spread along a power curve, gammas above 1.0 put more of them in the
+ * shadows where banding is easier to see and fewer in the highlights. */
+pub fn gamma_levels(bit_depth: u8, gamma: f32) -> Vec<u8> {
+    let last = ((1u16 << bit_depth) - 1) as f32;
+    let mut levels: Vec<u8> = (0..=last as u16)
+        .map(|i| (255.0 * (i as f32 / last).powf(gamma)).round() as u8)
+        .collect();
+    levels.dedup();
+    levels
+}
 
-        Ok(target_pixels)
+/**
+ * Calls `hook` with the column, row and color of every block of the grid, top to bottom.
+ * Changes the hook makes to the color end up in the upsampled result. */
+pub fn for_each_block(grid: &mut ImageBuffer, mut hook: impl FnMut(usize, usize, &mut [u8])) {
+    let width = grid.width;
+    for (i, color) in grid.data.chunks_exact_mut(grid.channels).enumerate() {
+        hook(i % width, i / width, color);
     }
 }
 
-pub fn reduce_bit_depth(pixels: &mut [u8], bit_depth: u8) -> Result<Vec<u8>, InterpolationError> {
-    if bit_depth == 0 || bit_depth > 8 {
-        return Err(InterpolationError::InvalidBitDepth(bit_depth));
+/// One pixel from the middle of every block of a pixelated image, the grid it was upsampled from
+pub fn sample_blocks(
+    image: &ImageBuffer,
+    (grid_width, grid_height): (usize, usize),
+) -> ImageBuffer {
+    let mut data = Vec::with_capacity(grid_width * grid_height * image.channels);
+    for block_y in 0..grid_height {
+        let y = (2 * block_y + 1) * image.height / (2 * grid_height);
+        for block_x in 0..grid_width {
+            let x = (2 * block_x + 1) * image.width / (2 * grid_width);
+            let index = (y * image.width + x) * image.channels;
+            data.extend_from_slice(&image.data[index..index + image.channels]);
+        }
     }
+    ImageBuffer::new(grid_width, grid_height, image.channels, data)
+}
 
-    let levels = 1 << bit_depth;
-    let step = (256u16 / levels as u16) as u8;
-    for byte in pixels.iter_mut() {
-        *byte = (*byte / step) * step;
+/**
+ * Variance of the source pixels under every block of the grid, summed over the channels.
+ * Blocks are laid out like `AverageAreaInterpolation` lays them out when downsampling. */
+pub fn block_variances(src: &ImageBuffer, (grid_width, grid_height): (usize, usize)) -> Vec<f32> {
+    let mut variances = Vec::with_capacity(grid_width * grid_height);
+    for block_y in 0..grid_height {
+        let (y_start, y_end) = (
+            block_y * src.height / grid_height,
+            (block_y + 1) * src.height / grid_height,
+        );
+        for block_x in 0..grid_width {
+            let (x_start, x_end) = (
+                block_x * src.width / grid_width,
+                (block_x + 1) * src.width / grid_width,
+            );
+            let mut sums = vec![(0f64, 0f64); src.channels];
+            for y in y_start..y_end {
+                let row = (y * src.width + x_start) * src.channels;
+                let pixels = &src.data[row..row + (x_end - x_start) * src.channels];
+                for pixel in pixels.chunks_exact(src.channels) {
+                    for (sum, &value) in sums.iter_mut().zip(pixel) {
+                        sum.0 += value as f64;
+                        sum.1 += (value as f64).powi(2);
+                    }
+                }
+            }
+            let count = ((x_end - x_start) * (y_end - y_start)).max(1) as f64;
+            let variance: f64 = sums
+                .iter()
+                .map(|(sum, squares)| squares / count - (sum / count).powi(2))
+                .sum();
+            variances.push(variance as f32);
+        }
     }
-    Ok(pixels.to_vec())
+    variances
 }
 
+/// Pixelates `src`, `grid_effect` may change the quantized grid before it is upsampled
 pub fn run_interpolation(
     algo: &dyn InterpolationAlgorithm,
-    src: Vec<u8>,
-    target_resolution: u16,
-    target_bit_depth: u8,
-    metadata: ImageInfo,
-) -> Result<Vec<u8>, InterpolationError> {
-    let src_width = metadata.width;
-    let src_height = metadata.height;
-    let downsampled_pixels = algo.downsample(
-        src,
-        src_width.into(),
-        src_height.into(),
-        target_resolution.into(),
-        target_resolution.into(),
-        metadata.pixel_format,
-    )?;
-    let mut target_pixels = algo.upsample(
-        downsampled_pixels,
-        target_resolution.into(),
-        target_resolution.into(),
-        src_width.into(),
-        src_height.into(),
-        metadata.pixel_format,
-    )?;
-    reduce_bit_depth(&mut target_pixels, target_bit_depth)
+    src: &ImageBuffer,
+    (grid_width, grid_height): (usize, usize),
+    quantization: &Quantization,
+    grid_effect: Option<&dyn Fn(&mut ImageBuffer)>,
+) -> Result<ImageBuffer, InterpolationError> {
+    let mut downsampled_pixels = info_span!("downsample").in_scope(|| {
+        algo.downsample(
+            &src.data,
+            src.width,
+            src.height,
+            grid_width,
+            grid_height,
+            src.channels,
+        )
+    })?;
+
+    // Colors are reduced on the virtual grid so dithering works on whole blocks
+    let quantizer = info_span!("quantize").in_scope(|| {
+        let quantizer = Quantizer::new(&quantization.reduction, &downsampled_pixels, src.channels)?
+            .with_distance(quantization.distance);
+        match quantizer {
+            Quantizer::AdaptiveBitDepth(bit_depth) => quantize_blocks(
+                &mut downsampled_pixels,
+                src.channels,
+                bit_depth,
+                &block_variances(src, (grid_width, grid_height)),
+            ),
+            _ => quantize_grid(
+                &mut downsampled_pixels,
+                grid_width,
+                grid_height,
+                src.channels,
+                &quantizer,
+                quantization.dithering.as_ref(),
+            )?,
+        }
+        Ok::<_, InterpolationError>(quantizer)
+    })?;
+    if let Some(effect) = grid_effect {
+        let mut grid = ImageBuffer::new(grid_width, grid_height, src.channels, downsampled_pixels);
+        effect(&mut grid);
+        downsampled_pixels = grid.data;
+    }
+
+    // Full size buffers come from the pool so batch runs do not allocate one per image
+    let mut target = ImageBuffer {
+        width: src.width,
+        height: src.height,
+        channels: src.channels,
+        data: pool::take(src.data.len()),
+    };
+    info_span!("upsample").in_scope(|| {
+        algo.upsample_into(&downsampled_pixels, grid_width, grid_height, &mut target)?;
+        // Upsampling may blend neighbouring blocks, so map those pixels back as well
+        quantizer.apply(&mut target.data, src.channels)
+    })?;
+    Ok(target)
+}
+
+/// Computes the size of a thumbnail whose longest side is at most `max_dimension`,
+/// keeping the aspect ratio of the source. Sources that already fit are never enlarged.
+pub fn thumbnail_dimensions(width: usize, height: usize, max_dimension: usize) -> (usize, usize) {
+    let longest = width.max(height);
+    if longest <= max_dimension {
+        return (width, height);
+    }
+
+    let scale = max_dimension as f64 / longest as f64;
+    let target_width = ((width as f64 * scale).round() as usize).clamp(1, max_dimension);
+    let target_height = ((height as f64 * scale).round() as usize).clamp(1, max_dimension);
+    (target_width, target_height)
+}
+
+/// Downsamples the image to thumbnail size without enlarging it back
+pub fn run_thumbnail(
+    algo: &dyn InterpolationAlgorithm,
+    src: &ImageBuffer,
+    max_dimension: u16,
+    quantization: &Quantization,
+) -> Result<ImageBuffer, InterpolationError> {
+    let (target_width, target_height) =
+        thumbnail_dimensions(src.width, src.height, max_dimension.into());
+    let mut target_pixels = info_span!("downsample").in_scope(|| {
+        algo.downsample(
+            &src.data,
+            src.width,
+            src.height,
+            target_width,
+            target_height,
+            src.channels,
+        )
+    })?;
+    info_span!("quantize").in_scope(|| {
+        let quantizer = Quantizer::new(&quantization.reduction, &target_pixels, src.channels)?
+            .with_distance(quantization.distance);
+        quantize_grid(
+            &mut target_pixels,
+            target_width,
+            target_height,
+            src.channels,
+            &quantizer,
+            quantization.dithering.as_ref(),
+        )
+    })?;
+    Ok(ImageBuffer::new(
+        target_width,
+        target_height,
+        src.channels,
+        target_pixels,
+    ))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{NearestNeighborInterpolation, reduce_bit_depth, run_interpolation};
+    use super::{
+        InterpolationAlgorithm, NearestNeighborInterpolation, Supersampled, for_each_block,
+        gamma_levels, reduce_bit_depth, round_bit_depth, round_to_levels, run_interpolation,
+        run_thumbnail, sample_blocks, thumbnail_dimensions,
+    };
+    use crate::color::Distance;
     use crate::interpolation::AverageAreaInterpolation;
-    use jpeg_decoder::{CodingProcess, ImageInfo, PixelFormat};
+    use crate::quantization::{ColorReduction, Quantization};
+    use crate::types::ImageBuffer;
 
     #[test]
     fn test_nearest_neighbor_interpolation() {
@@ -280,24 +434,22 @@ mod tests {
         let height = 4;
         let pixel_format = 3;
         let mock_pixels: Vec<u8> = vec![128u8; width * height * pixel_format];
-        let original_pixels = mock_pixels.clone();
-        let target_bit_depth = 8;
-        let metadata = ImageInfo {
-            width: width as u16,
-            height: height as u16,
-            pixel_format: PixelFormat::RGB24,
-            coding_process: CodingProcess::DctSequential,
+        let quantization = Quantization {
+            reduction: ColorReduction::BitDepth(8),
+            dithering: None,
+            distance: Distance::Rgb,
         };
+        let image = ImageBuffer::new(width, height, pixel_format, mock_pixels.clone());
         let target_resolution = 2;
         let result_pixels = run_interpolation(
             &NearestNeighborInterpolation,
-            mock_pixels,
-            target_resolution,
-            target_bit_depth,
-            metadata,
+            &image,
+            (target_resolution, target_resolution),
+            &quantization,
+            None,
         )
         .unwrap();
-        assert_eq!(result_pixels.len(), original_pixels.len());
+        assert_eq!(result_pixels.data.len(), mock_pixels.len());
     }
 
     #[test]
@@ -306,24 +458,68 @@ mod tests {
         let height = 4;
         let pixel_format = 3;
         let mock_pixels: Vec<u8> = vec![128u8; width * height * pixel_format];
-        let original_pixels = mock_pixels.clone();
-        let metadata = ImageInfo {
-            width: width as u16,
-            height: height as u16,
-            pixel_format: PixelFormat::RGB24,
-            coding_process: CodingProcess::DctSequential,
-        };
+        let image = ImageBuffer::new(width, height, pixel_format, mock_pixels.clone());
         let target_resolution = 2;
-        let target_bit_depth = 8;
+        let quantization = Quantization {
+            reduction: ColorReduction::BitDepth(8),
+            dithering: None,
+            distance: Distance::Rgb,
+        };
         let result_pixels = run_interpolation(
             &AverageAreaInterpolation,
-            mock_pixels,
-            target_resolution,
-            target_bit_depth,
-            metadata,
+            &image,
+            (target_resolution, target_resolution),
+            &quantization,
+            None,
+        )
+        .unwrap();
+        assert_eq!(result_pixels.data.len(), mock_pixels.len());
+    }
+
+    #[test]
+    fn test_block_hook_changes_result() {
+        let image = ImageBuffer::new(4, 4, 1, vec![100; 16]);
+        let quantization = Quantization {
+            reduction: ColorReduction::BitDepth(8),
+            dithering: None,
+            distance: Distance::Rgb,
+        };
+        let hook = |grid: &mut ImageBuffer| {
+            for_each_block(grid, |x, y, color| {
+                if (x, y) == (1, 0) {
+                    color[0] = 0;
+                }
+            })
+        };
+        let result = run_interpolation(
+            &AverageAreaInterpolation,
+            &image,
+            (2, 2),
+            &quantization,
+            Some(&hook),
         )
         .unwrap();
-        assert_eq!(result_pixels.len(), original_pixels.len());
+        assert_eq!(&result.data[..4], &[100, 100, 0, 0]);
+        assert_eq!(&result.data[8..12], &[100; 4]);
+        assert_eq!(sample_blocks(&result, (2, 2)).data, vec![100, 0, 100, 100]);
+    }
+
+    #[test]
+    fn test_supersampling_averages_stripes() {
+        let stripes: Vec<u8> = (0..8 * 8)
+            .map(|i| if i % 2 == 0 { 0 } else { 200 })
+            .collect();
+        let nearest = NearestNeighborInterpolation
+            .downsample(&stripes, 8, 8, 2, 2, 1)
+            .unwrap();
+        assert!(nearest.iter().all(|&value| value == nearest[0]));
+
+        let supersampled = Supersampled {
+            inner: Box::new(NearestNeighborInterpolation),
+            factor: 4,
+        };
+        let result = supersampled.downsample(&stripes, 8, 8, 2, 2, 1).unwrap();
+        assert_eq!(result, vec![100; 4]);
     }
 
     #[test]
@@ -333,23 +529,117 @@ mod tests {
         // 2-bit depth -> 4 levels -> step = 64
         // Expected values: 255 -> 192, 128 -> 128, 64 -> 64, etc.
         // (x / 64) * 64 = quantized value
-        let _ = reduce_bit_depth(&mut pixels, 2);
+        reduce_bit_depth(&mut pixels, 2).unwrap();
 
         let expected = vec![192, 128, 64, 0, 0, 0];
         assert_eq!(pixels, expected);
     }
 
+    #[test]
+    fn test_round_bit_depth() {
+        let mut pixels = vec![255, 160, 95, 31, 32, 0];
+        round_bit_depth(&mut pixels, 2).unwrap();
+        assert_eq!(pixels, vec![192, 192, 64, 0, 64, 0]);
+    }
+
+    #[test]
+    fn test_levels() {
+        let levels = gamma_levels(2, 2.2);
+        assert_eq!(levels, vec![0, 23, 105, 255]);
+        assert_eq!(
+            gamma_levels(3, 1.0),
+            vec![0, 36, 73, 109, 146, 182, 219, 255]
+        );
+
+        let mut pixels = vec![0, 11, 12, 100, 200, 255];
+        round_to_levels(&mut pixels, &levels);
+        assert_eq!(pixels, vec![0, 0, 23, 105, 255, 255]);
+    }
+
     #[test]
     #[should_panic(expected = "bit_depth must be between 1 and 8")]
     fn test_reduce_bit_depth_too_low() {
         let mut pixels = vec![0, 128, 255];
-        let _ = reduce_bit_depth(&mut pixels, 0); // Invalid bit depth
+        reduce_bit_depth(&mut pixels, 0).expect("bit_depth must be between 1 and 8"); // Invalid bit depth
     }
 
     #[test]
     #[should_panic(expected = "bit_depth must be between 1 and 8")]
     fn test_reduce_bit_depth_too_high() {
         let mut pixels = vec![0, 128, 255];
-        let _ = reduce_bit_depth(&mut pixels, 9); // Invalid bit depth
+        reduce_bit_depth(&mut pixels, 9).expect("bit_depth must be between 1 and 8"); // Invalid bit depth
+    }
+
+    #[test]
+    fn test_thumbnail_dimensions() {
+        // Landscape and portrait sources keep their aspect ratio
+        assert_eq!(thumbnail_dimensions(1000, 500, 256), (256, 128));
+        assert_eq!(thumbnail_dimensions(500, 1000, 256), (128, 256));
+        // Sources that already fit are left untouched
+        assert_eq!(thumbnail_dimensions(100, 50, 256), (100, 50));
+        // Extreme aspect ratios never collapse to zero
+        assert_eq!(thumbnail_dimensions(4000, 2, 100), (100, 1));
+    }
+
+    #[test]
+    fn test_run_thumbnail() {
+        let width = 8;
+        let height = 4;
+        let mock_pixels: Vec<u8> = vec![128u8; width * height * 3];
+        let image = ImageBuffer::new(width, height, 3, mock_pixels);
+        let quantization = Quantization {
+            reduction: ColorReduction::BitDepth(8),
+            dithering: None,
+            distance: Distance::Rgb,
+        };
+        let thumbnail = run_thumbnail(&AverageAreaInterpolation, &image, 4, &quantization).unwrap();
+        assert_eq!((thumbnail.width, thumbnail.height), (4, 2));
+        assert_eq!(thumbnail.data.len(), 4 * 2 * 3);
+        assert!(thumbnail.data.iter().all(|&p| p == 128));
+    }
+
+    #[test]
+    fn test_generic_components() {
+        let wide: Vec<u16> = vec![1000, 3000, 5000, 7000];
+        let downsampled = InterpolationAlgorithm::<u16>::downsample(
+            &AverageAreaInterpolation,
+            &wide,
+            2,
+            2,
+            1,
+            1,
+            1,
+        )
+        .unwrap();
+        assert_eq!(downsampled, vec![4000]);
+
+        let hdr: Vec<f32> = vec![0.25, 4.0];
+        let mut upsampled = ImageBuffer::new(4, 1, 1, vec![9.0; 4]);
+        NearestNeighborInterpolation
+            .upsample_into(&hdr, 2, 1, &mut upsampled)
+            .unwrap();
+        assert_eq!(upsampled.data, vec![0.25, 0.25, 4.0, 4.0]);
+    }
+
+    #[test]
+    fn test_upsample_reuses_allocation() {
+        let grid = vec![0u8, 40, 80, 120];
+        for algorithm in [
+            &AverageAreaInterpolation as &dyn InterpolationAlgorithm,
+            &NearestNeighborInterpolation,
+        ] {
+            // Like a buffer from the pool, empty but with room for the full size image
+            let data = Vec::with_capacity(4 * 4);
+            let pointer = data.as_ptr();
+            let mut target = ImageBuffer {
+                width: 4,
+                height: 4,
+                channels: 1,
+                data,
+            };
+            algorithm.upsample_into(&grid, 2, 2, &mut target).unwrap();
+            assert_eq!(target.data.as_ptr(), pointer);
+            assert_eq!(&target.data[..4], &[0, 0, 40, 40]);
+        }
     }
 }
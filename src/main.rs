@@ -1,78 +1,1868 @@
+mod animation;
+mod archive;
+mod capture;
 mod cli;
+mod color;
+#[cfg(unix)]
+mod daemon;
 mod decoder;
+mod diff;
+mod effects;
 mod encoder;
+#[cfg(feature = "http")]
+mod fetch;
+mod filters;
+mod geometry;
+#[cfg(feature = "gpu")]
+mod gpu;
+#[cfg(feature = "hdr")]
+mod hdr;
 mod interpolation;
+mod manifest;
+mod matrix;
+mod montage;
+mod overlay;
+mod palette;
+mod pool;
+mod quantization;
+mod stats;
+mod stream;
+mod traverse;
+#[cfg(feature = "tui")]
+mod tui;
+mod types;
 
-use clap::Parser;
-use cli::{Algorithm, Args, default_output_path};
-use decoder::decode;
-use encoder::encode;
+use animation::{
+    AnimationError, Frame, is_animation_path, read_gif_frames, select_frames, shared_palette,
+    write_gif_frames,
+};
+use archive::{ArchiveWriter, is_archive_path, read_archive};
+use capture::{Camera, screenshot};
+use clap::{Parser, ValueEnum};
+use cli::{
+    Algorithm, Args, AverageArgs, BatchArgs, BorderWidth, CaptureArgs, ColorsArgs, Command,
+    Denoise, DiffArgs, Dither, Equalize, Fit, Invert, Levels, MontageArgs, RenderArgs, Rounding,
+    Stage, Stylize, Threshold, WhiteBalance, default_output_path, default_thumbnail_path,
+    validate_input_path,
+};
+use color::{kelvin_to_linear, to_hex};
+use decoder::{DecodeOptions, InputFormat, check_limits, decode_bytes};
+use effects::{chromatic_aberration, glitch, pixel_sort, remap_colors, soften_blocks};
+use encoder::{
+    CHeaderEncoder, CsvEncoder, EncodeError, ImageEncoder, JPEG_QUALITY, JpegEncoder, JsonEncoder,
+    OutputFormat, encode, encode_jpeg_within,
+};
+use filters::{
+    CLAHE_TILES, adjust_exposure_gamma, apply_channel_gains, auto_levels, average_linear, equalize,
+    gaussian_blur, grading_gains, invert, kuwahara, median_filter, neutral_gains, non_local_means,
+    oil_paint, stack, unsharp_mask,
+};
+use geometry::{
+    Region, center_region, content_bounds, crop, pad, pad_to_aspect, salient_region,
+    warp_perspective,
+};
 use interpolation::{
-    AverageAreaInterpolation, InterpolationAlgorithm, NearestNeighborInterpolation,
-    run_interpolation,
+    AverageAreaInterpolation, InterpolationAlgorithm, InterpolationError,
+    NearestNeighborInterpolation, Supersampled, for_each_block, gamma_levels, run_interpolation,
+    run_thumbnail, sample_blocks,
 };
+use manifest::ManifestEntry;
+use matrix::read_matrix;
+use montage::montage;
+use overlay::{Watermark, draw_caption};
+use palette::{BUILT_IN_PALETTES, CANDIDATES_PER_COLOR, Palette};
+use quantization::{
+    ATKINSON, ColorReduction, DitherMatrixError, DitherMethod, Dithering, FLOYD_STEINBERG,
+    Quantization, Quantizer, ThresholdMatrix,
+};
+use stats::{AlgorithmScore, BatchSummary, Comparison, image_stats, loss_report};
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Instant;
+use stream::MjpegServer;
 use thiserror::Error;
+use tracing::{Level, debug, info_span, warn};
+use tracing_subscriber::fmt::format::FmtSpan;
+use traverse::{TraverseOptions, collect_inputs, exclude_patterns, read_exclude_file};
+use types::ImageBuffer;
 
 #[derive(Debug, Error)]
 pub enum UserFacingError {
     #[error("Failed to interpolate image: {0}")]
     InterpolationError(#[from] interpolation::InterpolationError),
+
+    #[error("Failed to compare images: {0}")]
+    DiffError(#[from] diff::DiffError),
+
+    #[error("Failed to extract colors: {0}")]
+    PaletteError(#[from] palette::PaletteError),
+
+    #[error("Failed to run batch: {0}")]
+    ManifestError(#[from] manifest::ManifestError),
+
+    #[error("Failed to run batch: {0}")]
+    TraverseError(#[from] traverse::TraverseError),
+
+    #[error("Failed to process animation: {0}")]
+    AnimationError(#[from] animation::AnimationError),
+
+    #[error("Failed to decode image: {0}")]
+    DecodeError(#[from] decoder::DecodeError),
+
+    #[error("Failed to encode image: {0}")]
+    EncodeError(#[from] encoder::EncodeError),
+
+    #[error("Failed to process archive: {0}")]
+    ArchiveError(#[from] archive::ArchiveError),
+
+    #[error("Failed to add watermark: {0}")]
+    WatermarkError(#[from] overlay::WatermarkError),
+
+    #[error("Failed to read grid: {0}")]
+    MatrixError(#[from] matrix::MatrixError),
+
+    #[error("Failed to load dither matrix: {0}")]
+    DitherMatrixError(#[from] quantization::DitherMatrixError),
+
+    #[error("Failed to capture input: {0}")]
+    CaptureError(#[from] capture::CaptureError),
+
+    #[cfg(feature = "http")]
+    #[error("Failed to download image: {0}")]
+    FetchError(#[from] fetch::FetchError),
+
+    #[cfg(feature = "tui")]
+    #[error("Terminal error: {0}")]
+    TerminalError(std::io::Error),
+
+    #[error("Failed to start server: {0}")]
+    ServerError(std::io::Error),
+
+    #[error("Failed to read input list: {0}")]
+    InputListError(std::io::Error),
+
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+
+    #[error("while {step} {}: {source}", path.display())]
+    Context {
+        step: PipelineStep,
+        path: PathBuf,
+        source: Box<UserFacingError>,
+    },
+}
+
+/// Part of the pipeline an error happened in, reported together with the file
+#[derive(Debug, Clone, Copy)]
+pub enum PipelineStep {
+    Decoding,
+    Pixelating,
+    Encoding,
+    ReadingManifest,
+    ReadingInputList,
+    ReadingArchive,
+    Traversing,
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    Downloading,
+    ExtractingColors,
+}
+
+impl fmt::Display for PipelineStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PipelineStep::Decoding => "decoding",
+            PipelineStep::Pixelating => "pixelating",
+            PipelineStep::Encoding => "encoding",
+            PipelineStep::ReadingManifest => "reading manifest",
+            PipelineStep::ReadingInputList => "reading input list",
+            PipelineStep::ReadingArchive => "reading archive",
+            PipelineStep::Traversing => "traversing",
+            PipelineStep::Downloading => "downloading",
+            PipelineStep::ExtractingColors => "extracting colors from",
+        })
+    }
+}
+
+/// Attaches the step and file to an error, errors that already have a context keep it
+trait Context<T> {
+    fn context(self, step: PipelineStep, path: &Path) -> Result<T, UserFacingError>;
+}
+
+impl<T, E: Into<UserFacingError>> Context<T> for Result<T, E> {
+    fn context(self, step: PipelineStep, path: &Path) -> Result<T, UserFacingError> {
+        self.map_err(|e| match e.into() {
+            e @ UserFacingError::Context { .. } => e,
+            e => UserFacingError::Context {
+                step,
+                path: path.to_path_buf(),
+                source: Box::new(e),
+            },
+        })
+    }
+}
+
+pub fn run_diff(args: &DiffArgs) -> Result<(), UserFacingError> {
+    let options = DecodeOptions::default();
+    let first = load_image(&args.first, &options)?;
+    let second = load_image(&args.second, &options)?;
+
+    let stats = diff::compare(&first, &second)?;
+    println!("{}", stats);
+
+    if let Some(output) = &args.output {
+        let difference = diff::difference_image(&first, &second, args.amplify)?;
+        encode(&difference, output.clone()).context(PipelineStep::Encoding, output)?;
+    }
+    Ok(())
+}
+
+/// Size of a single color square in the swatch image
+const SWATCH_SIZE: usize = 64;
+
+pub fn run_colors(args: &ColorsArgs) -> Result<(), UserFacingError> {
+    let image = load_image(&args.input, &DecodeOptions::default())?.to_rgb();
+    let quantizer = Quantizer::new(
+        &ColorReduction::Colors(args.count.into()),
+        &image.data,
+        image.channels,
+    )
+    .context(PipelineStep::ExtractingColors, &args.input)?;
+    let Quantizer::Palette(palette) = quantizer else {
+        unreachable!("color reductions always resolve to a palette");
+    };
+
+    let dominant = palette
+        .dominant_colors(&image.data, image.channels)
+        .context(PipelineStep::ExtractingColors, &args.input)?;
+    let pixels = (image.width * image.height).max(1) as f64;
+    for (color, count) in &dominant {
+        println!("{} {:6.2}%", to_hex(*color), 100.0 * *count as f64 / pixels);
+    }
+
+    if let Some(swatch) = &args.swatch {
+        let colors: Vec<[u8; 3]> = dominant.iter().map(|(color, _)| *color).collect();
+        encode(&Palette::swatch(&colors, SWATCH_SIZE), swatch.clone())
+            .context(PipelineStep::Encoding, swatch)?;
+    }
+    Ok(())
+}
+
+pub fn run_batch(args: &BatchArgs) -> Result<(), UserFacingError> {
+    let mut runs = if is_archive_path(&args.source) {
+        let defaults = Args::try_parse_from([
+            OsStr::new("smolres"),
+            OsStr::new("--input"),
+            args.source.as_os_str(),
+        ])
+        .map_err(|e| UserFacingError::InvalidInput(e.to_string()))
+        .context(PipelineStep::ReadingArchive, &args.source)?;
+        archive_jobs(&defaults, &args.source)?
+    } else {
+        batch_jobs(args)?
+    };
+    // Entries have no limits, decoder or determinism of their own, the batch's apply to all
+    for run in &mut runs {
+        run.args.limits = args.limits;
+        run.args.decoder = args.decoder;
+        run.args.deterministic = args.deterministic;
+    }
+
+    let jobs = args
+        .jobs
+        .unwrap_or_else(default_jobs)
+        .min(runs.len())
+        .max(1);
+    debug!(entries = runs.len(), jobs, "starting batch");
+
+    let start = Instant::now();
+    let summary = Mutex::default();
+    let result = run_batch_jobs(args, runs, jobs, &summary);
+    let mut summary: BatchSummary = summary.into_inner().unwrap();
+    summary.finish(start.elapsed());
+    match args.json {
+        true => println!(
+            "{}",
+            serde_json::to_string(&summary).expect("the summary only has numbers")
+        ),
+        false => println!("{summary}"),
+    }
+    result
+}
+
+/// Runs the jobs of a batch, into `--output-archive` if given
+fn run_batch_jobs(
+    args: &BatchArgs,
+    runs: Vec<Job>,
+    jobs: usize,
+    summary: &Mutex<BatchSummary>,
+) -> Result<(), UserFacingError> {
+    let Some(archive_path) = &args.output_archive else {
+        return run_parallel(runs, jobs, None, summary);
+    };
+    // Outputs keep their layout below the directory, manifest or archive they come from
+    let base = if args.source.is_dir() {
+        args.source.clone()
+    } else if is_archive_path(&args.source) {
+        archive_output_dir(&args.source)
+    } else {
+        args.source
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .to_path_buf()
+    };
+    let archive = ArchiveWriter::create(archive_path, &base, args.deterministic)
+        .context(PipelineStep::Encoding, archive_path)?;
+    let result = run_parallel(runs, jobs, Some(&archive), summary);
+    // Entries that finished before a failure are still written out
+    archive
+        .finish()
+        .context(PipelineStep::Encoding, archive_path)?;
+    result
+}
+
+/// Jobs for the images of a directory or the entries of a manifest
+fn batch_jobs(args: &BatchArgs) -> Result<Vec<Job>, UserFacingError> {
+    let step = if args.source.is_dir() {
+        PipelineStep::Traversing
+    } else {
+        PipelineStep::ReadingManifest
+    };
+    let entries = if args.source.is_dir() {
+        let mut patterns = args.exclude.clone();
+        if let Some(exclude_from) = &args.exclude_from {
+            patterns.extend(read_exclude_file(exclude_from).context(step, exclude_from)?);
+        }
+        let options = TraverseOptions {
+            follow_symlinks: args.follow_symlinks,
+            exclude: exclude_patterns(&patterns).context(step, &args.source)?,
+        };
+        collect_inputs(&args.source, &options)
+            .context(PipelineStep::Traversing, &args.source)?
+            .into_iter()
+            .map(ManifestEntry::from_input)
+            .collect()
+    } else {
+        manifest::read_manifest(&args.source)
+            .context(PipelineStep::ReadingManifest, &args.source)?
+    };
+    // Every entry is validated before the first one is processed
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            // Entries are numbered like the rows of a CSV file, after the header
+            let entry_args = Args::try_parse_from(entry.to_args())
+                .map_err(|e| manifest::ManifestError::InvalidEntry(index + 1, e.to_string()))
+                .context(step, &args.source)?;
+            Ok(Job::file(entry_args))
+        })
+        .collect()
+}
+
+/// Directory next to an archive input that its outputs are written to
+fn archive_output_dir(path: &Path) -> PathBuf {
+    path.with_extension("")
+}
+
+/// One job per supported image inside a zip or tar archive, with the settings of `args`
+fn archive_jobs(args: &Args, path: &Path) -> Result<Vec<Job>, UserFacingError> {
+    let base = archive_output_dir(path);
+    let entries = read_archive(path).context(PipelineStep::ReadingArchive, path)?;
+    Ok(entries
+        .into_iter()
+        .map(|(name, data)| {
+            let mut entry_args = args.clone();
+            entry_args.files_from = None;
+            entry_args.input = Some(base.join(name));
+            Job {
+                args: entry_args,
+                data: Some(data),
+            }
+        })
+        .collect())
+}
+
+/// Runs every path listed in `list` with the remaining settings of `args`
+fn run_files_from(args: &Args, list: &Path) -> Result<(), UserFacingError> {
+    let contents = if list == Path::new("-") {
+        io::read_to_string(io::stdin())
+    } else {
+        fs::read_to_string(list)
+    }
+    .map_err(UserFacingError::InputListError)
+    .context(PipelineStep::ReadingInputList, list)?;
+
+    let mut runs = vec![];
+    for line in contents
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.is_empty())
+    {
+        let input = validate_input_path(line)
+            .map_err(UserFacingError::InvalidInput)
+            .context(PipelineStep::ReadingInputList, list)?;
+        if is_archive_path(&input) {
+            runs.extend(archive_jobs(args, &input)?);
+            continue;
+        }
+        let mut file_args = args.clone();
+        file_args.files_from = None;
+        file_args.input = Some(input);
+        runs.push(Job::file(file_args));
+    }
+    run_parallel(runs, default_jobs(), None, &Mutex::default())
+}
+
+/// A single input of a batch with its own settings
+struct Job {
+    args: Args,
+    /// Contents of the input if it does not come from the file system, e.g. from an archive
+    data: Option<Vec<u8>>,
+}
+
+impl Job {
+    fn file(args: Args) -> Self {
+        Job { args, data: None }
+    }
+}
+
+/// One worker per available core unless `--jobs` says otherwise
+fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/**
+ * Runs the entries on `jobs` worker threads that take the next entry as soon as they are
+ * done. Entries that read the same file are decoded once and rendered one after another.
+ * Inputs that are broken or too large are skipped with a warning. After any other failure no
+ * new entries are started and the error of the earliest failed entry is returned. The counts
+ * and sizes of the entries are added to `summary`. */
+fn run_parallel(
+    runs: Vec<Job>,
+    jobs: usize,
+    archive: Option<&ArchiveWriter>,
+    summary: &Mutex<BatchSummary>,
+) -> Result<(), UserFacingError> {
+    let mut groups: Vec<Vec<(usize, Job)>> = Vec::new();
+    for (index, job) in runs.into_iter().enumerate() {
+        let shared = job.data.is_none().then(|| {
+            groups.iter_mut().find(|group| {
+                let first = &group[0].1;
+                first.data.is_none() && same_source(&first.args, &job.args)
+            })
+        });
+        match shared.flatten() {
+            Some(group) => group.push((index, job)),
+            None => groups.push(vec![(index, job)]),
+        }
+    }
+
+    let queue = Mutex::new(groups.into_iter());
+    let failed = AtomicBool::new(false);
+    let errors = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| {
+                while !failed.load(Ordering::Relaxed) {
+                    let Some(group) = queue.lock().unwrap().next() else {
+                        break;
+                    };
+                    if let Err(error) = process_group(group, archive, &failed, summary) {
+                        failed.store(true, Ordering::Relaxed);
+                        summary.lock().unwrap().failed += 1;
+                        errors.lock().unwrap().push(error);
+                    }
+                }
+            });
+        }
+    });
+
+    match errors
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .min_by_key(|(index, _)| *index)
+    {
+        Some((_, error)) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// Decodes the input shared by the entries of `group` once and renders each entry from it
+fn process_group(
+    group: Vec<(usize, Job)>,
+    archive: Option<&ArchiveWriter>,
+    failed: &AtomicBool,
+    summary: &Mutex<BatchSummary>,
+) -> Result<(), (usize, UserFacingError)> {
+    let (first, job) = &group[0];
+    let input = job.args.input.clone().unwrap_or_default();
+    let _span = info_span!("entry", input = %input.display()).entered();
+    let source = match load_source(&job.args, job.data.as_deref()) {
+        // One broken file does not stop the rest of the batch
+        Err(error) if is_corrupt_input(&error) => {
+            warn!("skipping input: {error}");
+            summary.lock().unwrap().skipped += group.len();
+            return Ok(());
+        }
+        result => result.map_err(|e| (*first, e))?,
+    };
+    summary.lock().unwrap().bytes_in += source.size;
+    let mut source = Some(source);
+    let last = group.len() - 1;
+    for (i, (index, job)) in group.into_iter().enumerate() {
+        if failed.load(Ordering::Relaxed) {
+            break;
+        }
+        // The last entry takes the decoded source instead of a copy
+        let source = if i == last {
+            source.take()
+        } else {
+            source.clone()
+        };
+        let written = render(
+            job.args,
+            source.expect("taken by the last entry"),
+            archive,
+            None,
+        )
+        .map_err(|e| (index, e))?;
+        let mut summary = summary.lock().unwrap();
+        summary.processed += 1;
+        summary.bytes_out += written;
+    }
+    Ok(())
+}
+
+/// Whether the input was read but is broken or over the limits, batches skip those
+fn is_corrupt_input(error: &UserFacingError) -> bool {
+    let UserFacingError::Context {
+        step: PipelineStep::Decoding,
+        source,
+        ..
+    } = error
+    else {
+        return false;
+    };
+    match &**source {
+        UserFacingError::DecodeError(error) => !matches!(error, decoder::DecodeError::Io(_)),
+        UserFacingError::AnimationError(_) => true,
+        _ => false,
+    }
+}
+
+pub fn run_average(args: &AverageArgs) -> Result<(), UserFacingError> {
+    let image = load_image(&args.input, &DecodeOptions::default())?.to_rgb();
+    let average =
+        AverageAreaInterpolation.downsample(&image.data, image.width, image.height, 1, 1, 3)?;
+    println!("{}", to_hex([average[0], average[1], average[2]]));
+
+    if let Some(output) = &args.output {
+        encode(&ImageBuffer::new(1, 1, 3, average), output.clone())
+            .context(PipelineStep::Encoding, output)?;
+    }
+    Ok(())
+}
+
+/**
+ * Pixelates webcam frames with the settings of `args`, which has no input of its own. With
+ * `--serve` every JPEG result is also streamed to the browsers connected to the server. */
+pub fn run_capture(args: &Args, capture_args: &CaptureArgs) -> Result<(), UserFacingError> {
+    let name = PathBuf::from(format!("webcam{}.jpeg", capture_args.device));
+    let (output, formats) = outputs(args, &name);
+    let server = match capture_args.serve {
+        Some(_) if !formats.contains(&OutputFormat::Jpeg) => {
+            return Err(UserFacingError::InvalidInput(String::from(
+                "--serve streams JPEG frames, the output must include JPEG",
+            )));
+        }
+        Some(address) => {
+            let server = MjpegServer::bind(address).map_err(UserFacingError::ServerError)?;
+            eprintln!("Streaming on http://{}", server.address());
+            Some(server)
+        }
+        None => None,
+    };
+
+    let mut camera = Camera::open(capture_args.device)?;
+    loop {
+        let frame = camera.next_frame()?;
+        let mut frame_args = args.clone();
+        frame_args.command = None;
+        frame_args.input = Some(name.clone());
+        process(frame_args, Some(&frame), None, None)?;
+        if let Some(server) = &server {
+            let path = OutputFormat::Jpeg.output_path(&output);
+            let jpeg = fs::read(&path)
+                .map_err(decoder::DecodeError::from)
+                .context(PipelineStep::Decoding, &path)?;
+            server.publish(jpeg);
+        }
+        if !capture_args.live {
+            return Ok(());
+        }
+    }
+}
+
+/// Scales every cell of a grid up to a square block, grid formats get the grid as it is
+pub fn run_render(args: &RenderArgs) -> Result<(), UserFacingError> {
+    let grid = read_matrix(&args.input).context(PipelineStep::Decoding, &args.input)?;
+    let is_grid = OutputFormat::from_path(&args.output).is_some_and(|format| format.is_grid());
+    let image = if is_grid {
+        grid
+    } else {
+        let (width, height) = (
+            grid.width * args.block_size as usize,
+            grid.height * args.block_size as usize,
+        );
+        let mut image = ImageBuffer::new(width, height, 3, vec![0; width * height * 3]);
+        NearestNeighborInterpolation.upsample_into(
+            &grid.data,
+            grid.width,
+            grid.height,
+            &mut image,
+        )?;
+        image
+    };
+    encode(&image, args.output.clone()).context(PipelineStep::Encoding, &args.output)
+}
+
+/**
+ * Pixelates every input with the settings of `args`, which has no input of its own, and
+ * tiles the results into one sheet labeled with their file names. */
+pub fn run_montage(args: &Args, montage_args: &MontageArgs) -> Result<(), UserFacingError> {
+    let quantization = quantization(args)?;
+    let watermark = load_watermark(args)?;
+    let mut tiles = vec![];
+    for input in &montage_args.inputs {
+        let mut tile_args = args.clone();
+        tile_args.command = None;
+        tile_args.input = Some(input.clone());
+        let source = load_source(&tile_args, None)?;
+        // Animations are represented by their first frame
+        let image = match source.decoded {
+            Decoded::Image(image) => image,
+            Decoded::Frames(frames) => {
+                let first = frames.into_iter().next().ok_or(AnimationError::Empty);
+                first.context(PipelineStep::Decoding, &source.name)?.image
+            }
+        };
+        let name = source
+            .name
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        let label = match montage_args.with_source {
+            true => {
+                tiles.push((image.clone(), name));
+                tile_args.resolution.to_string()
+            }
+            false => name,
+        };
+        let (_, mut target) = pixelate_still(
+            image,
+            &mut tile_args,
+            &source.name,
+            source.format.is_hdr(),
+            &quantization,
+        )?;
+        apply_overlays(&mut target, &tile_args, watermark.as_ref());
+        tiles.push((add_border(target, &tile_args), label));
+    }
+
+    let columns = match (montage_args.columns, montage_args.with_source) {
+        (Some(columns), _) => columns as usize,
+        (None, true) => 2,
+        (None, false) => (tiles.len() as f64).sqrt().ceil() as usize,
+    };
+    let sheet = montage(&tiles, columns, montage_args.tile_size as usize);
+    encode(&sheet, montage_args.output.clone())
+        .context(PipelineStep::Encoding, &montage_args.output)
+}
+
+/**
+ * Processes the jobs sent to the socket of `args` until the process is stopped. Every job is
+ * validated like a batch manifest entry and bound by the limits of the daemon. */
+#[cfg(unix)]
+pub fn run_daemon(args: &cli::DaemonArgs) -> Result<(), UserFacingError> {
+    let listener = daemon::bind(&args.socket).map_err(UserFacingError::ServerError)?;
+    eprintln!("Listening on {}", args.socket.display());
+    let limits = args.limits;
+    daemon::serve(listener, move |entry| {
+        // Only the message line of clap's error, the usage hint means nothing to a client
+        let mut job_args = Args::try_parse_from(entry.to_args()).map_err(|e| {
+            let message = e.to_string();
+            let first = message.lines().next().unwrap_or_default();
+            String::from(first.trim_start_matches("error: "))
+        })?;
+        job_args.limits = limits;
+        process(job_args, None, None, None).map_err(|e| e.to_string())
+    })
+    .map_err(UserFacingError::ServerError)
+}
+
+/**
+ * Pixelates the input once per algorithm, writes every result and prints how long each one
+ * took and how close it comes to the prepared image. */
+pub fn run_compare(args: &Args) -> Result<(), UserFacingError> {
+    let source = load_source(args, None)?;
+    let Decoded::Image(image) = source.decoded else {
+        return Err(UserFacingError::InvalidInput(String::from(
+            "--compare-algorithms only works for still images",
+        )));
+    };
+    let quantization = quantization(args)?;
+    let watermark = load_watermark(args)?;
+    let mut scores = vec![];
+    for &algorithm in Algorithm::value_variants() {
+        if algorithm == Algorithm::Auto {
+            continue;
+        }
+        let mut algorithm_args = args.clone();
+        algorithm_args.algorithm = Some(algorithm);
+        let source_image = image.clone();
+        let start = Instant::now();
+        let (prepared, mut target) = pixelate_still(
+            source_image,
+            &mut algorithm_args,
+            &source.name,
+            source.format.is_hdr(),
+            &quantization,
+        )?;
+        let duration = start.elapsed();
+        let psnr = diff::compare(&prepared, &target)?.psnr();
+        let ssim = diff::ssim(&prepared, &target)?;
+
+        let output = match &args.output {
+            Some(output) => algorithm_output_path(output, algorithm),
+            None => default_output_path(&source.name, algorithm_args.resolution, algorithm),
+        };
+        apply_overlays(&mut target, &algorithm_args, watermark.as_ref());
+        encode(&add_border(target, &algorithm_args), output.clone())
+            .context(PipelineStep::Encoding, &output)?;
+        scores.push(AlgorithmScore {
+            algorithm: algorithm.to_string(),
+            duration,
+            psnr,
+            ssim,
+            output,
+        });
+    }
+    println!("{}", Comparison(scores));
+    Ok(())
+}
+
+/// `--output` with the algorithm appended to its name, `out.png` becomes `out_nearest.png`
+fn algorithm_output_path(output: &Path, algorithm: Algorithm) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(ext) => format!("{stem}_{algorithm}.{}", ext.to_string_lossy()),
+        None => format!("{stem}_{algorithm}"),
+    };
+    output.with_file_name(name)
 }
 
 pub fn run(args: Args) -> Result<(), UserFacingError> {
+    if args.list_algorithms {
+        print_algorithms();
+        return Ok(());
+    }
+    if args.list_palettes {
+        print_palettes();
+        return Ok(());
+    }
+    match &args.command {
+        Some(Command::Diff(diff_args)) => return run_diff(diff_args),
+        Some(Command::Colors(colors_args)) => return run_colors(colors_args),
+        Some(Command::Batch(batch_args)) => return run_batch(batch_args),
+        Some(Command::Average(average_args)) => return run_average(average_args),
+        Some(Command::Capture(capture_args)) => return run_capture(&args, capture_args),
+        Some(Command::Render(render_args)) => return run_render(render_args),
+        Some(Command::Montage(montage_args)) => return run_montage(&args, montage_args),
+        #[cfg(unix)]
+        Some(Command::Daemon(daemon_args)) => return run_daemon(daemon_args),
+        #[cfg(feature = "tui")]
+        Some(Command::Tui(tui_args)) => return tui::run_tui(tui_args),
+        None => {}
+    }
+    if args.compare_algorithms {
+        return run_compare(&args);
+    }
+    if let Some(list) = &args.files_from {
+        return run_files_from(&args, list);
+    }
+    if let Some(input) = &args.input
+        && is_archive_path(input)
+    {
+        if args.output.is_some() {
+            return Err(UserFacingError::InvalidInput(String::from(
+                "--output cannot be used with an archive input",
+            )));
+        }
+        let jobs = archive_jobs(&args, input)?;
+        return run_parallel(jobs, default_jobs(), None, &Mutex::default());
+    }
+    let progress = args.progress.then_some(&print_progress as Progress);
+    if let Some(region) = args.screenshot {
+        let data = screenshot(region)?;
+        let mut args = args;
+        args.input = Some(PathBuf::from("screenshot.jpeg"));
+        return process(args, Some(&data), None, progress);
+    }
+    process(args, None, None, progress)
+}
+
+/// Receives the current step and how much of the whole run is done in percent
+type Progress<'a> = &'a dyn Fn(PipelineStep, u8);
+
+/// Progress line on stderr for `--progress`, redrawn in place until the run is done
+fn print_progress(step: PipelineStep, percent: u8) {
+    eprint!("\r{:<10} {:>3}%", step, percent);
+    if percent == 100 {
+        eprintln!();
+    }
+}
+
+fn report(progress: Option<Progress>, step: PipelineStep, percent: u8) {
+    if let Some(progress) = progress {
+        progress(step, percent);
+    }
+}
+
+/// A decoded input, shared by all outputs made from it
+#[derive(Clone)]
+struct Source {
+    /// Path of the input, URLs are named after their last path segment
+    name: PathBuf,
+    format: InputFormat,
+    decoded: Decoded,
+    /// Size of the input file in bytes
+    size: u64,
+}
+
+#[derive(Clone)]
+enum Decoded {
+    Image(ImageBuffer),
+    Frames(Vec<Frame>),
+}
+
+fn decode_options(args: &Args) -> DecodeOptions {
+    DecodeOptions {
+        #[cfg(feature = "hdr")]
+        tone_map_operator: match args.tone_map {
+            cli::ToneMap::Reinhard => hdr::reinhard,
+            cli::ToneMap::Aces => hdr::aces,
+        },
+        #[cfg(feature = "hdr")]
+        exposure: 2f32.powf(args.exposure),
+        limits: args.limits,
+        jpeg_backend: args.decoder,
+    }
+}
+
+/// Averages the images of `--stack` into the decoded input
+fn stack_inputs(image: ImageBuffer, args: &Args) -> Result<ImageBuffer, UserFacingError> {
+    let mut layers = vec![image];
+    for path in &args.stack {
+        let (name, data) = read_input(path)?;
+        let layer = decode_bytes(&name, &data, &decode_options(args))
+            .context(PipelineStep::Decoding, &name)?;
+        if (layer.width, layer.height) != (layers[0].width, layers[0].height) {
+            return Err(UserFacingError::InvalidInput(format!(
+                "{} is {}x{}, stacked images need the size of the input, {}x{}",
+                name.display(),
+                layer.width,
+                layer.height,
+                layers[0].width,
+                layers[0].height
+            )));
+        }
+        layers.push(layer);
+    }
+    // Gray layers are stacked with color ones as gray colors
+    if layers
+        .iter()
+        .any(|layer| layer.channels != layers[0].channels)
+    {
+        layers = layers.iter().map(ImageBuffer::to_rgb).collect();
+    }
+    let data = stack(
+        &layers
+            .iter()
+            .map(|layer| &layer.data[..])
+            .collect::<Vec<_>>(),
+    );
+    let first = &layers[0];
+    Ok(ImageBuffer::new(
+        first.width,
+        first.height,
+        first.channels,
+        data,
+    ))
+}
+
+/// Reads and decodes the input of `args`, from `data` instead of the file system if given
+fn load_source(args: &Args, data: Option<&[u8]>) -> Result<Source, UserFacingError> {
+    let input = args
+        .input
+        .as_ref()
+        .expect("input is required without a subcommand");
+    let (name, data) = match data {
+        Some(data) => (input.clone(), Cow::Borrowed(data)),
+        None => {
+            let (name, data) = read_input(input)?;
+            (name, Cow::Owned(data))
+        }
+    };
+    // Misnamed files are decoded by their content, not their extension
+    let format = InputFormat::detect(&name, &data);
+    let size = data.len() as u64;
+    let decoded = if format.is_animation() {
+        check_limits(format, &data, &args.limits).context(PipelineStep::Decoding, &name)?;
+        Decoded::Frames(read_gif_frames(&data[..]).context(PipelineStep::Decoding, &name)?)
+    } else {
+        let image = decode_bytes(&name, &data, &decode_options(args))
+            .context(PipelineStep::Decoding, &name)?;
+        debug!(
+            width = image.width,
+            height = image.height,
+            channels = image.channels,
+            "decoded"
+        );
+        Decoded::Image(image)
+    };
+    Ok(Source {
+        name,
+        format,
+        decoded,
+        size,
+    })
+}
+
+/// Whether both runs decode their input to the same pixels, so one decode can serve both
+fn same_source(a: &Args, b: &Args) -> bool {
+    #[cfg(feature = "hdr")]
+    if a.tone_map != b.tone_map || a.exposure != b.exposure {
+        return false;
+    }
+    a.input == b.input
+}
+
+/**
+ * Pixelates a single input, read from `data` instead of the file system if given.
+ * Outputs go into `archive` instead of their path if given. */
+fn process(
+    args: Args,
+    data: Option<&[u8]>,
+    archive: Option<&ArchiveWriter>,
+    progress: Option<Progress>,
+) -> Result<(), UserFacingError> {
+    report(progress, PipelineStep::Decoding, 0);
+    let source = load_source(&args, data)?;
+    render(args, source, archive, progress).map(drop)
+}
+
+/// Algorithms and dither modes with the descriptions their `--help` entries use
+fn print_algorithms() {
+    fn print_values<T: ValueEnum>(title: &str) {
+        println!("{title}:");
+        for value in T::value_variants().iter().filter_map(T::to_possible_value) {
+            let help = value
+                .get_help()
+                .map(ToString::to_string)
+                .unwrap_or_default();
+            println!("  {:<18}{help}", value.get_name());
+        }
+    }
+    print_values::<Algorithm>("Algorithms (--algorithm)");
+    print_values::<Dither>("Dither modes (--dither)");
+}
+
+fn print_palettes() {
+    for palette in BUILT_IN_PALETTES {
+        println!(
+            "{:<10}{:>3} colors  {}",
+            palette.name,
+            palette.colors.len(),
+            palette.description
+        );
+    }
+}
+
+/// How the colors of the result are reduced, as `args` asks for
+fn quantization(args: &Args) -> Result<Quantization, UserFacingError> {
+    let reduction = match (args.colors, args.threshold) {
+        _ if let Some(palette) = args.palette => {
+            ColorReduction::FixedPalette(Palette::new(palette.colors.to_vec())?)
+        }
+        (Some(colors), _) if args.colorblind_safe => ColorReduction::ColorblindSafe(colors.into()),
+        (Some(colors), _) => ColorReduction::Colors(colors.into()),
+        (None, Some(Threshold::Cutoff(cutoff))) => ColorReduction::Threshold(Some(cutoff)),
+        (None, Some(Threshold::Auto)) => ColorReduction::Threshold(None),
+        (None, None) if args.adaptive => ColorReduction::AdaptiveBitDepth(args.bit_depth),
+        (None, None) => match (&args.channel_levels, args.level_gamma, args.rounding) {
+            (Some(levels), _, _) => ColorReduction::Levels(levels.0.clone()),
+            // A single bit stays black and white by luminance, whatever the curve
+            (None, Some(gamma), _) if args.bit_depth > 1 => {
+                ColorReduction::Levels(gamma_levels(args.bit_depth, gamma))
+            }
+            (None, _, Rounding::Truncate) => ColorReduction::BitDepth(args.bit_depth),
+            (None, _, Rounding::Round) => ColorReduction::RoundedBitDepth(args.bit_depth),
+        },
+    };
+    let method = match (args.dither, &args.dither_matrix) {
+        (_, Some(path)) => Some(DitherMethod::Ordered(
+            fs::read_to_string(path)
+                .map_err(DitherMatrixError::from)
+                .and_then(|text| ThresholdMatrix::parse(&text))
+                .context(PipelineStep::Decoding, path)?,
+        )),
+        (Some(dither), None) => Some(match dither {
+            Dither::FloydSteinberg => DitherMethod::ErrorDiffusion(&FLOYD_STEINBERG),
+            Dither::Atkinson => DitherMethod::ErrorDiffusion(&ATKINSON),
+            Dither::Bayer2 => DitherMethod::Ordered(ThresholdMatrix::bayer(2)),
+            Dither::Bayer4 => DitherMethod::Ordered(ThresholdMatrix::bayer(4)),
+            Dither::Bayer8 => DitherMethod::Ordered(ThresholdMatrix::bayer(8)),
+        }),
+        // A single bit loses all shading without dithering
+        (None, None)
+            if matches!(
+                reduction,
+                ColorReduction::BitDepth(1) | ColorReduction::RoundedBitDepth(1)
+            ) =>
+        {
+            Some(DitherMethod::ErrorDiffusion(&FLOYD_STEINBERG))
+        }
+        (None, None) => None,
+    };
+    let dithering = method.map(|method| Dithering {
+        method,
+        strength: args.dither_strength,
+        serpentine: args.serpentine,
+    });
+    Ok(Quantization {
+        reduction,
+        dithering,
+        distance: args.distance,
+    })
+}
+
+/// Pixelates and encodes an already decoded input, returns the bytes written for all outputs
+fn render(
+    mut args: Args,
+    source: Source,
+    archive: Option<&ArchiveWriter>,
+    progress: Option<Progress>,
+) -> Result<u64, UserFacingError> {
+    let input = &source.name;
+
+    let quantization = quantization(&args)?;
+
+    let (output, formats) = outputs(&args, input);
+    if args.max_size.is_some()
+        && (source.format.is_animation() || formats.iter().any(|f| *f != OutputFormat::Jpeg))
+    {
+        return Err(UserFacingError::InvalidInput(String::from(
+            "--max-size only works for JPEG output",
+        )));
+    }
+
+    let image = match source.decoded {
+        Decoded::Image(image) if !args.stack.is_empty() => stack_inputs(image, &args)?,
+        Decoded::Image(image) => image,
+        Decoded::Frames(_) if !args.stack.is_empty() => {
+            return Err(UserFacingError::InvalidInput(String::from(
+                "--stack only works for still images",
+            )));
+        }
+        Decoded::Frames(frames) => {
+            return run_animation(
+                args,
+                input,
+                frames,
+                &output,
+                quantization,
+                archive,
+                progress,
+            );
+        }
+    };
+    report(progress, PipelineStep::Pixelating, 30);
+    let (image, mut target) = pixelate_still(
+        image,
+        &mut args,
+        input,
+        source.format.is_hdr(),
+        &quantization,
+    )?;
+    if args.stats {
+        println!("{}", image_stats(&image, &target, args.resolution.aspect()));
+    }
+    if args.report_loss {
+        println!("{}", loss_report(&image, &target, args.resolution.aspect()));
+    }
+    let watermark = load_watermark(&args)?;
+    apply_overlays(&mut target, &args, watermark.as_ref());
+    // Grid formats get one pixel per block, a thumbnail is its own grid
+    let grid = formats
+        .iter()
+        .any(OutputFormat::is_grid)
+        .then(|| match args.thumbnail {
+            Some(_) => target.clone(),
+            None => sample_blocks(&target, args.resolution.aspect()),
+        });
+    let target = add_border(target, &args);
+
+    // The result is encoded once per format from the same pixels
+    let c_header = CHeaderEncoder {
+        pixel_format: args.pixel_format,
+    };
+    let json = JsonEncoder {
+        values: args.matrix_values,
+    };
+    let csv = CsvEncoder {
+        values: args.matrix_values,
+    };
+    let jpeg = JpegEncoder {
+        quality: JPEG_QUALITY,
+        exif_thumbnail: args.exif_thumbnail,
+        backend: args.encoder,
+    };
+    let count = formats.len();
+    let mut written = 0;
+    for (i, format) in formats.into_iter().enumerate() {
+        report(
+            progress,
+            PipelineStep::Encoding,
+            (70 + 30 * i / count) as u8,
+        );
+        let path = format.output_path(&output);
+        match args.max_size {
+            Some(max_bytes) => {
+                let data = encode_jpeg_budget(&target, max_bytes, &args)
+                    .context(PipelineStep::Encoding, &path)?;
+                written += save(&path, archive, |writer| {
+                    writer.write_all(&data).map_err(EncodeError::from)
+                })?;
+            }
+            None => {
+                let pixels = match &grid {
+                    Some(grid) if format.is_grid() => grid,
+                    _ => &target,
+                };
+                let encoder: &dyn ImageEncoder = match format {
+                    OutputFormat::CHeader => &c_header,
+                    OutputFormat::Json => &json,
+                    OutputFormat::Csv => &csv,
+                    OutputFormat::Jpeg => &jpeg,
+                    _ => format.encoder(),
+                };
+                written += save(&path, archive, |writer| encoder.encode(pixels, writer))?;
+            }
+        }
+    }
+
+    report(progress, PipelineStep::Encoding, 100);
+
+    // Both buffers have the size of the source, the next image of a batch can reuse them
+    pool::recycle(image.data);
+    pool::recycle(target.data);
+    Ok(written)
+}
+
+/**
+ * Crops, fits, prepares and pixelates a still image. Returns the prepared image and the
+ * result, `args` takes on the resolution that was actually used. */
+fn pixelate_still(
+    mut image: ImageBuffer,
+    args: &mut Args,
+    input: &Path,
+    is_hdr: bool,
+    quantization: &Quantization,
+) -> Result<(ImageBuffer, ImageBuffer), UserFacingError> {
+    if let Some(quad) = &args.perspective {
+        image = warp_perspective(&image, quad);
+    }
+    if args.autocrop {
+        let region = content_bounds(&image, args.autocrop_tolerance);
+        debug!(?region, "autocrop");
+        image = crop(&image, region);
+    }
+    if args.match_orientation {
+        args.resolution = args.resolution.oriented_like(image.width, image.height);
+    }
+    if args.clamp_resolution {
+        clamp_resolution(args, input, image.width, image.height);
+    }
+    let mut image = fit(image, args);
+    prepare(&mut image, args, is_hdr);
+    // Palettes are RGB, gray images are reduced as gray colors like in `run_colors`
+    if quantization.reduction.uses_palette() && image.channels == 1 {
+        image = image.to_rgb();
+    }
+    let algorithm =
+        resolve_algorithm(&image, args, quantization).context(PipelineStep::Pixelating, input)?;
+    let algo = interpolation(algorithm, args)?;
+    let target = pixelate(&image, args, algo.as_ref(), quantization)
+        .context(PipelineStep::Pixelating, input)?;
+    Ok((image, target))
+}
+
+/// Where the result for `input` is written, and in which formats
+fn outputs(args: &Args, input: &Path) -> (PathBuf, Vec<OutputFormat>) {
     let algo = args.algorithm.unwrap_or(Algorithm::AverageArea);
+    let output = args.output.clone().unwrap_or_else(|| match args.thumbnail {
+        Some(max_dimension) => default_thumbnail_path(input, max_dimension, algo),
+        None => default_output_path(input, args.resolution, algo),
+    });
+    let formats = if args.format.is_empty() {
+        vec![OutputFormat::from_path(&output).unwrap_or(OutputFormat::Jpeg)]
+    } else {
+        args.format.clone()
+    };
+    (output, formats)
+}
+
+/**
+ * Encodes the result as JPEG at the highest quality within `max_bytes`. With
+ * `--shrink-to-fit` the image is scaled down by a quarter at a time while even the lowest
+ * quality is too large. */
+fn encode_jpeg_budget(
+    target: &ImageBuffer,
+    max_bytes: usize,
+    args: &Args,
+) -> Result<Vec<u8>, UserFacingError> {
+    let mut image = Cow::Borrowed(target);
+    loop {
+        if let Some(data) =
+            encode_jpeg_within(&image, max_bytes, args.exif_thumbnail, args.encoder)?
+        {
+            return Ok(data);
+        }
+        if !args.shrink_to_fit || image.width.max(image.height) <= 1 {
+            return Err(EncodeError::OverBudget(max_bytes).into());
+        }
+        let (width, height) = ((image.width * 3 / 4).max(1), (image.height * 3 / 4).max(1));
+        let data = NearestNeighborInterpolation.downsample(
+            &image.data,
+            image.width,
+            image.height,
+            width,
+            height,
+            image.channels,
+        )?;
+        debug!(width, height, "shrinking to fit the size budget");
+        image = Cow::Owned(ImageBuffer::new(width, height, image.channels, data));
+    }
+}
+
+/// Reads an input into memory, URLs are downloaded and named after their last path segment
+fn read_input(input: &Path) -> Result<(PathBuf, Vec<u8>), UserFacingError> {
+    #[cfg(feature = "http")]
+    if let Some(url) = input.to_str()
+        && cli::is_url(input)
+    {
+        return fetch::download(url).context(PipelineStep::Downloading, input);
+    }
+    let data = fs::read(input)
+        .map_err(decoder::DecodeError::from)
+        .context(PipelineStep::Decoding, input)?;
+    Ok((input.to_path_buf(), data))
+}
+
+/// Reads and decodes the input of a subcommand
+fn load_image(input: &Path, options: &DecodeOptions) -> Result<ImageBuffer, UserFacingError> {
+    let (name, data) = read_input(input)?;
+    decode_bytes(&name, &data, options).context(PipelineStep::Decoding, input)
+}
+
+/// Maps the source onto the grid, stretching it unless padding or cropping is asked for
+fn fit(image: ImageBuffer, args: &Args) -> ImageBuffer {
+    let aspect = args.resolution.aspect();
+    let image = match (args.fit, args.pad_square) {
+        (Fit::Contain, _) => pad_to_aspect(&image, aspect, [0, 0, 0]),
+        (Fit::Cover, _) => crop(&image, center_region(&image, aspect)),
+        (Fit::Smart, _) => crop(&image, salient_region(&[&image], aspect)),
+        (Fit::Stretch, Some(color)) => pad_to_aspect(&image, aspect, color),
+        (Fit::Stretch, None) => image,
+    };
+    match args.upscale && args.thumbnail.is_none() {
+        true => enlarge_to(image, aspect),
+        false => image,
+    }
+}
+
+/**
+ * Enlarges a source smaller than the grid by the smallest whole factor that gives every
+ * block at least one pixel, so small icons are pixelated instead of rejected. Larger
+ * sources are returned as they are. */
+fn enlarge_to(image: ImageBuffer, (grid_width, grid_height): (usize, usize)) -> ImageBuffer {
+    let factor = grid_width
+        .div_ceil(image.width.max(1))
+        .max(grid_height.div_ceil(image.height.max(1)));
+    if factor <= 1 {
+        return image;
+    }
+    let (width, height) = (image.width * factor, image.height * factor);
+    debug!(width, height, "enlarging small source");
+    let mut enlarged = ImageBuffer::new(
+        width,
+        height,
+        image.channels,
+        vec![0; width * height * image.channels],
+    );
+    NearestNeighborInterpolation
+        .upsample_into(&image.data, image.width, image.height, &mut enlarged)
+        .expect("the enlarged image is not smaller than the source");
+    enlarged
+}
+
+/// Shrinks the grid to a source smaller than it, which could not be downsampled otherwise
+fn clamp_resolution(args: &mut Args, input: &Path, width: usize, height: usize) {
+    let clamped = args.resolution.clamped_to(width, height);
+    if clamped != args.resolution {
+        warn!(
+            path = %input.display(),
+            "resolution {} exceeds the {width}x{height} source, using {clamped}",
+            args.resolution
+        );
+        args.resolution = clamped;
+    }
+}
+
+/// Watermark and caption drawn onto the pixelated result
+fn apply_overlays(target: &mut ImageBuffer, args: &Args, watermark: Option<&Watermark>) {
+    if let Some(watermark) = watermark {
+        watermark.apply(target);
+    }
+    if let Some(caption) = &args.caption {
+        draw_caption(target, caption, args.caption_color);
+    }
+}
+
+/// Frames the result, blocks follow the grid so a border of one block looks like a cell
+fn add_border(target: ImageBuffer, args: &Args) -> ImageBuffer {
+    let (horizontal, vertical) = match args.border {
+        None => return target,
+        Some(BorderWidth::Pixels(width)) => (width, width),
+        // Thumbnails are not upsampled, each of their pixels is a block
+        Some(BorderWidth::Blocks(width)) if args.thumbnail.is_some() => (width, width),
+        Some(BorderWidth::Blocks(width)) => {
+            let (columns, rows) = args.resolution.aspect();
+            (
+                width * target.width / columns.max(1),
+                width * target.height / rows.max(1),
+            )
+        }
+    };
+    pad(
+        &target,
+        target.width + 2 * horizontal,
+        target.height + 2 * vertical,
+        args.border_color,
+    )
+}
+
+fn load_watermark(args: &Args) -> Result<Option<Watermark>, UserFacingError> {
+    let Some(path) = &args.watermark else {
+        return Ok(None);
+    };
+    fs::read(path)
+        .map_err(overlay::WatermarkError::from)
+        .and_then(|png| Watermark::new(&png, args.watermark_pos, args.watermark_opacity))
+        .map(Some)
+        .context(PipelineStep::Decoding, path)
+}
+
+/// Filters applied to the decoded source before pixelation
+fn prepare(image: &mut ImageBuffer, args: &Args, is_hdr: bool) {
+    let _span = info_span!("prepare").entered();
+    let (src_width, src_height, channels) = (image.width, image.height, image.channels);
+
+    // HDR inputs already got their exposure before tone mapping
+    let exposure = if is_hdr { 0.0 } else { args.exposure };
+    if exposure != 0.0 || args.gamma != 1.0 {
+        adjust_exposure_gamma(&mut image.data, exposure, args.gamma);
+    }
+    if let Some(balance) = args.white_balance
+        && channels >= 3
+    {
+        let light = match balance {
+            WhiteBalance::Auto => average_linear(&image.data, channels),
+            WhiteBalance::Kelvin(kelvin) => kelvin_to_linear(kelvin),
+        };
+        apply_channel_gains(&mut image.data, channels, neutral_gains(light));
+    }
+    if (args.temperature != 0.0 || args.tint != 0.0) && channels >= 3 {
+        let gains = grading_gains(args.temperature, args.tint);
+        apply_channel_gains(&mut image.data, channels, gains);
+    }
+    if let Some(levels) = args.auto_levels {
+        auto_levels(
+            &mut image.data,
+            channels,
+            args.levels_clip,
+            levels == Levels::Channels,
+        );
+    }
+    if let Some(equalization) = args.equalize {
+        let tiles = match equalization {
+            Equalize::Global => 1,
+            Equalize::Clahe => CLAHE_TILES,
+        };
+        equalize(&mut image.data, src_width, src_height, channels, tiles);
+    }
+    if let Some(denoise) = args.denoise {
+        let radius = args.denoise_radius;
+        image.data = match denoise {
+            Denoise::Median => median_filter(&image.data, src_width, src_height, channels, radius),
+            Denoise::NonLocalMeans => {
+                non_local_means(&image.data, src_width, src_height, channels, 2 * radius)
+            }
+        };
+    }
+    if let Some(stylize) = args.stylize {
+        let radius = args.stylize_radius;
+        image.data = match stylize {
+            Stylize::Kuwahara => kuwahara(&image.data, src_width, src_height, channels, radius),
+            Stylize::OilPaint => oil_paint(&image.data, src_width, src_height, channels, radius),
+        };
+    }
+    if let Some(amount) = args.sharpen {
+        unsharp_mask(
+            &mut image.data,
+            src_width,
+            src_height,
+            channels,
+            args.sharpen_sigma,
+            amount,
+        );
+    }
+    if let Some(sigma) = args.blur
+        && args.blur_stage == Stage::Before
+    {
+        image.data = gaussian_blur(&image.data, src_width, src_height, channels, sigma);
+    }
+    if let Some(inversion) = args.invert
+        && args.invert_stage == Stage::Before
+    {
+        invert(&mut image.data, channels, inversion == Invert::Luma);
+    }
+}
+
+/// Pixels per block of the proxy `--algorithm auto` scores the algorithms on
+const PROXY_SCALE: usize = 4;
+
+/**
+ * The algorithm of `--algorithm`. `auto` pixelates a copy of `image` reduced to a few pixels
+ * per block with every algorithm and picks the one whose result scores best against it, by
+ * SSIM less a hundredth of the mean CIEDE2000 difference so both structure and color count. */
+fn resolve_algorithm(
+    image: &ImageBuffer,
+    args: &Args,
+    quantization: &Quantization,
+) -> Result<Algorithm, InterpolationError> {
+    let algorithm = args.algorithm.unwrap_or(Algorithm::AverageArea);
+    if algorithm != Algorithm::Auto || args.thumbnail.is_some() {
+        return Ok(match algorithm {
+            // Thumbnails are not upsampled again, there is nothing to compare
+            Algorithm::Auto => Algorithm::AverageArea,
+            algorithm => algorithm,
+        });
+    }
+
+    let grid = args.resolution.aspect();
+    let (width, height) = (
+        (grid.0 * PROXY_SCALE).min(image.width),
+        (grid.1 * PROXY_SCALE).min(image.height),
+    );
+    let data = AverageAreaInterpolation.downsample(
+        &image.data,
+        image.width,
+        image.height,
+        width,
+        height,
+        image.channels,
+    )?;
+    let proxy = ImageBuffer::new(width, height, image.channels, data);
+
+    let mut best = (Algorithm::AverageArea, f64::NEG_INFINITY);
+    for (algorithm, algo) in [
+        (
+            Algorithm::AverageArea,
+            &AverageAreaInterpolation as &dyn InterpolationAlgorithm,
+        ),
+        (Algorithm::Nearestneighbor, &NearestNeighborInterpolation),
+    ] {
+        let result = run_interpolation(algo, &proxy, grid, quantization, None)?;
+        let ssim = diff::ssim(&proxy, &result).expect("the result has the size of the proxy");
+        let delta_e = diff::compare(&proxy, &result)
+            .expect("the result has the size of the proxy")
+            .mean_delta_e;
+        let score = ssim - delta_e / 100.0;
+        debug!(%algorithm, ssim, delta_e, score, "scored algorithm");
+        pool::recycle(result.data);
+        if score > best.1 {
+            best = (algorithm, score);
+        }
+    }
+    Ok(best.0)
+}
+
+/// The interpolation of a resolved algorithm, wrapped for `--supersample`
+fn interpolation(
+    algorithm: Algorithm,
+    args: &Args,
+) -> Result<Box<dyn InterpolationAlgorithm>, UserFacingError> {
+    let inner = algorithm_interpolation(algorithm, args)?;
+    Ok(match args.supersample {
+        1 => inner,
+        factor => Box::new(Supersampled { inner, factor }),
+    })
+}
+
+/// The interpolation of a resolved algorithm, on the GPU with `--gpu`
+#[cfg_attr(not(feature = "gpu"), allow(unused_variables))]
+fn algorithm_interpolation(
+    algorithm: Algorithm,
+    args: &Args,
+) -> Result<Box<dyn InterpolationAlgorithm>, UserFacingError> {
+    #[cfg(feature = "gpu")]
+    if args.gpu {
+        return Ok(Box::new(gpu::GpuInterpolation::new(match algorithm {
+            Algorithm::Nearestneighbor => gpu::GpuSampling::Nearest,
+            Algorithm::AverageArea | Algorithm::Auto => gpu::GpuSampling::AverageArea,
+        })?));
+    }
+    Ok(match algorithm {
+        Algorithm::Nearestneighbor => Box::new(NearestNeighborInterpolation),
+        Algorithm::AverageArea | Algorithm::Auto => Box::new(AverageAreaInterpolation),
+    })
+}
+
+/// Pixelates or thumbnails the prepared image, including the filters that run afterwards
+fn pixelate(
+    image: &ImageBuffer,
+    args: &Args,
+    algo: &dyn InterpolationAlgorithm,
+    quantization: &Quantization,
+) -> Result<ImageBuffer, UserFacingError> {
+    let mut target = match args.thumbnail {
+        Some(max_dimension) => run_thumbnail(algo, image, max_dimension, quantization)?,
+        None => {
+            let effect = |grid: &mut ImageBuffer| {
+                if let Some(amount) = args.glitch {
+                    glitch(grid, amount, args.seed);
+                }
+                if args.print_blocks {
+                    for_each_block(grid, |x, y, color| {
+                        let rgb = match color.len() {
+                            1 => [color[0]; 3],
+                            _ => [color[0], color[1], color[2]],
+                        };
+                        println!("{x} {y} {}", to_hex(rgb));
+                    });
+                }
+            };
+            let grid_effect = (args.glitch.is_some() || args.print_blocks)
+                .then_some(&effect as &dyn Fn(&mut ImageBuffer));
+            run_interpolation(
+                algo,
+                image,
+                args.resolution.aspect(),
+                quantization,
+                grid_effect,
+            )?
+        }
+    };
+    // Remapping needs the exact quantized colors, so it runs before anything blends them
+    if !args.remap.is_empty() {
+        target = target.to_rgb();
+        remap_colors(&mut target, &args.remap);
+    }
+    if let Some(inversion) = args.invert
+        && args.invert_stage == Stage::After
+    {
+        invert(&mut target.data, target.channels, inversion == Invert::Luma);
+    }
+    if let Some(amount) = args.soften {
+        soften_blocks(&mut target, args.resolution.aspect(), amount);
+    }
+
+    if let Some(sigma) = args.blur
+        && args.blur_stage == Stage::After
+    {
+        target.data = gaussian_blur(
+            &target.data,
+            target.width,
+            target.height,
+            target.channels,
+            sigma,
+        );
+    }
+    if let Some(direction) = args.pixel_sort {
+        pixel_sort(&mut target, direction, args.sort_threshold);
+    }
+    if let Some(offset) = args.chromatic_aberration {
+        chromatic_aberration(&mut target, offset);
+    }
+    Ok(target)
+}
+
+/**
+ * Pixelates every frame of an animated GIF. With `--colors` a single palette is built
+ * for the whole animation up front so colors stay stable from frame to frame. */
+#[allow(clippy::too_many_arguments)]
+fn run_animation(
+    mut args: Args,
+    input: &Path,
+    frames: Vec<Frame>,
+    output: &Path,
+    quantization: Quantization,
+    archive: Option<&ArchiveWriter>,
+    progress: Option<Progress>,
+) -> Result<u64, UserFacingError> {
+    if args
+        .format
+        .iter()
+        .any(|format| *format != OutputFormat::Gif)
+        || !is_animation_path(output)
+    {
+        return Err(AnimationError::UnsupportedOutput(output.display().to_string()).into());
+    }
+
+    let mut frames = select_frames(frames, args.start, args.duration, args.fps)
+        .context(PipelineStep::Pixelating, input)?;
+    if let Some(quad) = &args.perspective {
+        for frame in frames.iter_mut() {
+            frame.image = warp_perspective(&frame.image, quad);
+        }
+    }
+    // Every frame is cropped the same, to the content of the whole animation
+    if args.autocrop
+        && let Some(region) = frames
+            .iter()
+            .map(|frame| content_bounds(&frame.image, args.autocrop_tolerance))
+            .reduce(Region::union)
+    {
+        for frame in frames.iter_mut() {
+            frame.image = crop(&frame.image, region);
+        }
+    }
+    if args.match_orientation || args.clamp_resolution {
+        let first = &frames.first().ok_or(AnimationError::Empty)?.image;
+        if args.match_orientation {
+            args.resolution = args.resolution.oriented_like(first.width, first.height);
+        }
+        if args.clamp_resolution {
+            clamp_resolution(&mut args, input, first.width, first.height);
+        }
+    }
+    let args = &args;
+    if args.fit == Fit::Smart {
+        let images: Vec<&ImageBuffer> = frames.iter().map(|frame| &frame.image).collect();
+        let window = salient_region(&images, args.resolution.aspect());
+        for frame in frames.iter_mut() {
+            frame.image = crop(&frame.image, window);
+        }
+    }
+    let mut frames: Vec<Frame> = frames
+        .into_iter()
+        .map(|frame| Frame {
+            image: fit(frame.image, args),
+            ..frame
+        })
+        .collect();
+    for frame in frames.iter_mut() {
+        prepare(&mut frame.image, args, false);
+    }
+    // Nothing is pixelated without frames, any algorithm will do
+    let algorithm = match frames.first() {
+        Some(first) => resolve_algorithm(&first.image, args, &quantization)
+            .context(PipelineStep::Pixelating, input)?,
+        None => Algorithm::AverageArea,
+    };
+    let algo = interpolation(algorithm, args)?;
 
-    let chosen_interpolation_algo: &dyn InterpolationAlgorithm = match algo {
-        Algorithm::AverageArea => &AverageAreaInterpolation,
-        Algorithm::Nearestneighbor => &NearestNeighborInterpolation,
+    let watermark = load_watermark(args)?;
+    let shared = match quantization.reduction {
+        ColorReduction::Colors(colors) => {
+            let images: Vec<ImageBuffer> = frames.iter().map(|f| f.image.clone()).collect();
+            Some(shared_palette(&images, colors).context(PipelineStep::Pixelating, input)?)
+        }
+        ColorReduction::ColorblindSafe(colors) => {
+            let images: Vec<ImageBuffer> = frames.iter().map(|f| f.image.clone()).collect();
+            let candidates = shared_palette(&images, colors * CANDIDATES_PER_COLOR)
+                .context(PipelineStep::Pixelating, input)?;
+            Some(candidates.colorblind_safe(colors))
+        }
+        _ => None,
     };
+    let count = frames.len();
+    for (i, frame) in frames.iter_mut().enumerate() {
+        report(
+            progress,
+            PipelineStep::Pixelating,
+            (20 + 60 * i / count) as u8,
+        );
+        let reduction = match &shared {
+            Some(palette) if args.refine_palette => ColorReduction::FixedPalette(
+                palette
+                    .refine(&frame.image.data, 3)
+                    .context(PipelineStep::Pixelating, input)?,
+            ),
+            Some(palette) => ColorReduction::FixedPalette(palette.clone()),
+            None => quantization.reduction.clone(),
+        };
+        let frame_quantization = Quantization {
+            reduction,
+            dithering: quantization.dithering.clone(),
+            distance: quantization.distance,
+        };
+        let mut target = pixelate(&frame.image, args, algo.as_ref(), &frame_quantization)
+            .context(PipelineStep::Pixelating, input)?;
+        apply_overlays(&mut target, args, watermark.as_ref());
+        frame.image = add_border(target, args);
+    }
 
-    let output = args
-        .output
-        .clone()
-        .unwrap_or_else(|| default_output_path(&args.input, args.resolution, algo));
+    report(progress, PipelineStep::Encoding, 80);
+    let written = save(output, archive, |writer| write_gif_frames(&frames, writer))?;
+    report(progress, PipelineStep::Encoding, 100);
+    Ok(written)
+}
 
-    let (pixel_vec, metadata) = decode(&args.input);
+/// Writes an output to its path, or into the archive of a batch run, and returns its size
+fn save<E: Into<UserFacingError>>(
+    path: &Path,
+    archive: Option<&ArchiveWriter>,
+    write: impl FnOnce(&mut dyn Write) -> Result<(), E>,
+) -> Result<u64, UserFacingError> {
+    match archive {
+        Some(archive) => {
+            let mut data = Vec::new();
+            write(&mut data).context(PipelineStep::Encoding, path)?;
+            archive
+                .add(path, &data)
+                .context(PipelineStep::Encoding, path)?;
+            Ok(data.len() as u64)
+        }
+        None => {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(encoder::EncodeError::from)
+                    .context(PipelineStep::Encoding, path)?;
+            }
+            let mut file = File::create(path)
+                .map(BufWriter::new)
+                .map_err(encoder::EncodeError::from)
+                .context(PipelineStep::Encoding, path)?;
+            write(&mut file).context(PipelineStep::Encoding, path)?;
+            file.flush()
+                .and_then(|_| file.get_ref().metadata())
+                .map(|metadata| metadata.len())
+                .map_err(encoder::EncodeError::from)
+                .context(PipelineStep::Encoding, path)
+        }
+    }
+}
 
-    let interpolated_pixels: Vec<u8> = run_interpolation(
-        chosen_interpolation_algo,
-        pixel_vec,
-        args.resolution,
-        args.bit_depth,
-        metadata,
-    )?;
-    encode(interpolated_pixels, metadata.height, metadata.width, output);
-    Ok(())
+/// Stage timings are printed to stderr when their span closes
+fn init_tracing(verbose: u8) {
+    let level = match verbose {
+        0 => Level::WARN,
+        1 => Level::INFO,
+        _ => Level::DEBUG,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_target(false)
+        .with_writer(std::io::stderr)
+        .init();
 }
 
-fn main() -> Result<(), UserFacingError> {
+fn main() -> ExitCode {
     let args = Args::parse();
-    let _ = run(args);
-    Ok(())
+    init_tracing(args.verbose);
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
 }
+
 #[cfg(test)]
 mod tests {
 
     use jpeg_decoder::Decoder;
 
-    use crate::cli::{Algorithm, Args};
-    use crate::run;
+    use crate::animation::{Frame, read_gif_frames, write_gif_frames};
+    use crate::archive::ArchiveWriter;
+    use crate::cli::Args;
+    use crate::palette::built_in_palette;
+    use crate::quantization::{DitherMethod, FLOYD_STEINBERG};
+    use crate::types::ImageBuffer;
+    use crate::{Job, encode, enlarge_to, process, quantization, run, run_parallel};
+    use clap::Parser;
     use std::fs::File;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
     use std::{env, fs};
 
+    #[test]
+    fn test_progress_reaches_completion() {
+        let output_path = env::temp_dir().join("output_progress.png");
+        let args = Args::parse_from([
+            "smolres",
+            "--input",
+            "examples/horse.jpeg",
+            "--output",
+            output_path.to_str().unwrap(),
+            "--format",
+            "png,jpeg",
+        ]);
+        let reports = Mutex::new(Vec::new());
+        let record = |_step, percent| reports.lock().unwrap().push(percent);
+
+        process(args, None, None, Some(&record)).expect("process() should succeed");
+
+        let reports = reports.into_inner().unwrap();
+        assert!(reports.is_sorted());
+        assert_eq!(reports.first(), Some(&0));
+        assert_eq!(reports.last(), Some(&100));
+
+        for ext in ["png", "jpeg"] {
+            fs::remove_file(output_path.with_extension(ext)).unwrap();
+        }
+    }
+
     #[test]
     fn test_run_method_average_area() {
-        let input_path = PathBuf::from("examples/horse_3.jpeg"); // Ensure this file exists
+        let input_path = PathBuf::from("examples/horse.jpeg"); // Ensure this file exists
         let temp_dir = env::temp_dir();
-        let output_path = temp_dir.join("output.jpeg");
-        let args = Args {
-            input: input_path.clone(),
-            output: Some(output_path.clone()),
-            resolution: 16,
-            bit_depth: 4,
-            algorithm: Some(Algorithm::AverageArea),
-        };
+        let output_path = temp_dir.join("output_average_area.jpeg");
+        let args = Args::parse_from([
+            "smolres",
+            "--input",
+            input_path.to_str().unwrap(),
+            "--output",
+            output_path.to_str().unwrap(),
+            "--resolution",
+            "16",
+            "--bit-depth",
+            "4",
+            "--algorithm",
+            "average-area",
+        ]);
 
         run(args).expect("run() should succeed");
 
@@ -99,14 +1889,20 @@ mod tests {
     fn test_run_method_nearest_neighbor() {
         let input_path = PathBuf::from("examples/horse.jpeg"); // Ensure this file exists
         let temp_dir = env::temp_dir();
-        let output_path = temp_dir.join("output.jpeg");
-        let args = Args {
-            input: input_path.clone(),
-            output: Some(output_path.clone()),
-            resolution: 16,
-            bit_depth: 4,
-            algorithm: Some(Algorithm::Nearestneighbor),
-        };
+        let output_path = temp_dir.join("output_nearest_neighbor.jpeg");
+        let args = Args::parse_from([
+            "smolres",
+            "--input",
+            input_path.to_str().unwrap(),
+            "--output",
+            output_path.to_str().unwrap(),
+            "--resolution",
+            "16",
+            "--bit-depth",
+            "4",
+            "--algorithm",
+            "nearestneighbor",
+        ]);
 
         run(args).expect("run() should succeed");
 
@@ -129,4 +1925,894 @@ mod tests {
         // Clean up
         fs::remove_file(output_path).unwrap();
     }
+
+    /// Pixels of a PNG written by a test
+    fn read_png(path: &Path) -> Vec<u8> {
+        let decoder = png::Decoder::new(std::io::BufReader::new(
+            File::open(path).expect("Failed to open output image"),
+        ));
+        let mut reader = decoder.read_info().expect("Failed to decode output image");
+        let mut pixels = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut pixels).unwrap();
+        pixels
+    }
+
+    #[test]
+    fn test_run_blur_after_pixelation() {
+        let output_path = env::temp_dir().join("output_blur_stage.png");
+        let unique_colors = |extra: &[&str]| {
+            let mut arguments = vec![
+                "smolres",
+                "--input",
+                "examples/horse.jpeg",
+                "--output",
+                output_path.to_str().unwrap(),
+                "--resolution",
+                "8",
+                "--bit-depth",
+                "8",
+                "--algorithm",
+                "nearestneighbor",
+            ];
+            arguments.extend(extra);
+            run(Args::parse_from(arguments)).expect("run() should succeed");
+            let pixels = read_png(&output_path);
+            let colors: std::collections::HashSet<&[u8]> = pixels.chunks_exact(3).collect();
+            colors.len()
+        };
+
+        // Sharp blocks have one color each, blurring the result blends their edges
+        assert!(unique_colors(&[]) <= 64);
+        assert!(unique_colors(&["--blur", "2", "--blur-stage", "after"]) > 64);
+
+        fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_sharpen_increases_contrast() {
+        let output_path = env::temp_dir().join("output_sharpen.png");
+        let variance = |extra: &[&str]| {
+            let mut arguments = vec![
+                "smolres",
+                "--input",
+                "examples/horse.jpeg",
+                "--output",
+                output_path.to_str().unwrap(),
+                "--resolution",
+                "32",
+                "--bit-depth",
+                "8",
+                "--algorithm",
+                "nearestneighbor",
+            ];
+            arguments.extend(extra);
+            run(Args::parse_from(arguments)).expect("run() should succeed");
+            let pixels = read_png(&output_path);
+            let mean = pixels.iter().map(|&v| v as f64).sum::<f64>() / pixels.len() as f64;
+            pixels
+                .iter()
+                .map(|&v| (v as f64 - mean).powi(2))
+                .sum::<f64>()
+                / pixels.len() as f64
+        };
+
+        // The sampled pixels come from sharpened edges, which pull apart dark and bright
+        let plain = variance(&[]);
+        assert!(variance(&["--sharpen", "0.8"]) > plain);
+
+        fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_one_bit_is_dithered_by_default() {
+        let method = |extra: &[&str]| {
+            let mut arguments = vec!["smolres", "--input", "examples/horse.jpeg"];
+            arguments.extend(extra);
+            quantization(&Args::parse_from(arguments))
+                .unwrap()
+                .dithering
+                .map(|dithering| dithering.method)
+        };
+        assert_eq!(
+            method(&["--bit-depth", "1"]),
+            Some(DitherMethod::ErrorDiffusion(&FLOYD_STEINBERG))
+        );
+        assert!(matches!(
+            method(&["--bit-depth", "1", "--dither", "bayer4"]),
+            Some(DitherMethod::Ordered(_))
+        ));
+        assert_eq!(method(&["--threshold", "127"]), None);
+        assert_eq!(method(&["--bit-depth", "2"]), None);
+    }
+
+    /// Writes a grayscale JPEG with a horizontal gradient
+    fn gray_jpeg(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(name);
+        let data = (0..64 * 64).map(|i| (i % 64 * 4) as u8).collect();
+        encode(&ImageBuffer::new(64, 64, 1, data), path.clone()).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_run_clamp_resolution() {
+        let input_path = env::temp_dir().join("input_clamp.jpeg");
+        let output_path = env::temp_dir().join("output_clamp.png");
+        let data = (0..8 * 5 * 3).map(|i| (i * 7 % 256) as u8).collect();
+        encode(&ImageBuffer::new(8, 5, 3, data), input_path.clone()).unwrap();
+        // The clamped grid is as large as the source, which is then upsampled to itself
+        for algorithm in ["average-area", "nearestneighbor"] {
+            run(Args::parse_from([
+                "smolres",
+                "--input",
+                input_path.to_str().unwrap(),
+                "--output",
+                output_path.to_str().unwrap(),
+                "--resolution",
+                "16",
+                "--clamp-resolution",
+                "--algorithm",
+                algorithm,
+            ]))
+            .expect("a grid larger than the source should be clamped");
+            assert_eq!(read_png(&output_path).len(), 8 * 5 * 3);
+        }
+        fs::remove_file(input_path).unwrap();
+        fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_colors_on_gray_input() {
+        let input_path = gray_jpeg("input_gray_colors.jpeg");
+        let output_path = env::temp_dir().join("output_gray_colors.png");
+        for extra in [
+            &[][..],
+            &["--dither", "floyd-steinberg"],
+            &["--colorblind-safe"],
+        ] {
+            let mut arguments = vec![
+                "smolres",
+                "--input",
+                input_path.to_str().unwrap(),
+                "--output",
+                output_path.to_str().unwrap(),
+                "--resolution",
+                "8",
+                "--colors",
+                "4",
+            ];
+            arguments.extend(extra);
+            run(Args::parse_from(arguments)).expect("gray inputs should get a palette");
+
+            let pixels = read_png(&output_path);
+            let colors: std::collections::HashSet<&[u8]> = pixels.chunks_exact(3).collect();
+            assert!(colors.len() <= 4);
+            assert!(
+                colors
+                    .iter()
+                    .all(|color| color[0] == color[1] && color[1] == color[2])
+            );
+        }
+        fs::remove_file(input_path).unwrap();
+        fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_built_in_palette_on_gray_input() {
+        let input_path = gray_jpeg("input_gray_palette.jpeg");
+        let output_path = env::temp_dir().join("output_gray_palette.png");
+        let args = Args::parse_from([
+            "smolres",
+            "--input",
+            input_path.to_str().unwrap(),
+            "--output",
+            output_path.to_str().unwrap(),
+            "--resolution",
+            "8",
+            "--palette",
+            "gameboy",
+        ]);
+        run(args).expect("gray inputs should map to a built-in palette");
+
+        let gameboy = built_in_palette("gameboy").unwrap();
+        let pixels = read_png(&output_path);
+        assert!(
+            pixels
+                .chunks_exact(3)
+                .all(|pixel| gameboy.colors.iter().any(|color| color == pixel))
+        );
+        fs::remove_file(input_path).unwrap();
+        fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_method_thumbnail() {
+        let input_path = PathBuf::from("examples/horse.jpeg"); // Ensure this file exists
+        let temp_dir = env::temp_dir();
+        let output_path = temp_dir.join("output_thumbnail.jpeg");
+        let args = Args::parse_from([
+            "smolres",
+            "--input",
+            input_path.to_str().unwrap(),
+            "--output",
+            output_path.to_str().unwrap(),
+            "--thumbnail",
+            "64",
+        ]);
+
+        run(args).expect("run() should succeed");
+
+        let mut output_file = File::open(&output_path).expect("Failed to open output image");
+        let mut decoder_out = Decoder::new(&mut output_file);
+        decoder_out.decode().expect("Failed to decode output image");
+        let info = decoder_out.info().unwrap();
+        assert_eq!(info.width.max(info.height), 64);
+
+        // Clean up
+        fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_multiple_formats() {
+        let output_path = env::temp_dir().join("output_formats.jpeg");
+        let args = Args::parse_from([
+            "smolres",
+            "--input",
+            "examples/horse.jpeg",
+            "--output",
+            output_path.to_str().unwrap(),
+            "--resolution",
+            "8",
+            "--format",
+            "jpeg,png,webp,gif",
+        ]);
+
+        run(args).expect("run() should succeed");
+
+        for ext in ["jpeg", "png", "webp", "gif"] {
+            let path = output_path.with_extension(ext);
+            assert!(path.exists(), "Output image was not created");
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_run_compare_algorithms() {
+        let output_path = env::temp_dir().join("output_compare.png");
+        let args = Args::parse_from([
+            "smolres",
+            "--input",
+            "examples/horse.jpeg",
+            "--output",
+            output_path.to_str().unwrap(),
+            "--resolution",
+            "8",
+            "--compare-algorithms",
+        ]);
+
+        run(args).expect("run() should succeed");
+
+        for algorithm in ["average", "nearest"] {
+            let path = env::temp_dir().join(format!("output_compare_{algorithm}.png"));
+            assert!(path.exists(), "Output image was not created");
+            fs::remove_file(path).unwrap();
+        }
+        assert!(!output_path.exists());
+    }
+
+    #[test]
+    fn test_run_gif_keeps_palette() {
+        let output_path = env::temp_dir().join("output_palette.gif");
+        let args = Args::parse_from([
+            "smolres",
+            "--input",
+            "examples/horse.jpeg",
+            "--output",
+            output_path.to_str().unwrap(),
+            "--colors",
+            "6",
+        ]);
+
+        run(args).expect("run() should succeed");
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options
+            .read_info(File::open(&output_path).expect("Failed to open output image"))
+            .expect("Failed to decode output image");
+        let palette_len = decoder.global_palette().unwrap().len() / 3;
+        assert!(palette_len <= 8, "GIF palette was not taken from --colors");
+        assert!(decoder.read_next_frame().unwrap().is_some());
+
+        // Clean up
+        fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_method_colors_dithered() {
+        let input_path = PathBuf::from("examples/horse.jpeg"); // Ensure this file exists
+        let temp_dir = env::temp_dir();
+        let output_path = temp_dir.join("output_colors.jpeg");
+        let args = Args::parse_from([
+            "smolres",
+            "--input",
+            input_path.to_str().unwrap(),
+            "--output",
+            output_path.to_str().unwrap(),
+            "--colors",
+            "8",
+            "--dither",
+            "floyd-steinberg",
+            "--dither-strength",
+            "0.5",
+            "--serpentine",
+        ]);
+
+        run(args).expect("run() should succeed");
+
+        assert!(output_path.exists(), "Output image was not created");
+
+        // Clean up
+        fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_diff() {
+        let output_path = env::temp_dir().join("output_diff.jpeg");
+        let args = Args::parse_from([
+            "smolres",
+            "diff",
+            "examples/horse.jpeg",
+            "examples/horse.jpeg",
+            "--output",
+            output_path.to_str().unwrap(),
+        ]);
+
+        run(args).expect("run() should succeed");
+
+        let mut output_file = File::open(&output_path).expect("Failed to open output image");
+        let mut decoder_out = Decoder::new(&mut output_file);
+        let pixels = decoder_out.decode().expect("Failed to decode output image");
+        // Comparing an image with itself leaves a black difference image
+        assert!(pixels.iter().all(|&p| p < 4));
+
+        // Clean up
+        fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_error_names_file_and_step() {
+        let input_path = env::temp_dir().join("broken_input.jpeg");
+        fs::write(&input_path, b"not a jpeg").unwrap();
+        let args = Args::parse_from(["smolres", "--input", input_path.to_str().unwrap()]);
+
+        let error = run(args).expect_err("run() should fail").to_string();
+        let expected = format!("while decoding {}: ", input_path.display());
+        assert!(error.starts_with(&expected), "{}", error);
+
+        // Clean up
+        fs::remove_file(input_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_colors_swatch() {
+        let output_path = env::temp_dir().join("output_swatch.jpeg");
+        let args = Args::parse_from([
+            "smolres",
+            "colors",
+            "examples/horse.jpeg",
+            "--count",
+            "4",
+            "--swatch",
+            output_path.to_str().unwrap(),
+        ]);
+
+        run(args).expect("run() should succeed");
+
+        let mut output_file = File::open(&output_path).expect("Failed to open output image");
+        let mut decoder_out = Decoder::new(&mut output_file);
+        decoder_out.decode().expect("Failed to decode output image");
+        let info = decoder_out.info().unwrap();
+        assert_eq!(info.height, 64);
+        assert!(info.width <= 4 * 64);
+
+        // Clean up
+        fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_batch_manifest() {
+        let temp_dir = env::temp_dir();
+        let manifest_path = temp_dir.join("batch_manifest.csv");
+        let input_path = fs::canonicalize("examples/horse.jpeg").unwrap();
+        fs::write(
+            &manifest_path,
+            format!(
+                "input,output,resolution,bit_depth,palette,algorithm\n\
+                 {0},output_batch_a.jpeg,8,2,,\n\
+                 {0},output_batch_b.jpeg,4,,6,nearestneighbor\n",
+                input_path.display()
+            ),
+        )
+        .expect("Failed to write temp file");
+
+        let args = Args::parse_from(["smolres", "batch", manifest_path.to_str().unwrap()]);
+        run(args).expect("run() should succeed");
+
+        for output in ["output_batch_a.jpeg", "output_batch_b.jpeg"] {
+            let output_path = temp_dir.join(output);
+            assert!(output_path.exists(), "Output image was not created");
+            fs::remove_file(output_path).unwrap();
+        }
+        fs::remove_file(manifest_path).unwrap();
+    }
+
+    #[test]
+    fn test_shared_source_matches_separate_runs() {
+        let temp_dir = env::temp_dir();
+        let manifest_path = temp_dir.join("batch_shared.csv");
+        let input_path = fs::canonicalize("examples/horse.jpeg").unwrap();
+        fs::write(
+            &manifest_path,
+            format!(
+                "input,output,resolution,bit_depth,palette,algorithm\n\
+                 {0},output_shared_a.png,8,2,,\n\
+                 {0},output_shared_b.png,4,,6,\n",
+                input_path.display()
+            ),
+        )
+        .expect("Failed to write temp file");
+        let args = Args::parse_from(["smolres", "batch", manifest_path.to_str().unwrap()]);
+        run(args).expect("run() should succeed");
+
+        let separate_path = temp_dir.join("output_separate.png");
+        for (output, resolution, option, value) in [
+            ("output_shared_a.png", "8", "--bit-depth", "2"),
+            ("output_shared_b.png", "4", "--colors", "6"),
+        ] {
+            let args = Args::parse_from([
+                "smolres",
+                "--input",
+                input_path.to_str().unwrap(),
+                "--output",
+                separate_path.to_str().unwrap(),
+                "--resolution",
+                resolution,
+                option,
+                value,
+            ]);
+            run(args).expect("run() should succeed");
+            let shared_path = temp_dir.join(output);
+            assert_eq!(
+                fs::read(&shared_path).unwrap(),
+                fs::read(&separate_path).unwrap()
+            );
+            fs::remove_file(shared_path).unwrap();
+        }
+        fs::remove_file(separate_path).unwrap();
+        fs::remove_file(manifest_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_batch_directory() {
+        let temp_dir = env::temp_dir().join("batch_directory");
+        fs::create_dir_all(temp_dir.join("nested")).unwrap();
+        fs::copy("examples/horse.jpeg", temp_dir.join("nested/horse.jpeg")).unwrap();
+
+        let args = Args::parse_from(["smolres", "batch", temp_dir.to_str().unwrap()]);
+        run(args).expect("run() should succeed");
+
+        let output_path = temp_dir.join("nested/horse_res16_average.jpeg");
+        assert!(output_path.exists(), "Output image was not created");
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_batch_skips_corrupt_inputs() {
+        let temp_dir = env::temp_dir().join("batch_corrupt");
+        fs::create_dir_all(&temp_dir).unwrap();
+        fs::copy("examples/horse.jpeg", temp_dir.join("horse.jpeg")).unwrap();
+        fs::write(temp_dir.join("broken.jpeg"), [0xff, 0xd8, 0xff, 0xe0, 0x00]).unwrap();
+
+        let args = Args::parse_from(["smolres", "batch", temp_dir.to_str().unwrap()]);
+        run(args).expect("a corrupt input should not stop the batch");
+
+        assert!(temp_dir.join("horse_res16_average.jpeg").exists());
+        assert!(!temp_dir.join("broken_res16_average.jpeg").exists());
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_batch_summary_counts() {
+        let temp_dir = env::temp_dir().join("batch_summary");
+        fs::create_dir_all(&temp_dir).unwrap();
+        fs::copy("examples/horse.jpeg", temp_dir.join("horse.jpeg")).unwrap();
+        fs::write(temp_dir.join("broken.jpeg"), [0xff, 0xd8, 0xff, 0xe0, 0x00]).unwrap();
+
+        let jobs = ["horse.jpeg", "broken.jpeg"].map(|name| {
+            let input = temp_dir.join(name);
+            Job::file(Args::parse_from(["smolres", "-i", input.to_str().unwrap()]))
+        });
+        let summary = Mutex::default();
+        run_parallel(jobs.into(), 2, None, &summary).unwrap();
+
+        let summary = summary.into_inner().unwrap();
+        assert_eq!(
+            (summary.processed, summary.skipped, summary.failed),
+            (1, 1, 0)
+        );
+        assert_eq!(
+            summary.bytes_in,
+            fs::metadata("examples/horse.jpeg").unwrap().len()
+        );
+        assert_eq!(
+            summary.bytes_out,
+            fs::metadata(temp_dir.join("horse_res16_average.jpeg"))
+                .unwrap()
+                .len()
+        );
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_batch_jobs() {
+        let temp_dir = env::temp_dir().join("batch_jobs");
+        fs::create_dir_all(&temp_dir).unwrap();
+        for name in ["a", "b", "c"] {
+            fs::copy(
+                "examples/horse.jpeg",
+                temp_dir.join(format!("{}.jpeg", name)),
+            )
+            .unwrap();
+        }
+
+        let args = Args::parse_from(["smolres", "batch", temp_dir.to_str().unwrap(), "-j", "2"]);
+        run(args).expect("run() should succeed");
+
+        for name in ["a", "b", "c"] {
+            let output_path = temp_dir.join(format!("{}_res16_average.jpeg", name));
+            assert!(output_path.exists(), "Output image was not created");
+        }
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_batch_output_archive() {
+        let temp_dir = env::temp_dir().join("batch_archive");
+        fs::create_dir_all(temp_dir.join("nested")).unwrap();
+        fs::copy("examples/horse.jpeg", temp_dir.join("nested/horse.jpeg")).unwrap();
+        let archive_path = env::temp_dir().join("batch_archive.zip");
+
+        let args = Args::parse_from([
+            "smolres",
+            "batch",
+            temp_dir.to_str().unwrap(),
+            "--output-archive",
+            archive_path.to_str().unwrap(),
+        ]);
+        run(args).expect("run() should succeed");
+
+        let mut archive = zip::ZipArchive::new(File::open(&archive_path).unwrap()).unwrap();
+        assert_eq!(archive.len(), 1);
+        assert!(archive.by_name("nested/horse_res16_average.jpeg").is_ok());
+        assert!(!temp_dir.join("nested/horse_res16_average.jpeg").exists());
+
+        fs::remove_dir_all(temp_dir).unwrap();
+        fs::remove_file(archive_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_archive_input() {
+        let temp_dir = env::temp_dir().join("archive_input");
+        fs::create_dir_all(&temp_dir).unwrap();
+        let archive_path = temp_dir.join("assets.tar");
+        let writer = ArchiveWriter::create(&archive_path, Path::new(""), false).unwrap();
+        writer
+            .add(
+                Path::new("photos/horse.jpeg"),
+                &fs::read("examples/horse.jpeg").unwrap(),
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        let args = Args::parse_from([
+            "smolres",
+            "--input",
+            archive_path.to_str().unwrap(),
+            "--resolution",
+            "8",
+        ]);
+        run(args).expect("run() should succeed");
+
+        assert!(
+            temp_dir
+                .join("assets/photos/horse_res8_average.jpeg")
+                .exists()
+        );
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_files_from() {
+        let temp_dir = env::temp_dir().join("files_from");
+        fs::create_dir_all(&temp_dir).unwrap();
+        let input_path = temp_dir.join("horse.jpeg");
+        fs::copy("examples/horse.jpeg", &input_path).unwrap();
+        let list_path = temp_dir.join("inputs.txt");
+        fs::write(&list_path, format!("{}\n\n", input_path.display())).unwrap();
+
+        let args = Args::parse_from([
+            "smolres",
+            "--files-from",
+            list_path.to_str().unwrap(),
+            "--resolution",
+            "8",
+        ]);
+        run(args).expect("run() should succeed");
+
+        assert!(temp_dir.join("horse_res8_average.jpeg").exists());
+        fs::remove_dir_all(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_strip_metadata() {
+        // Source metadata is never copied, so this also passes without the flag, it guards
+        // against an encoder that starts to carry it over. The source has EXIF and ICC
+        // segments right after the start of image marker
+        let mut source = fs::read("examples/horse.jpeg").unwrap();
+        let exif = [&[0xff, 0xe1, 0, 17][..], b"Exif\0\0GPS-SECRET"].concat();
+        let icc = [&[0xff, 0xe2, 0, 16][..], b"ICC_PROFILE\0\x01\x01"].concat();
+        source.splice(2..2, [exif, icc].concat());
+        let temp_dir = env::temp_dir();
+        let input_path = temp_dir.join("metadata_source.jpeg");
+        fs::write(&input_path, source).unwrap();
+
+        let output_path = temp_dir.join("output_strip_metadata.jpeg");
+        let args = Args::parse_from([
+            "smolres",
+            "--input",
+            input_path.to_str().unwrap(),
+            "--output",
+            output_path.to_str().unwrap(),
+            "--format",
+            "jpeg,png,webp,gif",
+            "--strip-metadata",
+        ]);
+        run(args).expect("run() should succeed");
+
+        for extension in ["jpeg", "png", "webp", "gif"] {
+            let path = output_path.with_extension(extension);
+            let data = fs::read(&path).unwrap();
+            for marker in [&b"Exif"[..], b"GPS-SECRET", b"ICC_PROFILE"] {
+                assert!(
+                    !data.windows(marker.len()).any(|w| w == marker),
+                    "{extension} output contains metadata"
+                );
+            }
+            fs::remove_file(path).unwrap();
+        }
+        fs::remove_file(input_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_deterministic() {
+        let output_path = env::temp_dir().join("output_deterministic.jpeg");
+        let mut encoders = vec!["jpeg-encoder"];
+        if cfg!(feature = "mozjpeg") {
+            encoders.push("mozjpeg");
+        }
+        for encoder in encoders {
+            let outputs: Vec<Vec<Vec<u8>>> = (0..2)
+                .map(|_| {
+                    run(Args::parse_from([
+                        "smolres",
+                        "--input",
+                        "examples/horse.jpeg",
+                        "--output",
+                        output_path.to_str().unwrap(),
+                        "--format",
+                        "jpeg,png,webp,gif",
+                        "--colors",
+                        "8",
+                        "--dither",
+                        "floyd-steinberg",
+                        "--encoder",
+                        encoder,
+                        "--deterministic",
+                    ]))
+                    .expect("run() should succeed");
+                    ["jpeg", "png", "webp", "gif"]
+                        .map(|extension| fs::read(output_path.with_extension(extension)).unwrap())
+                        .to_vec()
+                })
+                .collect();
+            assert_eq!(
+                outputs[0], outputs[1],
+                "{encoder} outputs differ between runs"
+            );
+        }
+        for extension in ["jpeg", "png", "webp", "gif"] {
+            fs::remove_file(output_path.with_extension(extension)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_run_average() {
+        let output_path = env::temp_dir().join("output_average.png");
+        let args = Args::parse_from([
+            "smolres",
+            "average",
+            "examples/horse.jpeg",
+            "--output",
+            output_path.to_str().unwrap(),
+        ]);
+
+        run(args).expect("run() should succeed");
+
+        let decoder = png::Decoder::new(std::io::BufReader::new(
+            File::open(&output_path).expect("Failed to open output image"),
+        ));
+        let reader = decoder.read_info().expect("Failed to decode output image");
+        assert_eq!((reader.info().width, reader.info().height), (1, 1));
+
+        // Clean up
+        fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_enlarge_to() {
+        let image = ImageBuffer::new(2, 1, 1, vec![10, 20]);
+        let enlarged = enlarge_to(image, (5, 2));
+        assert_eq!((enlarged.width, enlarged.height), (6, 3));
+        assert_eq!(&enlarged.data[..6], &[10, 10, 10, 20, 20, 20]);
+
+        let image = ImageBuffer::new(8, 8, 1, vec![0; 64]);
+        assert_eq!(enlarge_to(image, (4, 4)).width, 8);
+    }
+
+    #[test]
+    fn test_run_render_block_sizes() {
+        let grid_path = env::temp_dir().join("input_render.json");
+        let output_path = env::temp_dir().join("output_render.png");
+        let data = (0..3 * 2 * 3).map(|i| (i * 13) as u8).collect();
+        encode(&ImageBuffer::new(3, 2, 3, data), grid_path.clone()).unwrap();
+        for block_size in [1, 4] {
+            run(Args::parse_from([
+                "smolres",
+                "render",
+                grid_path.to_str().unwrap(),
+                "--output",
+                output_path.to_str().unwrap(),
+                "--block-size",
+                &block_size.to_string(),
+            ]))
+            .expect("grids should render at any block size");
+            let pixels = read_png(&output_path);
+            assert_eq!(pixels.len(), 3 * 2 * 3 * block_size * block_size);
+            assert_eq!(&pixels[..3], &[0, 13, 26]);
+        }
+        fs::remove_file(grid_path).unwrap();
+        fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_upscale_nearest_neighbor() {
+        let input_path = env::temp_dir().join("input_upscale.jpeg");
+        let output_path = env::temp_dir().join("output_upscale.png");
+        let data = (0..8 * 8 * 3).map(|i| (i * 7 % 256) as u8).collect();
+        encode(&ImageBuffer::new(8, 8, 3, data), input_path.clone()).unwrap();
+        // 8 divides the grid, so the enlarged source is exactly as large as the grid
+        run(Args::parse_from([
+            "smolres",
+            "--input",
+            input_path.to_str().unwrap(),
+            "--output",
+            output_path.to_str().unwrap(),
+            "--resolution",
+            "16",
+            "--upscale",
+            "--algorithm",
+            "nearestneighbor",
+        ]))
+        .expect("small sources should be enlarged to the grid");
+        assert_eq!(read_png(&output_path).len(), 16 * 16 * 3);
+        fs::remove_file(input_path).unwrap();
+        fs::remove_file(output_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_animation_shared_palette() {
+        let temp_dir = env::temp_dir();
+        let input_path = temp_dir.join("input_animation.gif");
+        let output_path = temp_dir.join("output_animation.gif");
+
+        // Three frames of a gradient that slowly shifts, per frame palettes would differ
+        let frames: Vec<Frame> = (0..3u8)
+            .map(|shift| {
+                let data = (0..32 * 32)
+                    .flat_map(|i| {
+                        let x = (i % 32) as u8;
+                        [x * 8 + shift, 255 - x * 8, shift * 20]
+                    })
+                    .collect();
+                Frame {
+                    image: ImageBuffer::new(32, 32, 3, data),
+                    delay: 5,
+                }
+            })
+            .collect();
+        write_gif_frames(&frames, File::create(&input_path).unwrap()).unwrap();
+
+        let args = Args::parse_from([
+            "smolres",
+            "--input",
+            input_path.to_str().unwrap(),
+            "--output",
+            output_path.to_str().unwrap(),
+            "--resolution",
+            "8",
+            "--colors",
+            "4",
+        ]);
+
+        run(args).expect("run() should succeed");
+
+        let decoded = read_gif_frames(File::open(&output_path).unwrap()).unwrap();
+        assert_eq!(decoded.len(), 3);
+        let mut colors: Vec<&[u8]> = decoded
+            .iter()
+            .flat_map(|frame| frame.image.data.chunks_exact(3))
+            .collect();
+        colors.sort_unstable();
+        colors.dedup();
+        assert!(colors.len() <= 4, "frames do not share one palette");
+
+        // Clean up
+        fs::remove_file(input_path).unwrap();
+        fs::remove_file(output_path).unwrap();
+    }
+
+    #[cfg(feature = "radiance")]
+    #[test]
+    fn test_run_method_hdr_input() {
+        let temp_dir = env::temp_dir();
+        let input_path = temp_dir.join("input_gradient.hdr");
+        let output_path = temp_dir.join("output_hdr.jpeg");
+
+        // 16x16 flat Radiance file with a horizontal gradient above 1.0
+        let mut hdr = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 16 +X 16\n".to_vec();
+        for _ in 0..16 {
+            for x in 0..16u8 {
+                hdr.extend_from_slice(&[x * 16, x * 16, 128, 130]);
+            }
+        }
+        fs::write(&input_path, hdr).expect("Failed to write temp file");
+
+        let args = Args::parse_from([
+            "smolres",
+            "--input",
+            input_path.to_str().unwrap(),
+            "--output",
+            output_path.to_str().unwrap(),
+            "--resolution",
+            "4",
+            "--tone-map",
+            "aces",
+            "--exposure",
+            "-1.5",
+            "--gamma",
+            "1.2",
+        ]);
+
+        run(args).expect("run() should succeed");
+
+        let mut output_file = File::open(&output_path).expect("Failed to open output image");
+        let mut decoder_out = Decoder::new(&mut output_file);
+        decoder_out.decode().expect("Failed to decode output image");
+        let info = decoder_out.info().unwrap();
+        assert_eq!((info.width, info.height), (16, 16));
+
+        // Clean up
+        fs::remove_file(input_path).unwrap();
+        fs::remove_file(output_path).unwrap();
+    }
 }
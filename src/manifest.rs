@@ -0,0 +1,182 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::cli::Algorithm;
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("Failed to read manifest: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid CSV manifest: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[error("Invalid JSON manifest: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Manifest must be a .csv or .json file: {0}")]
+    UnsupportedFormat(String),
+
+    #[error("Invalid manifest entry {0}: {1}")]
+    InvalidEntry(usize, String),
+}
+
+/// One row of a batch manifest, unset settings fall back to the CLI defaults
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ManifestEntry {
+    pub input: PathBuf,
+    pub output: Option<PathBuf>,
+    pub resolution: Option<u16>,
+    pub bit_depth: Option<u8>,
+    /// Size of the optimized palette, replaces the bit depth reduction
+    pub palette: Option<u16>,
+    pub algorithm: Option<Algorithm>,
+}
+
+impl ManifestEntry {
+    /// Entry that processes `input` with the default settings
+    pub fn from_input(input: PathBuf) -> Self {
+        ManifestEntry {
+            input,
+            output: None,
+            resolution: None,
+            bit_depth: None,
+            palette: None,
+            algorithm: None,
+        }
+    }
+
+    /// Command line arguments equivalent to this entry, so it is validated like a regular run
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![
+            String::from("smolres"),
+            String::from("--input"),
+            self.input.display().to_string(),
+        ];
+        if let Some(output) = &self.output {
+            args.extend([String::from("--output"), output.display().to_string()]);
+        }
+        if let Some(resolution) = self.resolution {
+            args.extend([String::from("--resolution"), resolution.to_string()]);
+        }
+        if let Some(bit_depth) = self.bit_depth {
+            args.extend([String::from("--bit-depth"), bit_depth.to_string()]);
+        }
+        if let Some(palette) = self.palette {
+            args.extend([String::from("--colors"), palette.to_string()]);
+        }
+        if let Some(algorithm) = self.algorithm {
+            let value = algorithm
+                .to_possible_value()
+                .expect("algorithms are never skipped");
+            args.extend([String::from("--algorithm"), String::from(value.get_name())]);
+        }
+        args
+    }
+}
+
+/**
+ * Reads a CSV (with a header row) or JSON (array of objects) manifest.
+ * Relative paths are resolved against the directory of the manifest. */
+pub fn read_manifest(path: &Path) -> Result<Vec<ManifestEntry>, ManifestError> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    let mut entries: Vec<ManifestEntry> = match ext.as_deref() {
+        Some("csv") => csv::Reader::from_path(path)?
+            .deserialize()
+            .collect::<Result<_, _>>()?,
+        Some("json") => serde_json::from_reader(BufReader::new(File::open(path)?))?,
+        _ => return Err(ManifestError::UnsupportedFormat(path.display().to_string())),
+    };
+
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    for entry in entries.iter_mut() {
+        entry.input = base.join(&entry.input);
+        entry.output = entry.output.as_ref().map(|output| base.join(output));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{ManifestEntry, read_manifest};
+    use crate::cli::Algorithm;
+
+    #[test]
+    fn test_read_csv_manifest() {
+        let manifest_path = env::temp_dir().join("test_manifest.csv");
+        fs::write(
+            &manifest_path,
+            "input,output,resolution,bit_depth,palette,algorithm\n\
+             a.jpeg,out/a.jpeg,32,4,,nearestneighbor\n\
+             b.jpeg,,,,16,\n",
+        )
+        .expect("Failed to write temp file");
+
+        let entries = read_manifest(&manifest_path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].input, env::temp_dir().join("a.jpeg"));
+        assert_eq!(entries[0].output, Some(env::temp_dir().join("out/a.jpeg")));
+        assert_eq!(entries[0].algorithm, Some(Algorithm::Nearestneighbor));
+        assert_eq!(
+            (entries[1].resolution, entries[1].palette),
+            (None, Some(16))
+        );
+
+        // Clean up
+        fs::remove_file(manifest_path).unwrap();
+    }
+
+    #[test]
+    fn test_read_json_manifest() {
+        let manifest_path = env::temp_dir().join("test_manifest.json");
+        fs::write(
+            &manifest_path,
+            r#"[{"input": "a.jpeg", "resolution": 8, "algorithm": "average-area"}]"#,
+        )
+        .expect("Failed to write temp file");
+
+        let entries = read_manifest(&manifest_path).unwrap();
+        assert_eq!(entries[0].resolution, Some(8));
+        assert_eq!(entries[0].algorithm, Some(Algorithm::AverageArea));
+        assert_eq!(entries[0].bit_depth, None);
+
+        // Clean up
+        fs::remove_file(manifest_path).unwrap();
+    }
+
+    #[test]
+    fn test_entry_to_args() {
+        let entry = ManifestEntry {
+            input: PathBuf::from("a.jpeg"),
+            output: None,
+            resolution: Some(8),
+            bit_depth: None,
+            palette: Some(4),
+            algorithm: None,
+        };
+        assert_eq!(
+            entry.to_args(),
+            vec![
+                "smolres",
+                "--input",
+                "a.jpeg",
+                "--resolution",
+                "8",
+                "--colors",
+                "4"
+            ]
+        );
+    }
+}
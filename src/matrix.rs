@@ -0,0 +1,199 @@
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::color::from_hex;
+use crate::types::ImageBuffer;
+
+#[derive(Debug, Error)]
+pub enum MatrixError {
+    #[error("Failed to read grid: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid JSON grid: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Invalid CSV grid: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[error("Grid must be a .json or .csv file: {0}")]
+    UnsupportedFormat(String),
+
+    #[error("Invalid color in grid: {0}")]
+    InvalidColor(String),
+
+    #[error("Palette index {0} is out of range")]
+    IndexOutOfRange(usize),
+
+    #[error("Palette indices need the palette of a JSON grid")]
+    MissingPalette,
+
+    #[error("Row {0} has {1} cells instead of {2}")]
+    RowLength(usize, usize, usize),
+
+    #[error("Grid has no cells")]
+    Empty,
+}
+
+/// A grid cell of the json and csv formats
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Cell {
+    Hex(String),
+    Index(usize),
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cell::Hex(hex) => f.write_str(hex),
+            Cell::Index(index) => write!(f, "{index}"),
+        }
+    }
+}
+
+/// Rows of a grid, indices refer to `palette`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Matrix {
+    pub width: usize,
+    pub height: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub palette: Option<Vec<String>>,
+    pub rows: Vec<Vec<Cell>>,
+}
+
+/**
+ * Reads a grid written by the json or csv format back into an RGB image with one pixel per
+ * cell. CSV cells that are whole numbers are indices, anything else is a hex color. */
+pub fn read_matrix(path: &Path) -> Result<ImageBuffer, MatrixError> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase);
+    let (palette, rows) = match extension.as_deref() {
+        Some("json") => {
+            let matrix: Matrix = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+            (matrix.palette, matrix.rows)
+        }
+        Some("csv") => {
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_reader(File::open(path)?);
+            let mut rows = vec![];
+            for record in reader.records() {
+                let cells = record?
+                    .iter()
+                    .map(|field| match field.trim().parse() {
+                        Ok(index) => Cell::Index(index),
+                        Err(_) => Cell::Hex(field.trim().to_string()),
+                    })
+                    .collect();
+                rows.push(cells);
+            }
+            (None, rows)
+        }
+        _ => return Err(MatrixError::UnsupportedFormat(path.display().to_string())),
+    };
+    to_image(palette.as_deref(), &rows)
+}
+
+fn to_image(palette: Option<&[String]>, rows: &[Vec<Cell>]) -> Result<ImageBuffer, MatrixError> {
+    let parse = |hex: &str| from_hex(hex).ok_or_else(|| MatrixError::InvalidColor(hex.into()));
+    let palette = palette
+        .map(|colors| {
+            colors
+                .iter()
+                .map(|hex| parse(hex))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+
+    let width = rows.first().map_or(0, Vec::len);
+    if width == 0 {
+        return Err(MatrixError::Empty);
+    }
+    let mut data = Vec::with_capacity(width * rows.len() * 3);
+    for (y, row) in rows.iter().enumerate() {
+        if row.len() != width {
+            return Err(MatrixError::RowLength(y, row.len(), width));
+        }
+        for cell in row {
+            let color = match cell {
+                Cell::Hex(hex) => parse(hex)?,
+                Cell::Index(index) => *palette
+                    .as_ref()
+                    .ok_or(MatrixError::MissingPalette)?
+                    .get(*index)
+                    .ok_or(MatrixError::IndexOutOfRange(*index))?,
+            };
+            data.extend(color);
+        }
+    }
+    Ok(ImageBuffer::new(width, rows.len(), 3, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::{MatrixError, read_matrix};
+    use crate::encoder::{CsvEncoder, ImageEncoder, JsonEncoder, MatrixValues};
+    use crate::types::ImageBuffer;
+
+    #[test]
+    fn test_round_trip() {
+        let image = ImageBuffer::new(3, 2, 3, (0..18).map(|v| v * 10).collect());
+        for values in [MatrixValues::Hex, MatrixValues::Index] {
+            for (name, encoder) in [
+                (
+                    "matrix_round_trip.json",
+                    &JsonEncoder { values } as &dyn ImageEncoder,
+                ),
+                ("matrix_round_trip.csv", &CsvEncoder { values }),
+            ] {
+                // CSV indices lose their palette and cannot be read back
+                if name.ends_with("csv") && values == MatrixValues::Index {
+                    continue;
+                }
+                let path = env::temp_dir().join(name);
+                let mut data = Vec::new();
+                encoder.encode(&image, &mut data).unwrap();
+                fs::write(&path, data).unwrap();
+                assert_eq!(read_matrix(&path).unwrap().data, image.data, "{name}");
+                fs::remove_file(path).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_matrix() {
+        let path = env::temp_dir().join("matrix_read.json");
+        fs::write(
+            &path,
+            r##"{"width":2,"height":1,"palette":["#000000","#ffffff"],"rows":[[1,0]]}"##,
+        )
+        .unwrap();
+        let image = read_matrix(&path).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.data, vec![255, 255, 255, 0, 0, 0]);
+        fs::remove_file(path).unwrap();
+
+        let path = env::temp_dir().join("matrix_read.csv");
+        fs::write(&path, "#ff0000,#00ff00\n#0000ff\n").unwrap();
+        assert!(matches!(
+            read_matrix(&path),
+            Err(MatrixError::RowLength(1, 1, 2))
+        ));
+        fs::write(&path, "1,0\n").unwrap();
+        assert!(matches!(
+            read_matrix(&path),
+            Err(MatrixError::MissingPalette)
+        ));
+        fs::remove_file(path).unwrap();
+    }
+}
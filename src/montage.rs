@@ -0,0 +1,56 @@
+use crate::geometry::{pad, scale_to_fit};
+use crate::overlay::draw_caption;
+use crate::types::ImageBuffer;
+
+/// Color of the sheet behind and between the tiles
+const BACKGROUND: [u8; 3] = [32, 32, 32];
+
+/// Pixels between neighbouring tiles and around the sheet
+const GAP: usize = 8;
+
+/// Color of the labels
+const LABEL_COLOR: [u8; 3] = [255, 255, 255];
+
+/**
+ * Tiles the images row by row into one sheet, `columns` per row. Every image is scaled into
+ * a square tile of `tile_size` pixels and labeled at the bottom. */
+pub fn montage(tiles: &[(ImageBuffer, String)], columns: usize, tile_size: usize) -> ImageBuffer {
+    let columns = columns.clamp(1, tiles.len().max(1));
+    let rows = tiles.len().div_ceil(columns);
+    let width = columns * (tile_size + GAP) + GAP;
+    let height = rows * (tile_size + GAP) + GAP;
+    let mut data = BACKGROUND.repeat(width * height);
+
+    for (i, (image, label)) in tiles.iter().enumerate() {
+        let scaled = scale_to_fit(&image.to_rgb(), tile_size, tile_size);
+        let mut tile = pad(&scaled, tile_size, tile_size, BACKGROUND);
+        draw_caption(&mut tile, label, LABEL_COLOR);
+        let (left, top) = (
+            GAP + (i % columns) * (tile_size + GAP),
+            GAP + (i / columns) * (tile_size + GAP),
+        );
+        for (y, row) in tile.data.chunks_exact(tile_size * 3).enumerate() {
+            let start = ((top + y) * width + left) * 3;
+            data[start..start + row.len()].copy_from_slice(row);
+        }
+    }
+    ImageBuffer::new(width, height, 3, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BACKGROUND, GAP, montage};
+    use crate::types::ImageBuffer;
+
+    #[test]
+    fn test_montage_layout() {
+        let tile = || (ImageBuffer::new(4, 4, 3, vec![200; 48]), String::new());
+        let sheet = montage(&[tile(), tile(), tile()], 2, 16);
+        assert_eq!((sheet.width, sheet.height), (2 * 24 + GAP, 2 * 24 + GAP));
+
+        let pixel = |x: usize, y: usize| &sheet.data[(y * sheet.width + x) * 3..][..3];
+        assert_eq!(pixel(GAP, GAP), &[200, 200, 200]);
+        assert_eq!(pixel(GAP + 24, GAP + 24), &BACKGROUND);
+        assert_eq!(pixel(GAP + 24, GAP + 15), &[200, 200, 200]);
+    }
+}
@@ -0,0 +1,290 @@
+use std::io::Cursor;
+
+use thiserror::Error;
+
+use crate::cli::Position;
+use crate::color::luma;
+use crate::types::ImageBuffer;
+
+#[derive(Debug, Error)]
+pub enum WatermarkError {
+    #[error("Failed to read watermark: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid PNG: {0}")]
+    Png(#[from] png::DecodingError),
+}
+
+/// Bitmap font for the printable ASCII range from ` ` to `_`, lowercase letters are drawn
+/// as uppercase. Each glyph is 3x5 pixels, one row per entry with the leftmost pixel in the
+/// highest bit.
+const FONT: [[u8; 5]; 64] = [
+    [0b000, 0b000, 0b000, 0b000, 0b000], // ' '
+    [0b010, 0b010, 0b010, 0b000, 0b010], // '!'
+    [0b101, 0b101, 0b000, 0b000, 0b000], // '"'
+    [0b101, 0b111, 0b101, 0b111, 0b101], // '#'
+    [0b011, 0b110, 0b010, 0b011, 0b110], // '$'
+    [0b101, 0b001, 0b010, 0b100, 0b101], // '%'
+    [0b010, 0b101, 0b010, 0b101, 0b011], // '&'
+    [0b010, 0b010, 0b000, 0b000, 0b000], // '''
+    [0b001, 0b010, 0b010, 0b010, 0b001], // '('
+    [0b100, 0b010, 0b010, 0b010, 0b100], // ')'
+    [0b000, 0b101, 0b010, 0b101, 0b000], // '*'
+    [0b000, 0b010, 0b111, 0b010, 0b000], // '+'
+    [0b000, 0b000, 0b000, 0b010, 0b100], // ','
+    [0b000, 0b000, 0b111, 0b000, 0b000], // '-'
+    [0b000, 0b000, 0b000, 0b000, 0b010], // '.'
+    [0b001, 0b001, 0b010, 0b100, 0b100], // '/'
+    [0b111, 0b101, 0b101, 0b101, 0b111], // '0'
+    [0b010, 0b110, 0b010, 0b010, 0b111], // '1'
+    [0b111, 0b001, 0b111, 0b100, 0b111], // '2'
+    [0b111, 0b001, 0b111, 0b001, 0b111], // '3'
+    [0b101, 0b101, 0b111, 0b001, 0b001], // '4'
+    [0b111, 0b100, 0b111, 0b001, 0b111], // '5'
+    [0b111, 0b100, 0b111, 0b101, 0b111], // '6'
+    [0b111, 0b001, 0b001, 0b001, 0b001], // '7'
+    [0b111, 0b101, 0b111, 0b101, 0b111], // '8'
+    [0b111, 0b101, 0b111, 0b001, 0b111], // '9'
+    [0b000, 0b010, 0b000, 0b010, 0b000], // ':'
+    [0b000, 0b010, 0b000, 0b010, 0b100], // ';'
+    [0b001, 0b010, 0b100, 0b010, 0b001], // '<'
+    [0b000, 0b111, 0b000, 0b111, 0b000], // '='
+    [0b100, 0b010, 0b001, 0b010, 0b100], // '>'
+    [0b111, 0b001, 0b010, 0b000, 0b010], // '?'
+    [0b010, 0b101, 0b111, 0b100, 0b011], // '@'
+    [0b010, 0b101, 0b111, 0b101, 0b101], // 'A'
+    [0b110, 0b101, 0b110, 0b101, 0b110], // 'B'
+    [0b011, 0b100, 0b100, 0b100, 0b011], // 'C'
+    [0b110, 0b101, 0b101, 0b101, 0b110], // 'D'
+    [0b111, 0b100, 0b110, 0b100, 0b111], // 'E'
+    [0b111, 0b100, 0b110, 0b100, 0b100], // 'F'
+    [0b011, 0b100, 0b101, 0b101, 0b011], // 'G'
+    [0b101, 0b101, 0b111, 0b101, 0b101], // 'H'
+    [0b111, 0b010, 0b010, 0b010, 0b111], // 'I'
+    [0b001, 0b001, 0b001, 0b101, 0b010], // 'J'
+    [0b101, 0b101, 0b110, 0b101, 0b101], // 'K'
+    [0b100, 0b100, 0b100, 0b100, 0b111], // 'L'
+    [0b101, 0b111, 0b111, 0b101, 0b101], // 'M'
+    [0b110, 0b101, 0b101, 0b101, 0b101], // 'N'
+    [0b010, 0b101, 0b101, 0b101, 0b010], // 'O'
+    [0b110, 0b101, 0b110, 0b100, 0b100], // 'P'
+    [0b010, 0b101, 0b101, 0b110, 0b011], // 'Q'
+    [0b110, 0b101, 0b110, 0b101, 0b101], // 'R'
+    [0b011, 0b100, 0b010, 0b001, 0b110], // 'S'
+    [0b111, 0b010, 0b010, 0b010, 0b010], // 'T'
+    [0b101, 0b101, 0b101, 0b101, 0b111], // 'U'
+    [0b101, 0b101, 0b101, 0b101, 0b010], // 'V'
+    [0b101, 0b101, 0b111, 0b111, 0b101], // 'W'
+    [0b101, 0b101, 0b010, 0b101, 0b101], // 'X'
+    [0b101, 0b101, 0b010, 0b010, 0b010], // 'Y'
+    [0b111, 0b001, 0b010, 0b100, 0b111], // 'Z'
+    [0b011, 0b010, 0b010, 0b010, 0b011], // '['
+    [0b100, 0b100, 0b010, 0b001, 0b001], // '\'
+    [0b110, 0b010, 0b010, 0b010, 0b110], // ']'
+    [0b010, 0b101, 0b000, 0b000, 0b000], // '^'
+    [0b000, 0b000, 0b000, 0b000, 0b111], // '_'
+];
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+
+/// Image composited onto the pixelated result, e.g. a logo
+pub struct Watermark {
+    /// RGBA pixels, the alpha channel is respected when blending
+    image: ImageBuffer,
+    position: Position,
+    opacity: f32,
+}
+
+impl Watermark {
+    pub fn new(png: &[u8], position: Position, opacity: f32) -> Result<Self, WatermarkError> {
+        Ok(Watermark {
+            image: read_png_rgba(png)?,
+            position,
+            opacity,
+        })
+    }
+
+    /**
+     * Blends the watermark onto the image at its position, keeping a small margin to the
+     * edges. Parts that do not fit are cut off, the watermark is never scaled. */
+    pub fn apply(&self, target: &mut ImageBuffer) {
+        let margin = target.width.min(target.height) / 50;
+        let (width, height) = (self.image.width, self.image.height);
+        let free_x = target.width.saturating_sub(width);
+        let free_y = target.height.saturating_sub(height);
+        let (left, top) = match self.position {
+            Position::TopLeft => (0, 0),
+            Position::TopRight => (free_x, 0),
+            Position::BottomLeft => (0, free_y),
+            Position::BottomRight => (free_x, free_y),
+            Position::Center => (free_x / 2, free_y / 2),
+        };
+        let left = match self.position {
+            Position::TopLeft | Position::BottomLeft => (left + margin).min(free_x),
+            Position::TopRight | Position::BottomRight => left.saturating_sub(margin),
+            Position::Center => left,
+        };
+        let top = match self.position {
+            Position::TopLeft | Position::TopRight => (top + margin).min(free_y),
+            Position::BottomLeft | Position::BottomRight => top.saturating_sub(margin),
+            Position::Center => top,
+        };
+
+        let channels = target.channels;
+        for y in 0..height.min(target.height - top) {
+            for x in 0..width.min(target.width - left) {
+                let src = &self.image.data[(y * width + x) * 4..][..4];
+                let alpha = src[3] as f32 / 255.0 * self.opacity;
+                let color = match channels {
+                    1 => vec![luma([src[0], src[1], src[2]])],
+                    _ => src[..3].to_vec(),
+                };
+                let idx = ((top + y) * target.width + left + x) * channels;
+                for (dst, &src) in target.data[idx..idx + channels].iter_mut().zip(&color) {
+                    *dst = (*dst as f32 * (1.0 - alpha) + src as f32 * alpha).round() as u8;
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Writes a single line of text centered at the bottom of the image with the bundled pixel
+ * font and a dark outline, so it stays readable on any background. The font is scaled up
+ * by whole pixels as far as the text still fits. */
+pub fn draw_caption(target: &mut ImageBuffer, text: &str, color: [u8; 3]) {
+    let glyphs: Vec<&[u8; 5]> = text.chars().map(glyph).collect();
+    if glyphs.is_empty() {
+        return;
+    }
+    // One pixel of spacing between glyphs and of outline around the text
+    let width = glyphs.len() * (GLYPH_WIDTH + 1) - 1 + 2;
+    let height = GLYPH_HEIGHT + 2;
+    let mut mask = vec![false; width * height];
+    for (i, glyph) in glyphs.iter().enumerate() {
+        for (y, row) in glyph.iter().enumerate() {
+            for x in 0..GLYPH_WIDTH {
+                if row & (1 << (GLYPH_WIDTH - 1 - x)) != 0 {
+                    mask[(y + 1) * width + 1 + i * (GLYPH_WIDTH + 1) + x] = true;
+                }
+            }
+        }
+    }
+    let outline = |x: usize, y: usize| {
+        (y.saturating_sub(1)..(y + 2).min(height))
+            .any(|ny| (x.saturating_sub(1)..(x + 2).min(width)).any(|nx| mask[ny * width + nx]))
+    };
+
+    let scale = (target.width * 9 / 10 / width)
+        .min(target.height / 8 / height)
+        .max(1);
+    let left = target.width.saturating_sub(width * scale) / 2;
+    let top = target
+        .height
+        .saturating_sub(height * scale + target.width.min(target.height) / 50);
+    let (text_color, outline_color) = match target.channels {
+        1 => (vec![luma(color)], vec![0]),
+        _ => (color.to_vec(), vec![0; 3]),
+    };
+    for y in 0..(height * scale).min(target.height - top) {
+        for x in 0..(width * scale).min(target.width - left) {
+            let (mx, my) = (x / scale, y / scale);
+            let pixel = if mask[my * width + mx] {
+                &text_color
+            } else if outline(mx, my) {
+                &outline_color
+            } else {
+                continue;
+            };
+            let idx = ((top + y) * target.width + left + x) * target.channels;
+            target.data[idx..idx + target.channels].copy_from_slice(pixel);
+        }
+    }
+}
+
+fn glyph(c: char) -> &'static [u8; 5] {
+    let c = c.to_ascii_uppercase();
+    match c {
+        ' '..='_' => &FONT[c as usize - ' ' as usize],
+        _ => &FONT['?' as usize - ' ' as usize],
+    }
+}
+
+/// Decodes any PNG into 8 bit RGBA, images without transparency become opaque
+fn read_png_rgba(data: &[u8]) -> Result<ImageBuffer, WatermarkError> {
+    let mut decoder = png::Decoder::new(Cursor::new(data));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size().unwrap_or_default()];
+    let info = reader.next_frame(&mut buffer)?;
+    buffer.truncate(info.buffer_size());
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let data = match info.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        // Palettes are expanded by the transformations
+        png::ColorType::Grayscale | png::ColorType::Indexed => {
+            buffer.iter().flat_map(|&v| [v, v, v, 255]).collect()
+        }
+    };
+    Ok(ImageBuffer::new(width, height, 4, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Watermark, draw_caption, glyph, read_png_rgba};
+    use crate::cli::Position;
+    use crate::encoder::OutputFormat;
+    use crate::types::ImageBuffer;
+
+    fn logo() -> Vec<u8> {
+        let mut png = Vec::new();
+        OutputFormat::Png
+            .encoder()
+            .encode(&ImageBuffer::new(1, 1, 3, vec![255, 255, 255]), &mut png)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn test_read_png_rgba() {
+        let image = read_png_rgba(&logo()).unwrap();
+        assert_eq!(image.channels, 4);
+        assert_eq!(image.data, vec![255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_watermark_in_corner_with_opacity() {
+        let mut target = ImageBuffer::new(2, 2, 3, vec![0; 12]);
+        Watermark::new(&logo(), Position::BottomRight, 0.5)
+            .unwrap()
+            .apply(&mut target);
+        assert_eq!(&target.data[..9], &[0; 9]);
+        assert_eq!(&target.data[9..], &[128, 128, 128]);
+    }
+
+    #[test]
+    fn test_glyph_fallbacks() {
+        assert_eq!(glyph('a'), glyph('A'));
+        assert_eq!(glyph('~'), glyph('?'));
+        assert_eq!(glyph('é'), glyph('?'));
+    }
+
+    #[test]
+    fn test_caption_is_outlined() {
+        // "I" at scale 1 is 5x7 with the outline, it exactly covers a 5x7 image
+        let mut target = ImageBuffer::new(5, 7, 1, vec![128; 35]);
+        draw_caption(&mut target, "I", [255, 255, 255]);
+        assert_eq!(&target.data[..5], &[0; 5]);
+        assert_eq!(&target.data[5..10], &[0, 255, 255, 255, 0]);
+        assert_eq!(&target.data[10..15], &[0, 0, 255, 0, 0]);
+    }
+}
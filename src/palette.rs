@@ -0,0 +1,381 @@
+use std::collections::{HashMap, HashSet};
+
+use smolres_core::palette::{
+    average_color, map_to_palette, median_cut, nearest_index, nearest_indices, sample_colors,
+};
+use thiserror::Error;
+
+use crate::color::{Deficiency, Distance, luma};
+use crate::types::ImageBuffer;
+
+/// Median cut entries built per color of a colorblind safe palette to pick from
+pub const CANDIDATES_PER_COLOR: usize = 4;
+
+/// The fixed 16 colors of the PICO-8 fantasy console, in the order of their indices
+pub const PICO8: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0x1d, 0x2b, 0x53],
+    [0x7e, 0x25, 0x53],
+    [0x00, 0x87, 0x51],
+    [0xab, 0x52, 0x36],
+    [0x5f, 0x57, 0x4f],
+    [0xc2, 0xc3, 0xc7],
+    [0xff, 0xf1, 0xe8],
+    [0xff, 0x00, 0x4d],
+    [0xff, 0xa3, 0x00],
+    [0xff, 0xec, 0x27],
+    [0x00, 0xe4, 0x36],
+    [0x29, 0xad, 0xff],
+    [0x83, 0x76, 0x9c],
+    [0xff, 0x77, 0xa8],
+    [0xff, 0xcc, 0xaa],
+];
+
+/// Fixed palette that `--palette` selects by name
+#[derive(Debug, PartialEq, Eq)]
+pub struct NamedPalette {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub colors: &'static [[u8; 3]],
+}
+
+pub const BUILT_IN_PALETTES: &[NamedPalette] = &[
+    NamedPalette {
+        name: "pico-8",
+        description: "The 16 colors of the PICO-8 fantasy console",
+        colors: &PICO8,
+    },
+    NamedPalette {
+        name: "gameboy",
+        description: "The four greens of the original Game Boy screen",
+        colors: &[
+            [0x0f, 0x38, 0x0f],
+            [0x30, 0x62, 0x30],
+            [0x8b, 0xac, 0x0f],
+            [0x9b, 0xbc, 0x0f],
+        ],
+    },
+    NamedPalette {
+        name: "cga",
+        description: "CGA mode 4 palette 1 in high intensity, black, cyan, magenta and white",
+        colors: &[
+            [0x00, 0x00, 0x00],
+            [0x55, 0xff, 0xff],
+            [0xff, 0x55, 0xff],
+            [0xff, 0xff, 0xff],
+        ],
+    },
+];
+
+/// Looks up a built-in palette, ignoring case
+pub fn built_in_palette(name: &str) -> Option<&'static NamedPalette> {
+    BUILT_IN_PALETTES
+        .iter()
+        .find(|palette| palette.name.eq_ignore_ascii_case(name))
+}
+
+#[derive(Debug, Error)]
+pub enum PaletteError {
+    #[error("Palette quantization requires RGB pixels, got {0} bytes per pixel")]
+    UnsupportedPixelFormat(usize),
+
+    #[error("Palette must contain at least one color")]
+    Empty,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+    distance: Distance,
+}
+
+impl Palette {
+    pub fn new(colors: Vec<[u8; 3]>) -> Result<Self, PaletteError> {
+        if colors.is_empty() {
+            return Err(PaletteError::Empty);
+        }
+        Ok(Palette {
+            colors,
+            distance: Distance::default(),
+        })
+    }
+
+    /// Uses `distance` to find the nearest entries instead of plain RGB distance
+    pub fn with_distance(self, distance: Distance) -> Self {
+        Palette { distance, ..self }
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn colors(&self) -> &[[u8; 3]] {
+        &self.colors
+    }
+
+    /// Palette of exactly the colors used by the pixels, `None` if there are more than `max_colors`
+    pub fn exact(pixels: &[u8], pixel_bytes: usize, max_colors: usize) -> Option<Self> {
+        let mut colors = HashSet::new();
+        for pixel in pixels.chunks_exact(pixel_bytes) {
+            colors.insert([pixel[0], pixel[1], pixel[2]]);
+            if colors.len() > max_colors {
+                return None;
+            }
+        }
+        let mut colors: Vec<[u8; 3]> = colors.into_iter().collect();
+        colors.sort_unstable();
+        Palette::new(colors).ok()
+    }
+
+    /**
+     * One k-means step towards the given pixels: every entry moves to the average of the
+     * pixels closest to it, entries without any pixels stay where they are. */
+    pub fn refine(&self, pixels: &[u8], pixel_bytes: usize) -> Result<Self, PaletteError> {
+        if pixel_bytes != 3 {
+            return Err(PaletteError::UnsupportedPixelFormat(pixel_bytes));
+        }
+
+        let samples = sample_colors(pixels, pixel_bytes);
+        let mut members: Vec<Vec<[u8; 3]>> = vec![vec![]; self.colors.len()];
+        for (&color, index) in samples.iter().zip(self.indices(samples.as_flattened(), 3)) {
+            members[index].push(color);
+        }
+        let colors = self
+            .colors
+            .iter()
+            .zip(&members)
+            .map(|(color, members)| {
+                if members.is_empty() {
+                    *color
+                } else {
+                    average_color(members)
+                }
+            })
+            .collect();
+        Palette::new(colors)
+    }
+
+    /// Index of the nearest palette entry for every pixel
+    pub fn indices(&self, pixels: &[u8], pixel_bytes: usize) -> Vec<usize> {
+        nearest_indices(&self.colors, pixels, pixel_bytes, |a, b| {
+            self.distance.between(a, b)
+        })
+    }
+
+    /// Palette entries with the number of pixels mapped to them, most frequent first
+    pub fn dominant_colors(
+        &self,
+        pixels: &[u8],
+        pixel_bytes: usize,
+    ) -> Result<Vec<([u8; 3], usize)>, PaletteError> {
+        if pixel_bytes != 3 {
+            return Err(PaletteError::UnsupportedPixelFormat(pixel_bytes));
+        }
+
+        let mut counts: HashMap<[u8; 3], usize> = HashMap::new();
+        for pixel in pixels.chunks_exact(pixel_bytes) {
+            *counts.entry([pixel[0], pixel[1], pixel[2]]).or_default() += 1;
+        }
+        let mut dominant: HashMap<[u8; 3], usize> = HashMap::new();
+        for (color, count) in counts {
+            *dominant.entry(self.nearest(color)).or_default() += count;
+        }
+
+        let mut dominant: Vec<([u8; 3], usize)> = dominant.into_iter().collect();
+        dominant.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        Ok(dominant)
+    }
+
+    /// Renders the colors side by side as `size`x`size` squares
+    pub fn swatch(colors: &[[u8; 3]], size: usize) -> ImageBuffer {
+        let width = colors.len() * size;
+        let mut data = Vec::with_capacity(width * size * 3);
+        for _ in 0..size {
+            for color in colors {
+                for _ in 0..size {
+                    data.extend_from_slice(color);
+                }
+            }
+        }
+        ImageBuffer::new(width, size, 3, data)
+    }
+
+    /// Builds a palette of at most `max_colors` entries with the median cut algorithm
+    pub fn median_cut(
+        pixels: &[u8],
+        pixel_bytes: usize,
+        max_colors: usize,
+    ) -> Result<Self, PaletteError> {
+        if pixel_bytes != 3 {
+            return Err(PaletteError::UnsupportedPixelFormat(pixel_bytes));
+        }
+        Palette::new(median_cut(sample_colors(pixels, pixel_bytes), max_colors))
+    }
+
+    /**
+     * Picks up to `max_colors` entries that stay as far apart as possible for normal vision
+     * and under each simulated color vision deficiency. Starting with the darkest entry the
+     * one whose closest selected neighbor is the farthest away is added until the budget is
+     * used up, so the palette should offer several candidates per slot to choose from. */
+    pub fn colorblind_safe(&self, max_colors: usize) -> Self {
+        // Every entry as seen with normal vision followed by each deficiency
+        let views: Vec<Vec<[f32; 3]>> = self
+            .colors
+            .iter()
+            .map(|&color| {
+                let mut views = vec![color.map(|c| c as f32 / 255.0)];
+                views.extend(Deficiency::ALL.map(|deficiency| deficiency.simulate(color)));
+                views
+            })
+            .collect();
+        let distance = |a: usize, b: usize| {
+            views[a]
+                .iter()
+                .zip(&views[b])
+                .map(|(x, y)| x.iter().zip(y).map(|(p, q)| (p - q) * (p - q)).sum::<f32>())
+                .fold(f32::INFINITY, f32::min)
+        };
+
+        let darkest = (0..self.colors.len())
+            .min_by_key(|&i| luma(self.colors[i]))
+            .expect("palette is never empty");
+        let mut selected = vec![darkest];
+        let mut closest: Vec<f32> = (0..self.colors.len())
+            .map(|i| distance(i, darkest))
+            .collect();
+        while selected.len() < max_colors.min(self.colors.len()) {
+            let (next, _) = closest
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .expect("palette is never empty");
+            selected.push(next);
+            for (i, value) in closest.iter_mut().enumerate() {
+                *value = value.min(distance(i, next));
+            }
+        }
+
+        let mut colors: Vec<[u8; 3]> = selected.into_iter().map(|i| self.colors[i]).collect();
+        colors.sort_unstable();
+        Palette {
+            colors,
+            distance: self.distance,
+        }
+    }
+
+    /// Returns the palette entry closest to `color` by the distance of the palette
+    pub fn nearest(&self, color: [u8; 3]) -> [u8; 3] {
+        self.colors[nearest_index(&self.colors, color, |a, b| self.distance.between(a, b))]
+    }
+
+    /// Replaces every pixel with its nearest palette entry
+    pub fn apply(&self, pixels: &mut [u8], pixel_bytes: usize) -> Result<(), PaletteError> {
+        if pixel_bytes != 3 {
+            return Err(PaletteError::UnsupportedPixelFormat(pixel_bytes));
+        }
+
+        map_to_palette(pixels, pixel_bytes, &self.colors, |a, b| {
+            self.distance.between(a, b)
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Palette;
+    use crate::color::Distance;
+
+    #[test]
+    fn test_median_cut_respects_budget() {
+        let pixels: Vec<u8> = (0..=255u8).flat_map(|v| [v, 255 - v, v / 2]).collect();
+        let palette = Palette::median_cut(&pixels, 3, 16).unwrap();
+        assert!(palette.colors.len() <= 16);
+        assert!(palette.colors.len() > 1);
+    }
+
+    #[test]
+    fn test_median_cut_finds_distinct_colors() {
+        let mut pixels = vec![];
+        for _ in 0..10 {
+            pixels.extend_from_slice(&[255, 0, 0]);
+            pixels.extend_from_slice(&[0, 0, 255]);
+        }
+        let palette = Palette::median_cut(&pixels, 3, 4).unwrap();
+        assert_eq!(palette.colors, &[[0, 0, 255], [255, 0, 0]]);
+    }
+
+    #[test]
+    fn test_apply_maps_to_nearest() {
+        let palette = Palette::new(vec![[0, 0, 0], [255, 255, 255]]).unwrap();
+        let mut pixels = vec![10, 20, 30, 200, 220, 250];
+        palette.apply(&mut pixels, 3).unwrap();
+        assert_eq!(pixels, vec![0, 0, 0, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_nearest_by_distance() {
+        // Dark blue is closer to black in RGB, but looks closer to blue
+        let palette = Palette::new(vec![[0, 0, 0], [0, 0, 255]]).unwrap();
+        assert_eq!(palette.nearest([0, 0, 120]), [0, 0, 0]);
+        let palette = palette.with_distance(Distance::Lab);
+        assert_eq!(palette.nearest([0, 0, 120]), [0, 0, 255]);
+        assert_eq!(palette.indices(&[0, 0, 120], 3), vec![1]);
+    }
+
+    #[test]
+    fn test_dominant_colors_ordered_by_frequency() {
+        let palette = Palette::new(vec![[0, 0, 0], [255, 255, 255]]).unwrap();
+        let pixels = vec![250, 250, 250, 5, 5, 5, 255, 255, 255];
+        let dominant = palette.dominant_colors(&pixels, 3).unwrap();
+        assert_eq!(dominant, vec![([255, 255, 255], 2), ([0, 0, 0], 1)]);
+
+        let swatch = Palette::swatch(&[[0, 0, 0], [255, 255, 255]], 2);
+        assert_eq!((swatch.width, swatch.height), (4, 2));
+        assert_eq!(
+            &swatch.data[..12],
+            &[0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn test_exact_palette_and_indices() {
+        let pixels = vec![255, 0, 0, 0, 0, 255, 255, 0, 0];
+        let palette = Palette::exact(&pixels, 3, 2).unwrap();
+        assert_eq!(palette.colors(), &[[0, 0, 255], [255, 0, 0]]);
+        assert_eq!(palette.indices(&pixels, 3), vec![1, 0, 1]);
+        assert!(Palette::exact(&pixels, 3, 1).is_none());
+    }
+
+    #[test]
+    fn test_refine_moves_towards_pixels() {
+        let palette = Palette::new(vec![[0, 0, 0], [200, 200, 200], [0, 0, 255]]).unwrap();
+        let pixels = vec![20, 20, 20, 40, 40, 40, 250, 250, 250];
+        let refined = palette.refine(&pixels, 3).unwrap();
+        assert_eq!(
+            refined.colors(),
+            &[[30, 30, 30], [250, 250, 250], [0, 0, 255]]
+        );
+    }
+
+    #[test]
+    fn test_colorblind_safe_avoids_red_green_pairs() {
+        let candidates =
+            Palette::new(vec![[0, 0, 0], [200, 40, 40], [40, 160, 40], [40, 40, 220]]).unwrap();
+        let palette = candidates.colorblind_safe(3);
+        assert_eq!(palette.len(), 3);
+        assert!(palette.colors().contains(&[0, 0, 0]));
+        assert!(palette.colors().contains(&[40, 40, 220]));
+        // Both of red and green would be confused under protanopia and deuteranopia
+        assert!(
+            !(palette.colors().contains(&[200, 40, 40])
+                && palette.colors().contains(&[40, 160, 40]))
+        );
+    }
+
+    #[test]
+    fn test_unsupported_pixel_format() {
+        let pixels = vec![0u8; 16];
+        assert!(Palette::median_cut(&pixels, 1, 4).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Buffers kept per size class, older ones are dropped beyond this
+const MAX_BUFFERS_PER_CLASS: usize = 4;
+
+/**
+ * Reusable pixel buffers grouped by size class, the power of two at or below their
+ * capacity. Batch runs process many images of similar size, so a buffer released
+ * after one image can hold the next one without another multi-megabyte allocation. */
+#[derive(Default)]
+pub struct BufferPool {
+    classes: HashMap<u32, Vec<Vec<u8>>>,
+}
+
+fn size_class(len: usize) -> u32 {
+    usize::BITS - len.max(1).leading_zeros() - 1
+}
+
+impl BufferPool {
+    /// Returns an empty buffer with room for at least `len` bytes
+    pub fn take(&mut self, len: usize) -> Vec<u8> {
+        // Buffers one class up always fit, buffers of the same class only if they are large enough
+        for class in [size_class(len), size_class(len) + 1] {
+            let Some(buffers) = self.classes.get_mut(&class) else {
+                continue;
+            };
+            if let Some(index) = buffers.iter().position(|b| b.capacity() >= len) {
+                let mut buffer = buffers.swap_remove(index);
+                buffer.clear();
+                return buffer;
+            }
+        }
+        Vec::with_capacity(len)
+    }
+
+    pub fn give(&mut self, buffer: Vec<u8>) {
+        if buffer.capacity() == 0 {
+            return;
+        }
+        let buffers = self
+            .classes
+            .entry(size_class(buffer.capacity()))
+            .or_default();
+        if buffers.len() == MAX_BUFFERS_PER_CLASS {
+            buffers.remove(0);
+        }
+        buffers.push(buffer);
+    }
+}
+
+thread_local! {
+    static POOL: RefCell<BufferPool> = RefCell::new(BufferPool::default());
+}
+
+/// Takes a buffer from the pool of the current thread
+pub fn take(len: usize) -> Vec<u8> {
+    POOL.with(|pool| pool.borrow_mut().take(len))
+}
+
+/// Hands a buffer back to the pool of the current thread once an image is done
+pub fn recycle(buffer: Vec<u8>) {
+    POOL.with(|pool| pool.borrow_mut().give(buffer));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferPool, size_class};
+
+    #[test]
+    fn test_size_class() {
+        assert_eq!(size_class(1), 0);
+        assert_eq!(size_class(1024), 10);
+        assert_eq!(size_class(1500), 10);
+    }
+
+    #[test]
+    fn test_reuses_allocation() {
+        let mut pool = BufferPool::default();
+        let buffer = vec![7u8; 3000];
+        let pointer = buffer.as_ptr();
+        pool.give(buffer);
+
+        let reused = pool.take(2500);
+        assert_eq!(reused.as_ptr(), pointer);
+        assert!(reused.is_empty());
+
+        // Too small buffers are never handed out
+        pool.give(reused);
+        let larger = pool.take(5000);
+        assert_ne!(larger.as_ptr(), pointer);
+        assert!(larger.capacity() >= 5000);
+    }
+}
@@ -0,0 +1,564 @@
+pub use smolres_core::dither::{ATKINSON, DiffusionKernel, FLOYD_STEINBERG, bayer_matrix};
+use smolres_core::dither::{diffuse_error, ordered_dither};
+use thiserror::Error;
+
+use crate::color::{Distance, luma};
+use crate::interpolation::{
+    InterpolationError, reduce_bit_depth, round_bit_depth, round_to_levels,
+};
+use crate::palette::{CANDIDATES_PER_COLOR, Palette};
+
+/// How the colors of the image get reduced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColorReduction {
+    /// Truncate every channel to the given number of bits
+    BitDepth(u8),
+    /// Round every channel to the nearest level of the given number of bits
+    RoundedBitDepth(u8),
+    /// Round every channel to the nearest of the given sorted levels
+    Levels(Vec<u8>),
+    /// Truncate the smoother half of the blocks to one bit less and the more detailed half to
+    /// one bit more than the given depth, so the average stays the same
+    AdaptiveBitDepth(u8),
+    /// Build an optimized palette with the given number of colors
+    Colors(usize),
+    /// Like `Colors`, but keeps the colors distinguishable under color vision deficiencies
+    ColorblindSafe(usize),
+    /// Map to a palette chosen up front, e.g. one shared by all frames of an animation
+    FixedPalette(Palette),
+    /// Black and white without dithering, pixels brighter than the cutoff become white.
+    /// Without a cutoff it is chosen per image with Otsu's method
+    Threshold(Option<u8>),
+}
+
+impl ColorReduction {
+    /// Whether the colors are mapped to an RGB palette, which needs RGB pixels
+    pub fn uses_palette(&self) -> bool {
+        matches!(
+            self,
+            ColorReduction::Colors(_)
+                | ColorReduction::ColorblindSafe(_)
+                | ColorReduction::FixedPalette(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DitherMethod {
+    /// Pushes the quantization error of every pixel onto its unvisited neighbours
+    ErrorDiffusion(&'static DiffusionKernel),
+    /// Compares every pixel against a tiled threshold matrix
+    Ordered(ThresholdMatrix),
+}
+
+#[derive(Debug, Error)]
+pub enum DitherMatrixError {
+    #[error("Failed to read dither matrix: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Line {0}: '{1}' is not a non-negative integer")]
+    InvalidValue(usize, String),
+
+    #[error("Line {0} has {1} values instead of {2}")]
+    RowLength(usize, usize, usize),
+
+    #[error("Dither matrix has no values")]
+    Empty,
+}
+
+/// Thresholds of ordered dithering between 0.0 and 1.0, tiled over the image
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThresholdMatrix {
+    width: usize,
+    height: usize,
+    thresholds: Vec<f32>,
+}
+
+impl ThresholdMatrix {
+    /// Spreads the ranks evenly, the lowest rank lights up first as the pixels get brighter
+    fn from_ranks(rows: Vec<Vec<usize>>) -> Self {
+        let (width, height) = (rows[0].len(), rows.len());
+        let values: Vec<usize> = rows.into_iter().flatten().collect();
+        let lowest = values.iter().copied().min().unwrap_or(0);
+        let levels = (values.iter().copied().max().unwrap_or(0) - lowest + 1) as f32;
+        let thresholds = values
+            .into_iter()
+            .map(|value| (value - lowest) as f32 + 0.5)
+            .map(|rank| rank / levels)
+            .collect();
+        ThresholdMatrix {
+            width,
+            height,
+            thresholds,
+        }
+    }
+
+    pub fn bayer(size: usize) -> Self {
+        Self::from_ranks(bayer_matrix(size))
+    }
+
+    /**
+     * Reads a matrix of ranks, one row per line with the values separated by whitespace or
+     * commas. Empty lines and lines starting with `#` are skipped, e.g. for a halftone dot:
+     *
+     * ```text
+     * 6 4 5
+     * 3 0 1
+     * 8 2 7
+     * ``` */
+    pub fn parse(text: &str) -> Result<Self, DitherMatrixError> {
+        let mut rows: Vec<Vec<usize>> = vec![];
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let row = line
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|value| !value.is_empty())
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| DitherMatrixError::InvalidValue(index + 1, value.into()))
+                })
+                .collect::<Result<Vec<usize>, _>>()?;
+            if let Some(first) = rows.first()
+                && first.len() != row.len()
+            {
+                return Err(DitherMatrixError::RowLength(
+                    index + 1,
+                    row.len(),
+                    first.len(),
+                ));
+            }
+            rows.push(row);
+        }
+        if rows.is_empty() {
+            return Err(DitherMatrixError::Empty);
+        }
+        Ok(Self::from_ranks(rows))
+    }
+
+    fn threshold(&self, x: usize, y: usize) -> f32 {
+        self.thresholds[(y % self.height) * self.width + x % self.width]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dithering {
+    pub method: DitherMethod,
+    /// How much of the error or threshold gets applied, between 0.0 and 1.0
+    pub strength: f32,
+    /// Error diffusion alternates the scan direction per row, ordered dithering ignores this
+    pub serpentine: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantization {
+    pub reduction: ColorReduction,
+    pub dithering: Option<Dithering>,
+    /// Metric palettes find the nearest entry with
+    pub distance: Distance,
+}
+
+/// A color reduction resolved against a concrete image
+pub enum Quantizer {
+    BitDepth(u8),
+    RoundedBitDepth(u8),
+    Levels(Vec<u8>),
+    /// Per block bit depths around the given one, see `quantize_blocks`. Pixels that do not
+    /// belong to a single block are truncated to the finer depth
+    AdaptiveBitDepth(u8),
+    /// Pure black and white by luminance, pixels brighter than the cutoff become white
+    Monochrome(u8),
+    Palette(Palette),
+}
+
+impl Quantizer {
+    /// Makes palettes map colors by `distance`, other reductions do not compare colors
+    pub fn with_distance(self, distance: Distance) -> Self {
+        match self {
+            Quantizer::Palette(palette) => Quantizer::Palette(palette.with_distance(distance)),
+            quantizer => quantizer,
+        }
+    }
+
+    /// Resolves the color reduction, palettes are built from the given pixels
+    pub fn new(
+        reduction: &ColorReduction,
+        pixels: &[u8],
+        pixel_bytes: usize,
+    ) -> Result<Self, InterpolationError> {
+        match reduction {
+            ColorReduction::BitDepth(bit_depth) | ColorReduction::RoundedBitDepth(bit_depth) => {
+                if *bit_depth == 0 || *bit_depth > 8 {
+                    return Err(InterpolationError::InvalidBitDepth(*bit_depth));
+                }
+                // Truncating every channel to one bit gives dark primaries instead of black and white
+                if *bit_depth == 1 {
+                    return Ok(Quantizer::Monochrome(127));
+                }
+                match reduction {
+                    ColorReduction::BitDepth(_) => Ok(Quantizer::BitDepth(*bit_depth)),
+                    _ => Ok(Quantizer::RoundedBitDepth(*bit_depth)),
+                }
+            }
+            ColorReduction::Levels(levels) => Ok(Quantizer::Levels(levels.clone())),
+            ColorReduction::AdaptiveBitDepth(bit_depth) => match bit_depth {
+                2..=7 => Ok(Quantizer::AdaptiveBitDepth(*bit_depth)),
+                _ => Err(InterpolationError::AdaptiveBitDepth(*bit_depth)),
+            },
+            ColorReduction::Colors(colors) => Ok(Quantizer::Palette(Palette::median_cut(
+                pixels,
+                pixel_bytes,
+                *colors,
+            )?)),
+            ColorReduction::ColorblindSafe(colors) => Ok(Quantizer::Palette(
+                Palette::median_cut(pixels, pixel_bytes, colors * CANDIDATES_PER_COLOR)?
+                    .colorblind_safe(*colors),
+            )),
+            ColorReduction::FixedPalette(palette) => Ok(Quantizer::Palette(palette.clone())),
+            ColorReduction::Threshold(Some(cutoff)) => Ok(Quantizer::Monochrome(*cutoff)),
+            ColorReduction::Threshold(None) => {
+                Ok(Quantizer::Monochrome(otsu_threshold(pixels, pixel_bytes)))
+            }
+        }
+    }
+
+    pub fn apply(&self, pixels: &mut [u8], pixel_bytes: usize) -> Result<(), InterpolationError> {
+        match self {
+            Quantizer::BitDepth(bit_depth) => reduce_bit_depth(pixels, *bit_depth)?,
+            Quantizer::RoundedBitDepth(bit_depth) => round_bit_depth(pixels, *bit_depth)?,
+            Quantizer::Levels(levels) => round_to_levels(pixels, levels),
+            Quantizer::AdaptiveBitDepth(bit_depth) => reduce_bit_depth(pixels, bit_depth + 1)?,
+            Quantizer::Monochrome(_) => {
+                for pixel in pixels.chunks_exact_mut(pixel_bytes) {
+                    self.quantize_pixel(pixel);
+                }
+            }
+            Quantizer::Palette(palette) => palette.apply(pixels, pixel_bytes)?,
+        }
+        Ok(())
+    }
+
+    /// Rough distance between neighbouring output levels, used to scale ordered dithering
+    fn spread(&self) -> f32 {
+        match self {
+            Quantizer::BitDepth(bit_depth) | Quantizer::RoundedBitDepth(bit_depth) => {
+                (256u16 >> bit_depth) as f32
+            }
+            Quantizer::Levels(levels) => 256.0 / levels.len() as f32,
+            Quantizer::AdaptiveBitDepth(bit_depth) => (256u16 >> (bit_depth + 1)) as f32,
+            Quantizer::Monochrome(_) => 256.0,
+            Quantizer::Palette(palette) => 256.0 / (palette.len() as f32).cbrt(),
+        }
+    }
+
+    fn quantize_pixel(&self, pixel: &mut [u8]) {
+        match self {
+            Quantizer::BitDepth(bit_depth) => {
+                let step = (256u16 >> bit_depth) as u8;
+                for channel in pixel.iter_mut() {
+                    *channel = (*channel / step) * step;
+                }
+            }
+            Quantizer::RoundedBitDepth(bit_depth) => {
+                round_bit_depth(pixel, *bit_depth).expect("bit depth is validated in new")
+            }
+            Quantizer::AdaptiveBitDepth(bit_depth) => {
+                Quantizer::BitDepth(bit_depth + 1).quantize_pixel(pixel)
+            }
+            Quantizer::Levels(levels) => round_to_levels(pixel, levels),
+            Quantizer::Monochrome(cutoff) => {
+                let value = if brightness(pixel) > *cutoff { 255 } else { 0 };
+                pixel.fill(value);
+            }
+            Quantizer::Palette(palette) => {
+                let mapped = palette.nearest([pixel[0], pixel[1], pixel[2]]);
+                pixel.copy_from_slice(&mapped);
+            }
+        }
+    }
+}
+
+/// Quantizes the pixels with the requested dithering method
+pub fn dither(
+    pixels: &mut [u8],
+    width: usize,
+    height: usize,
+    pixel_bytes: usize,
+    quantizer: &Quantizer,
+    dithering: &Dithering,
+) -> Result<(), InterpolationError> {
+    if let Quantizer::Palette(_) = quantizer
+        && pixel_bytes != 3
+    {
+        return Err(crate::palette::PaletteError::UnsupportedPixelFormat(pixel_bytes).into());
+    }
+
+    let strength = dithering.strength.clamp(0.0, 1.0);
+    let quantize = |pixel: &mut [u8]| quantizer.quantize_pixel(pixel);
+    match &dithering.method {
+        DitherMethod::ErrorDiffusion(kernel) => diffuse_error(
+            pixels,
+            width,
+            height,
+            pixel_bytes,
+            kernel,
+            strength,
+            dithering.serpentine,
+            quantize,
+        ),
+        DitherMethod::Ordered(matrix) => {
+            // Truncating quantizers need thresholds in [0, 1), nearest color ones centered around 0
+            let bias = match quantizer {
+                Quantizer::BitDepth(_) | Quantizer::AdaptiveBitDepth(_) => 0.0,
+                Quantizer::RoundedBitDepth(_)
+                | Quantizer::Levels(_)
+                | Quantizer::Monochrome(_)
+                | Quantizer::Palette(_) => 0.5,
+            };
+            ordered_dither(
+                pixels,
+                width,
+                pixel_bytes,
+                |x, y| matrix.threshold(x, y) - bias,
+                quantizer.spread() * strength,
+                quantize,
+            )
+        }
+    }
+    Ok(())
+}
+
+/// Quantizes a pixel grid, dithering it if requested
+pub fn quantize_grid(
+    pixels: &mut [u8],
+    width: usize,
+    height: usize,
+    pixel_bytes: usize,
+    quantizer: &Quantizer,
+    dithering: Option<&Dithering>,
+) -> Result<(), InterpolationError> {
+    // Dithering a gray image spreads only the luminance error, not a colored one
+    if let Quantizer::Monochrome(_) = quantizer {
+        for pixel in pixels.chunks_exact_mut(pixel_bytes) {
+            pixel.fill(brightness(pixel));
+        }
+    }
+    match dithering {
+        Some(dithering) => dither(pixels, width, height, pixel_bytes, quantizer, dithering),
+        None => quantizer.apply(pixels, pixel_bytes),
+    }
+}
+
+/**
+ * Truncates the grid with a bit depth per block: the half of the blocks whose source had the
+ * least `detail` get one bit less than `bit_depth`, the other half one bit more. Smooth areas
+ * hide coarse steps, so the levels go where there is detail to show. */
+pub fn quantize_blocks(pixels: &mut [u8], pixel_bytes: usize, bit_depth: u8, detail: &[f32]) {
+    let mut ranks: Vec<usize> = (0..detail.len()).collect();
+    ranks.sort_by(|&a, &b| detail[a].total_cmp(&detail[b]));
+    for (rank, &block) in ranks.iter().enumerate() {
+        let depth = match rank < ranks.len() / 2 {
+            true => bit_depth - 1,
+            false => bit_depth + 1,
+        };
+        let step = (256u16 >> depth) as u8;
+        for channel in &mut pixels[block * pixel_bytes..(block + 1) * pixel_bytes] {
+            *channel = (*channel / step) * step;
+        }
+    }
+}
+
+/**
+ * Otsu's method: the cutoff that separates the luminance histogram into a dark and a bright
+ * class with the largest variance between them. */
+fn otsu_threshold(pixels: &[u8], pixel_bytes: usize) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in pixels.chunks_exact(pixel_bytes) {
+        histogram[brightness(pixel) as usize] += 1;
+    }
+    let total: u64 = histogram.iter().sum();
+    let sum: u64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(v, &n)| v as u64 * n)
+        .sum();
+
+    let (mut dark, mut dark_sum) = (0u64, 0u64);
+    let (mut best, mut best_variance) = (127, 0.0);
+    for (value, &count) in histogram.iter().enumerate() {
+        dark += count;
+        dark_sum += value as u64 * count;
+        let bright = total - dark;
+        if dark == 0 || bright == 0 {
+            continue;
+        }
+        let dark_mean = dark_sum as f64 / dark as f64;
+        let bright_mean = (sum - dark_sum) as f64 / bright as f64;
+        let variance = dark as f64 * bright as f64 * (dark_mean - bright_mean).powi(2);
+        if variance > best_variance {
+            (best, best_variance) = (value as u8, variance);
+        }
+    }
+    best
+}
+
+fn brightness(pixel: &[u8]) -> u8 {
+    match pixel.len() {
+        1 => pixel[0],
+        _ => luma([pixel[0], pixel[1], pixel[2]]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ColorReduction, DitherMatrixError, DitherMethod, Dithering, FLOYD_STEINBERG, Quantizer,
+        ThresholdMatrix, bayer_matrix, dither, quantize_blocks, quantize_grid,
+    };
+
+    #[test]
+    fn test_dither_mixes_levels() {
+        // A flat mid gray cannot be represented with 1 bit, dithering mixes black and white
+        let mut pixels = vec![100u8; 8 * 8];
+        let quantizer = Quantizer::new(&ColorReduction::BitDepth(1), &pixels, 1).unwrap();
+        let dithering = Dithering {
+            method: DitherMethod::ErrorDiffusion(&FLOYD_STEINBERG),
+            strength: 1.0,
+            serpentine: false,
+        };
+        dither(&mut pixels, 8, 8, 1, &quantizer, &dithering).unwrap();
+        assert!(pixels.contains(&0));
+        assert!(pixels.contains(&255));
+        assert!(pixels.iter().all(|&p| p == 0 || p == 255));
+    }
+
+    #[test]
+    fn test_one_bit_is_black_and_white_by_luminance() {
+        // Saturated blue is dark and yellow is bright, truncation would keep them colored
+        let mut pixels = vec![0, 0, 255, 255, 255, 0];
+        let quantizer = Quantizer::new(&ColorReduction::BitDepth(1), &pixels, 3).unwrap();
+        quantize_grid(&mut pixels, 2, 1, 3, &quantizer, None).unwrap();
+        assert_eq!(pixels, vec![0, 0, 0, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_threshold_cutoff_and_otsu() {
+        let source = vec![20, 30, 40, 180, 200, 220];
+        let mut pixels = source.clone();
+        let quantizer = Quantizer::new(&ColorReduction::Threshold(Some(35)), &pixels, 1).unwrap();
+        quantize_grid(&mut pixels, 6, 1, 1, &quantizer, None).unwrap();
+        assert_eq!(pixels, vec![0, 0, 255, 255, 255, 255]);
+
+        // Otsu splits between the two clusters
+        let Quantizer::Monochrome(cutoff) =
+            Quantizer::new(&ColorReduction::Threshold(None), &source, 1).unwrap()
+        else {
+            panic!("thresholds resolve to black and white");
+        };
+        assert!((40..180).contains(&cutoff));
+    }
+
+    #[test]
+    fn test_dither_zero_strength_matches_plain_quantization() {
+        let source: Vec<u8> = (0..64).map(|v| (v * 4) as u8).collect();
+        let quantizer = Quantizer::new(&ColorReduction::BitDepth(2), &source, 1).unwrap();
+
+        let mut dithered = source.clone();
+        let dithering = Dithering {
+            method: DitherMethod::ErrorDiffusion(&FLOYD_STEINBERG),
+            strength: 0.0,
+            serpentine: false,
+        };
+        dither(&mut dithered, 8, 8, 1, &quantizer, &dithering).unwrap();
+
+        let mut plain = source.clone();
+        quantizer.apply(&mut plain, 1).unwrap();
+        assert_eq!(dithered, plain);
+    }
+
+    #[test]
+    fn test_serpentine_keeps_average() {
+        // Both scan orders distribute the full error, so the mean brightness is preserved
+        let source = vec![100u8; 16 * 16];
+        let quantizer = Quantizer::new(&ColorReduction::BitDepth(1), &source, 1).unwrap();
+        let mut outputs = vec![];
+        for serpentine in [false, true] {
+            let mut pixels = source.clone();
+            let dithering = Dithering {
+                method: DitherMethod::ErrorDiffusion(&FLOYD_STEINBERG),
+                strength: 1.0,
+                serpentine,
+            };
+            dither(&mut pixels, 16, 16, 1, &quantizer, &dithering).unwrap();
+            let mean = pixels.iter().map(|&p| p as f32).sum::<f32>() / pixels.len() as f32;
+            assert!((mean - 100.0).abs() < 8.0);
+            outputs.push(pixels);
+        }
+        assert_ne!(outputs[0], outputs[1]);
+    }
+
+    #[test]
+    fn test_bayer_matrix() {
+        assert_eq!(bayer_matrix(2), vec![vec![0, 2], vec![3, 1]]);
+        let matrix = bayer_matrix(8);
+        let mut values: Vec<usize> = matrix.into_iter().flatten().collect();
+        values.sort_unstable();
+        assert_eq!(values, (0..64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_ordered_dither_mixes_levels() {
+        let mut pixels = vec![64u8; 4 * 4];
+        let quantizer = Quantizer::new(&ColorReduction::BitDepth(1), &pixels, 1).unwrap();
+        let dithering = Dithering {
+            method: DitherMethod::Ordered(ThresholdMatrix::bayer(4)),
+            strength: 1.0,
+            serpentine: false,
+        };
+        dither(&mut pixels, 4, 4, 1, &quantizer, &dithering).unwrap();
+        // A quarter of the way to white lights up a quarter of the matrix cells
+        assert_eq!(pixels.iter().filter(|&&p| p == 255).count(), 4);
+        assert_eq!(pixels.iter().filter(|&&p| p == 0).count(), 12);
+    }
+
+    #[test]
+    fn test_dither_matrix_file() {
+        // Diagonal lines, the highest rank lights up first
+        let matrix = ThresholdMatrix::parse("# diagonal\n0, 1, 2\n\n2 0 1\n1 2 0\n").unwrap();
+        let mut pixels = vec![64u8; 6 * 6];
+        let quantizer = Quantizer::new(&ColorReduction::BitDepth(1), &pixels, 1).unwrap();
+        let dithering = Dithering {
+            method: DitherMethod::Ordered(matrix),
+            strength: 1.0,
+            serpentine: false,
+        };
+        dither(&mut pixels, 6, 6, 1, &quantizer, &dithering).unwrap();
+        assert_eq!(pixels.iter().filter(|&&p| p == 255).count(), 12);
+        assert_eq!((pixels[2], pixels[6], pixels[6 * 2 + 1]), (255, 255, 255));
+
+        assert!(matches!(
+            ThresholdMatrix::parse("0 1\n2\n"),
+            Err(DitherMatrixError::RowLength(2, 1, 2))
+        ));
+        assert!(matches!(
+            ThresholdMatrix::parse("0 x"),
+            Err(DitherMatrixError::InvalidValue(1, _))
+        ));
+        assert!(matches!(
+            ThresholdMatrix::parse("# nothing\n"),
+            Err(DitherMatrixError::Empty)
+        ));
+    }
+
+    #[test]
+    fn test_quantize_blocks_follows_detail() {
+        let mut pixels = vec![100u8; 4];
+        quantize_blocks(&mut pixels, 1, 3, &[5.0, 0.0, 9.0, 1.0]);
+        assert_eq!(pixels, vec![96, 64, 96, 64]);
+
+        assert!(Quantizer::new(&ColorReduction::AdaptiveBitDepth(8), &pixels, 1).is_err());
+    }
+}
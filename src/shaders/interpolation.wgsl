@@ -0,0 +1,85 @@
+// Mirrors AverageAreaInterpolation and NearestNeighborInterpolation, one invocation per target pixel.
+// Channels are stored widened to u32 since WGSL has no 8 bit storage type.
+
+struct Params {
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+    channels: u32,
+    mode: u32,
+    _pad0: u32,
+    _pad1: u32,
+}
+
+const MODE_NEAREST: u32 = 1u;
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> src: array<u32>;
+@group(0) @binding(2) var<storage, read_write> dst: array<u32>;
+
+fn average(x_start: u32, x_end: u32, y_start: u32, y_end: u32, out_idx: u32) {
+    var sums = array<u32, 4>(0u, 0u, 0u, 0u);
+    var count = 0u;
+    for (var y = y_start; y < y_end; y++) {
+        for (var x = x_start; x < x_end; x++) {
+            let idx = (y * params.src_width + x) * params.channels;
+            for (var c = 0u; c < params.channels; c++) {
+                sums[c] += src[idx + c];
+            }
+            count++;
+        }
+    }
+    for (var c = 0u; c < params.channels; c++) {
+        dst[out_idx + c] = sums[c] / max(count, 1u);
+    }
+}
+
+fn nearest(x: u32, y: u32, out_idx: u32) {
+    let src_x = x * params.src_width / params.dst_width;
+    let src_y = y * params.src_height / params.dst_height;
+    let idx = (src_y * params.src_width + src_x) * params.channels;
+    for (var c = 0u; c < params.channels; c++) {
+        dst[out_idx + c] = src[idx + c];
+    }
+}
+
+@compute @workgroup_size(8, 8)
+fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.dst_width || id.y >= params.dst_height) {
+        return;
+    }
+    let out_idx = (id.y * params.dst_width + id.x) * params.channels;
+    if (params.mode == MODE_NEAREST) {
+        nearest(id.x, id.y, out_idx);
+        return;
+    }
+    // Block edges are spread over the whole source like on the CPU
+    average(
+        id.x * params.src_width / params.dst_width,
+        (id.x + 1u) * params.src_width / params.dst_width,
+        id.y * params.src_height / params.dst_height,
+        (id.y + 1u) * params.src_height / params.dst_height,
+        out_idx,
+    );
+}
+
+@compute @workgroup_size(8, 8)
+fn upsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.dst_width || id.y >= params.dst_height) {
+        return;
+    }
+    let out_idx = (id.y * params.dst_width + id.x) * params.channels;
+    if (params.mode == MODE_NEAREST) {
+        nearest(id.x, id.y, out_idx);
+        return;
+    }
+    // Target pixels that straddle two blocks blend them, like the CPU version
+    average(
+        id.x * params.src_width / params.dst_width,
+        min(((id.x + 1u) * params.src_width + params.dst_width - 1u) / params.dst_width, params.src_width),
+        id.y * params.src_height / params.dst_height,
+        min(((id.y + 1u) * params.src_height + params.dst_height - 1u) / params.dst_height, params.src_height),
+        out_idx,
+    );
+}
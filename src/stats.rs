@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::types::ImageBuffer;
+
+/// Figures that help judge whether a result could use fewer colors or a lower bit depth
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageStats {
+    pub unique_colors: usize,
+    /// Shannon entropy of the color histogram in bits per pixel
+    pub entropy: f64,
+    /// Variance of the source channel values inside a block, averaged over all blocks
+    pub block_variance: f64,
+}
+
+impl fmt::Display for ImageStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "unique colors: {}", self.unique_colors)?;
+        writeln!(f, "entropy: {:.4} bits per pixel", self.entropy)?;
+        write!(f, "block variance: {:.4}", self.block_variance)
+    }
+}
+
+/**
+ * Statistics of `result`, the pixelation of `source` into a grid of `grid_width` x
+ * `grid_height` blocks. A high block variance means the blocks flatten a lot of detail. */
+pub fn image_stats(
+    source: &ImageBuffer,
+    result: &ImageBuffer,
+    (grid_width, grid_height): (usize, usize),
+) -> ImageStats {
+    let mut counts: HashMap<&[u8], usize> = HashMap::new();
+    for pixel in result.data.chunks_exact(result.channels) {
+        *counts.entry(pixel).or_default() += 1;
+    }
+    let pixels = (result.width * result.height).max(1) as f64;
+    let entropy = counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / pixels;
+            -p * p.log2()
+        })
+        .sum::<f64>()
+        .max(0.0);
+
+    // Same block edges as the average area downsampling
+    let channels = source.channels;
+    let mut variance_sum = 0.0;
+    for block_y in 0..grid_height {
+        let (y_start, y_end) = (
+            block_y * source.height / grid_height,
+            (block_y + 1) * source.height / grid_height,
+        );
+        for block_x in 0..grid_width {
+            let (x_start, x_end) = (
+                block_x * source.width / grid_width,
+                (block_x + 1) * source.width / grid_width,
+            );
+            let (mut sums, mut squares) = (vec![0f64; channels], vec![0f64; channels]);
+            for y in y_start..y_end {
+                let row = &source.data[(y * source.width + x_start) * channels..];
+                for pixel in row[..(x_end - x_start) * channels].chunks_exact(channels) {
+                    for (channel, &value) in pixel.iter().enumerate() {
+                        sums[channel] += value as f64;
+                        squares[channel] += (value as f64).powi(2);
+                    }
+                }
+            }
+            let count = ((x_end - x_start) * (y_end - y_start)).max(1) as f64;
+            variance_sum += sums
+                .iter()
+                .zip(&squares)
+                .map(|(sum, square)| square / count - (sum / count).powi(2))
+                .sum::<f64>()
+                / channels as f64;
+        }
+    }
+
+    ImageStats {
+        unique_colors: counts.len(),
+        entropy,
+        block_variance: variance_sum / (grid_width * grid_height).max(1) as f64,
+    }
+}
+
+/// How much of the source a pixelation discards
+#[derive(Debug, Clone, PartialEq)]
+pub struct LossReport {
+    /// Share of the source variance the result still reproduces, 1 for a lossless result
+    pub variance_explained: f64,
+    /// Share of the detail inside blocks, measured with a Laplacian filter, that is flattened
+    pub high_frequency_removed: f64,
+}
+
+impl fmt::Display for LossReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "variance explained: {:.2}%",
+            100.0 * self.variance_explained
+        )?;
+        write!(
+            f,
+            "high-frequency energy removed: {:.2}%",
+            100.0 * self.high_frequency_removed
+        )
+    }
+}
+
+/**
+ * Compares `result` with the `source` it was pixelated from, both of the same size. The
+ * Laplacian is only taken away from block edges, so the edges the blocks add do not count
+ * as detail. */
+pub fn loss_report(
+    source: &ImageBuffer,
+    result: &ImageBuffer,
+    (grid_width, grid_height): (usize, usize),
+) -> LossReport {
+    debug_assert_eq!(
+        (source.width, source.height, source.channels),
+        (result.width, result.height, result.channels)
+    );
+    let channels = source.channels;
+    let pixels = (source.width * source.height).max(1) as f64;
+    let mut means = vec![0f64; channels];
+    for pixel in source.data.chunks_exact(channels) {
+        for (mean, &value) in means.iter_mut().zip(pixel) {
+            *mean += value as f64 / pixels;
+        }
+    }
+    let (mut total, mut residual) = (0f64, 0f64);
+    for (index, (&value, &kept)) in source.data.iter().zip(&result.data).enumerate() {
+        total += (value as f64 - means[index % channels]).powi(2);
+        residual += (value as f64 - kept as f64).powi(2);
+    }
+
+    // Block of every column and row, with the same edges as the average area downsampling
+    let blocks = |size: usize, count: usize| -> Vec<usize> {
+        (0..size)
+            .map(|i| ((i + 1) * count).div_ceil(size) - 1)
+            .collect()
+    };
+    let (columns, rows) = (
+        blocks(source.width, grid_width),
+        blocks(source.height, grid_height),
+    );
+    let laplacian = |image: &ImageBuffer, x: usize, y: usize, channel: usize| {
+        let at = |x: usize, y: usize| image.data[(y * image.width + x) * channels + channel] as f64;
+        4.0 * at(x, y) - at(x - 1, y) - at(x + 1, y) - at(x, y - 1) - at(x, y + 1)
+    };
+    let (mut source_energy, mut result_energy) = (0f64, 0f64);
+    for y in 1..source.height.saturating_sub(1) {
+        if rows[y - 1] != rows[y + 1] {
+            continue;
+        }
+        for x in 1..source.width.saturating_sub(1) {
+            if columns[x - 1] != columns[x + 1] {
+                continue;
+            }
+            for channel in 0..channels {
+                source_energy += laplacian(source, x, y, channel).powi(2);
+                result_energy += laplacian(result, x, y, channel).powi(2);
+            }
+        }
+    }
+
+    let share = |part: f64, whole: f64| if whole > 0.0 { part / whole } else { 0.0 };
+    LossReport {
+        variance_explained: if total > 0.0 {
+            (1.0 - residual / total).max(0.0)
+        } else {
+            1.0
+        },
+        high_frequency_removed: (1.0 - share(result_energy, source_energy)).clamp(0.0, 1.0),
+    }
+}
+
+/// How one algorithm did in `--compare-algorithms`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlgorithmScore {
+    pub algorithm: String,
+    /// Time the pixelation took, decoding and encoding excluded
+    pub duration: Duration,
+    /// PSNR of the result against the prepared image in dB
+    pub psnr: f64,
+    pub ssim: f64,
+    pub output: PathBuf,
+}
+
+/// Results of `--compare-algorithms`, printed as a table with one row per algorithm
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison(pub Vec<AlgorithmScore>);
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<16}{:>10}{:>12}{:>8}  output",
+            "algorithm", "time", "psnr", "ssim"
+        )?;
+        for score in &self.0 {
+            write!(
+                f,
+                "\n{:<16}{:>8.1}ms{:>9.2} dB{:>8.4}  {}",
+                score.algorithm,
+                score.duration.as_secs_f64() * 1000.0,
+                score.psnr,
+                score.ssim,
+                score.output.display()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Totals of a batch run, for monitoring and tuning large jobs
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BatchSummary {
+    pub processed: usize,
+    /// Inputs that are broken or over the limits
+    pub skipped: usize,
+    pub failed: usize,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub wall_time_seconds: f64,
+    pub files_per_second: f64,
+}
+
+impl BatchSummary {
+    /// Records how long the whole batch took and the throughput that makes for
+    pub fn finish(&mut self, wall_time: Duration) {
+        self.wall_time_seconds = wall_time.as_secs_f64();
+        self.files_per_second = match self.wall_time_seconds {
+            0.0 => 0.0,
+            seconds => self.processed as f64 / seconds,
+        };
+    }
+}
+
+impl fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "files: {} processed, {} skipped, {} failed",
+            self.processed, self.skipped, self.failed
+        )?;
+        writeln!(f, "bytes: {} in, {} out", self.bytes_in, self.bytes_out)?;
+        write!(
+            f,
+            "wall time: {:.2} s, {:.2} files per second",
+            self.wall_time_seconds, self.files_per_second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AlgorithmScore, BatchSummary, Comparison, image_stats, loss_report};
+    use crate::types::ImageBuffer;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn test_image_stats() {
+        // Two blocks, the left one flat and the right one alternating between 0 and 20
+        let source = ImageBuffer::new(4, 1, 1, vec![10, 10, 0, 20]);
+        let result = ImageBuffer::new(4, 1, 1, vec![10, 10, 10, 10]);
+        let stats = image_stats(&source, &result, (2, 1));
+        assert_eq!(stats.unique_colors, 1);
+        assert_eq!(stats.entropy, 0.0);
+        assert_eq!(stats.block_variance, 50.0);
+
+        let result = ImageBuffer::new(4, 1, 1, vec![0, 0, 255, 255]);
+        let stats = image_stats(&source, &result, (4, 1));
+        assert_eq!(stats.unique_colors, 2);
+        assert_eq!(stats.entropy, 1.0);
+        assert_eq!(stats.block_variance, 0.0);
+    }
+
+    #[test]
+    fn test_loss_report() {
+        // A checkerboard inside each 3x3 block
+        let source = ImageBuffer::new(
+            6,
+            3,
+            1,
+            (0..18)
+                .map(|i| if (i % 6 + i / 6) % 2 == 0 { 0 } else { 100 })
+                .collect(),
+        );
+        let lossless = loss_report(&source, &source, (2, 1));
+        assert_eq!(lossless.variance_explained, 1.0);
+        assert_eq!(lossless.high_frequency_removed, 0.0);
+
+        let flat = ImageBuffer::new(6, 3, 1, vec![50; 18]);
+        let report = loss_report(&source, &flat, (2, 1));
+        assert_eq!(report.variance_explained, 0.0);
+        assert_eq!(report.high_frequency_removed, 1.0);
+    }
+
+    #[test]
+    fn test_comparison_table() {
+        let table = Comparison(vec![AlgorithmScore {
+            algorithm: String::from("average-area"),
+            duration: Duration::from_micros(12_345),
+            psnr: 27.5,
+            ssim: 0.8125,
+            output: PathBuf::from("out_average-area.png"),
+        }])
+        .to_string();
+        let mut lines = table.lines();
+        assert_eq!(
+            lines.next(),
+            Some("algorithm             time        psnr    ssim  output")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("average-area        12.3ms    27.50 dB  0.8125  out_average-area.png")
+        );
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn test_batch_summary() {
+        let mut summary = BatchSummary {
+            processed: 6,
+            skipped: 1,
+            bytes_in: 2048,
+            bytes_out: 512,
+            ..BatchSummary::default()
+        };
+        summary.finish(Duration::from_millis(1500));
+        assert_eq!(summary.files_per_second, 4.0);
+        assert_eq!(
+            summary.to_string(),
+            "files: 6 processed, 1 skipped, 0 failed\nbytes: 2048 in, 512 out\n\
+             wall time: 1.50 s, 4.00 files per second"
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+const BOUNDARY: &str = "frame";
+
+/// The last published frame and how many frames were published so far
+#[derive(Default)]
+struct Latest {
+    frame: Option<Arc<Vec<u8>>>,
+    count: u64,
+}
+
+type Shared = (Mutex<Latest>, Condvar);
+
+/**
+ * Serves the last published JPEG as an MJPEG stream over HTTP, which browsers show as a
+ * live image. Every request gets the stream, whatever its path. Clients that are slower
+ * than the frames skip to the latest one. */
+pub struct MjpegServer {
+    latest: Arc<Shared>,
+    address: SocketAddr,
+}
+
+impl MjpegServer {
+    pub fn bind(address: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let latest: Arc<Shared> = Arc::default();
+        let shared = Arc::clone(&latest);
+        thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                let latest = Arc::clone(&shared);
+                // A client that disconnects only ends its own thread
+                thread::spawn(move || stream_frames(client, &latest));
+            }
+        });
+        Ok(MjpegServer { latest, address })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn publish(&self, frame: Vec<u8>) {
+        let (latest, published) = &*self.latest;
+        let mut latest = latest.lock().unwrap();
+        latest.frame = Some(Arc::new(frame));
+        latest.count += 1;
+        published.notify_all();
+    }
+}
+
+fn stream_frames(mut client: TcpStream, latest: &Shared) -> io::Result<()> {
+    // The request line and headers are read but do not change the response
+    let mut request = BufReader::new(client.try_clone()?);
+    let mut line = String::new();
+    while request.read_line(&mut line)? > 0 && line != "\r\n" {
+        line.clear();
+    }
+    write!(
+        client,
+        "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary={BOUNDARY}\r\n\
+         Cache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+
+    let (latest, published) = latest;
+    let mut seen = 0;
+    loop {
+        let frame = {
+            let latest = published
+                .wait_while(latest.lock().unwrap(), |latest| latest.count == seen)
+                .unwrap();
+            seen = latest.count;
+            Arc::clone(latest.frame.as_ref().expect("published frames are kept"))
+        };
+        write!(
+            client,
+            "--{BOUNDARY}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+            frame.len()
+        )?;
+        client.write_all(&frame)?;
+        client.write_all(b"\r\n")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    use super::MjpegServer;
+
+    #[test]
+    fn test_streams_published_frames() {
+        let server = MjpegServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        server.publish(b"first".to_vec());
+        let mut client = TcpStream::connect(server.address()).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+
+        let mut received = Vec::new();
+        let mut buffer = [0; 256];
+        let mut read_until = |needle: &[u8], received: &mut Vec<u8>| {
+            while !received
+                .windows(needle.len())
+                .any(|window| window == needle)
+            {
+                let read = client.read(&mut buffer).unwrap();
+                assert!(read > 0, "stream ended early");
+                received.extend_from_slice(&buffer[..read]);
+            }
+        };
+        read_until(b"first\r\n", &mut received);
+        server.publish(b"second".to_vec());
+        read_until(b"second\r\n", &mut received);
+
+        let response = String::from_utf8(received).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("multipart/x-mixed-replace; boundary=frame"));
+        assert!(response.contains("--frame\r\nContent-Type: image/jpeg\r\nContent-Length: 6\r\n"));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::cli::is_input_path;
+
+#[derive(Debug, Error)]
+pub enum TraverseError {
+    #[error("Failed to read directory: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid exclude pattern: {0}")]
+    Pattern(#[from] globset::Error),
+}
+
+/// How a directory tree is walked in batch mode
+#[derive(Debug, Clone, Default)]
+pub struct TraverseOptions {
+    /// Descend into linked directories and pick up linked files, links are skipped otherwise
+    pub follow_symlinks: bool,
+    /// Paths relative to the root that are skipped, matching directories are not entered
+    pub exclude: GlobSet,
+}
+
+/// Compiles gitignore style patterns: `*` does not cross directory boundaries, patterns
+/// without a `/` like `*.bak` match in any directory and others like `tmp/**` are relative
+/// to the root
+pub fn exclude_patterns(patterns: &[String]) -> Result<GlobSet, TraverseError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(exclude_glob(pattern)?);
+    }
+    Ok(builder.build()?)
+}
+
+fn exclude_glob(pattern: &str) -> Result<Glob, globset::Error> {
+    let pattern = pattern.trim_end_matches('/');
+    let pattern = match pattern.strip_prefix('/') {
+        Some(anchored) => anchored.to_string(),
+        None if !pattern.contains('/') => format!("**/{}", pattern),
+        None => pattern.to_string(),
+    };
+    GlobBuilder::new(&pattern).literal_separator(true).build()
+}
+
+/// Reads one pattern per line like a `.gitignore`, blank lines and `#` comments are skipped
+pub fn read_exclude_file(path: &Path) -> Result<Vec<String>, TraverseError> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// Whether the pattern compiles, for validating command line arguments
+pub fn is_valid_pattern(pattern: &str) -> Result<(), globset::Error> {
+    exclude_glob(pattern).map(|_| ())
+}
+
+/**
+ * Collects every supported input image below `root`, sorted so runs are reproducible.
+ * Each directory is entered at most once, which breaks cycles formed by symlinks. */
+pub fn collect_inputs(
+    root: &Path,
+    options: &TraverseOptions,
+) -> Result<Vec<PathBuf>, TraverseError> {
+    let mut visited = HashSet::new();
+    let mut inputs = Vec::new();
+    visit(root, root, options, &mut visited, &mut inputs)?;
+    inputs.sort();
+    Ok(inputs)
+}
+
+fn visit(
+    root: &Path,
+    dir: &Path,
+    options: &TraverseOptions,
+    visited: &mut HashSet<PathBuf>,
+    inputs: &mut Vec<PathBuf>,
+) -> Result<(), TraverseError> {
+    // Canonical paths identify a directory no matter through which link it was reached
+    if !visited.insert(fs::canonicalize(dir)?) {
+        warn!(path = %dir.display(), "skipping directory that was already visited");
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        if options.exclude.is_match(relative) {
+            debug!(path = %path.display(), "skipping excluded path");
+            continue;
+        }
+        let mut file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            if !options.follow_symlinks {
+                debug!(path = %path.display(), "skipping symlink");
+                continue;
+            }
+            match fs::metadata(&path) {
+                Ok(metadata) => file_type = metadata.file_type(),
+                Err(_) => {
+                    warn!(path = %path.display(), "skipping broken symlink");
+                    continue;
+                }
+            }
+        }
+
+        if file_type.is_dir() {
+            visit(root, &path, options, visited, inputs)?;
+        } else if is_input_path(&path) {
+            inputs.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    use super::{TraverseOptions, collect_inputs, exclude_patterns};
+
+    #[test]
+    fn test_collects_supported_images_recursively() {
+        let root = env::temp_dir().join("traverse_recursive");
+        fs::create_dir_all(root.join("nested")).unwrap();
+        for file in ["b.jpeg", "notes.txt", "nested/a.JPG", "nested/c.gif"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+
+        let inputs = collect_inputs(&root, &TraverseOptions::default()).unwrap();
+        assert_eq!(
+            inputs,
+            vec![
+                root.join("b.jpeg"),
+                root.join("nested/a.JPG"),
+                root.join("nested/c.gif"),
+            ]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_exclude_patterns() {
+        let root = env::temp_dir().join("traverse_exclude");
+        fs::create_dir_all(root.join("tmp/deep")).unwrap();
+        fs::create_dir_all(root.join("keep/tmp")).unwrap();
+        for file in [
+            "a.jpeg",
+            "tmp/deep/b.jpeg",
+            "keep/tmp/c.jpeg",
+            "keep/d.jpeg",
+        ] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+
+        let options = TraverseOptions {
+            exclude: exclude_patterns(&[String::from("tmp/**"), String::from("d.*")]).unwrap(),
+            ..Default::default()
+        };
+        let inputs = collect_inputs(&root, &options).unwrap();
+        assert_eq!(
+            inputs,
+            vec![root.join("a.jpeg"), root.join("keep/tmp/c.jpeg")]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_policy_and_cycles() {
+        use std::os::unix::fs::symlink;
+
+        let root = env::temp_dir().join("traverse_symlinks");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("assets")).unwrap();
+        fs::write(root.join("assets/a.jpeg"), b"").unwrap();
+        symlink(Path::new("../assets"), root.join("assets/loop")).unwrap();
+        symlink(Path::new("a.jpeg"), root.join("assets/linked.jpeg")).unwrap();
+
+        let inputs = collect_inputs(&root, &TraverseOptions::default()).unwrap();
+        assert_eq!(inputs, vec![root.join("assets/a.jpeg")]);
+
+        let options = TraverseOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let inputs = collect_inputs(&root, &options).unwrap();
+        assert_eq!(
+            inputs,
+            vec![root.join("assets/a.jpeg"), root.join("assets/linked.jpeg")]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
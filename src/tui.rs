@@ -0,0 +1,280 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use ratatui::DefaultTerminal;
+use ratatui::buffer::Buffer;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::Color;
+use ratatui::text::Line;
+use ratatui::widgets::{Paragraph, Widget};
+
+use crate::cli::{Algorithm, Args, TuiArgs, default_output_path};
+use crate::decoder::{DecodeOptions, decode_bytes};
+use crate::interpolation::{
+    AverageAreaInterpolation, InterpolationAlgorithm, NearestNeighborInterpolation,
+};
+use crate::quantization::{ColorReduction, Quantizer, quantize_grid};
+use crate::types::ImageBuffer;
+use crate::{Context, PipelineStep, UserFacingError, process, read_input};
+
+const KEY_HELP: &str =
+    "←/→ resolution  PgUp/PgDn ×2  ↑/↓ bit depth  a algorithm  enter save  q quit";
+
+/// Settings that can be changed while previewing
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Settings {
+    resolution: u16,
+    bit_depth: u8,
+    algorithm: Algorithm,
+}
+
+enum Action {
+    Continue,
+    Save,
+    Quit,
+}
+
+impl Settings {
+    fn handle_key(&mut self, key: KeyCode) -> Action {
+        match key {
+            KeyCode::Right | KeyCode::Char('l' | '+') => {
+                self.resolution = self.resolution.saturating_add(1)
+            }
+            KeyCode::Left | KeyCode::Char('h' | '-') => {
+                self.resolution = self.resolution.saturating_sub(1).max(1)
+            }
+            KeyCode::PageUp => self.resolution = self.resolution.saturating_mul(2),
+            KeyCode::PageDown => self.resolution = (self.resolution / 2).max(1),
+            KeyCode::Up | KeyCode::Char('k') => self.bit_depth = (self.bit_depth + 1).min(8),
+            KeyCode::Down | KeyCode::Char('j') => self.bit_depth = (self.bit_depth - 1).max(1),
+            KeyCode::Tab | KeyCode::Char('a') => {
+                self.algorithm = match self.algorithm {
+                    Algorithm::AverageArea => Algorithm::Nearestneighbor,
+                    Algorithm::Nearestneighbor | Algorithm::Auto => Algorithm::AverageArea,
+                }
+            }
+            KeyCode::Enter | KeyCode::Char('s') => return Action::Save,
+            KeyCode::Esc | KeyCode::Char('q') => return Action::Quit,
+            _ => {}
+        }
+        Action::Continue
+    }
+}
+
+/**
+ * Previews the pixelation in the terminal and lets the settings be adjusted with the
+ * keyboard. Confirming runs the regular pipeline with them and writes the output. */
+pub fn run_tui(args: &TuiArgs) -> Result<(), UserFacingError> {
+    let (name, data) = read_input(&args.input)?;
+    let image = decode_bytes(&name, &data, &DecodeOptions::default())
+        .context(PipelineStep::Decoding, &args.input)?
+        .to_rgb();
+    let mut settings = Settings {
+        resolution: args.resolution,
+        bit_depth: args.bit_depth,
+        algorithm: args.algorithm,
+    };
+
+    let mut terminal = ratatui::try_init().map_err(UserFacingError::TerminalError)?;
+    let confirmed = preview_loop(&mut terminal, &image, &mut settings);
+    ratatui::try_restore().map_err(UserFacingError::TerminalError)?;
+    let confirmed = confirmed.map_err(UserFacingError::TerminalError)?;
+    if confirmed {
+        let output = save(args, &name, &data, &settings)?;
+        println!("Saved to {}", output.display());
+    }
+    Ok(())
+}
+
+/// Returns whether the settings were confirmed
+fn preview_loop(
+    terminal: &mut DefaultTerminal,
+    image: &ImageBuffer,
+    settings: &mut Settings,
+) -> std::io::Result<bool> {
+    let aspect = image.width as f64 / image.height.max(1) as f64;
+    let mut shown = None;
+    let mut grid = Err(String::new());
+    loop {
+        // Only pixelate again when a setting actually changed
+        if shown != Some(*settings) {
+            grid = preview(image, settings).map_err(|e| e.to_string());
+            shown = Some(*settings);
+        }
+        terminal.draw(|frame| {
+            let [preview_area, status_area] =
+                Layout::vertical([Constraint::Min(1), Constraint::Length(2)]).areas(frame.area());
+            match &grid {
+                Ok(grid) => frame.render_widget(Blocks { grid, aspect }, preview_area),
+                Err(error) => frame.render_widget(Paragraph::new(error.as_str()), preview_area),
+            }
+            let status = format!(
+                "resolution {}  bit depth {}  algorithm {}",
+                settings.resolution, settings.bit_depth, settings.algorithm
+            );
+            frame.render_widget(
+                Paragraph::new(vec![Line::from(status), Line::from(KEY_HELP)]),
+                status_area,
+            );
+        })?;
+
+        if let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            match settings.handle_key(key.code) {
+                Action::Continue => {}
+                Action::Save => return Ok(true),
+                Action::Quit => return Ok(false),
+            }
+        }
+    }
+}
+
+/// Downsampled and quantized grid, the upsampling is left to the terminal rendering
+fn preview(image: &ImageBuffer, settings: &Settings) -> Result<ImageBuffer, UserFacingError> {
+    // The preview is redrawn on every key press, too often to score the algorithms first
+    let algo: &dyn InterpolationAlgorithm = match settings.algorithm {
+        Algorithm::AverageArea | Algorithm::Auto => &AverageAreaInterpolation,
+        Algorithm::Nearestneighbor => &NearestNeighborInterpolation,
+    };
+    let grid = settings.resolution as usize;
+    let mut pixels = algo.downsample(&image.data, image.width, image.height, grid, grid, 3)?;
+    let quantizer = Quantizer::new(&ColorReduction::BitDepth(settings.bit_depth), &pixels, 3)?;
+    quantize_grid(&mut pixels, grid, grid, 3, &quantizer, None)?;
+    Ok(ImageBuffer::new(grid, grid, 3, pixels))
+}
+
+/// Runs the regular pipeline with the confirmed settings, returns the written path
+fn save(
+    args: &TuiArgs,
+    name: &Path,
+    data: &[u8],
+    settings: &Settings,
+) -> Result<PathBuf, UserFacingError> {
+    let algorithm = settings
+        .algorithm
+        .to_possible_value()
+        .expect("algorithms are never skipped");
+    let mut argv: Vec<OsString> = vec![
+        "smolres".into(),
+        "--input".into(),
+        args.input.clone().into(),
+        "--resolution".into(),
+        settings.resolution.to_string().into(),
+        "--bit-depth".into(),
+        settings.bit_depth.to_string().into(),
+        "--algorithm".into(),
+        algorithm.get_name().into(),
+    ];
+    if let Some(output) = &args.output {
+        argv.extend(["--output".into(), output.clone().into()]);
+    }
+    let mut save_args =
+        Args::try_parse_from(argv).map_err(|e| UserFacingError::InvalidInput(e.to_string()))?;
+
+    // The input was read already, URLs are not downloaded a second time
+    save_args.input = Some(name.to_path_buf());
+    let output = save_args.output.clone().unwrap_or_else(|| {
+        default_output_path(name, settings.resolution.into(), settings.algorithm)
+    });
+    process(save_args, Some(data), None, None)?;
+    Ok(output)
+}
+
+/// Draws the grid with the aspect ratio of the source, two pixels per terminal cell
+struct Blocks<'a> {
+    grid: &'a ImageBuffer,
+    aspect: f64,
+}
+
+impl Blocks<'_> {
+    fn color(&self, x: usize, y: usize, width: usize, height: usize) -> Color {
+        let grid_x = x * self.grid.width / width;
+        let grid_y = y * self.grid.height / height;
+        let idx = (grid_y * self.grid.width + grid_x) * 3;
+        let pixel = &self.grid.data[idx..idx + 3];
+        Color::Rgb(pixel[0], pixel[1], pixel[2])
+    }
+}
+
+impl Widget for Blocks<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        // A cell is about twice as high as wide, so each holds two square pixels
+        let (max_width, max_height) = (area.width as f64, area.height as f64 * 2.0);
+        let (width, height) = if max_width / max_height > self.aspect {
+            (max_height * self.aspect, max_height)
+        } else {
+            (max_width, max_width / self.aspect)
+        };
+        let (width, height) = ((width as usize).max(1), (height as usize).max(1));
+        let left = area.x + (area.width - width as u16) / 2;
+        let top = area.y + (area.height - height.div_ceil(2) as u16) / 2;
+
+        for row in 0..height.div_ceil(2) {
+            for x in 0..width {
+                let upper = self.color(x, row * 2, width, height);
+                let lower = if row * 2 + 1 < height {
+                    self.color(x, row * 2 + 1, width, height)
+                } else {
+                    Color::Reset
+                };
+                if let Some(cell) = buf.cell_mut((left + x as u16, top + row as u16)) {
+                    cell.set_symbol("▀").set_fg(upper).set_bg(lower);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::buffer::Buffer;
+    use ratatui::crossterm::event::KeyCode;
+    use ratatui::layout::Rect;
+    use ratatui::style::Color;
+    use ratatui::widgets::Widget;
+
+    use super::{Blocks, Settings};
+    use crate::cli::Algorithm;
+    use crate::types::ImageBuffer;
+
+    #[test]
+    fn test_settings_stay_in_range() {
+        let mut settings = Settings {
+            resolution: 1,
+            bit_depth: 8,
+            algorithm: Algorithm::AverageArea,
+        };
+        for key in [KeyCode::Left, KeyCode::PageDown, KeyCode::Up, KeyCode::Tab] {
+            settings.handle_key(key);
+        }
+        assert_eq!(
+            settings,
+            Settings {
+                resolution: 1,
+                bit_depth: 8,
+                algorithm: Algorithm::Nearestneighbor,
+            }
+        );
+    }
+
+    #[test]
+    fn test_blocks_keep_aspect_ratio() {
+        // Two pixels side by side, rendered into a square area
+        let grid = ImageBuffer::new(2, 1, 3, vec![255, 0, 0, 0, 0, 255]);
+        let area = Rect::new(0, 0, 4, 4);
+        let mut buf = Buffer::empty(area);
+        Blocks {
+            grid: &grid,
+            aspect: 2.0,
+        }
+        .render(area, &mut buf);
+
+        // 4x2 pixels fit in a single row of cells in the middle of the area
+        assert_eq!(buf[(0, 1)].fg, Color::Rgb(255, 0, 0));
+        assert_eq!(buf[(3, 1)].bg, Color::Rgb(0, 0, 255));
+        assert_eq!(buf[(0, 0)].symbol(), " ");
+    }
+}
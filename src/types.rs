@@ -1 +1 @@
-
+pub use smolres_core::types::{Component, ImageBuffer};